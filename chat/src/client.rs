@@ -1,13 +1,19 @@
-use crate::common::{ChatId, UserId};
+use crate::common::{ChatId, ChatKind, UserId};
 use crate::proto;
 
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use tokio_stream::StreamExt;
 
 type InnerClient = proto::chat_client::ChatClient<tonic::transport::Channel>;
 
 pub struct Client {
     pub token: String,
-    // TODO: your code here.
+    /// Токен для `refresh`, пуст до логина.
+    pub refresh_token: String,
+    /// Время, после которого `token` перестаёт действовать, если известно.
+    pub token_expires_at: Option<SystemTime>,
+    inner: InnerClient,
 }
 
 #[derive(Clone, Debug)]
@@ -20,7 +26,9 @@ pub struct User {
 #[derive(Clone, Debug)]
 pub struct Chat {
     pub id: ChatId,
+    /// У личной комнаты – имя собеседника.
     pub name: String,
+    pub kind: ChatKind,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -28,6 +36,21 @@ pub struct StreamMessagesResponseEntry {
     pub user_id: UserId,
     pub user_name: String,
     pub content: String,
+    /// Номер сообщения в комнате, начиная с единицы.
+    pub seq: u64,
+    /// Время отправки сообщения.
+    pub timestamp: SystemTime,
+}
+
+/// Какие сообщения возвращает `Client::get_messages`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessagesPage {
+    /// Последние сообщения.
+    Last,
+    /// Последние сообщения с номерами меньше заданного.
+    Before(u64),
+    /// Первые сообщения с номерами больше заданного.
+    After(u64),
 }
 
 #[derive(Debug)]
@@ -42,8 +65,8 @@ impl Client {
         token: Option<String>,
         dst: String,
     ) -> Result<Self, tonic::transport::Error> {
-        // TODO: your code here.
-        unimplemented!()
+        let inner = InnerClient::connect(dst).await?;
+        Ok(Self::new(token, inner))
     }
 
     /// Подключает к серверу, затем осущствляет логин с заданными юзернеймом и паролем.
@@ -52,13 +75,32 @@ impl Client {
         password: String,
         dst: String,
     ) -> Result<Self, ConnectLoginError> {
-        // TODO: your code here.
-        unimplemented!()
+        let mut client = Self::connect(None, dst)
+            .await
+            .map_err(ConnectLoginError::Connect)?;
+        client
+            .login(user_name, password)
+            .await
+            .map_err(ConnectLoginError::Login)?;
+        Ok(client)
     }
 
     pub fn new(token: Option<String>, inner: InnerClient) -> Client {
-        // TODO: your code here.
-        unimplemented!()
+        Client {
+            token: token.unwrap_or_default(),
+            refresh_token: String::new(),
+            token_expires_at: None,
+            inner,
+        }
+    }
+
+    fn start_session(&mut self, session: Option<proto::Session>) -> Result<(), tonic::Status> {
+        let session =
+            session.ok_or_else(|| tonic::Status::internal("no session in the response"))?;
+        self.token = session.token;
+        self.refresh_token = session.refresh_token;
+        self.token_expires_at = Some(from_micros(session.expires_at_micros));
+        Ok(())
     }
 
     /// Создаёт новые коды, необходимые для регистрации пользователя.
@@ -70,14 +112,21 @@ impl Client {
         &mut self,
         num_codes: u32,
     ) -> Result<Vec<String>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::CreateJoinCodesRequest {
+            token: self.token.clone(),
+            num_codes,
+        };
+        let response = self.inner.create_join_codes(request).await?.into_inner();
+        Ok(response.join_codes)
     }
 
     /// Возвращает список всех неиспользованных кодов.
     pub async fn list_join_codes(&mut self) -> Result<Vec<String>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::ListJoinCodesRequest {
+            token: self.token.clone(),
+        };
+        let response = self.inner.list_join_codes(request).await?.into_inner();
+        Ok(response.join_codes)
     }
 
     /// Использует код для регистрации. Каждый код можно использовать только один раз.
@@ -96,8 +145,14 @@ impl Client {
         user_name: String,
         password: String,
     ) -> Result<UserId, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::JoinRequest {
+            join_code,
+            user_name,
+            password,
+        };
+        let response = self.inner.join(request).await?.into_inner();
+        self.start_session(response.session)?;
+        Ok(UserId(response.user_id))
     }
 
     /// Логин с заданными юзернеймом и паролем.
@@ -108,28 +163,84 @@ impl Client {
         user_name: String,
         password: String,
     ) -> Result<(), tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::LoginRequest {
+            user_name,
+            password,
+        };
+        let response = self.inner.login(request).await?.into_inner();
+        self.start_session(response.session)
+    }
+
+    /// Получает новые токены в обмен на `refresh_token`, когда срок действия `token` истекает.
+    /// Старые токены при этом перестают действовать.
+    pub async fn refresh(&mut self) -> Result<(), tonic::Status> {
+        let request = proto::RefreshTokenRequest {
+            refresh_token: self.refresh_token.clone(),
+        };
+        let response = self.inner.refresh_token(request).await?.into_inner();
+        self.start_session(response.session)
+    }
+
+    /// Отзывает токены и завершает все потоки сообщений пользователя.
+    pub async fn logout(&mut self) -> Result<(), tonic::Status> {
+        let request = proto::LogoutRequest {
+            token: self.token.clone(),
+        };
+        self.inner.logout(request).await?;
+        self.token.clear();
+        self.refresh_token.clear();
+        self.token_expires_at = None;
+        Ok(())
+    }
+
+    /// Меняет пароль. Новый пароль должен удовлетворять тем же требованиям, что и при
+    /// регистрации.
+    pub async fn change_password(
+        &mut self,
+        old_password: String,
+        new_password: String,
+    ) -> Result<(), tonic::Status> {
+        let request = proto::ChangePasswordRequest {
+            token: self.token.clone(),
+            old_password,
+            new_password,
+        };
+        self.inner.change_password(request).await?;
+        Ok(())
     }
 
     /// Возвращает список всех пользователей.
     pub async fn list_users(&mut self) -> Result<Vec<User>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::ListUsersRequest {
+            token: self.token.clone(),
+        };
+        let response = self.inner.list_users(request).await?.into_inner();
+        Ok(response.users.into_iter().map(User::from).collect())
     }
 
     /// Возвращает информацию о пользователе.
     pub async fn get_user(&mut self, user_id: UserId) -> Result<User, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::GetUserRequest {
+            token: self.token.clone(),
+            user_id: user_id.0,
+        };
+        let response = self.inner.get_user(request).await?.into_inner();
+        let user = response
+            .user
+            .ok_or_else(|| tonic::Status::internal("no user in the response"))?;
+        Ok(user.into())
     }
 
     /// Отправляет голос за бан пользователя. Если голосующий является администратором, то
     /// пользователь сразу же банится. В противном случае пользователь банится при превышении
     /// половины числа всех зарегистрированных пользователей числом проголосовавщих за бан.
     pub async fn ban_user(&mut self, user_id: UserId) -> Result<bool, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::BanUserRequest {
+            token: self.token.clone(),
+            user_id: user_id.0,
+        };
+        let response = self.inner.ban_user(request).await?.into_inner();
+        Ok(response.pending)
     }
 
     /// Отзывает голос за бан пользователя. Если голосующий является администратором, то
@@ -137,47 +248,215 @@ impl Client {
     /// голос удаляется из множества голосов, иначе не происходит ничего – отозвать свершившийся бан
     /// может только администратор.
     pub async fn unban_user(&mut self, user_id: UserId) -> Result<(), tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::UnbanUserRequest {
+            token: self.token.clone(),
+            user_id: user_id.0,
+        };
+        self.inner.unban_user(request).await?;
+        Ok(())
     }
 
-    /// Создаёт комнату с чатом.
+    /// Создаёт открытую комнату с чатом.
     pub async fn create_chat_room(&mut self, name: String) -> Result<ChatId, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        self.do_create_chat_room(name, false).await
+    }
+
+    /// Создаёт закрытую комнату, которую видят только создатель и приглашённые им пользователи.
+    pub async fn create_private_chat_room(
+        &mut self,
+        name: String,
+    ) -> Result<ChatId, tonic::Status> {
+        self.do_create_chat_room(name, true).await
+    }
+
+    async fn do_create_chat_room(
+        &mut self,
+        name: String,
+        private: bool,
+    ) -> Result<ChatId, tonic::Status> {
+        let request = proto::CreateChatRoomRequest {
+            token: self.token.clone(),
+            name,
+            private,
+        };
+        let response = self.inner.create_chat_room(request).await?.into_inner();
+        Ok(ChatId(response.chat_id))
+    }
+
+    /// Приглашает пользователя в закрытую комнату. Доступно только создателю комнаты.
+    pub async fn invite_to_chat_room(
+        &mut self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<(), tonic::Status> {
+        let request = proto::InviteToChatRoomRequest {
+            token: self.token.clone(),
+            chat_id: chat_id.0,
+            user_id: user_id.0,
+        };
+        self.inner.invite_to_chat_room(request).await?;
+        Ok(())
     }
 
-    /// Возвращает список всех комнат.
+    /// Выгоняет пользователя из закрытой комнаты. Доступно только создателю комнаты. Потоки
+    /// сообщений, открытые выгнанным пользователем, завершаются ошибкой.
+    pub async fn kick_from_chat_room(
+        &mut self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<(), tonic::Status> {
+        let request = proto::KickFromChatRoomRequest {
+            token: self.token.clone(),
+            chat_id: chat_id.0,
+            user_id: user_id.0,
+        };
+        self.inner.kick_from_chat_room(request).await?;
+        Ok(())
+    }
+
+    /// Возвращает участников закрытой или личной комнаты, начиная с создателя. У открытых
+    /// комнат участников нет.
+    pub async fn list_chat_room_members(
+        &mut self,
+        chat_id: ChatId,
+    ) -> Result<Vec<UserId>, tonic::Status> {
+        let request = proto::ListChatRoomMembersRequest {
+            token: self.token.clone(),
+            chat_id: chat_id.0,
+        };
+        let response = self
+            .inner
+            .list_chat_room_members(request)
+            .await?
+            .into_inner();
+        Ok(response.user_ids.into_iter().map(UserId).collect())
+    }
+
+    /// Возвращает личную комнату с пользователем, создавая её при первом обращении.
+    pub async fn open_direct(&mut self, user_id: UserId) -> Result<ChatId, tonic::Status> {
+        let request = proto::OpenDirectRequest {
+            token: self.token.clone(),
+            user_id: user_id.0,
+        };
+        let response = self.inner.open_direct(request).await?.into_inner();
+        Ok(ChatId(response.chat_id))
+    }
+
+    /// Возвращает список комнат, которые видит пользователь: открытые, а также закрытые и
+    /// личные, в которых он участвует.
     pub async fn list_chat_rooms(&mut self) -> Result<Vec<Chat>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::ListChatRoomsRequest {
+            token: self.token.clone(),
+        };
+        let response = self.inner.list_chat_rooms(request).await?.into_inner();
+        let chats = response
+            .chat_rooms
+            .into_iter()
+            .map(|entry| Chat {
+                id: ChatId(entry.chat_id),
+                kind: entry.kind().into(),
+                name: entry.name,
+            })
+            .collect();
+        Ok(chats)
     }
 
     /// Возвращает информацию о комнате.
     pub async fn get_chat_room(&mut self, chat_id: ChatId) -> Result<Chat, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::GetChatRoomRequest {
+            token: self.token.clone(),
+            chat_id: chat_id.0,
+        };
+        let response = self.inner.get_chat_room(request).await?.into_inner();
+        Ok(Chat {
+            id: chat_id,
+            kind: response.kind().into(),
+            name: response.name,
+        })
     }
 
-    /// Посылает сообщение в комнату.
+    /// Посылает сообщение в комнату. Возвращает номер сообщения.
     pub async fn send_message(
         &mut self,
         chat_id: ChatId,
         content: String,
-    ) -> Result<(), tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+    ) -> Result<u64, tonic::Status> {
+        let request = proto::SendMessageRequest {
+            token: self.token.clone(),
+            chat_id: chat_id.0,
+            content,
+        };
+        let response = self.inner.send_message(request).await?.into_inner();
+        Ok(response.seq)
     }
 
-    /// Возвращает поток с сообщениями, которые пришли после ответа на этот запрос.
+    /// Возвращает не больше `limit` сообщений из комнаты в порядке их номеров. `limit == 0`
+    /// обозначает число по умолчанию, 100 сообщений.
+    pub async fn get_messages(
+        &mut self,
+        chat_id: ChatId,
+        page: MessagesPage,
+        limit: u32,
+    ) -> Result<Vec<StreamMessagesResponseEntry>, tonic::Status> {
+        let (before_seq, after_seq) = match page {
+            MessagesPage::Last => (None, None),
+            MessagesPage::Before(seq) => (Some(seq), None),
+            MessagesPage::After(seq) => (None, Some(seq)),
+        };
+        let request = proto::GetMessagesRequest {
+            token: self.token.clone(),
+            chat_id: chat_id.0,
+            before_seq,
+            after_seq,
+            limit,
+        };
+        let response = self.inner.get_messages(request).await?.into_inner();
+        Ok(response.messages.into_iter().map(Into::into).collect())
+    }
+
+    /// Возвращает поток с сообщениями, которые пришли после ответа на этот запрос. Если задан
+    /// `after_seq`, поток сначала возвращает сообщения с номерами больше него, так что
+    /// переподключившийся клиент не пропустит и не получит дважды ни одного сообщения.
     pub async fn stream_messages(
         &mut self,
         chat_id: ChatId,
+        after_seq: Option<u64>,
     ) -> Result<
         impl futures::Stream<Item = Result<StreamMessagesResponseEntry, tonic::Status>>,
         tonic::Status,
     > {
-        // TODO: your code here.
-        unimplemented!()
+        let request = proto::StreamMessagesRequest {
+            token: self.token.clone(),
+            chat_id: chat_id.0,
+            after_seq,
+        };
+        let stream = self.inner.stream_messages(request).await?.into_inner();
+        Ok(stream.map(|entry| entry.map(StreamMessagesResponseEntry::from)))
+    }
+}
+
+impl From<proto::UserPublic> for User {
+    fn from(user: proto::UserPublic) -> Self {
+        Self {
+            id: UserId(user.id),
+            name: user.name,
+            banned: user.banned,
+        }
+    }
+}
+
+impl From<proto::StreamMessagesResponseEntry> for StreamMessagesResponseEntry {
+    fn from(entry: proto::StreamMessagesResponseEntry) -> Self {
+        Self {
+            user_id: UserId(entry.user_id),
+            user_name: entry.user_name,
+            content: entry.content,
+            seq: entry.seq,
+            timestamp: from_micros(entry.timestamp_micros),
+        }
     }
 }
+
+fn from_micros(micros: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_micros(micros.max(0) as u64)
+}
@@ -0,0 +1,498 @@
+use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{env, fs, io};
+
+use futures::{future, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+
+use chat::client::{Chat, MessagesPage, StreamMessagesResponseEntry};
+use chat::{ChatKind, Client, UserId};
+
+const DEFAULT_ADDR: &str = "http://127.0.0.1:8000";
+
+/// Сколько последних сообщений показывается при входе в комнату.
+const HISTORY_LEN: u32 = 20;
+
+/// За сколько до истечения токена клиент получает новый.
+const REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+const HELP: &str = "\
+/join <code> [name]             зарегистрироваться по коду, пароль спрашивается отдельно
+/login [name]                   войти, пароль спрашивается отдельно
+/logout                         выйти и забыть токены
+/passwd                         сменить пароль
+/users                          список пользователей
+/ban <user>, /unban <user>      проголосовать за бан или отозвать голос
+/rooms                          список доступных комнат
+/create [--private] <name>      создать комнату
+/enter <room>                   войти в комнату по имени или номеру
+/dm <user>                      войти в личную комнату с пользователем
+/invite <user>, /kick <user>    пригласить в текущую закрытую комнату или выгнать из неё
+/codes <n>                      создать коды для регистрации (только администратор)
+/quit                           выйти из клиента
+Строка без косой черты отправляется в текущую комнату.";
+
+#[derive(StructOpt)]
+struct Opts {
+    /// Адрес сервера. По умолчанию берётся из файла настроек, иначе http://127.0.0.1:8000.
+    #[structopt(long)]
+    addr: Option<String>,
+
+    /// Токен администратора, с которым работает клиент вместо сохранённого.
+    #[structopt(long)]
+    admin_token: Option<String>,
+
+    /// Файл настроек, по умолчанию `$XDG_CONFIG_HOME/chat/client.json`.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+}
+
+/// Настройки и учётные данные, которые клиент помнит между запусками. Пароль не сохраняется.
+#[derive(Default, Serialize, Deserialize)]
+struct Config {
+    addr: Option<String>,
+    user_name: Option<String>,
+    token: Option<String>,
+    refresh_token: Option<String>,
+    /// Время истечения токена в секундах с начала эпохи Unix.
+    token_expires_at: Option<u64>,
+}
+
+impl Config {
+    fn load(path: &Path) -> io::Result<Self> {
+        match fs::read(path) {
+            Ok(contents) => Ok(serde_json::from_slice(&contents)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // NB: в файле лежат токены, так что читать его может только владелец. `mode` действует
+        // лишь на новый файл, поэтому права существующего исправляются отдельно.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+        file.write_all(&serde_json::to_vec_pretty(self)?)
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => env::var_os("HOME")
+            .map(|home| Path::new(&home).join(".config"))
+            .unwrap_or_default(),
+    };
+    config_dir.join("chat").join("client.json")
+}
+
+type MessageStream =
+    Pin<Box<dyn Stream<Item = Result<StreamMessagesResponseEntry, tonic::Status>> + Send>>;
+
+/// Комната, в которую пишет пользователь, вместе с потоком её сообщений.
+struct Room {
+    chat: Chat,
+    messages: MessageStream,
+}
+
+struct App {
+    client: Client,
+    config: Config,
+    config_path: PathBuf,
+    /// Администратор работает со своим токеном, который не сохраняется.
+    is_admin: bool,
+    room: Option<Room>,
+}
+
+type Result<T, E = Box<dyn Error>> = std::result::Result<T, E>;
+
+impl App {
+    /// Выполняет строку, введённую пользователем. Возвращает `false`, если пора выходить.
+    async fn handle(&mut self, line: &str, input: &mut Lines<BufReader<Stdin>>) -> Result<bool> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(true);
+        }
+        let command = line.strip_prefix('/').map(str::trim_start);
+        let name = command.map(|command| command.split_whitespace().next().unwrap_or_default());
+        // NB: этим командам сессия не нужна, а `/join` и `/login` как раз начинают новую, даже
+        // если старую уже не обновить.
+        if !matches!(name, Some("help" | "quit" | "join" | "login")) {
+            self.refresh_if_expiring().await?;
+        }
+        let Some(command) = command else {
+            self.send(line).await?;
+            return Ok(true);
+        };
+
+        let mut args = command.split_whitespace();
+        let name = args.next().unwrap_or_default();
+        let rest = || command[name.len()..].trim();
+        match name {
+            "help" => println!("{}", HELP),
+            "quit" => return Ok(false),
+            "join" => {
+                let usage = "usage: /join <code> [name], the password is asked for separately";
+                let code = args.next().ok_or(usage)?;
+                let user_name = args.next();
+                if args.next().is_some() {
+                    return Err(usage.into());
+                }
+                let user_name = arg_or_prompt(user_name, "name", input).await?;
+                let password = prompt_password("password", input).await?;
+                self.client
+                    .join(code.to_string(), user_name.clone(), password)
+                    .await?;
+                self.logged_in(user_name)?;
+            }
+            "login" => {
+                let user_name = args.next();
+                if args.next().is_some() {
+                    return Err("usage: /login [name], the password is asked for separately".into());
+                }
+                let user_name = match user_name {
+                    Some(user_name) => user_name.to_string(),
+                    None => match self.config.user_name.clone() {
+                        Some(user_name) => user_name,
+                        None => prompt("name", input).await?,
+                    },
+                };
+                let password = prompt_password("password", input).await?;
+                self.client.login(user_name.clone(), password).await?;
+                self.logged_in(user_name)?;
+            }
+            "logout" => {
+                self.room = None;
+                self.client.logout().await?;
+                self.config.token = None;
+                self.config.refresh_token = None;
+                self.config.token_expires_at = None;
+                self.config.save(&self.config_path)?;
+                println!("logged out");
+            }
+            "passwd" => {
+                let old_password = prompt_password("old password", input).await?;
+                let new_password = prompt_password("new password", input).await?;
+                self.client
+                    .change_password(old_password, new_password)
+                    .await?;
+                println!("password changed");
+            }
+            "users" => {
+                for user in self.client.list_users().await? {
+                    let banned = if user.banned { " (banned)" } else { "" };
+                    println!("{:>4} {}{}", user.id.0, user.name, banned);
+                }
+            }
+            "ban" => {
+                let user_id = self.find_user(args.next()).await?;
+                let pending = self.client.ban_user(user_id).await?;
+                println!("{}", if pending { "vote counted" } else { "banned" });
+            }
+            "unban" => {
+                let user_id = self.find_user(args.next()).await?;
+                self.client.unban_user(user_id).await?;
+                println!("vote withdrawn");
+            }
+            "rooms" => {
+                for chat in self.client.list_chat_rooms().await? {
+                    let current = self.room.as_ref().map(|room| room.chat.id) == Some(chat.id);
+                    let kind = match chat.kind {
+                        ChatKind::Public => "",
+                        ChatKind::Private => " (private)",
+                        ChatKind::Direct => " (direct)",
+                    };
+                    let mark = if current { '*' } else { ' ' };
+                    println!("{}{:>4} {}{}", mark, chat.id.0, chat.name, kind);
+                }
+            }
+            "create" => {
+                let (private, room_name) = match rest().strip_prefix("--private") {
+                    Some(room_name) => (true, room_name.trim()),
+                    None => (false, rest()),
+                };
+                if room_name.is_empty() {
+                    return Err("usage: /create [--private] <name>".into());
+                }
+                let chat_id = match private {
+                    true => {
+                        self.client
+                            .create_private_chat_room(room_name.to_string())
+                            .await?
+                    }
+                    false => self.client.create_chat_room(room_name.to_string()).await?,
+                };
+                println!("created room {}", chat_id.0);
+            }
+            "enter" => {
+                let chat = self.find_room(rest()).await?;
+                self.enter(chat).await?;
+            }
+            "dm" => {
+                let user_id = self.find_user(args.next()).await?;
+                let chat_id = self.client.open_direct(user_id).await?;
+                let chat = self.client.get_chat_room(chat_id).await?;
+                self.enter(chat).await?;
+            }
+            "invite" | "kick" => {
+                let user_id = self.find_user(args.next()).await?;
+                let chat_id = self.current_room()?.chat.id;
+                if name == "invite" {
+                    self.client.invite_to_chat_room(chat_id, user_id).await?;
+                } else {
+                    self.client.kick_from_chat_room(chat_id, user_id).await?;
+                }
+            }
+            "codes" => {
+                let num_codes = args
+                    .next()
+                    .and_then(|num_codes| num_codes.parse().ok())
+                    .ok_or("usage: /codes <n>")?;
+                for code in self.client.create_join_codes(num_codes).await? {
+                    println!("{}", code);
+                }
+            }
+            _ => return Err(format!("unknown command /{}, see /help", name).into()),
+        }
+        Ok(true)
+    }
+
+    async fn send(&mut self, content: &str) -> Result<()> {
+        let chat_id = self.current_room()?.chat.id;
+        self.client
+            .send_message(chat_id, content.to_string())
+            .await?;
+        Ok(())
+    }
+
+    fn current_room(&self) -> Result<&Room> {
+        Ok(self
+            .room
+            .as_ref()
+            .ok_or("not in a room, see /rooms and /enter")?)
+    }
+
+    /// Показывает последние сообщения комнаты и подписывается на новые.
+    async fn enter(&mut self, chat: Chat) -> Result<()> {
+        let history = self
+            .client
+            .get_messages(chat.id, MessagesPage::Last, HISTORY_LEN)
+            .await?;
+        let last_seq = history.last().map_or(0, |message| message.seq);
+        let messages = self.client.stream_messages(chat.id, Some(last_seq)).await?;
+
+        println!("--- {} ---", chat.name);
+        for message in &history {
+            print_message(message);
+        }
+        self.room = Some(Room {
+            chat,
+            messages: Box::pin(messages),
+        });
+        Ok(())
+    }
+
+    fn on_message(&mut self, message: Option<Result<StreamMessagesResponseEntry, tonic::Status>>) {
+        match message {
+            Some(Ok(message)) => print_message(&message),
+            Some(Err(status)) => {
+                eprintln!("left the room: {}", status.message());
+                self.room = None;
+            }
+            None => {
+                eprintln!("left the room: the server closed the stream");
+                self.room = None;
+            }
+        }
+    }
+
+    async fn find_user(&mut self, user: Option<&str>) -> Result<UserId> {
+        let user = user.ok_or("no user given")?;
+        let users = self.client.list_users().await?;
+        if let Some(found) = users.iter().find(|found| found.name == user) {
+            return Ok(found.id);
+        }
+        match user.parse() {
+            Ok(id) if users.iter().any(|found| found.id.0 == id) => Ok(UserId(id)),
+            _ => Err(format!("no user {}", user).into()),
+        }
+    }
+
+    async fn find_room(&mut self, room: &str) -> Result<Chat> {
+        let chats = self.client.list_chat_rooms().await?;
+        let by_id = |chat: &Chat| room.parse() == Ok(chat.id.0);
+        let found = match chats.iter().position(|chat| chat.name == room) {
+            Some(position) => Some(position),
+            None => chats.iter().position(by_id),
+        };
+        match found {
+            Some(position) => Ok(chats[position].clone()),
+            None => Err(format!("no room {}", room).into()),
+        }
+    }
+
+    fn logged_in(&mut self, user_name: String) -> Result<()> {
+        println!("logged in as {}", user_name);
+        self.is_admin = false;
+        self.config.user_name = Some(user_name);
+        self.save_tokens()
+    }
+
+    fn save_tokens(&mut self) -> Result<()> {
+        if self.is_admin {
+            return Ok(());
+        }
+        self.config.token = Some(self.client.token.clone());
+        self.config.refresh_token = Some(self.client.refresh_token.clone());
+        self.config.token_expires_at = self
+            .client
+            .token_expires_at
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs());
+        self.config.save(&self.config_path)?;
+        Ok(())
+    }
+
+    /// Получает новый токен, если старый скоро истечёт.
+    async fn refresh_if_expiring(&mut self) -> Result<()> {
+        let Some(expires_at) = self.client.token_expires_at else {
+            return Ok(());
+        };
+        if self.client.refresh_token.is_empty() || SystemTime::now() + REFRESH_MARGIN < expires_at {
+            return Ok(());
+        }
+        if let Err(status) = self.client.refresh().await {
+            // NB: токен для обновления тоже истёк, поможет только новый логин.
+            self.client.token_expires_at = None;
+            return Err(format!("session has ended, /login again: {}", status.message()).into());
+        }
+        self.save_tokens()
+    }
+}
+
+fn print_message(message: &StreamMessagesResponseEntry) {
+    let time = chrono::DateTime::<chrono::Local>::from(message.timestamp);
+    println!(
+        "[{}] {}: {}",
+        time.format("%H:%M"),
+        message.user_name,
+        message.content
+    );
+}
+
+async fn prompt(what: &str, input: &mut Lines<BufReader<Stdin>>) -> Result<String> {
+    print!("{}: ", what);
+    io::stdout().flush()?;
+    let line = input.next_line().await?.ok_or("unexpected end of input")?;
+    Ok(line.trim().to_string())
+}
+
+/// Спрашивает пароль, не показывая его на экране. Если ввод идёт не с терминала, пароль
+/// читается как обычная строка.
+async fn prompt_password(what: &str, input: &mut Lines<BufReader<Stdin>>) -> Result<String> {
+    if !io::stdin().is_terminal() {
+        return prompt(what, input).await;
+    }
+    let prompt = format!("{}: ", what);
+    // NB: rpassword читает терминал напрямую и блокирует поток, поэтому работает вне рантайма.
+    let password =
+        tokio::task::spawn_blocking(move || rpassword::prompt_password(prompt)).await??;
+    Ok(password)
+}
+
+async fn arg_or_prompt(
+    arg: Option<&str>,
+    what: &str,
+    input: &mut Lines<BufReader<Stdin>>,
+) -> Result<String> {
+    match arg {
+        Some(arg) => Ok(arg.to_string()),
+        None => prompt(what, input).await,
+    }
+}
+
+async fn next_message(
+    room: &mut Option<Room>,
+) -> Option<Result<StreamMessagesResponseEntry, tonic::Status>> {
+    match room {
+        Some(room) => room.messages.next().await,
+        None => future::pending().await,
+    }
+}
+
+enum Input {
+    Line(Option<String>),
+    Message(Option<Result<StreamMessagesResponseEntry, tonic::Status>>),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let opts = Opts::from_args();
+    let config_path = opts.config.unwrap_or_else(default_config_path);
+    let mut config = Config::load(&config_path)?;
+    if opts.addr.is_some() {
+        config.addr = opts.addr;
+    }
+    let addr = config
+        .addr
+        .clone()
+        .unwrap_or_else(|| DEFAULT_ADDR.to_string());
+
+    let is_admin = opts.admin_token.is_some();
+    let token = opts.admin_token.or_else(|| config.token.clone());
+    let mut client = Client::connect(token, addr.clone()).await?;
+    if !is_admin {
+        client.refresh_token = config.refresh_token.clone().unwrap_or_default();
+        client.token_expires_at = config
+            .token_expires_at
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    config.save(&config_path)?;
+
+    println!("connected to {}, see /help", addr);
+    match &config.user_name {
+        _ if is_admin => println!("using the admin token"),
+        Some(user_name) if config.token.is_some() => println!("logged in as {}", user_name),
+        _ => println!("use /join or /login"),
+    }
+
+    let mut app = App {
+        client,
+        config,
+        config_path,
+        is_admin,
+        room: None,
+    };
+    let mut input = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        // NB: пользователь печатает, пока приходят сообщения, так что обрабатывается то, что пришло
+        // первым.
+        let next = tokio::select! {
+            line = input.next_line() => Input::Line(line?),
+            message = next_message(&mut app.room) => Input::Message(message),
+        };
+        match next {
+            Input::Line(None) => break,
+            Input::Line(Some(line)) => match app.handle(&line, &mut input).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => eprintln!("error: {}", err),
+            },
+            Input::Message(message) => app.on_message(message),
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,47 @@
+use serde::{Deserialize, Serialize};
+
+use crate::proto;
+
 pub const ADMIN_UID: UserId = UserId(0);
 
-#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Debug)]
+#[derive(
+    Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize,
+)]
 pub struct UserId(pub u32);
 
-#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Debug)]
+#[derive(
+    Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize,
+)]
 pub struct ChatId(pub u32);
+
+/// Кто видит комнату и может в ней писать.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub enum ChatKind {
+    /// Комната доступна всем пользователям.
+    #[default]
+    Public,
+    /// Комната доступна только создателю и приглашённым им пользователям.
+    Private,
+    /// Личная переписка двух пользователей, см. `Client::open_direct`.
+    Direct,
+}
+
+impl From<ChatKind> for proto::ChatRoomKind {
+    fn from(kind: ChatKind) -> Self {
+        match kind {
+            ChatKind::Public => Self::Public,
+            ChatKind::Private => Self::Private,
+            ChatKind::Direct => Self::Direct,
+        }
+    }
+}
+
+impl From<proto::ChatRoomKind> for ChatKind {
+    fn from(kind: proto::ChatRoomKind) -> Self {
+        match kind {
+            proto::ChatRoomKind::Public => Self::Public,
+            proto::ChatRoomKind::Private => Self::Private,
+            proto::ChatRoomKind::Direct => Self::Direct,
+        }
+    }
+}
@@ -1,23 +1,491 @@
-use std::collections::{HashMap, HashSet};
-use std::iter;
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{error, fmt, io, iter};
 
-use tokio::sync::{mpsc, Mutex, RwLock};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::sync::{mpsc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::proto;
 
-use crate::common::{ChatId, UserId, ADMIN_UID};
+use crate::common::{ChatId, ChatKind, UserId, ADMIN_UID};
+
+mod password;
+mod state;
+mod storage;
+mod writer;
+
+use password::{hash_token, PasswordHash};
+use state::{Event, Message, Session, State};
+pub use storage::{Contents, FileStorage, MemoryStorage, Storage};
+use writer::{Writer, Written};
+
+/// Имя администратора в сообщениях, недоступное для регистрации.
+const ADMIN_NAME: &str = "admin";
+
+/// Число сообщений, которое возвращает `GetMessages`, если оно не задано в запросе.
+const DEFAULT_MESSAGES_LIMIT: usize = 100;
+
+/// Наибольшее число сообщений, которое возвращает `GetMessages`.
+const MAX_MESSAGES_LIMIT: usize = 1000;
+
+type MessageSender =
+    mpsc::UnboundedSender<Result<proto::StreamMessagesResponseEntry, tonic::Status>>;
+
+/// Время жизни токенов, которые выдаются при логине.
+#[derive(Clone, Copy, Debug)]
+pub struct TokenLifetimes {
+    pub token: Duration,
+    pub refresh_token: Duration,
+}
+
+impl Default for TokenLifetimes {
+    fn default() -> Self {
+        Self {
+            token: Duration::from_secs(60 * 60),
+            refresh_token: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
 
-#[derive(Default)]
 pub struct Service {
-    // TODO: your code here.
+    admin_token: String,
+    token_lifetimes: TokenLifetimes,
+    inner: RwLock<Inner>,
+}
+
+struct Inner {
+    state: State,
+    writer: Writer,
+    /// Номер последнего события, отданного на запись.
+    seq: u64,
+    /// Открытые потоки сообщений вместе с пользователями, которые их читают.
+    subscribers: HashMap<ChatId, Vec<(UserId, MessageSender)>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record<E> {
+    seq: u64,
+    event: E,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<S> {
+    seq: u64,
+    state: S,
+}
+
+impl Service {
+    /// Восстанавливает состояние из хранилища: загружает снимок и применяет к нему события
+    /// из журнала.
+    pub fn new(
+        admin_token: String,
+        token_lifetimes: TokenLifetimes,
+        mut storage: Box<dyn Storage>,
+    ) -> io::Result<Self> {
+        let Contents { snapshot, records } = storage.load()?;
+        let is_empty = snapshot.is_none() && records.is_empty();
+        let (mut seq, mut state) = match snapshot {
+            Some(snapshot) => {
+                let snapshot: Snapshot<State> = serde_json::from_slice(&snapshot)?;
+                (snapshot.seq, snapshot.state)
+            }
+            None => (0, State::default()),
+        };
+        state.index_sessions();
+        for record in records {
+            let record: Record<Event> = serde_json::from_slice(&record)?;
+            if record.seq > seq {
+                seq = record.seq;
+                state.apply(record.event);
+            }
+        }
+
+        // NB: состояние сохраняется заново при каждом запуске, чтобы на диске не осталось
+        // ничего в старом формате, например пароля в открытом виде.
+        if !is_empty {
+            writer::write_snapshot(&mut *storage, seq, &state)?;
+        }
+        let inner = Inner {
+            writer: Writer::spawn(storage, seq),
+            state,
+            seq,
+            subscribers: HashMap::new(),
+        };
+        Ok(Self {
+            admin_token,
+            token_lifetimes,
+            inner: RwLock::new(inner),
+        })
+    }
+
+    /// Блокирует состояние на чтение. Если событие не удалось записать, состояние разошлось
+    /// с хранилищем, и сервер больше не отвечает на запросы.
+    async fn read(&self) -> Result<RwLockReadGuard<'_, Inner>, tonic::Status> {
+        let inner = self.inner.read().await;
+        inner.check_storage()?;
+        Ok(inner)
+    }
+
+    /// Блокирует состояние на запись, см. `read`.
+    async fn write(&self) -> Result<RwLockWriteGuard<'_, Inner>, tonic::Status> {
+        let inner = self.inner.write().await;
+        inner.check_storage()?;
+        Ok(inner)
+    }
+
+    /// Дожидается записи события. Если она не удалась, завершает все потоки сообщений.
+    async fn wait(&self, written: Written) -> Result<(), tonic::Status> {
+        let result = written.wait().await;
+        if result.is_err() {
+            self.inner.write().await.close_all_streams();
+        }
+        result
+    }
+
+    /// Возвращает пользователя, которому принадлежит токен.
+    fn authenticate(&self, state: &State, token: &str) -> Result<UserId, tonic::Status> {
+        if bool::from(token.as_bytes().ct_eq(self.admin_token.as_bytes())) {
+            return Ok(ADMIN_UID);
+        }
+        let user_id = *state
+            .tokens
+            .get(&hash_token(token))
+            .ok_or_else(|| tonic::Status::unauthenticated("invalid token"))?;
+        let user = &state.users[&user_id];
+        if user.banned {
+            return Err(tonic::Status::permission_denied("user is banned"));
+        }
+        if user.session.as_ref().unwrap().expires_at_micros <= now_micros() {
+            return Err(tonic::Status::unauthenticated("token has expired"));
+        }
+        Ok(user_id)
+    }
+
+    /// Как `authenticate`, но не пускает администратора, у которого нет ни пароля, ни сессии.
+    fn authenticate_user(&self, state: &State, token: &str) -> Result<UserId, tonic::Status> {
+        match self.authenticate(state, token)? {
+            ADMIN_UID => Err(tonic::Status::failed_precondition(
+                "the admin has no password or session",
+            )),
+            user_id => Ok(user_id),
+        }
+    }
+
+    /// Начинает сессию: возвращает её в том виде, в котором она хранится, и токены для
+    /// клиента.
+    fn new_session(&self) -> (Session, proto::Session) {
+        let now = now_micros();
+        let after = |lifetime: Duration| now.saturating_add(lifetime.as_micros() as i64);
+        let tokens = proto::Session {
+            token: random_token(),
+            expires_at_micros: after(self.token_lifetimes.token),
+            refresh_token: random_token(),
+            refresh_expires_at_micros: after(self.token_lifetimes.refresh_token),
+        };
+        let session = Session {
+            token_hash: hash_token(&tokens.token),
+            expires_at_micros: tokens.expires_at_micros,
+            refresh_token_hash: hash_token(&tokens.refresh_token),
+            refresh_expires_at_micros: tokens.refresh_expires_at_micros,
+        };
+        (session, tokens)
+    }
+
+    fn authenticate_admin(&self, state: &State, token: &str) -> Result<(), tonic::Status> {
+        match self.authenticate(state, token)? {
+            ADMIN_UID => Ok(()),
+            _ => Err(tonic::Status::permission_denied(
+                "only the admin may do this",
+            )),
+        }
+    }
+}
+
+impl Inner {
+    /// Применяет событие к состоянию и отдаёт его на запись в журнал. Отвечать на запрос
+    /// можно, только дождавшись записи без блокировки, см. `Service::wait`.
+    ///
+    /// NB: другие запросы видят событие до того, как оно записано. Если сервер упадёт в
+    /// этот момент, событие потеряется, но и запрос, который его создал, ещё не выполнен. Если
+    /// же событие не удастся записать, сервер перестанет отвечать на запросы, см.
+    /// `Service::read`.
+    fn commit(&mut self, event: Event) -> Written {
+        self.seq += 1;
+        let written = self.writer.write(self.seq, event.clone());
+        self.apply(event);
+        written
+    }
+
+    /// Как `commit`, но после записи выполняет `then`, см. `Writer::write_then`.
+    fn commit_then(&mut self, event: Event, then: impl FnOnce() + Send + 'static) -> Written {
+        self.seq += 1;
+        let written = self.writer.write_then(self.seq, event.clone(), then);
+        self.apply(event);
+        written
+    }
+
+    fn apply(&mut self, event: Event) {
+        self.state.apply(event);
+        self.writer.snapshot_if_due(self.seq, &self.state);
+    }
+
+    fn check_storage(&self) -> Result<(), tonic::Status> {
+        if self.writer.is_failed() {
+            return Err(tonic::Status::unavailable(
+                "the server failed to store a change and has stopped",
+            ));
+        }
+        Ok(())
+    }
+
+    fn user_name(&self, user_id: UserId) -> &str {
+        match user_id {
+            ADMIN_UID => ADMIN_NAME,
+            _ => &self.state.users[&user_id].name,
+        }
+    }
+
+    fn check_user(&self, user_id: UserId) -> Result<(), tonic::Status> {
+        if user_id != ADMIN_UID && !self.state.users.contains_key(&user_id) {
+            return Err(tonic::Status::not_found("no such user"));
+        }
+        Ok(())
+    }
+
+    fn check_chat(&self, chat_id: ChatId) -> Result<&state::Chat, tonic::Status> {
+        self.state
+            .chats
+            .get(&chat_id)
+            .ok_or_else(|| tonic::Status::not_found("no such chat room"))
+    }
+
+    /// Завершает потоки сообщений пользователя, во всех комнатах или в одной, с ошибкой.
+    fn close_streams(
+        &mut self,
+        user_id: UserId,
+        chat_id: Option<ChatId>,
+        code: tonic::Code,
+        message: &str,
+    ) {
+        for (id, subscribers) in &mut self.subscribers {
+            if chat_id.is_none_or(|chat_id| chat_id == *id) {
+                subscribers.retain(|(subscriber_id, sender)| {
+                    if *subscriber_id != user_id {
+                        return true;
+                    }
+                    let _ = sender.send(Err(tonic::Status::new(code, message)));
+                    false
+                });
+            }
+        }
+    }
+
+    /// Завершает все потоки сообщений: сервер больше не отвечает на запросы.
+    fn close_all_streams(&mut self) {
+        for (_, sender) in self
+            .subscribers
+            .drain()
+            .flat_map(|(_, subscribers)| subscribers)
+        {
+            let _ = sender.send(Err(tonic::Status::unavailable(
+                "the server failed to store a change and has stopped",
+            )));
+        }
+    }
+
+    /// Возвращает комнату, если пользователь её видит.
+    fn check_member(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<&state::Chat, tonic::Status> {
+        let chat = self.check_chat(chat_id)?;
+        if !chat.is_visible_to(user_id) {
+            return Err(tonic::Status::permission_denied(
+                "not a member of the chat room",
+            ));
+        }
+        Ok(chat)
+    }
+
+    /// Проверяет, что пользователь может приглашать в комнату и выгонять из неё.
+    fn check_owner(&self, chat_id: ChatId, user_id: UserId) -> Result<(), tonic::Status> {
+        let chat = self.check_member(chat_id, user_id)?;
+        if chat.kind != ChatKind::Private {
+            return Err(tonic::Status::failed_precondition(
+                "only private chat rooms have members to manage",
+            ));
+        }
+        if chat.owner_id != user_id {
+            return Err(tonic::Status::permission_denied(
+                "only the creator of the chat room may do this",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Имя комнаты для пользователя: у личной комнаты это имя собеседника.
+    fn chat_name(&self, chat: &state::Chat, user_id: UserId) -> String {
+        match chat.kind {
+            ChatKind::Direct => {
+                let peer_id = chat.members.iter().find(|&&id| id != user_id);
+                self.user_name(*peer_id.unwrap_or(&user_id)).to_string()
+            }
+            _ => chat.name.clone(),
+        }
+    }
+
+    fn message_entry(&self, seq: u64, message: &Message) -> proto::StreamMessagesResponseEntry {
+        proto::StreamMessagesResponseEntry {
+            user_id: message.user_id.0,
+            user_name: self.user_name(message.user_id).to_string(),
+            content: message.content.clone(),
+            seq,
+            timestamp_micros: message.timestamp_micros,
+        }
+    }
+}
+
+fn now_micros() -> i64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    since_epoch.as_micros() as i64
+}
+
+/// Считает хеш пароля, не блокируя асинхронный рантайм.
+async fn hash_password(password: String) -> Result<PasswordHash, tonic::Status> {
+    tokio::task::spawn_blocking(move || PasswordHash::new(&password))
+        .await
+        .map_err(|err| tonic::Status::internal(err.to_string()))
+}
+
+/// Проверяет пароль, не блокируя асинхронный рантайм.
+async fn verify_password(hash: PasswordHash, password: String) -> Result<bool, tonic::Status> {
+    tokio::task::spawn_blocking(move || hash.verify(&password))
+        .await
+        .map_err(|err| tonic::Status::internal(err.to_string()))
+}
+
+fn storage_error(err: impl fmt::Display) -> tonic::Status {
+    tonic::Status::internal(format!("failed to store the change: {}", err))
+}
+
+fn random_token() -> String {
+    format!("{:032x}", rand::thread_rng().gen::<u128>())
+}
+
+fn random_join_code() -> String {
+    let mut rng = rand::thread_rng();
+    iter::repeat_with(|| char::from(rng.sample(Alphanumeric)))
+        .take(16)
+        .collect()
 }
 
-/// Запускает сервер.
+/// Проверяет, что по коду `join_code` можно зарегистрироваться под именем `user_name`.
+fn check_join(state: &State, join_code: &str, user_name: &str) -> Result<(), tonic::Status> {
+    if !state.join_codes.contains(join_code) {
+        return Err(tonic::Status::permission_denied("invalid join code"));
+    }
+    if user_name == ADMIN_NAME || state.find_user(user_name).is_some() {
+        return Err(tonic::Status::already_exists("user name is taken"));
+    }
+    Ok(())
+}
+
+fn validate_user_name(name: &str) -> Result<(), tonic::Status> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(tonic::Status::invalid_argument(
+            "user name may only contain latin letters, digits and underscores",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_password(password: &str) -> Result<(), tonic::Status> {
+    let has = |f: fn(&char) -> bool| password.chars().any(|c| f(&c));
+    if password.chars().count() < 10
+        || !has(char::is_ascii_alphabetic)
+        || !has(char::is_ascii_digit)
+        || !has(char::is_ascii_punctuation)
+    {
+        return Err(tonic::Status::invalid_argument(
+            "password must be at least 10 characters long and contain a latin letter, \
+            a digit and a punctuation character",
+        ));
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum ServeError {
+    Storage(io::Error),
+    Transport(tonic::transport::Error),
+}
+
+impl fmt::Display for ServeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Storage(err) => write!(f, "failed to load the state: {}", err),
+            Self::Transport(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for ServeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Storage(err) => Some(err),
+            Self::Transport(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ServeError {
+    fn from(err: io::Error) -> Self {
+        Self::Storage(err)
+    }
+}
+
+impl From<tonic::transport::Error> for ServeError {
+    fn from(err: tonic::transport::Error) -> Self {
+        Self::Transport(err)
+    }
+}
+
+/// Запускает сервер. Если задана директория `data_dir`, состояние сервера хранится в ней
+/// и восстанавливается при перезапуске, иначе оно теряется.
 pub async fn serve(
     admin_token: String,
     addr: std::net::SocketAddr,
-) -> Result<(), tonic::transport::Error> {
-    // TODO: your code here.
+    data_dir: Option<PathBuf>,
+) -> Result<(), ServeError> {
+    let storage: Box<dyn Storage> = match data_dir {
+        Some(dir) => Box::new(FileStorage::open(dir)?),
+        None => Box::new(MemoryStorage::default()),
+    };
+    serve_with_storage(admin_token, addr, TokenLifetimes::default(), storage).await
+}
+
+/// Запускает сервер, хранящий состояние в `storage` и выдающий токены с временем жизни
+/// `token_lifetimes`.
+pub async fn serve_with_storage(
+    admin_token: String,
+    addr: std::net::SocketAddr,
+    token_lifetimes: TokenLifetimes,
+    storage: Box<dyn Storage>,
+) -> Result<(), ServeError> {
+    let service = Service::new(admin_token, token_lifetimes, storage)?;
+    tonic::transport::Server::builder()
+        .add_service(proto::chat_server::ChatServer::new(service))
+        .serve(addr)
+        .await?;
+    Ok(())
 }
 
 #[tonic::async_trait]
@@ -26,96 +494,515 @@ impl proto::chat_server::Chat for Service {
         &self,
         request: tonic::Request<proto::CreateJoinCodesRequest>,
     ) -> Result<tonic::Response<proto::CreateJoinCodesResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        self.authenticate_admin(&inner.state, &request.token)?;
+
+        let join_codes: Vec<_> = iter::repeat_with(random_join_code)
+            .take(request.num_codes as usize)
+            .collect();
+        let written = inner.commit(Event::JoinCodesCreated {
+            join_codes: join_codes.clone(),
+        });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::CreateJoinCodesResponse {
+            join_codes,
+        }))
     }
 
     async fn list_join_codes(
         &self,
         request: tonic::Request<proto::ListJoinCodesRequest>,
     ) -> Result<tonic::Response<proto::ListJoinCodesResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let inner = self.read().await?;
+        self.authenticate_admin(&inner.state, &request.token)?;
+
+        Ok(tonic::Response::new(proto::ListJoinCodesResponse {
+            join_codes: inner.state.join_codes.iter().cloned().collect(),
+        }))
     }
 
     async fn join(
         &self,
         request: tonic::Request<proto::JoinRequest>,
     ) -> Result<tonic::Response<proto::JoinResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        validate_user_name(&request.user_name)?;
+        validate_password(&request.password)?;
+
+        // NB: хеширование медленное, поэтому код и имя проверяются до него, чтобы запрос с
+        // выдуманным кодом не стоил серверу хеша, а после него проверка повторяется под
+        // блокировкой на запись.
+        check_join(
+            &self.read().await?.state,
+            &request.join_code,
+            &request.user_name,
+        )?;
+        let password = hash_password(request.password).await?;
+        let mut inner = self.write().await?;
+        check_join(&inner.state, &request.join_code, &request.user_name)?;
+
+        let user_id = inner.state.next_user_id();
+        let (session, tokens) = self.new_session();
+        let written = inner.commit(Event::UserJoined {
+            user_id,
+            join_code: request.join_code,
+            name: request.user_name,
+            password,
+            session,
+        });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::JoinResponse {
+            token: tokens.token.clone(),
+            user_id: user_id.0,
+            session: Some(tokens),
+        }))
     }
 
     async fn login(
         &self,
         request: tonic::Request<proto::LoginRequest>,
     ) -> Result<tonic::Response<proto::LoginResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let (user_id, hash) = {
+            let inner = self.read().await?;
+            let user_id = inner.state.find_user(&request.user_name);
+            let hash = match user_id {
+                Some(user_id) => inner.state.users[&user_id].password.clone(),
+                None => PasswordHash::dummy().clone(),
+            };
+            (user_id, hash)
+        };
+        let verified = verify_password(hash, request.password).await?;
+        let user_id = user_id
+            .filter(|_| verified)
+            .ok_or_else(|| tonic::Status::unauthenticated("invalid user name or password"))?;
+
+        let mut inner = self.write().await?;
+        if inner.state.users[&user_id].banned {
+            return Err(tonic::Status::permission_denied("user is banned"));
+        }
+        let (session, tokens) = self.new_session();
+        let written = inner.commit(Event::LoggedIn { user_id, session });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::LoginResponse {
+            token: tokens.token.clone(),
+            user_id: user_id.0,
+            session: Some(tokens),
+        }))
+    }
+
+    async fn refresh_token(
+        &self,
+        request: tonic::Request<proto::RefreshTokenRequest>,
+    ) -> Result<tonic::Response<proto::RefreshTokenResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let user_id = *inner
+            .state
+            .refresh_tokens
+            .get(&hash_token(&request.refresh_token))
+            .ok_or_else(|| tonic::Status::unauthenticated("invalid refresh token"))?;
+        let user = &inner.state.users[&user_id];
+        if user.banned {
+            return Err(tonic::Status::permission_denied("user is banned"));
+        }
+        if user.session.as_ref().unwrap().refresh_expires_at_micros <= now_micros() {
+            return Err(tonic::Status::unauthenticated("refresh token has expired"));
+        }
+
+        let (session, tokens) = self.new_session();
+        let written = inner.commit(Event::LoggedIn { user_id, session });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::RefreshTokenResponse {
+            session: Some(tokens),
+        }))
+    }
+
+    async fn logout(
+        &self,
+        request: tonic::Request<proto::LogoutRequest>,
+    ) -> Result<tonic::Response<proto::LogoutResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let user_id = self.authenticate_user(&inner.state, &request.token)?;
+
+        let written = inner.commit(Event::LoggedOut { user_id });
+        inner.close_streams(user_id, None, tonic::Code::Unauthenticated, "logged out");
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::LogoutResponse {}))
+    }
+
+    async fn change_password(
+        &self,
+        request: tonic::Request<proto::ChangePasswordRequest>,
+    ) -> Result<tonic::Response<proto::ChangePasswordResponse>, tonic::Status> {
+        let request = request.into_inner();
+        validate_password(&request.new_password)?;
+        let (user_id, hash) = {
+            let inner = self.read().await?;
+            let user_id = self.authenticate_user(&inner.state, &request.token)?;
+            (user_id, inner.state.users[&user_id].password.clone())
+        };
+        if !verify_password(hash, request.old_password).await? {
+            return Err(tonic::Status::permission_denied("wrong password"));
+        }
+        let password = hash_password(request.new_password).await?;
+
+        let mut inner = self.write().await?;
+        if self.authenticate_user(&inner.state, &request.token)? != user_id {
+            return Err(tonic::Status::unauthenticated("invalid token"));
+        }
+        let written = inner.commit(Event::PasswordChanged { user_id, password });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::ChangePasswordResponse {}))
     }
 
     async fn list_users(
         &self,
         request: tonic::Request<proto::ListUsersRequest>,
     ) -> Result<tonic::Response<proto::ListUsersResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let inner = self.read().await?;
+        self.authenticate(&inner.state, &request.token)?;
+
+        let users = inner
+            .state
+            .users
+            .iter()
+            .map(|(id, user)| proto::UserPublic {
+                id: id.0,
+                name: user.name.clone(),
+                banned: user.banned,
+            })
+            .collect();
+        Ok(tonic::Response::new(proto::ListUsersResponse { users }))
     }
 
     async fn get_user(
         &self,
         request: tonic::Request<proto::GetUserRequest>,
     ) -> Result<tonic::Response<proto::GetUserResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let inner = self.read().await?;
+        self.authenticate(&inner.state, &request.token)?;
+
+        let user = inner
+            .state
+            .users
+            .get(&UserId(request.user_id))
+            .ok_or_else(|| tonic::Status::not_found("no such user"))?;
+        Ok(tonic::Response::new(proto::GetUserResponse {
+            user: Some(proto::UserPublic {
+                id: request.user_id,
+                name: user.name.clone(),
+                banned: user.banned,
+            }),
+        }))
     }
 
     async fn ban_user(
         &self,
         request: tonic::Request<proto::BanUserRequest>,
     ) -> Result<tonic::Response<proto::BanUserResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let voter_id = self.authenticate(&inner.state, &request.token)?;
+        let user_id = UserId(request.user_id);
+        inner.check_user(user_id)?;
+        if user_id == ADMIN_UID {
+            return Err(tonic::Status::permission_denied(
+                "the admin cannot be banned",
+            ));
+        }
+
+        let written = inner.commit(Event::BanVoted { user_id, voter_id });
+        let banned = inner.state.users[&user_id].banned;
+        if banned {
+            inner.close_streams(
+                user_id,
+                None,
+                tonic::Code::PermissionDenied,
+                "user is banned",
+            );
+        }
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::BanUserResponse {
+            pending: !banned,
+        }))
     }
 
     async fn unban_user(
         &self,
         request: tonic::Request<proto::UnbanUserRequest>,
     ) -> Result<tonic::Response<proto::UnbanUserResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let voter_id = self.authenticate(&inner.state, &request.token)?;
+        let user_id = UserId(request.user_id);
+        inner.check_user(user_id)?;
+        if user_id == ADMIN_UID {
+            return Err(tonic::Status::permission_denied(
+                "the admin cannot be unbanned",
+            ));
+        }
+
+        let written = inner.commit(Event::BanVoteWithdrawn { user_id, voter_id });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::UnbanUserResponse {}))
     }
 
     async fn create_chat_room(
         &self,
         request: tonic::Request<proto::CreateChatRoomRequest>,
     ) -> Result<tonic::Response<proto::CreateChatRoomResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+
+        let (kind, members) = match request.private {
+            true => (ChatKind::Private, BTreeSet::from([user_id])),
+            false => (ChatKind::Public, BTreeSet::new()),
+        };
+        let chat_id = inner.state.next_chat_id();
+        let written = inner.commit(Event::ChatCreated {
+            chat_id,
+            name: request.name,
+            kind,
+            owner_id: user_id,
+            members,
+        });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::CreateChatRoomResponse {
+            chat_id: chat_id.0,
+        }))
     }
 
     async fn list_chat_rooms(
         &self,
         request: tonic::Request<proto::ListChatRoomsRequest>,
     ) -> Result<tonic::Response<proto::ListChatRoomsResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let inner = self.read().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+
+        let chat_rooms = inner
+            .state
+            .chats
+            .iter()
+            .filter(|(_, chat)| chat.is_visible_to(user_id))
+            .map(|(id, chat)| proto::ListChatRoomsEntry {
+                name: inner.chat_name(chat, user_id),
+                chat_id: id.0,
+                kind: proto::ChatRoomKind::from(chat.kind) as i32,
+            })
+            .collect();
+        Ok(tonic::Response::new(proto::ListChatRoomsResponse {
+            chat_rooms,
+        }))
     }
 
     async fn get_chat_room(
         &self,
         request: tonic::Request<proto::GetChatRoomRequest>,
     ) -> Result<tonic::Response<proto::GetChatRoomResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let inner = self.read().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+
+        let chat = inner.check_member(ChatId(request.chat_id), user_id)?;
+        Ok(tonic::Response::new(proto::GetChatRoomResponse {
+            name: inner.chat_name(chat, user_id),
+            kind: proto::ChatRoomKind::from(chat.kind) as i32,
+        }))
+    }
+
+    async fn invite_to_chat_room(
+        &self,
+        request: tonic::Request<proto::InviteToChatRoomRequest>,
+    ) -> Result<tonic::Response<proto::InviteToChatRoomResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let owner_id = self.authenticate(&inner.state, &request.token)?;
+        let chat_id = ChatId(request.chat_id);
+        inner.check_owner(chat_id, owner_id)?;
+        let user_id = UserId(request.user_id);
+        inner.check_user(user_id)?;
+
+        if !inner.state.chats[&chat_id].members.contains(&user_id) {
+            let written = inner.commit(Event::MemberInvited { chat_id, user_id });
+            drop(inner);
+            self.wait(written).await?;
+        }
+        Ok(tonic::Response::new(proto::InviteToChatRoomResponse {}))
+    }
+
+    async fn kick_from_chat_room(
+        &self,
+        request: tonic::Request<proto::KickFromChatRoomRequest>,
+    ) -> Result<tonic::Response<proto::KickFromChatRoomResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let owner_id = self.authenticate(&inner.state, &request.token)?;
+        let chat_id = ChatId(request.chat_id);
+        inner.check_owner(chat_id, owner_id)?;
+        let user_id = UserId(request.user_id);
+        if user_id == owner_id {
+            return Err(tonic::Status::invalid_argument(
+                "the creator cannot leave the chat room",
+            ));
+        }
+        if !inner.state.chats[&chat_id].members.contains(&user_id) {
+            return Err(tonic::Status::not_found(
+                "the user is not a member of the chat room",
+            ));
+        }
+
+        let written = inner.commit(Event::MemberKicked { chat_id, user_id });
+        inner.close_streams(
+            user_id,
+            Some(chat_id),
+            tonic::Code::PermissionDenied,
+            "kicked from the chat room",
+        );
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::KickFromChatRoomResponse {}))
+    }
+
+    async fn list_chat_room_members(
+        &self,
+        request: tonic::Request<proto::ListChatRoomMembersRequest>,
+    ) -> Result<tonic::Response<proto::ListChatRoomMembersResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let inner = self.read().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+
+        let chat = inner.check_member(ChatId(request.chat_id), user_id)?;
+        let owner = chat.members.get(&chat.owner_id);
+        let others = chat.members.iter().filter(|&&id| id != chat.owner_id);
+        let user_ids = owner.into_iter().chain(others).map(|id| id.0).collect();
+        Ok(tonic::Response::new(proto::ListChatRoomMembersResponse {
+            user_ids,
+        }))
+    }
+
+    async fn open_direct(
+        &self,
+        request: tonic::Request<proto::OpenDirectRequest>,
+    ) -> Result<tonic::Response<proto::OpenDirectResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+        let peer_id = UserId(request.user_id);
+        inner.check_user(peer_id)?;
+        if peer_id == user_id {
+            return Err(tonic::Status::invalid_argument(
+                "cannot open a direct chat with yourself",
+            ));
+        }
+
+        if let Some(chat_id) = inner.state.find_direct(user_id, peer_id) {
+            return Ok(tonic::Response::new(proto::OpenDirectResponse {
+                chat_id: chat_id.0,
+            }));
+        }
+        let chat_id = inner.state.next_chat_id();
+        let written = inner.commit(Event::ChatCreated {
+            chat_id,
+            name: String::new(),
+            kind: ChatKind::Direct,
+            owner_id: user_id,
+            members: BTreeSet::from([user_id, peer_id]),
+        });
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::OpenDirectResponse {
+            chat_id: chat_id.0,
+        }))
     }
 
     async fn send_message(
         &self,
         request: tonic::Request<proto::SendMessageRequest>,
     ) -> Result<tonic::Response<proto::SendMessageResponse>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+        let chat_id = ChatId(request.chat_id);
+        inner.check_member(chat_id, user_id)?;
+
+        let message = Message {
+            user_id,
+            content: request.content,
+            timestamp_micros: now_micros(),
+        };
+        let seq = inner.state.chats[&chat_id].messages.len() as u64 + 1;
+        let entry = inner.message_entry(seq, &message);
+        // NB: сообщение рассылается только после записи, в порядке журнала. Потоки, открытые
+        // позже, получат его вместе с остальными сообщениями комнаты.
+        let subscribers = inner.subscribers.entry(chat_id).or_default();
+        subscribers.retain(|(_, sender)| !sender.is_closed());
+        let senders: Vec<_> = subscribers
+            .iter()
+            .map(|(_, sender)| sender.clone())
+            .collect();
+        let written = inner.commit_then(
+            Event::MessageSent {
+                chat_id,
+                user_id,
+                content: message.content,
+                timestamp_micros: message.timestamp_micros,
+            },
+            move || {
+                for sender in senders {
+                    let _ = sender.send(Ok(entry.clone()));
+                }
+            },
+        );
+        drop(inner);
+        self.wait(written).await?;
+        Ok(tonic::Response::new(proto::SendMessageResponse { seq }))
+    }
+
+    async fn get_messages(
+        &self,
+        request: tonic::Request<proto::GetMessagesRequest>,
+    ) -> Result<tonic::Response<proto::GetMessagesResponse>, tonic::Status> {
+        let request = request.into_inner();
+        let inner = self.read().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+        let chat = inner.check_member(ChatId(request.chat_id), user_id)?;
+
+        let limit = match request.limit as usize {
+            0 => DEFAULT_MESSAGES_LIMIT,
+            limit => limit.min(MAX_MESSAGES_LIMIT),
+        };
+        let range = chat.messages(
+            request.after_seq.unwrap_or(0),
+            request.before_seq.unwrap_or(u64::MAX),
+        );
+        let page: Vec<_> = match request.after_seq {
+            Some(_) => range.take(limit).collect(),
+            None => range.rev().take(limit).rev().collect(),
+        };
+        let messages = page
+            .into_iter()
+            .map(|(seq, message)| inner.message_entry(seq, message))
+            .collect();
+        Ok(tonic::Response::new(proto::GetMessagesResponse {
+            messages,
+        }))
     }
 
     type StreamMessagesStream = tokio_stream::wrappers::UnboundedReceiverStream<
@@ -126,8 +1013,27 @@ impl proto::chat_server::Chat for Service {
         &self,
         request: tonic::Request<proto::StreamMessagesRequest>,
     ) -> Result<tonic::Response<Self::StreamMessagesStream>, tonic::Status> {
-        // TODO: your code here.
-        unimplemented!()
+        let request = request.into_inner();
+        let mut inner = self.write().await?;
+        let user_id = self.authenticate(&inner.state, &request.token)?;
+        let chat_id = ChatId(request.chat_id);
+        let chat = inner.check_member(chat_id, user_id)?;
+
+        // NB: рассылка новых сообщений берёт список потоков под той же блокировкой, так что
+        // ни одно сообщение не пропадёт и не придёт дважды.
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if let Some(after_seq) = request.after_seq {
+            for (seq, message) in chat.messages(after_seq, u64::MAX) {
+                let _ = sender.send(Ok(inner.message_entry(seq, message)));
+            }
+        }
+        inner
+            .subscribers
+            .entry(chat_id)
+            .or_default()
+            .push((user_id, sender));
+        Ok(tonic::Response::new(
+            tokio_stream::wrappers::UnboundedReceiverStream::new(receiver),
+        ))
     }
 }
-
@@ -1,6 +1,9 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use structopt::StructOpt;
 
-use chat::server;
+use chat::server::{self, FileStorage, MemoryStorage, Storage, TokenLifetimes};
 
 #[derive(StructOpt)]
 struct Opts {
@@ -9,11 +12,38 @@ struct Opts {
 
     #[structopt(long)]
     addr: String,
+
+    /// Директория, в которой хранится состояние сервера.
+    #[structopt(long, parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+
+    /// Время жизни токена в секундах.
+    #[structopt(long, default_value = "3600")]
+    token_ttl: u64,
+
+    /// Время жизни токена для обновления сессии в секундах.
+    #[structopt(long, default_value = "2592000")]
+    refresh_token_ttl: u64,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
     let opts = Opts::from_args();
-    server::serve(opts.admin_token, opts.addr.parse()?).await?;
+    let storage: Box<dyn Storage> = match opts.data_dir {
+        Some(dir) => Box::new(FileStorage::open(dir)?),
+        None => Box::new(MemoryStorage::default()),
+    };
+    let token_lifetimes = TokenLifetimes {
+        token: Duration::from_secs(opts.token_ttl),
+        refresh_token: Duration::from_secs(opts.refresh_token_ttl),
+    };
+    server::serve_with_storage(
+        opts.admin_token,
+        opts.addr.parse()?,
+        token_lifetimes,
+        storage,
+    )
+    .await?;
     Ok(())
 }
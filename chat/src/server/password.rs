@@ -0,0 +1,128 @@
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// Хеш пароля с солью. Параметры Argon2id хранятся вместе с хешем, так что их можно поменять,
+/// не трогая уже сохранённые пароли.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "StoredPassword")]
+pub struct PasswordHash {
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    /// Соль и хеш в шестнадцатеричной записи.
+    salt: String,
+    hash: String,
+}
+
+impl PasswordHash {
+    /// Считает хеш пароля со случайной солью. Занимает заметное время, поэтому не должен
+    /// вызываться в асинхронном коде напрямую.
+    pub fn new(password: &str) -> Self {
+        let params = Params::default();
+        let salt: [u8; SALT_LEN] = rand::thread_rng().gen();
+        let hash = compute(&params, password, &salt).expect("default parameters are valid");
+        Self {
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            salt: to_hex(&salt),
+            hash: to_hex(&hash),
+        }
+    }
+
+    /// Хеш, с которым сравнивается пароль несуществующего пользователя, чтобы по времени
+    /// ответа нельзя было узнать, есть ли пользователь.
+    pub fn dummy() -> &'static Self {
+        static DUMMY: OnceLock<PasswordHash> = OnceLock::new();
+        DUMMY.get_or_init(|| Self::new(""))
+    }
+
+    /// Проверяет пароль, сравнивая хеши за время, не зависящее от их содержимого.
+    pub fn verify(&self, password: &str) -> bool {
+        let (Some(salt), Some(hash)) = (from_hex(&self.salt), from_hex(&self.hash)) else {
+            return false;
+        };
+        let Ok(params) = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(HASH_LEN)) else {
+            return false;
+        };
+        match compute(&params, password, &salt) {
+            Some(computed) => computed[..].ct_eq(&hash[..]).into(),
+            None => false,
+        }
+    }
+}
+
+/// Хеш токена, под которым сервер хранит сессию. Токены случайные и длинные, поэтому
+/// медленный хеш с солью им не нужен.
+pub fn hash_token(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Пароль в том виде, в котором он сохранён.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredPassword {
+    Hash {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+        salt: String,
+        hash: String,
+    },
+    /// Пароль в открытом виде, как его хранили до появления хешей. Хешируется при загрузке.
+    Plain(String),
+}
+
+impl From<StoredPassword> for PasswordHash {
+    fn from(password: StoredPassword) -> Self {
+        match password {
+            StoredPassword::Hash {
+                m_cost,
+                t_cost,
+                p_cost,
+                salt,
+                hash,
+            } => Self {
+                m_cost,
+                t_cost,
+                p_cost,
+                salt,
+                hash,
+            },
+            StoredPassword::Plain(password) => Self::new(&password),
+        }
+    }
+}
+
+fn compute(params: &Params, password: &str, salt: &[u8]) -> Option<[u8; HASH_LEN]> {
+    let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone());
+    let mut hash = [0; HASH_LEN];
+    argon2
+        .hash_password_into(password.as_bytes(), salt, &mut hash)
+        .ok()?;
+    Some(hash)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ChatId, ChatKind, UserId, ADMIN_UID};
+
+use super::password::{hash_token, PasswordHash};
+
+/// Всё, что сервер должен помнить между перезапусками.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct State {
+    /// Неиспользованные коды для регистрации.
+    pub join_codes: BTreeSet<String>,
+    pub users: BTreeMap<UserId, User>,
+    /// Хеши токенов залогиненных пользователей, кроме администратора. Строятся по сессиям
+    /// пользователей, см. `index_sessions`, поэтому не сохраняются.
+    #[serde(skip)]
+    pub tokens: HashMap<String, UserId>,
+    #[serde(skip)]
+    pub refresh_tokens: HashMap<String, UserId>,
+    pub chats: BTreeMap<ChatId, Chat>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct User {
+    pub name: String,
+    pub password: PasswordHash,
+    /// Сессия, начатая последним логином. Новый логин завершает предыдущую сессию.
+    // NB: у пользователей, сохранённых до появления сессий, вместо неё просто токен, см.
+    // `StoredSession`.
+    #[serde(default, alias = "token")]
+    pub session: Option<Session>,
+    pub banned: bool,
+    pub ban_votes: BTreeSet<UserId>,
+}
+
+/// Сессия хранит только хеши токенов, см. `hash_token`: сами токены знает лишь клиент.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "StoredSession")]
+pub struct Session {
+    pub token_hash: String,
+    /// Время в микросекундах с начала эпохи Unix, после которого токен недействителен.
+    pub expires_at_micros: i64,
+    /// Хеш токена, на который можно получить новую сессию, см. `RefreshToken`.
+    pub refresh_token_hash: String,
+    pub refresh_expires_at_micros: i64,
+}
+
+/// Сессия в том виде, в котором она сохранена.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredSession {
+    Hashed {
+        token_hash: String,
+        expires_at_micros: i64,
+        refresh_token_hash: String,
+        refresh_expires_at_micros: i64,
+    },
+    /// Сессия с токенами в открытом виде.
+    Plain {
+        token: String,
+        expires_at_micros: i64,
+        refresh_token: String,
+        refresh_expires_at_micros: i64,
+    },
+    /// Токен без срока действия, который выдавался до появления сессий. Такая сессия уже
+    /// истекла, так что пользователю нужно залогиниться заново.
+    Token(String),
+}
+
+impl From<StoredSession> for Session {
+    fn from(session: StoredSession) -> Self {
+        match session {
+            StoredSession::Hashed {
+                token_hash,
+                expires_at_micros,
+                refresh_token_hash,
+                refresh_expires_at_micros,
+            } => Self {
+                token_hash,
+                expires_at_micros,
+                refresh_token_hash,
+                refresh_expires_at_micros,
+            },
+            StoredSession::Plain {
+                token,
+                expires_at_micros,
+                refresh_token,
+                refresh_expires_at_micros,
+            } => Self {
+                token_hash: hash_token(&token),
+                expires_at_micros,
+                refresh_token_hash: hash_token(&refresh_token),
+                refresh_expires_at_micros,
+            },
+            // NB: хеш токена не бывает пустой строкой, так что обновить такую сессию нельзя.
+            StoredSession::Token(token) => Self {
+                token_hash: hash_token(&token),
+                expires_at_micros: 0,
+                refresh_token_hash: String::new(),
+                refresh_expires_at_micros: 0,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Chat {
+    /// Пусто у личных комнат: их имя зависит от того, кто на них смотрит.
+    pub name: String,
+    // NB: комнаты, созданные до появления закрытых, открытые, принадлежат администратору и
+    // не имеют участников.
+    #[serde(default)]
+    pub kind: ChatKind,
+    #[serde(default)]
+    pub owner_id: UserId,
+    /// Участники закрытых и личных комнат, включая создателя. У открытых комнат пусто.
+    #[serde(default)]
+    pub members: BTreeSet<UserId>,
+    /// Сообщения по порядку: номер сообщения на единицу больше его индекса.
+    pub messages: Vec<Message>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub user_id: UserId,
+    pub content: String,
+    /// Время отправки в микросекундах с начала эпохи Unix.
+    pub timestamp_micros: i64,
+}
+
+impl Chat {
+    pub fn is_visible_to(&self, user_id: UserId) -> bool {
+        self.kind == ChatKind::Public || self.members.contains(&user_id)
+    }
+
+    /// Возвращает сообщения с номерами строго между `after_seq` и `before_seq` вместе с их
+    /// номерами.
+    pub fn messages(
+        &self,
+        after_seq: u64,
+        before_seq: u64,
+    ) -> impl DoubleEndedIterator<Item = (u64, &Message)> + ExactSizeIterator {
+        let end = before_seq.saturating_sub(1).min(self.messages.len() as u64);
+        let start = after_seq.min(end);
+        self.messages[start as usize..end as usize]
+            .iter()
+            .enumerate()
+            .map(move |(i, message)| (start + i as u64 + 1, message))
+    }
+}
+
+/// Изменение состояния. События создаются только после всех проверок, поэтому их применение
+/// не может завершиться ошибкой.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Event {
+    JoinCodesCreated {
+        join_codes: Vec<String>,
+    },
+    UserJoined {
+        user_id: UserId,
+        join_code: String,
+        name: String,
+        password: PasswordHash,
+        #[serde(alias = "token")]
+        session: Session,
+    },
+    LoggedIn {
+        user_id: UserId,
+        #[serde(alias = "token")]
+        session: Session,
+    },
+    LoggedOut {
+        user_id: UserId,
+    },
+    PasswordChanged {
+        user_id: UserId,
+        password: PasswordHash,
+    },
+    BanVoted {
+        user_id: UserId,
+        voter_id: UserId,
+    },
+    BanVoteWithdrawn {
+        user_id: UserId,
+        voter_id: UserId,
+    },
+    ChatCreated {
+        chat_id: ChatId,
+        name: String,
+        #[serde(default)]
+        kind: ChatKind,
+        #[serde(default)]
+        owner_id: UserId,
+        #[serde(default)]
+        members: BTreeSet<UserId>,
+    },
+    MemberInvited {
+        chat_id: ChatId,
+        user_id: UserId,
+    },
+    MemberKicked {
+        chat_id: ChatId,
+        user_id: UserId,
+    },
+    MessageSent {
+        chat_id: ChatId,
+        user_id: UserId,
+        content: String,
+        timestamp_micros: i64,
+    },
+}
+
+impl State {
+    pub fn next_user_id(&self) -> UserId {
+        let last = self.users.keys().next_back().copied().unwrap_or(ADMIN_UID);
+        UserId(last.0 + 1)
+    }
+
+    pub fn next_chat_id(&self) -> ChatId {
+        match self.chats.keys().next_back() {
+            Some(last) => ChatId(last.0 + 1),
+            None => ChatId(0),
+        }
+    }
+
+    /// Возвращает личную комнату двух пользователей, если она уже есть.
+    pub fn find_direct(&self, first: UserId, second: UserId) -> Option<ChatId> {
+        self.chats
+            .iter()
+            .find(|(_, chat)| {
+                chat.kind == ChatKind::Direct
+                    && chat.members.contains(&first)
+                    && chat.members.contains(&second)
+            })
+            .map(|(&id, _)| id)
+    }
+
+    pub fn find_user(&self, name: &str) -> Option<UserId> {
+        self.users
+            .iter()
+            .find(|(_, user)| user.name == name)
+            .map(|(&id, _)| id)
+    }
+
+    /// Заполняет `tokens` и `refresh_tokens` по сессиям пользователей, например после
+    /// загрузки снимка.
+    pub fn index_sessions(&mut self) {
+        self.tokens.clear();
+        self.refresh_tokens.clear();
+        for (&user_id, user) in &self.users {
+            if let Some(session) = &user.session {
+                self.tokens.insert(session.token_hash.clone(), user_id);
+                self.refresh_tokens
+                    .insert(session.refresh_token_hash.clone(), user_id);
+            }
+        }
+    }
+
+    fn start_session(&mut self, user_id: UserId, session: Session) {
+        self.end_session(user_id);
+        self.tokens.insert(session.token_hash.clone(), user_id);
+        self.refresh_tokens
+            .insert(session.refresh_token_hash.clone(), user_id);
+        self.users.get_mut(&user_id).unwrap().session = Some(session);
+    }
+
+    fn end_session(&mut self, user_id: UserId) {
+        if let Some(session) = self.users.get_mut(&user_id).unwrap().session.take() {
+            self.tokens.remove(&session.token_hash);
+            self.refresh_tokens.remove(&session.refresh_token_hash);
+        }
+    }
+
+    pub fn apply(&mut self, event: Event) {
+        match event {
+            Event::JoinCodesCreated { join_codes } => self.join_codes.extend(join_codes),
+            Event::UserJoined {
+                user_id,
+                join_code,
+                name,
+                password,
+                session,
+            } => {
+                self.join_codes.remove(&join_code);
+                let user = User {
+                    name,
+                    password,
+                    session: None,
+                    banned: false,
+                    ban_votes: BTreeSet::new(),
+                };
+                self.users.insert(user_id, user);
+                self.start_session(user_id, session);
+            }
+            Event::LoggedIn { user_id, session } => self.start_session(user_id, session),
+            Event::LoggedOut { user_id } => self.end_session(user_id),
+            Event::PasswordChanged { user_id, password } => {
+                self.users.get_mut(&user_id).unwrap().password = password;
+            }
+            Event::BanVoted { user_id, voter_id } => {
+                let num_users = self.users.len();
+                let user = self.users.get_mut(&user_id).unwrap();
+                if voter_id == ADMIN_UID {
+                    user.banned = true;
+                } else {
+                    user.ban_votes.insert(voter_id);
+                    user.banned |= user.ban_votes.len() * 2 > num_users;
+                }
+                if user.banned {
+                    self.end_session(user_id);
+                }
+            }
+            Event::BanVoteWithdrawn { user_id, voter_id } => {
+                let user = self.users.get_mut(&user_id).unwrap();
+                if voter_id == ADMIN_UID {
+                    user.banned = false;
+                    user.ban_votes.clear();
+                } else if !user.banned {
+                    user.ban_votes.remove(&voter_id);
+                }
+            }
+            Event::ChatCreated {
+                chat_id,
+                name,
+                kind,
+                owner_id,
+                members,
+            } => {
+                let chat = Chat {
+                    name,
+                    kind,
+                    owner_id,
+                    members,
+                    messages: vec![],
+                };
+                self.chats.insert(chat_id, chat);
+            }
+            Event::MemberInvited { chat_id, user_id } => {
+                self.chats
+                    .get_mut(&chat_id)
+                    .unwrap()
+                    .members
+                    .insert(user_id);
+            }
+            Event::MemberKicked { chat_id, user_id } => {
+                self.chats
+                    .get_mut(&chat_id)
+                    .unwrap()
+                    .members
+                    .remove(&user_id);
+            }
+            Event::MessageSent {
+                chat_id,
+                user_id,
+                content,
+                timestamp_micros,
+            } => {
+                let message = Message {
+                    user_id,
+                    content,
+                    timestamp_micros,
+                };
+                self.chats.get_mut(&chat_id).unwrap().messages.push(message);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// Хранилище состояния сервера: последний снимок и журнал изменений, сделанных после него.
+/// Содержимое записей хранилищу не важно, оно лишь должно сохранять их порядок.
+pub trait Storage: Send + Sync {
+    /// Возвращает последний снимок и записи, добавленные после него.
+    fn load(&mut self) -> io::Result<Contents>;
+
+    /// Добавляет запись в журнал. После возврата из метода запись не должна теряться.
+    fn append(&mut self, record: &[u8]) -> io::Result<()>;
+
+    /// Заменяет снимок. Снимок учитывает все добавленные записи, поэтому их можно удалить.
+    fn write_snapshot(&mut self, snapshot: &[u8]) -> io::Result<()>;
+}
+
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Contents {
+    pub snapshot: Option<Vec<u8>>,
+    pub records: Vec<Vec<u8>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Хранит состояние в памяти, поэтому оно теряется при остановке сервера.
+#[derive(Default)]
+pub struct MemoryStorage {
+    contents: Contents,
+}
+
+impl Storage for MemoryStorage {
+    fn load(&mut self) -> io::Result<Contents> {
+        Ok(self.contents.clone())
+    }
+
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        self.contents.records.push(record.to_vec());
+        Ok(())
+    }
+
+    fn write_snapshot(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        self.contents.snapshot = Some(snapshot.to_vec());
+        self.contents.records.clear();
+        Ok(())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const LOG_FILE: &str = "log";
+
+/// Хранит состояние в директории: файл со снимком, который заменяется атомарно, и журнал,
+/// в конец которого дописываются записи, по одной на строку.
+pub struct FileStorage {
+    dir: PathBuf,
+    log: File,
+}
+
+impl FileStorage {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE))?;
+        Ok(Self { dir, log })
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&mut self) -> io::Result<Contents> {
+        let snapshot = match fs::read(self.dir.join(SNAPSHOT_FILE)) {
+            Ok(snapshot) => Some(snapshot),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+
+        let mut records = vec![];
+        let mut len = 0;
+        let mut reader = BufReader::new(File::open(self.dir.join(LOG_FILE))?);
+        loop {
+            let mut record = vec![];
+            let read = reader.read_until(b'\n', &mut record)?;
+            // NB: запись без перевода строки оборвана падением, так что её запись не была
+            // подтверждена. Она отрезается, чтобы следующая запись начиналась с новой строки.
+            if record.pop() != Some(b'\n') {
+                if read > 0 {
+                    self.log.set_len(len)?;
+                }
+                break;
+            }
+            len += read as u64;
+            records.push(record);
+        }
+        Ok(Contents { snapshot, records })
+    }
+
+    fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        debug_assert!(!record.contains(&b'\n'));
+        let mut line = Vec::with_capacity(record.len() + 1);
+        line.extend_from_slice(record);
+        line.push(b'\n');
+        self.log.write_all(&line)?;
+        self.log.sync_data()
+    }
+
+    fn write_snapshot(&mut self, snapshot: &[u8]) -> io::Result<()> {
+        let path = self.dir.join(SNAPSHOT_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(snapshot)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+
+        // NB: если это не удастся или сервер упадёт раньше, записи, которые учитывает снимок,
+        // будут пропущены при загрузке по их номерам.
+        self.log.set_len(0)?;
+        self.log.sync_data()
+    }
+}
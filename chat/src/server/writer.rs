@@ -0,0 +1,177 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+
+use super::state::{Event, State};
+use super::storage::Storage;
+use super::{storage_error, Record, Snapshot};
+
+/// Наименьшее время между снимками. Пока снимок не записан, события копятся в журнале.
+const SNAPSHOT_PERIOD: Duration = Duration::from_secs(60);
+
+/// Действие, которое выполняется, когда событие записано.
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// Пишет события в хранилище в отдельном потоке, чтобы запись на диск не блокировала ни
+/// асинхронный рантайм, ни состояние сервера.
+///
+/// Раз в `SNAPSHOT_PERIOD` сервер отдаёт потоку копию своего состояния, и поток сохраняет её
+/// снимком. Пока копия не записана, сервер занимает вдвое больше памяти.
+pub struct Writer {
+    sender: mpsc::Sender<Request>,
+    /// Не удалось записать событие. Состояние сервера уже его учитывает и разошлось с
+    /// хранилищем, так что следующие события не записываются.
+    failed: Arc<AtomicBool>,
+    /// Номер последнего события, отданного в снимок.
+    snapshot_seq: u64,
+    snapshot_time: Instant,
+}
+
+enum Request {
+    Append {
+        seq: u64,
+        event: Event,
+        then: Option<Callback>,
+        done: oneshot::Sender<io::Result<()>>,
+    },
+    Snapshot {
+        seq: u64,
+        state: State,
+    },
+}
+
+/// Событие, отданное на запись. См. `wait`.
+pub struct Written(oneshot::Receiver<io::Result<()>>);
+
+impl Writer {
+    /// Запускает поток. `seq` - номер последнего события, уже сохранённого в снимке.
+    pub fn spawn(mut storage: Box<dyn Storage>, seq: u64) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let failed = Arc::new(AtomicBool::new(false));
+        let thread_failed = failed.clone();
+        thread::spawn(move || {
+            for request in receiver {
+                handle(&mut *storage, &thread_failed, request);
+            }
+        });
+        Self {
+            sender,
+            failed,
+            snapshot_seq: seq,
+            snapshot_time: Instant::now(),
+        }
+    }
+
+    /// Отдаёт событие на запись. События пишутся в журнал в том порядке, в котором отданы.
+    pub fn write(&self, seq: u64, event: Event) -> Written {
+        self.send(seq, event, None)
+    }
+
+    /// Как `write`, но после записи выполняет `then`. Такие действия выполняются в порядке
+    /// событий и только для тех событий, которые удалось записать.
+    pub fn write_then(
+        &self,
+        seq: u64,
+        event: Event,
+        then: impl FnOnce() + Send + 'static,
+    ) -> Written {
+        self.send(seq, event, Some(Box::new(then)))
+    }
+
+    fn send(&self, seq: u64, event: Event, then: Option<Callback>) -> Written {
+        let (done, receiver) = oneshot::channel();
+        // NB: если поток завершился, `done` удаляется, и `Written::wait` вернёт ошибку.
+        let _ = self.sender.send(Request::Append {
+            seq,
+            event,
+            then,
+            done,
+        });
+        Written(receiver)
+    }
+
+    /// Отдаёт на запись снимок состояния `state` после события `seq`, если с прошлого снимка
+    /// прошло `SNAPSHOT_PERIOD`.
+    pub fn snapshot_if_due(&mut self, seq: u64, state: &State) {
+        if seq == self.snapshot_seq || self.snapshot_time.elapsed() < SNAPSHOT_PERIOD {
+            return;
+        }
+        self.snapshot_seq = seq;
+        self.snapshot_time = Instant::now();
+        let state = state.clone();
+        let _ = self.sender.send(Request::Snapshot { seq, state });
+    }
+
+    /// Не удалось записать одно из событий.
+    pub fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+}
+
+impl Written {
+    /// Дожидается, пока событие окажется в журнале. После этого оно не потеряется.
+    pub async fn wait(self) -> Result<(), tonic::Status> {
+        match self.0.await {
+            Ok(result) => result.map_err(storage_error),
+            Err(_) => Err(storage_error("the writer has stopped")),
+        }
+    }
+}
+
+/// Сохраняет состояние после события `seq` снимком.
+pub fn write_snapshot(storage: &mut dyn Storage, seq: u64, state: &State) -> io::Result<()> {
+    let snapshot = Snapshot { seq, state };
+    storage.write_snapshot(&serde_json::to_vec(&snapshot)?)
+}
+
+fn handle(storage: &mut dyn Storage, failed: &AtomicBool, request: Request) {
+    match request {
+        Request::Append {
+            seq,
+            event,
+            then,
+            done,
+        } => {
+            let result = append(storage, failed, seq, &event);
+            if result.is_ok() {
+                if let Some(then) = then {
+                    then();
+                }
+            }
+            let _ = done.send(result);
+        }
+        Request::Snapshot { seq, state } => {
+            if failed.load(Ordering::SeqCst) {
+                return;
+            }
+            // NB: события уже в журнале, так что после неудачного снимка они просто копятся в нём
+            // до следующего.
+            if let Err(err) = write_snapshot(storage, seq, &state) {
+                log::error!("failed to write a snapshot: {}", err);
+            }
+        }
+    }
+}
+
+fn append(
+    storage: &mut dyn Storage,
+    failed: &AtomicBool,
+    seq: u64,
+    event: &Event,
+) -> io::Result<()> {
+    if failed.load(Ordering::SeqCst) {
+        return Err(io::Error::other("an earlier change failed to be stored"));
+    }
+    let result = serde_json::to_vec(&Record { seq, event })
+        .map_err(io::Error::from)
+        .and_then(|record| storage.append(&record));
+    if let Err(err) = &result {
+        log::error!("failed to store a change, the server stops: {}", err);
+        failed.store(true, Ordering::SeqCst);
+    }
+    result
+}
@@ -1,32 +1,63 @@
 use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
 use std::sync::atomic;
 use std::time::Duration;
 
+use chat::server::{FileStorage, MemoryStorage, Storage, TokenLifetimes};
+
 use futures::join;
 
 use pretty_assertions::{assert_eq, assert_ne};
 
+use tokio::io::AsyncBufReadExt;
 use tokio_stream::StreamExt;
 
 const ADMIN_TOKEN: &str = "8931a63a84126797b7fc8344cb0e2f5f";
 
 async fn serve() -> String {
-    static PORT: atomic::AtomicU16 = atomic::AtomicU16::new(8000);
+    serve_with_data_dir(None).await.0
+}
 
-    let port = PORT.fetch_add(1, atomic::Ordering::SeqCst);
-    let addr_str = format!("127.0.0.1:{}", port);
-    let addr = addr_str.parse().expect("failed to parse SERVER_ADDR");
+async fn serve_with_data_dir(data_dir: Option<PathBuf>) -> (String, tokio::task::JoinHandle<()>) {
+    let (addr_str, addr) = next_addr();
+    let server = tokio::spawn(async move {
+        chat::serve(ADMIN_TOKEN.to_string(), addr, data_dir)
+            .await
+            .expect("failed to start the server");
+    });
+    wait_for_server(addr_str, server).await
+}
 
-    tokio::spawn(async move {
-        chat::serve(ADMIN_TOKEN.to_string(), addr)
+async fn serve_with_token_lifetimes(token_lifetimes: TokenLifetimes) -> String {
+    let (addr_str, addr) = next_addr();
+    let server = tokio::spawn(async move {
+        let storage = Box::new(MemoryStorage::default());
+        chat::server::serve_with_storage(ADMIN_TOKEN.to_string(), addr, token_lifetimes, storage)
             .await
             .expect("failed to start the server");
     });
+    wait_for_server(addr_str, server).await.0
+}
 
+fn next_addr() -> (String, std::net::SocketAddr) {
+    static PORT: atomic::AtomicU16 = atomic::AtomicU16::new(8000);
+
+    let port = PORT.fetch_add(1, atomic::Ordering::SeqCst);
+    let addr_str = format!("127.0.0.1:{}", port);
+    let addr = addr_str.parse().expect("failed to parse SERVER_ADDR");
+    (addr_str, addr)
+}
+
+async fn wait_for_server(
+    addr_str: String,
+    server: tokio::task::JoinHandle<()>,
+) -> (String, tokio::task::JoinHandle<()>) {
     // Wait for 200*50ms = 10s.
     for _ in 0..200 {
         if tokio::net::TcpStream::connect(&addr_str).await.is_ok() {
-            return format!("http://{}", addr_str);
+            return (format!("http://{}", addr_str), server);
         }
 
         tokio::time::sleep(Duration::from_millis(50)).await;
@@ -262,6 +293,193 @@ async fn test_cant_login_if_banned() {
         .expect_err("managed to log in despite being banned");
 }
 
+#[tokio::test]
+async fn test_token_refresh() {
+    let server_addr = serve_with_token_lifetimes(TokenLifetimes {
+        token: Duration::from_millis(500),
+        refresh_token: Duration::from_secs(60),
+    })
+    .await;
+    let mut admin_client =
+        chat::Client::connect(Some(ADMIN_TOKEN.to_string()), server_addr.clone())
+            .await
+            .expect("failed to connect to server");
+    join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+    assert!(alice_client.token_expires_at.unwrap() > std::time::SystemTime::now());
+    alice_client
+        .list_users()
+        .await
+        .expect("failed to list users");
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let err = alice_client
+        .list_users()
+        .await
+        .expect_err("managed to list users with an expired token");
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+    let old_refresh_token = alice_client.refresh_token.clone();
+    alice_client.refresh().await.expect("failed to refresh");
+    alice_client
+        .list_users()
+        .await
+        .expect("failed to list users after a refresh");
+
+    alice_client.refresh_token = old_refresh_token;
+    alice_client
+        .refresh()
+        .await
+        .expect_err("managed to refresh with a used refresh token");
+}
+
+#[tokio::test]
+async fn test_logout() {
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+    let general_cid = alice_client
+        .create_chat_room("general".to_string())
+        .await
+        .expect("failed to create chat room");
+    let stream = alice_client
+        .stream_messages(general_cid, None)
+        .await
+        .expect("failed to stream messages");
+
+    let (token, refresh_token) = (
+        alice_client.token.clone(),
+        alice_client.refresh_token.clone(),
+    );
+    alice_client.logout().await.expect("failed to logout");
+    let messages: Vec<_> = stream.collect().await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages[0].as_ref().unwrap_err().code(),
+        tonic::Code::Unauthenticated
+    );
+
+    alice_client.token = token;
+    alice_client.refresh_token = refresh_token;
+    alice_client
+        .list_users()
+        .await
+        .expect_err("managed to list users after a logout");
+    alice_client
+        .refresh()
+        .await
+        .expect_err("managed to refresh after a logout");
+    admin_client
+        .logout()
+        .await
+        .expect_err("managed to log the admin out");
+}
+
+#[tokio::test]
+async fn test_change_password() {
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+
+    alice_client
+        .change_password("wr0ngpassword!".to_string(), "n3wsecret!!".to_string())
+        .await
+        .expect_err("managed to change password with a wrong old one");
+    alice_client
+        .change_password("t0psecret!".to_string(), "weak".to_string())
+        .await
+        .expect_err("managed to change password to a weak one");
+    alice_client
+        .change_password("t0psecret!".to_string(), "n3wsecret!!".to_string())
+        .await
+        .expect("failed to change password");
+    alice_client
+        .list_users()
+        .await
+        .expect("the session ended after a password change");
+
+    alice_client
+        .login("alice".to_string(), "t0psecret!".to_string())
+        .await
+        .expect_err("managed to login with the old password");
+    alice_client
+        .login("alice".to_string(), "n3wsecret!!".to_string())
+        .await
+        .expect("failed to login with the new password");
+}
+
+#[tokio::test]
+async fn test_ban_revokes_tokens() {
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    let eve_uid = join(&server_addr, &mut admin_client, "eve", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let mut eve_client = chat::Client::connect_login(
+        "eve".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+    let general_cid = admin_client
+        .create_chat_room("general".to_string())
+        .await
+        .expect("failed to create chat room");
+    let stream = eve_client
+        .stream_messages(general_cid, None)
+        .await
+        .expect("failed to stream messages");
+
+    admin_client
+        .ban_user(eve_uid)
+        .await
+        .expect("failed to ban eve");
+    let messages: Vec<_> = stream.collect().await;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages[0].as_ref().unwrap_err().code(),
+        tonic::Code::PermissionDenied
+    );
+
+    admin_client
+        .unban_user(eve_uid)
+        .await
+        .expect("failed to unban eve");
+    eve_client
+        .list_users()
+        .await
+        .expect_err("the token survived a ban");
+    eve_client
+        .refresh()
+        .await
+        .expect_err("the refresh token survived a ban");
+}
+
 #[tokio::test]
 async fn test_list_users() {
     let (server_addr, mut admin_client) = serve_and_connect_admin().await;
@@ -627,6 +845,810 @@ async fn test_communicate_in_chat_rooms() {
     }
 }
 
+#[tokio::test]
+async fn test_private_chat_rooms() {
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    let alice_uid = join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let bob_uid = join(&server_addr, &mut admin_client, "bob", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+    let mut bob_client = chat::Client::connect_login(
+        "bob".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+
+    let general_cid = alice_client
+        .create_chat_room("general".to_string())
+        .await
+        .expect("failed to create chat room");
+    let secret_cid = alice_client
+        .create_private_chat_room("secret".to_string())
+        .await
+        .expect("failed to create chat room");
+
+    let list_chat_rooms = |chats: Vec<chat::client::Chat>| -> Vec<_> {
+        chats.into_iter().map(|chat| (chat.id, chat.kind)).collect()
+    };
+    assert_eq!(
+        list_chat_rooms(alice_client.list_chat_rooms().await.unwrap()),
+        [
+            (general_cid, chat::ChatKind::Public),
+            (secret_cid, chat::ChatKind::Private)
+        ]
+    );
+    assert_eq!(
+        list_chat_rooms(bob_client.list_chat_rooms().await.unwrap()),
+        [(general_cid, chat::ChatKind::Public)]
+    );
+    bob_client
+        .send_message(secret_cid, "let me in".to_string())
+        .await
+        .expect_err("managed to send a message to a private chat room");
+    assert!(
+        bob_client.stream_messages(secret_cid, None).await.is_err(),
+        "managed to stream a private chat room"
+    );
+    bob_client
+        .invite_to_chat_room(secret_cid, bob_uid)
+        .await
+        .expect_err("managed to invite oneself to a private chat room");
+
+    alice_client
+        .invite_to_chat_room(secret_cid, bob_uid)
+        .await
+        .expect("failed to invite");
+    assert_eq!(
+        alice_client
+            .list_chat_room_members(secret_cid)
+            .await
+            .expect("failed to list members"),
+        [alice_uid, bob_uid]
+    );
+    assert_eq!(
+        list_chat_rooms(bob_client.list_chat_rooms().await.unwrap()),
+        [
+            (general_cid, chat::ChatKind::Public),
+            (secret_cid, chat::ChatKind::Private)
+        ]
+    );
+    let bob_stream = bob_client
+        .stream_messages(secret_cid, None)
+        .await
+        .expect("failed to stream messages");
+    bob_client
+        .send_message(secret_cid, "thanks!".to_string())
+        .await
+        .expect("failed to send message");
+    bob_client
+        .kick_from_chat_room(secret_cid, alice_uid)
+        .await
+        .expect_err("managed to kick the creator");
+
+    alice_client
+        .kick_from_chat_room(secret_cid, bob_uid)
+        .await
+        .expect("failed to kick");
+    let messages: Vec<_> = bob_stream.collect().await;
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].as_ref().unwrap().content, "thanks!");
+    assert_eq!(
+        messages[1].as_ref().unwrap_err().code(),
+        tonic::Code::PermissionDenied
+    );
+    bob_client
+        .get_messages(secret_cid, chat::client::MessagesPage::Last, 0)
+        .await
+        .expect_err("managed to read a private chat room after a kick");
+    assert_eq!(
+        list_chat_rooms(bob_client.list_chat_rooms().await.unwrap()),
+        [(general_cid, chat::ChatKind::Public)]
+    );
+
+    alice_client
+        .invite_to_chat_room(general_cid, bob_uid)
+        .await
+        .expect_err("managed to invite to a public chat room");
+}
+
+#[tokio::test]
+async fn test_direct_messages() {
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    let alice_uid = join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let bob_uid = join(&server_addr, &mut admin_client, "bob", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let eve_uid = join(&server_addr, &mut admin_client, "eve", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let mut clients = Vec::new();
+    for name in ["alice", "bob", "eve"] {
+        let client = chat::Client::connect_login(
+            name.to_string(),
+            "t0psecret!".to_string(),
+            server_addr.clone(),
+        )
+        .await
+        .expect("failed to login");
+        clients.push(client);
+    }
+    let [alice_client, bob_client, eve_client] = &mut clients[..] else {
+        unreachable!();
+    };
+
+    let direct_cid = alice_client
+        .open_direct(bob_uid)
+        .await
+        .expect("failed to open direct chat");
+    assert_eq!(
+        bob_client
+            .open_direct(alice_uid)
+            .await
+            .expect("failed to open direct chat"),
+        direct_cid
+    );
+    alice_client
+        .open_direct(alice_uid)
+        .await
+        .expect_err("managed to open a direct chat with oneself");
+
+    for (client, peer_name) in [(&mut *alice_client, "bob"), (&mut *bob_client, "alice")] {
+        let chats = client
+            .list_chat_rooms()
+            .await
+            .expect("failed to list chat rooms");
+        assert_eq!(chats.len(), 1);
+        assert_eq!(
+            (chats[0].id, chats[0].name.as_str(), chats[0].kind),
+            (direct_cid, peer_name, chat::ChatKind::Direct)
+        );
+    }
+    assert!(eve_client.list_chat_rooms().await.unwrap().is_empty());
+
+    alice_client
+        .send_message(direct_cid, "hi bob".to_string())
+        .await
+        .expect("failed to send message");
+    let messages = bob_client
+        .get_messages(direct_cid, chat::client::MessagesPage::Last, 0)
+        .await
+        .expect("failed to get messages");
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].content, "hi bob");
+    eve_client
+        .get_messages(direct_cid, chat::client::MessagesPage::Last, 0)
+        .await
+        .expect_err("managed to read someone else's direct chat");
+    alice_client
+        .invite_to_chat_room(direct_cid, eve_uid)
+        .await
+        .expect_err("managed to invite to a direct chat");
+}
+
+#[tokio::test]
+async fn test_get_messages() {
+    use chat::client::MessagesPage;
+
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+
+    let general_cid = alice_client
+        .create_chat_room("general".to_string())
+        .await
+        .expect("failed to create chat room");
+    for i in 1..=5 {
+        let seq = alice_client
+            .send_message(general_cid, format!("message #{}", i))
+            .await
+            .expect("failed to send message");
+        assert_eq!(seq, i);
+    }
+
+    for (page, limit, expected_seqs) in [
+        (MessagesPage::Last, 2, vec![4, 5]),
+        (MessagesPage::Last, 0, vec![1, 2, 3, 4, 5]),
+        (MessagesPage::Before(4), 2, vec![2, 3]),
+        (MessagesPage::Before(2), 10, vec![1]),
+        (MessagesPage::After(1), 2, vec![2, 3]),
+        (MessagesPage::After(3), 10, vec![4, 5]),
+        (MessagesPage::After(5), 10, vec![]),
+    ] {
+        let messages = alice_client
+            .get_messages(general_cid, page, limit)
+            .await
+            .expect("failed to get messages");
+        let seqs: Vec<_> = messages.iter().map(|msg| msg.seq).collect();
+        assert_eq!(seqs, expected_seqs, "{:?}, limit {}", page, limit);
+        for msg in messages {
+            assert_eq!(msg.content, format!("message #{}", msg.seq));
+            assert_eq!(msg.user_name, "alice");
+        }
+    }
+
+    let messages = alice_client
+        .get_messages(general_cid, MessagesPage::Last, 0)
+        .await
+        .expect("failed to get messages");
+    assert!(messages
+        .windows(2)
+        .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+    alice_client
+        .get_messages(chat::ChatId(general_cid.0 + 1), MessagesPage::Last, 0)
+        .await
+        .expect_err("got messages of a nonexistent chat room");
+}
+
+#[tokio::test]
+async fn test_resume_stream() {
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+
+    let general_cid = alice_client
+        .create_chat_room("general".to_string())
+        .await
+        .expect("failed to create chat room");
+    for i in 1..=3 {
+        alice_client
+            .send_message(general_cid, format!("message #{}", i))
+            .await
+            .expect("failed to send message");
+    }
+
+    // NB: the client was disconnected after receiving the first message.
+    let stream = alice_client
+        .stream_messages(general_cid, Some(1))
+        .await
+        .expect("failed to stream messages")
+        .take(4)
+        .map(|msg| msg.expect("failed to receive message"))
+        .collect::<Vec<_>>();
+    for i in 4..=5 {
+        alice_client
+            .send_message(general_cid, format!("message #{}", i))
+            .await
+            .expect("failed to send message");
+    }
+
+    let messages = stream.await;
+    let messages: Vec<_> = messages
+        .into_iter()
+        .map(|msg| (msg.seq, msg.content))
+        .collect();
+    assert_eq!(
+        messages,
+        (2..=5)
+            .map(|i| (i, format!("message #{}", i)))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_restart() {
+    let data_dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let data_dir = data_dir.path().to_path_buf();
+
+    let (server_addr, server) = serve_with_data_dir(Some(data_dir.clone())).await;
+    let mut admin_client =
+        chat::Client::connect(Some(ADMIN_TOKEN.to_string()), server_addr.clone())
+            .await
+            .expect("failed to connect to server");
+    let alice_uid = join(&server_addr, &mut admin_client, "alice", "t0psecret!")
+        .await
+        .expect("failed to join");
+    let eve_uid = join(&server_addr, &mut admin_client, "eve", "t0psecret!")
+        .await
+        .expect("failed to join");
+    admin_client
+        .ban_user(eve_uid)
+        .await
+        .expect("failed to ban eve");
+    let join_codes = admin_client
+        .create_join_codes(2)
+        .await
+        .expect("failed to create join codes");
+
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+    let general_cid = alice_client
+        .create_chat_room("general".to_string())
+        .await
+        .expect("failed to create chat room");
+    alice_client
+        .send_message(general_cid, "anyone here?".to_string())
+        .await
+        .expect("failed to send message");
+    server.abort();
+
+    // NB: the second restart loads the snapshot written by the first one.
+    for _ in 0..2 {
+        let (server_addr, server) = serve_with_data_dir(Some(data_dir.clone())).await;
+        let mut client = chat::Client::connect(Some(alice_client.token.clone()), server_addr)
+            .await
+            .expect("failed to connect to server");
+
+        let mut users: Vec<_> = client
+            .list_users()
+            .await
+            .expect("failed to list users")
+            .into_iter()
+            .map(|user| (user.id, user.name, user.banned))
+            .collect();
+        users.sort_by_key(|&(id, _, _)| id);
+        assert_eq!(
+            users,
+            [
+                (alice_uid, "alice".to_string(), false),
+                (eve_uid, "eve".to_string(), true),
+            ]
+        );
+
+        let chats = client
+            .list_chat_rooms()
+            .await
+            .expect("failed to list chat rooms");
+        assert_eq!(chats.len(), 1);
+        assert_eq!(
+            (chats[0].id, chats[0].name.as_str()),
+            (general_cid, "general")
+        );
+        let messages: Vec<_> = client
+            .get_messages(general_cid, chat::client::MessagesPage::Last, 0)
+            .await
+            .expect("failed to get messages")
+            .into_iter()
+            .map(|msg| (msg.seq, msg.user_id, msg.content))
+            .collect();
+        assert_eq!(messages, [(1, alice_uid, "anyone here?".to_string())]);
+
+        server.abort();
+    }
+
+    let (server_addr, _server) = serve_with_data_dir(Some(data_dir.clone())).await;
+    let mut admin_client =
+        chat::Client::connect(Some(ADMIN_TOKEN.to_string()), server_addr.clone())
+            .await
+            .expect("failed to connect to server");
+    let listed_join_codes: HashSet<_> = admin_client
+        .list_join_codes()
+        .await
+        .expect("failed to list join codes")
+        .into_iter()
+        .collect();
+    assert_eq!(listed_join_codes, join_codes.into_iter().collect());
+
+    let bob_uid = join(&server_addr, &mut admin_client, "bob", "t0psecret!")
+        .await
+        .expect("failed to join");
+    assert!(bob_uid != alice_uid && bob_uid != eve_uid);
+    let memes_cid = admin_client
+        .create_chat_room("memes".to_string())
+        .await
+        .expect("failed to create chat room");
+    assert_ne!(memes_cid, general_cid);
+
+    for file in ["snapshot", "log"] {
+        let contents = std::fs::read_to_string(data_dir.join(file)).unwrap();
+        assert!(
+            !contents.contains("t0psecret!"),
+            "a password is stored in {}",
+            file
+        );
+        assert!(
+            !contents.contains(&alice_client.token),
+            "a token is stored in {}",
+            file
+        );
+    }
+}
+
+/// Snapshot written as the server stored its state before passwords were hashed and tokens
+/// expired.
+fn plaintext_snapshot() -> String {
+    serde_json::json!({
+        "seq": 1,
+        "state": {
+            "join_codes": ["bobcode"],
+            "users": {
+                "1": {
+                    "name": "alice",
+                    "password": "t0psecret!",
+                    "token": "aliceoldtoken",
+                    "banned": false,
+                    "ban_votes": [],
+                },
+            },
+            "tokens": {"aliceoldtoken": 1},
+            "chats": {
+                "0": {
+                    "name": "general",
+                    "messages": [
+                        {"user_id": 1, "content": "anyone here?", "timestamp_micros": 1},
+                    ],
+                },
+            },
+        },
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn test_restart_purges_plaintext_credentials() {
+    let data_dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let data_dir = data_dir.path().to_path_buf();
+    std::fs::write(data_dir.join("snapshot"), plaintext_snapshot()).unwrap();
+    std::fs::write(data_dir.join("log"), "").unwrap();
+
+    let (_server_addr, _server) = serve_with_data_dir(Some(data_dir.clone())).await;
+    for file in ["snapshot", "log"] {
+        let contents = std::fs::read_to_string(data_dir.join(file)).unwrap();
+        for secret in ["t0psecret!", "aliceoldtoken"] {
+            assert!(
+                !contents.contains(secret),
+                "{} is stored in {}",
+                secret,
+                file
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_restart_with_plaintext_credentials() {
+    let data_dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let data_dir = data_dir.path().to_path_buf();
+
+    let records = [
+        serde_json::json!({"seq": 2, "event": {"UserJoined": {
+            "user_id": 2,
+            "join_code": "bobcode",
+            "name": "bob",
+            "password": "b0bsecret!",
+            "token": "bobtoken",
+        }}}),
+        serde_json::json!({"seq": 3, "event": {"LoggedIn": {
+            "user_id": 1,
+            "token": "alicetoken",
+        }}}),
+    ];
+    std::fs::write(data_dir.join("snapshot"), plaintext_snapshot()).unwrap();
+    let log: String = records
+        .iter()
+        .map(|record| format!("{}\n", record))
+        .collect();
+    std::fs::write(data_dir.join("log"), log).unwrap();
+
+    let (server_addr, _server) = serve_with_data_dir(Some(data_dir.clone())).await;
+    let mut client = chat::Client::connect(Some("alicetoken".to_string()), server_addr.clone())
+        .await
+        .expect("failed to connect to server");
+    let err = client.list_users().await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unauthenticated);
+
+    let mut alice_client = chat::Client::connect_login(
+        "alice".to_string(),
+        "t0psecret!".to_string(),
+        server_addr.clone(),
+    )
+    .await
+    .expect("failed to login");
+    let users: HashSet<_> = alice_client
+        .list_users()
+        .await
+        .expect("failed to list users")
+        .into_iter()
+        .map(|user| (user.id, user.name))
+        .collect();
+    assert_eq!(
+        users,
+        HashSet::from([
+            (chat::UserId(1), "alice".to_string()),
+            (chat::UserId(2), "bob".to_string()),
+        ])
+    );
+    let messages: Vec<_> = alice_client
+        .get_messages(chat::ChatId(0), chat::client::MessagesPage::Last, 0)
+        .await
+        .expect("failed to get messages")
+        .into_iter()
+        .map(|msg| (msg.seq, msg.content))
+        .collect();
+    assert_eq!(messages, [(1, "anyone here?".to_string())]);
+
+    chat::Client::connect_login("bob".to_string(), "b0bsecret!".to_string(), server_addr)
+        .await
+        .expect("failed to login");
+}
+
+/// Storage that fails to append records once `fail` is set.
+struct FailingStorage {
+    storage: MemoryStorage,
+    fail: std::sync::Arc<atomic::AtomicBool>,
+}
+
+impl Storage for FailingStorage {
+    fn load(&mut self) -> std::io::Result<chat::server::Contents> {
+        self.storage.load()
+    }
+
+    fn append(&mut self, record: &[u8]) -> std::io::Result<()> {
+        if self.fail.load(atomic::Ordering::SeqCst) {
+            return Err(std::io::Error::other("disk is full"));
+        }
+        self.storage.append(record)
+    }
+
+    fn write_snapshot(&mut self, snapshot: &[u8]) -> std::io::Result<()> {
+        self.storage.write_snapshot(snapshot)
+    }
+}
+
+#[tokio::test]
+async fn test_storage_failure() {
+    let fail = std::sync::Arc::new(atomic::AtomicBool::new(false));
+    let storage = Box::new(FailingStorage {
+        storage: MemoryStorage::default(),
+        fail: fail.clone(),
+    });
+    let (addr_str, addr) = next_addr();
+    let server = tokio::spawn(async move {
+        chat::server::serve_with_storage(
+            ADMIN_TOKEN.to_string(),
+            addr,
+            TokenLifetimes::default(),
+            storage,
+        )
+        .await
+        .expect("failed to start the server");
+    });
+    let (server_addr, _server) = wait_for_server(addr_str, server).await;
+    let mut admin_client = chat::Client::connect(Some(ADMIN_TOKEN.to_string()), server_addr)
+        .await
+        .expect("failed to connect to server");
+
+    let general_cid = admin_client
+        .create_chat_room("general".to_string())
+        .await
+        .expect("failed to create chat room");
+    let mut stream = admin_client
+        .stream_messages(general_cid, None)
+        .await
+        .expect("failed to stream messages");
+
+    fail.store(true, atomic::Ordering::SeqCst);
+    admin_client
+        .send_message(general_cid, "lost".to_string())
+        .await
+        .expect_err("sent a message that was not stored");
+
+    // The message is not delivered, and the server stops serving.
+    let err = stream
+        .next()
+        .await
+        .expect("the stream ended without an error")
+        .expect_err("received a message that was not stored");
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+    assert!(stream.next().await.is_none());
+    fail.store(false, atomic::Ordering::SeqCst);
+    let err = admin_client.list_users().await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+    let err = admin_client
+        .send_message(general_cid, "after".to_string())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unavailable);
+}
+
+#[tokio::test]
+async fn test_terminal_client() {
+    use tokio::io::{AsyncWriteExt, BufReader};
+
+    let (server_addr, mut admin_client) = serve_and_connect_admin().await;
+    let join_code = admin_client
+        .create_join_codes(1)
+        .await
+        .expect("failed to create join codes")
+        .pop()
+        .unwrap();
+    let config_dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let config_path = config_dir.path().join("client.json");
+
+    let spawn_client = |args: &[&str]| {
+        tokio::process::Command::new(env!("CARGO_BIN_EXE_chat-client"))
+            .args(args)
+            .arg("--config")
+            .arg(&config_path)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("failed to start the client")
+    };
+    let mut client = spawn_client(&["--addr", &server_addr]);
+    let mut stdin = client.stdin.take().unwrap();
+    let mut stdout = BufReader::new(client.stdout.take().unwrap()).lines();
+    let input = format!(
+        "/join {} alice\nt0psecret!\n/create general\n/enter general\n",
+        join_code
+    );
+    stdin.write_all(input.as_bytes()).await.unwrap();
+    wait_for_line(&mut stdout, "logged in as alice").await;
+    wait_for_line(&mut stdout, "--- general ---").await;
+
+    let general_cid = admin_client.list_chat_rooms().await.unwrap()[0].id;
+    admin_client
+        .send_message(general_cid, "welcome, alice".to_string())
+        .await
+        .expect("failed to send message");
+    wait_for_line(&mut stdout, "admin: welcome, alice").await;
+    stdin.write_all(b"thanks!\n").await.unwrap();
+    wait_for_line(&mut stdout, "alice: thanks!").await;
+    stdin.write_all(b"/quit\n").await.unwrap();
+    assert!(client.wait().await.unwrap().success());
+
+    // The second run logs in with the saved credentials.
+    let mut client = spawn_client(&[]);
+    let mut stdin = client.stdin.take().unwrap();
+    let mut stdout = BufReader::new(client.stdout.take().unwrap()).lines();
+    stdin.write_all(b"/rooms\n/quit\n").await.unwrap();
+    let mut output = vec![];
+    while let Some(line) = stdout.next_line().await.unwrap() {
+        output.push(line);
+    }
+    assert!(client.wait().await.unwrap().success());
+    assert!(
+        output.contains(&"logged in as alice".to_string()),
+        "{:?}",
+        output
+    );
+    assert!(
+        output.contains(&format!(" {:>4} general", general_cid.0)),
+        "{:?}",
+        output
+    );
+}
+
+#[tokio::test]
+async fn test_terminal_client_login_after_session_ends() {
+    use tokio::io::{AsyncWriteExt, BufReader};
+
+    let server_addr = serve_with_token_lifetimes(TokenLifetimes {
+        token: Duration::from_secs(1),
+        refresh_token: Duration::from_secs(1),
+    })
+    .await;
+    let mut admin_client =
+        chat::Client::connect(Some(ADMIN_TOKEN.to_string()), server_addr.clone())
+            .await
+            .expect("failed to connect to server");
+    let join_code = admin_client
+        .create_join_codes(1)
+        .await
+        .expect("failed to create join codes")
+        .pop()
+        .unwrap();
+    let config_dir = tempfile::tempdir().expect("failed to create a temporary directory");
+    let config_path = config_dir.path().join("client.json");
+
+    let run_client = |args: Vec<String>, input: String| {
+        let config_path = config_path.clone();
+        async move {
+            let mut client = tokio::process::Command::new(env!("CARGO_BIN_EXE_chat-client"))
+                .args(args)
+                .arg("--config")
+                .arg(config_path)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .expect("failed to start the client");
+            let mut stdin = client.stdin.take().unwrap();
+            stdin.write_all(input.as_bytes()).await.unwrap();
+            drop(stdin);
+            let mut stdout = BufReader::new(client.stdout.take().unwrap()).lines();
+            let mut output = vec![];
+            while let Some(line) = stdout.next_line().await.unwrap() {
+                output.push(line);
+            }
+            assert!(client.wait().await.unwrap().success());
+            output
+        }
+    };
+    let output = run_client(
+        vec!["--addr".to_string(), server_addr],
+        format!("/join {} alice\nt0psecret!\n/quit\n", join_code),
+    )
+    .await;
+    assert!(
+        output
+            .iter()
+            .any(|line| line.contains("logged in as alice")),
+        "{:?}",
+        output
+    );
+
+    // Both saved tokens expire, and the client logs in anew. It also greets alice on start.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let output = run_client(vec![], "/login\nt0psecret!\n/quit\n".to_string()).await;
+    let logged_in = output
+        .iter()
+        .filter(|line| line.contains("logged in as alice"))
+        .count();
+    assert_eq!(logged_in, 2, "{:?}", output);
+}
+
+#[test]
+fn test_file_storage() {
+    let data_dir = tempfile::tempdir().expect("failed to create a temporary directory");
+
+    let mut storage = FileStorage::open(data_dir.path()).expect("failed to open storage");
+    storage.append(b"one").unwrap();
+    storage.append(b"two").unwrap();
+    drop(storage);
+
+    // A record cut short by a crash is dropped.
+    let mut log = OpenOptions::new()
+        .append(true)
+        .open(data_dir.path().join("log"))
+        .unwrap();
+    log.write_all(b"thr").unwrap();
+
+    let mut storage = FileStorage::open(data_dir.path()).expect("failed to open storage");
+    let contents = storage.load().unwrap();
+    assert_eq!(contents.snapshot, None);
+    assert_eq!(contents.records, [b"one".to_vec(), b"two".to_vec()]);
+
+    storage.append(b"three").unwrap();
+    let contents = storage.load().unwrap();
+    assert_eq!(
+        contents.records,
+        [b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+    );
+
+    storage.write_snapshot(b"snapshot").unwrap();
+    storage.append(b"four").unwrap();
+    let mut storage = FileStorage::open(data_dir.path()).expect("failed to open storage");
+    let contents = storage.load().unwrap();
+    assert_eq!(contents.snapshot, Some(b"snapshot".to_vec()));
+    assert_eq!(contents.records, [b"four".to_vec()]);
+}
+
 async fn join(
     server_addr: &str,
     admin_client: &mut chat::Client,
@@ -658,10 +1680,27 @@ async fn stream_messages(
     num_messages: usize,
 ) -> impl futures::Future<Output = Vec<chat::client::StreamMessagesResponseEntry>> {
     client
-        .stream_messages(cid)
+        .stream_messages(cid, None)
         .await
         .expect("failed to stream messages")
         .take(num_messages)
         .map(|msg| msg.expect("failed to receive message"))
         .collect()
 }
+
+async fn wait_for_line(
+    stdout: &mut tokio::io::Lines<tokio::io::BufReader<tokio::process::ChildStdout>>,
+    expected: &str,
+) {
+    let wait = async {
+        while let Some(line) = stdout.next_line().await.unwrap() {
+            if line.contains(expected) {
+                return;
+            }
+        }
+        panic!("the client exited before printing {:?}", expected);
+    };
+    tokio::time::timeout(Duration::from_secs(10), wait)
+        .await
+        .unwrap_or_else(|_| panic!("the client did not print {:?}", expected));
+}
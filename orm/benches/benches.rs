@@ -0,0 +1,64 @@
+use orm::{Connection, Object};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tempfile::NamedTempFile;
+
+#[derive(Object)]
+struct Account {
+    owner: String,
+    balance: i64,
+    is_active: bool,
+}
+
+fn create_and_modify_100k(conn: &mut Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let accounts: Vec<_> = (0..100_000)
+        .map(|i| {
+            tx.create(Account {
+                owner: format!("owner{}", i),
+                balance: i,
+                is_active: false,
+            })
+            .unwrap()
+        })
+        .collect();
+
+    // NB: fetching flushes the new objects, so these are updates.
+    tx.select::<Account>().limit(1).fetch().unwrap();
+    for account in &accounts {
+        let mut account = account.borrow_mut();
+        account.balance += 100;
+        account.is_active = true;
+    }
+    tx.commit().unwrap();
+}
+
+fn bench_100k_objects(c: &mut Criterion) {
+    let mut group = c.benchmark_group("100k_objects");
+    group.sample_size(10);
+
+    group.bench_function("sqlite_file", |b| {
+        b.iter_batched(
+            || {
+                let path = NamedTempFile::new().unwrap().into_temp_path();
+                let conn = Connection::open_sqlite_file(&path).unwrap();
+                (path, conn)
+            },
+            |(_path, mut conn)| create_and_modify_100k(&mut conn),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.bench_function("native", |b| {
+        b.iter_batched(
+            || Connection::open_memory_native().unwrap(),
+            |mut conn| create_and_modify_100k(&mut conn),
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_100k_objects);
+criterion_main!(benches);
@@ -1,10 +1,457 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, Attribute, Data, DeriveInput, FieldsNamed, Ident, LitStr, Type};
+use quote::{format_ident, quote};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Attribute, Data, DataEnum, DeriveInput, Expr, Fields, FieldsNamed, Ident, Lit, LitStr, Token,
+    Type,
+};
 
-#[proc_macro_derive(Object, attributes(table_name, column_name))]
+#[proc_macro_derive(
+    Object,
+    attributes(table_name, column_name, on_delete, unique, index, orm)
+)]
 pub fn derive_object(input: TokenStream) -> TokenStream {
-    // TODO: your code here.
-    unimplemented!()
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
 }
 
+struct ObjectField {
+    ident: Ident,
+    ty: Type,
+    column_name: LitStr,
+    on_delete: Option<Ident>,
+    renamed_from: Option<LitStr>,
+    default: Option<Expr>,
+    /// Whether the field has `#[unique]` and `#[index]` respectively.
+    unique: bool,
+    index: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let vis = &input.vis;
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "Object can only be derived for structs",
+        ));
+    };
+    let fields = match &data.fields {
+        Fields::Named(fields) => parse_fields(fields)?,
+        Fields::Unit => vec![],
+        Fields::Unnamed(_) => {
+            return Err(syn::Error::new(
+                data.fields.span(),
+                "Object cannot be derived for tuple structs",
+            ))
+        }
+    };
+
+    let type_name = LitStr::new(&name.to_string(), name.span());
+    let table_name = match find_name_attr(&input.attrs, "table_name")? {
+        Some(table_name) => table_name,
+        None => type_name.clone(),
+    };
+    let columns_name = format_ident!("{}Columns", name);
+
+    let mut indexes = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        if field.unique {
+            indexes.push((vec![i], true));
+        }
+        if field.index {
+            indexes.push((vec![i], false));
+        }
+    }
+    for attr in &input.attrs {
+        let unique = if attr.path.is_ident("unique") {
+            true
+        } else if attr.path.is_ident("index") {
+            false
+        } else {
+            continue;
+        };
+        let idents = attr.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)?;
+        let positions = idents
+            .iter()
+            .map(|ident| {
+                let position = fields.iter().position(|field| field.ident == *ident);
+                position.ok_or_else(|| {
+                    syn::Error::new(ident.span(), format!("no field `{}` in `{}`", ident, name))
+                })
+            })
+            .collect::<syn::Result<Vec<_>>>()?;
+        if positions.is_empty() {
+            return Err(syn::Error::new(attr.span(), "expected a list of fields"));
+        }
+        indexes.push((positions, unique));
+    }
+    let indexes = indexes.iter().map(|(positions, unique)| {
+        quote!(::orm::object::Index {
+            fields: &[#(#positions),*],
+            unique: #unique,
+        })
+    });
+
+    let mut dropped_columns = vec![];
+    for option in parse_orm_attrs(&input.attrs)? {
+        match option.name.to_string().as_str() {
+            "drop_column" => dropped_columns.push(option.lit_str()?),
+            _ => return Err(option.unknown()),
+        }
+    }
+
+    let idents: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+    let attr_names: Vec<_> = idents
+        .iter()
+        .map(|ident| LitStr::new(&ident.to_string(), ident.span()))
+        .collect();
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let column_names: Vec<_> = fields.iter().map(|field| &field.column_name).collect();
+    let on_deletes = fields.iter().map(|field| match &field.on_delete {
+        Some(policy) => quote!(::orm::object::OnDelete::#policy),
+        None => quote!(::orm::object::OnDelete::Restrict),
+    });
+    let renames = fields.iter().map(|field| match &field.renamed_from {
+        Some(renamed_from) => quote!(Some(#renamed_from)),
+        None => quote!(None),
+    });
+    let defaults = fields.iter().map(|field| match &field.default {
+        Some(default) => {
+            let ty = &field.ty;
+            quote!(Some({
+                fn default() -> ::core::result::Result<
+                    ::orm::data::Value<'static>,
+                    ::orm::data::ConversionError,
+                > {
+                    let value: #ty = ::core::convert::Into::into(#default);
+                    let result = ::orm::data::ToValue::to_value(&value)
+                        .map(::orm::data::Value::into_owned);
+                    result
+                }
+                default as fn() -> ::core::result::Result<_, _>
+            }))
+        }
+        None => quote!(None),
+    });
+    let indices: Vec<_> = (0..fields.len()).collect();
+    let schema = quote!(<Self as ::orm::Object>::SCHEMA);
+    let reference_checks = fields
+        .iter()
+        .filter(|field| field.on_delete.is_some())
+        .map(|field| {
+            let ty = &field.ty;
+            let message = format!("`{}::{}` is not a reference", name, field.ident);
+            quote! {
+                const _: () = assert!(
+                    <#ty as ::orm::AsDataType>::TARGET.is_some(),
+                    #message,
+                );
+            }
+        });
+
+    Ok(quote! {
+        #[doc = concat!("Columns of [`", stringify!(#name), "`], used to build queries.")]
+        #[allow(dead_code)]
+        #vis struct #columns_name {
+            #(pub #idents: ::orm::query::Column<#name, #types>,)*
+        }
+
+        impl ::orm::Object for #name {
+            const SCHEMA: ::orm::object::Schema = ::orm::object::Schema {
+                type_name: #type_name,
+                table_name: #table_name,
+                fields: &[#(::orm::object::Field {
+                    attr_name: #attr_names,
+                    column_name: #column_names,
+                    data_type: <#types as ::orm::AsDataType>::DATA_TYPE,
+                    nullable: <#types as ::orm::AsDataType>::NULLABLE,
+                    reference: ::orm::object::Reference::of::<#types>(#on_deletes),
+                    renamed_from: #renames,
+                    default: #defaults,
+                },)*],
+                dropped_columns: &[#(#dropped_columns),*],
+                indexes: &[#(#indexes),*],
+            };
+
+            type Columns = #columns_name;
+            const COLUMNS: Self::Columns = #columns_name {
+                #(#idents: ::orm::query::Column::new(#indices),)*
+            };
+
+            fn to_row(&self) -> ::orm::Result<::orm::storage::Row<'_>> {
+                Ok(vec![#(
+                    ::orm::data::ToValue::to_value(&self.#idents)
+                        .map_err(|err| #schema.invalid_value(#indices, err))?
+                ),*])
+            }
+
+            fn from_row(row: ::orm::storage::Row<'static>) -> ::orm::Result<Self> {
+                let mut values = row.into_iter();
+                Ok(Self {
+                    #(#idents: ::orm::data::FromValue::from_value(values.next().unwrap())
+                        .map_err(|err| #schema.unexpected_value(#indices, err))?,)*
+                })
+            }
+        }
+
+        #(#reference_checks)*
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[proc_macro_derive(Enum, attributes(orm))]
+pub fn derive_enum(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_enum(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_enum(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let Data::Enum(DataEnum { variants, .. }) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "Enum can only be derived for enums",
+        ));
+    };
+
+    let mut is_integer = false;
+    for option in parse_orm_attrs(&input.attrs)? {
+        match option.name.to_string().as_str() {
+            "repr" => {
+                let repr = option.lit_str()?;
+                is_integer = match repr.value().as_str() {
+                    "text" => false,
+                    "integer" => true,
+                    _ => {
+                        return Err(syn::Error::new(
+                            repr.span(),
+                            "expected \"text\" or \"integer\"",
+                        ))
+                    }
+                };
+            }
+            _ => return Err(option.unknown()),
+        }
+    }
+
+    let mut idents = vec![];
+    let mut texts = vec![];
+    for variant in variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new(
+                variant.fields.span(),
+                "Enum can only be derived for enums without fields",
+            ));
+        }
+        let mut text = LitStr::new(&variant.ident.to_string(), variant.ident.span());
+        for option in parse_orm_attrs(&variant.attrs)? {
+            match option.name.to_string().as_str() {
+                "rename" if !is_integer => text = option.lit_str()?,
+                _ => return Err(option.unknown()),
+            }
+        }
+        idents.push(&variant.ident);
+        texts.push(text);
+    }
+
+    let unknown = format!("unknown variant of {}: {{:?}}", name);
+    let unknown = quote! {
+        ::core::result::Result::Err(::orm::data::ConversionError(::std::format!(#unknown, x)))
+    };
+    let (data_type, to_value, from_value) = if is_integer {
+        (
+            quote!(Int64),
+            quote! {
+                match value {
+                    #(#name::#idents => ::orm::data::Value::Int64(#name::#idents as i64),)*
+                }
+            },
+            quote! {
+                let x: i64 = ::orm::data::FromValue::from_value(value)?;
+                match x {
+                    #(x if x == Self::#idents as i64 => Ok(Self::#idents),)*
+                    x => #unknown,
+                }
+            },
+        )
+    } else {
+        (
+            quote!(String),
+            quote! {
+                match value {
+                    #(#name::#idents => ::orm::data::Value::String(
+                        ::std::borrow::Cow::Borrowed(#texts),
+                    ),)*
+                }
+            },
+            quote! {
+                let text: ::std::string::String = ::orm::data::FromValue::from_value(value)?;
+                match text.as_str() {
+                    #(#texts => Ok(Self::#idents),)*
+                    x => #unknown,
+                }
+            },
+        )
+    };
+
+    Ok(quote! {
+        impl ::orm::AsDataType for #name {
+            const DATA_TYPE: ::orm::data::DataType = ::orm::data::DataType::#data_type;
+        }
+
+        impl ::core::convert::From<&#name> for ::orm::data::Value<'_> {
+            fn from(value: &#name) -> Self {
+                #to_value
+            }
+        }
+
+        impl ::core::convert::From<#name> for ::orm::data::Value<'_> {
+            fn from(value: #name) -> Self {
+                (&value).into()
+            }
+        }
+
+        impl ::orm::data::ToValue for #name {
+            fn to_value(
+                &self,
+            ) -> ::core::result::Result<::orm::data::Value<'_>, ::orm::data::ConversionError> {
+                Ok(self.into())
+            }
+        }
+
+        impl ::orm::data::FromValue for #name {
+            fn from_value(
+                value: ::orm::data::Value<'static>,
+            ) -> ::core::result::Result<Self, ::orm::data::ConversionError> {
+                #from_value
+            }
+        }
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn parse_fields(fields: &FieldsNamed) -> syn::Result<Vec<ObjectField>> {
+    fields
+        .named
+        .iter()
+        .map(|field| {
+            let ident = field.ident.clone().unwrap();
+            let column_name = match find_name_attr(&field.attrs, "column_name")? {
+                Some(column_name) => column_name,
+                None => LitStr::new(&ident.to_string(), ident.span()),
+            };
+            let on_delete = find_name_attr(&field.attrs, "on_delete")?
+                .map(|policy| {
+                    let variant = match policy.value().as_str() {
+                        "restrict" => "Restrict",
+                        "cascade" => "Cascade",
+                        "nullify" => "Nullify",
+                        _ => {
+                            return Err(syn::Error::new(
+                                policy.span(),
+                                "expected \"restrict\", \"cascade\" or \"nullify\"",
+                            ))
+                        }
+                    };
+                    Ok(Ident::new(variant, policy.span()))
+                })
+                .transpose()?;
+
+            let unique = find_flag_attr(&field.attrs, "unique")?;
+            let index = find_flag_attr(&field.attrs, "index")?;
+
+            let mut renamed_from = None;
+            let mut default = None;
+            for option in parse_orm_attrs(&field.attrs)? {
+                match option.name.to_string().as_str() {
+                    "renamed_from" => renamed_from = Some(option.lit_str()?),
+                    "default" => default = Some(option.value),
+                    _ => return Err(option.unknown()),
+                }
+            }
+
+            Ok(ObjectField {
+                ident,
+                ty: field.ty.clone(),
+                column_name,
+                on_delete,
+                renamed_from,
+                default,
+                unique,
+                index,
+            })
+        })
+        .collect()
+}
+
+/// Find an attribute like `#[table_name("name")]` and return its argument.
+fn find_name_attr(attrs: &[Attribute], name: &str) -> syn::Result<Option<LitStr>> {
+    attrs
+        .iter()
+        .find(|attr| attr.path.is_ident(name))
+        .map(|attr| attr.parse_args::<LitStr>())
+        .transpose()
+}
+
+/// Whether there is an attribute like `#[unique]`, which takes no arguments.
+fn find_flag_attr(attrs: &[Attribute], name: &str) -> syn::Result<bool> {
+    match attrs.iter().find(|attr| attr.path.is_ident(name)) {
+        Some(attr) if !attr.tokens.is_empty() => Err(syn::Error::new(
+            attr.tokens.span(),
+            format!("`#[{}]` takes no arguments on a field", name),
+        )),
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+/// An option of the `#[orm(name = value, ...)]` attribute.
+struct OrmOption {
+    name: Ident,
+    value: Expr,
+}
+
+impl OrmOption {
+    fn lit_str(&self) -> syn::Result<LitStr> {
+        match &self.value {
+            Expr::Lit(expr) => match &expr.lit {
+                Lit::Str(lit) => Ok(lit.clone()),
+                _ => Err(syn::Error::new(expr.span(), "expected a string literal")),
+            },
+            value => Err(syn::Error::new(value.span(), "expected a string literal")),
+        }
+    }
+
+    fn unknown(&self) -> syn::Error {
+        syn::Error::new(self.name.span(), format!("unknown option `{}`", self.name))
+    }
+}
+
+impl Parse for OrmOption {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let value = input.parse()?;
+        Ok(Self { name, value })
+    }
+}
+
+fn parse_orm_attrs(attrs: &[Attribute]) -> syn::Result<Vec<OrmOption>> {
+    let mut options = vec![];
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("orm")) {
+        options.extend(attr.parse_args_with(Punctuated::<OrmOption, Token![,]>::parse_terminated)?);
+    }
+    Ok(options)
+}
@@ -0,0 +1,147 @@
+use crate::{data::Value, object::Schema, storage::Row, Enum, Object, ObjectId};
+
+use std::{fmt::Write, sync::Arc, time::SystemTime};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Change of an object made by a committed transaction, see `Connection::on_commit`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub type_name: &'static str,
+    pub table_name: &'static str,
+    pub object_id: ObjectId,
+    pub kind: ChangeKind,
+    /// Changed fields, or all of them for created and deleted objects.
+    pub fields: Vec<FieldChange>,
+}
+
+#[derive(Enum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    #[orm(rename = "create")]
+    Create,
+    #[orm(rename = "update")]
+    Update,
+    #[orm(rename = "delete")]
+    Delete,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub attr_name: &'static str,
+    /// Value before the transaction, `None` for created objects.
+    pub old_value: Option<Value<'static>>,
+    /// Value after the transaction, `None` for deleted objects.
+    pub new_value: Option<Value<'static>>,
+}
+
+impl Change {
+    /// Compare an object with the row it was loaded from, if any.
+    pub(crate) fn new(
+        schema: &'static Schema,
+        object_id: ObjectId,
+        original: Option<&Row<'static>>,
+        current: Option<Row>,
+    ) -> Option<Self> {
+        let kind = match (original, &current) {
+            (None, None) => return None,
+            (None, Some(_)) => ChangeKind::Create,
+            (Some(_), Some(_)) => ChangeKind::Update,
+            (Some(_), None) => ChangeKind::Delete,
+        };
+
+        let fields: Vec<_> = schema
+            .fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| FieldChange {
+                attr_name: field.attr_name,
+                old_value: original.map(|row| row[i].clone()),
+                new_value: current.as_ref().map(|row| row[i].clone().into_owned()),
+            })
+            .filter(|change| kind != ChangeKind::Update || change.old_value != change.new_value)
+            .collect();
+        if kind == ChangeKind::Update && fields.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            type_name: schema.type_name,
+            table_name: schema.table_name,
+            object_id,
+            kind,
+            fields,
+        })
+    }
+
+    fn audit_entries(&self, committed_at: SystemTime) -> impl Iterator<Item = AuditEntry> + '_ {
+        self.fields.iter().map(move |field| AuditEntry {
+            type_name: self.type_name.to_string(),
+            object_id: self.object_id.into_i64(),
+            kind: self.kind,
+            attr_name: field.attr_name.to_string(),
+            old_value: field.old_value.as_ref().and_then(audit_text),
+            new_value: field.new_value.as_ref().and_then(audit_text),
+            committed_at,
+        })
+    }
+}
+
+/// Text of a value in the audit log, `None` for NULL.
+fn audit_text(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(s) => s.to_string(),
+        Value::Bytes(bytes) => bytes.iter().fold("x".to_string(), |mut text, byte| {
+            let _ = write!(text, "{:02x}", byte);
+            text
+        }),
+        Value::Int64(x) => x.to_string(),
+        Value::Float64(x) => x.to_string(),
+        Value::Bool(x) => x.to_string(),
+        Value::Null => return None,
+    };
+    Some(text)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Changed field of an object, written to the audit log in the same transaction as the
+/// change, see `Connection::enable_audit_log`.
+#[derive(Object, Clone, Debug, PartialEq)]
+#[table_name("orm_audit_log")]
+pub struct AuditEntry {
+    pub type_name: String,
+    pub object_id: i64,
+    pub kind: ChangeKind,
+    pub attr_name: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub committed_at: SystemTime,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) type Observer = Arc<dyn Fn(&[Change]) + Send + Sync>;
+
+/// What a connection does with the changes of its transactions.
+#[derive(Clone, Default)]
+pub(crate) struct CommitHooks {
+    pub observers: Vec<Observer>,
+    pub audit_log: bool,
+}
+
+impl CommitHooks {
+    pub fn is_tracking(&self) -> bool {
+        self.audit_log || !self.observers.is_empty()
+    }
+
+    pub fn audit_entries(&self, changes: &[Change]) -> Vec<AuditEntry> {
+        if !self.audit_log {
+            return vec![];
+        }
+        let committed_at = SystemTime::now();
+        changes
+            .iter()
+            .flat_map(|change| change.audit_entries(committed_at))
+            .collect()
+    }
+}
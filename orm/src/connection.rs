@@ -1,16 +1,38 @@
-use crate::{storage::StorageTransaction, Result, Transaction};
+use crate::{
+    audit::{Change, CommitHooks},
+    memory::MemoryConnection,
+    storage::{SqliteTransaction, StorageTransaction},
+    Error, Result, RetryPolicy, Transaction,
+};
 
-use std::path::Path;
+use std::{path::Path, sync::Arc, thread};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-trait StorageConnection {
+pub(crate) trait StorageConnection: Send {
     fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>>;
+    fn try_clone(&self) -> Result<Box<dyn StorageConnection>>;
 }
 
 impl StorageConnection for rusqlite::Connection {
     fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
-        Ok(Box::new(self.transaction()?))
+        Ok(Box::new(SqliteTransaction::new(self.transaction()?)))
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn StorageConnection>> {
+        let path: String = self.query_row(
+            "SELECT file FROM pragma_database_list WHERE name = 'main'",
+            [],
+            |row| row.get(0),
+        )?;
+        if path.is_empty() {
+            return Err(Error::Storage(
+                "an in-memory SQLite database cannot be shared".into(),
+            ));
+        }
+        let conn = rusqlite::Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        Ok(Box::new(conn))
     }
 }
 
@@ -18,22 +40,99 @@ impl StorageConnection for rusqlite::Connection {
 
 pub struct Connection {
     inner: Box<dyn StorageConnection>,
+    hooks: CommitHooks,
 }
 
 impl Connection {
     pub fn open_sqlite_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_sqlite(rusqlite::Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::with_sqlite(rusqlite::Connection::open_in_memory()?)
+    }
+
+    /// Open a database kept in memory by this crate rather than by SQLite. Its transactions
+    /// are snapshot-isolated and fail with `Error::LockConflict` on write-write conflicts.
+    pub fn open_memory_native() -> Result<Self> {
         Ok(Self {
-            inner: Box::new(rusqlite::Connection::open(path)?),
+            inner: Box::new(MemoryConnection::new()),
+            hooks: CommitHooks::default(),
         })
     }
 
-    pub fn open_in_memory() -> Result<Self> {
+    /// Open another connection to the same database. Not supported by SQLite in-memory
+    /// databases, which are private to their connection. The new connection keeps the
+    /// observers and the audit log of this one.
+    pub fn try_clone(&self) -> Result<Self> {
         Ok(Self {
-            inner: Box::new(rusqlite::Connection::open_in_memory()?),
+            inner: self.inner.try_clone()?,
+            hooks: self.hooks.clone(),
+        })
+    }
+
+    /// Call `observer` after each successful commit with the objects the transaction has
+    /// created, updated and deleted. Rows removed by `OnDelete::Cascade` are not reported.
+    pub fn on_commit(&mut self, observer: impl Fn(&[Change]) + Send + Sync + 'static) {
+        self.hooks.observers.push(Arc::new(observer));
+    }
+
+    /// Write an `AuditEntry` for every changed field along with the change itself, so that
+    /// the log can be read with `tx.select::<AuditEntry>()`.
+    pub fn enable_audit_log(&mut self) {
+        self.hooks.audit_log = true;
+    }
+
+    fn with_sqlite(conn: rusqlite::Connection) -> Result<Self> {
+        // NB: references are declared as foreign keys, which SQLite ignores by default.
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        Ok(Self {
+            inner: Box::new(conn),
+            hooks: CommitHooks::default(),
         })
     }
 
     pub fn new_transaction(&mut self) -> Result<Transaction<'_>> {
-        Ok(Transaction::new(self.inner.new_transaction()?))
+        Ok(Transaction::new(self.inner.new_transaction()?, &self.hooks))
+    }
+
+    /// Run `f` in a new transaction and commit it. If either fails with `Error::LockConflict`,
+    /// the transaction is rolled back and run again after a backoff, so `f` may be called
+    /// several times. Other errors are returned as is.
+    ///
+    /// Fails with `Error::RetriesExhausted` once `policy.max_attempts` attempts have conflicted.
+    pub fn transact<R>(
+        &mut self,
+        policy: &RetryPolicy,
+        mut f: impl FnMut(&Transaction<'_>) -> Result<R>,
+    ) -> Result<R> {
+        let max_attempts = policy.max_attempts.max(1);
+        for attempt in 1..=max_attempts {
+            match self.try_transact(&mut f) {
+                Err(Error::LockConflict) => {}
+                res => return res,
+            }
+            if attempt < max_attempts {
+                thread::sleep(policy.backoff(attempt));
+            }
+        }
+        Err(Error::RetriesExhausted {
+            attempts: max_attempts,
+        })
+    }
+
+    fn try_transact<R>(&mut self, f: &mut impl FnMut(&Transaction<'_>) -> Result<R>) -> Result<R> {
+        let tx = self.new_transaction()?;
+        match f(&tx) {
+            Ok(value) => {
+                tx.commit()?;
+                Ok(value)
+            }
+            Err(err) => {
+                // NB: the error of the closure is more telling than a failed rollback.
+                let _ = tx.rollback();
+                Err(err)
+            }
+        }
     }
 }
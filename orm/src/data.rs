@@ -1,10 +1,34 @@
-use std::{borrow::Cow, fmt};
+use crate::object::Schema;
+
+use std::{
+    borrow::Cow,
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub struct ObjectId(i64);
 
+impl ObjectId {
+    pub fn into_i64(self) -> i64 {
+        self.0
+    }
+}
+
+impl From<i64> for ObjectId {
+    fn from(id: i64) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -14,16 +38,293 @@ pub enum DataType {
     Int64,
     Float64,
     Bool,
+    /// Id of another object, see `Ref`.
+    Ref,
+    /// Point in time, stored as microseconds since the Unix epoch.
+    Timestamp,
+    /// Any serializable value, stored as JSON text, see `Json`.
+    Json,
+}
+
+impl DataType {
+    pub(crate) fn sql_type(self) -> &'static str {
+        match self {
+            Self::String => "TEXT",
+            Self::Bytes => "BLOB",
+            Self::Int64 => "BIGINT",
+            Self::Float64 => "REAL",
+            Self::Bool => "TINYINT",
+            Self::Ref => "BIGINT",
+            Self::Timestamp => "TIMESTAMP",
+            Self::Json => "TEXT",
+        }
+    }
+}
+
+/// Types of object fields, each stored in a column of the corresponding `DataType`.
+pub trait AsDataType {
+    const DATA_TYPE: DataType;
+    /// Whether the column may hold NULL.
+    const NULLABLE: bool = false;
+    /// Schema of the objects that values of this type refer to, if any.
+    const TARGET: Option<fn() -> &'static Schema> = None;
+}
+
+impl AsDataType for String {
+    const DATA_TYPE: DataType = DataType::String;
+}
+
+impl AsDataType for Vec<u8> {
+    const DATA_TYPE: DataType = DataType::Bytes;
+}
+
+impl AsDataType for i64 {
+    const DATA_TYPE: DataType = DataType::Int64;
+}
+
+impl AsDataType for f64 {
+    const DATA_TYPE: DataType = DataType::Float64;
+}
+
+impl AsDataType for bool {
+    const DATA_TYPE: DataType = DataType::Bool;
+}
+
+impl AsDataType for SystemTime {
+    const DATA_TYPE: DataType = DataType::Timestamp;
+}
+
+/// A nullable column, `None` being stored as NULL.
+impl<T: AsDataType> AsDataType for Option<T> {
+    const DATA_TYPE: DataType = T::DATA_TYPE;
+    const NULLABLE: bool = true;
+    const TARGET: Option<fn() -> &'static Schema> = T::TARGET;
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value<'a> {
     String(Cow<'a, str>),
     Bytes(Cow<'a, [u8]>),
     Int64(i64),
     Float64(f64),
     Bool(bool),
+    Null,
+}
+
+impl Value<'_> {
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Self::String(s) => Value::String(Cow::Owned(s.into_owned())),
+            Self::Bytes(b) => Value::Bytes(Cow::Owned(b.into_owned())),
+            Self::Int64(x) => Value::Int64(x),
+            Self::Float64(x) => Value::Float64(x),
+            Self::Bool(x) => Value::Bool(x),
+            Self::Null => Value::Null,
+        }
+    }
+}
+
+impl<'a> From<&'a String> for Value<'a> {
+    fn from(s: &'a String) -> Self {
+        Self::String(Cow::Borrowed(s))
+    }
+}
+
+impl From<String> for Value<'_> {
+    fn from(s: String) -> Self {
+        Self::String(Cow::Owned(s))
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for Value<'a> {
+    fn from(bytes: &'a Vec<u8>) -> Self {
+        Self::Bytes(Cow::Borrowed(bytes))
+    }
 }
 
-// TODO: you might want to add some code here.
+impl From<Vec<u8>> for Value<'_> {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(Cow::Owned(bytes))
+    }
+}
+
+macro_rules! impl_copy_value {
+    ($type:ty, $variant:ident) => {
+        impl From<&$type> for Value<'_> {
+            fn from(x: &$type) -> Self {
+                Self::$variant(*x)
+            }
+        }
+
+        impl From<$type> for Value<'_> {
+            fn from(x: $type) -> Self {
+                Self::$variant(x)
+            }
+        }
+    };
+}
+
+impl_copy_value!(i64, Int64);
+impl_copy_value!(f64, Float64);
+impl_copy_value!(bool, Bool);
+
+impl From<&SystemTime> for Value<'_> {
+    fn from(time: &SystemTime) -> Self {
+        Self::Int64(timestamp_micros(*time))
+    }
+}
+
+impl From<SystemTime> for Value<'_> {
+    fn from(time: SystemTime) -> Self {
+        (&time).into()
+    }
+}
+
+impl<'a, T> From<&'a Option<T>> for Value<'a>
+where
+    Value<'a>: From<&'a T>,
+{
+    fn from(value: &'a Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => Self::Null,
+        }
+    }
+}
+
+impl<'a, T> From<Option<T>> for Value<'a>
+where
+    Value<'a>: From<T>,
+{
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => Self::Null,
+        }
+    }
+}
+
+// NB: the storage only returns values of the types the schema asks for, so a mismatch here
+// is a bug in the storage rather than in the data.
+macro_rules! impl_from_value {
+    ($type:ty, $variant:ident, $convert:expr) => {
+        impl From<Value<'_>> for $type {
+            fn from(value: Value<'_>) -> Self {
+                match value {
+                    Value::$variant(x) => $convert(x),
+                    value => panic!(
+                        "expected a value of type {:?}, got {:?}",
+                        DataType::$variant,
+                        value,
+                    ),
+                }
+            }
+        }
+    };
+}
+
+impl_from_value!(String, String, Cow::into_owned);
+impl_from_value!(Vec<u8>, Bytes, Cow::into_owned);
+impl_from_value!(i64, Int64, std::convert::identity);
+impl_from_value!(f64, Float64, std::convert::identity);
+impl_from_value!(bool, Bool, std::convert::identity);
+
+impl From<Value<'_>> for SystemTime {
+    fn from(value: Value<'_>) -> Self {
+        from_timestamp_micros(value.into())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A value that can't be converted to or from a field, e.g. an unknown variant of an enum.
+/// The message describes the value, and the field is added by `Object::from_row` and
+/// `Object::to_row`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionError(pub String);
+
+/// Conversion of a value read from the storage into a field, used by `#[derive(Object)]`.
+///
+/// The value is always of the `DataType` of the field, or NULL if the field is nullable,
+/// but its contents may still be invalid.
+pub trait FromValue: Sized {
+    fn from_value(value: Value<'static>) -> Result<Self, ConversionError>;
+}
+
+/// Conversion of a field into the value stored in the storage, used by `#[derive(Object)]`.
+pub trait ToValue {
+    fn to_value(&self) -> Result<Value<'_>, ConversionError>;
+}
+
+macro_rules! impl_field_conversions {
+    ($($type:ty, $variant:ident, $convert:expr;)*) => {
+        $(
+            impl FromValue for $type {
+                fn from_value(value: Value<'static>) -> Result<Self, ConversionError> {
+                    match value {
+                        Value::$variant(x) => Ok($convert(x)),
+                        value => Err(ConversionError(format!("{:?}", value))),
+                    }
+                }
+            }
+
+            impl ToValue for $type {
+                fn to_value(&self) -> Result<Value<'_>, ConversionError> {
+                    Ok(self.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_field_conversions! {
+    String, String, Cow::into_owned;
+    Vec<u8>, Bytes, Cow::into_owned;
+    i64, Int64, std::convert::identity;
+    f64, Float64, std::convert::identity;
+    bool, Bool, std::convert::identity;
+    SystemTime, Int64, from_timestamp_micros;
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value<'static>) -> Result<Self, ConversionError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Result<Value<'_>, ConversionError> {
+        match self {
+            Some(value) => value.to_value(),
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+// NB: microseconds cover about 292 thousand years either way, which is enough for any
+// timestamp while keeping the column an integer.
+fn timestamp_micros(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros().try_into().unwrap_or(i64::MAX),
+        Err(err) => {
+            let before = err.duration().as_micros().try_into().unwrap_or(i64::MAX);
+            -before
+        }
+    }
+}
+
+fn from_timestamp_micros(micros: i64) -> SystemTime {
+    let duration = Duration::from_micros(micros.unsigned_abs());
+    if micros >= 0 {
+        UNIX_EPOCH + duration
+    } else {
+        UNIX_EPOCH - duration
+    }
+}
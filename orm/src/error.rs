@@ -1,4 +1,7 @@
-use crate::{data::DataType, object::Schema, ObjectId};
+use crate::{
+    data::{DataType, Value},
+    ObjectId,
+};
 
 use thiserror::Error;
 
@@ -9,19 +12,38 @@ pub enum Error {
     #[error(transparent)]
     NotFound(Box<NotFoundError>),
     #[error(transparent)]
+    ObjectBorrowed(Box<ObjectBorrowedError>),
+    #[error(transparent)]
     UnexpectedType(Box<UnexpectedTypeError>),
     #[error(transparent)]
+    InvalidValue(Box<InvalidValueError>),
+    #[error(transparent)]
     MissingColumn(Box<MissingColumnError>),
+    #[error(transparent)]
+    DanglingReference(Box<DanglingReferenceError>),
+    #[error(transparent)]
+    UniqueViolation(Box<UniqueViolationError>),
     #[error("database is locked")]
     LockConflict,
+    /// Returned by `Connection::transact` once every attempt has hit a lock conflict.
+    #[error("database is still locked after {attempts} attempts")]
+    RetriesExhausted { attempts: u32 },
     #[error("storage error: {0}")]
     Storage(#[source] Box<dyn std::error::Error>),
 }
 
 impl From<rusqlite::Error> for Error {
     fn from(err: rusqlite::Error) -> Self {
-        // TODO: your code here.
-        unimplemented!()
+        match err {
+            rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error {
+                    code: rusqlite::ErrorCode::DatabaseBusy,
+                    ..
+                },
+                _,
+            ) => Self::LockConflict,
+            err => Self::Storage(Box::new(err)),
+        }
     }
 }
 
@@ -36,6 +58,18 @@ pub struct NotFoundError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// An object that is borrowed while the transaction has to read or restore it, e.g. a new
+/// object that is mutably borrowed when a query has to write it to the storage, or an object
+/// borrowed while a savepoint is rolled back.
+#[derive(Error, Debug)]
+#[error("object is borrowed: type '{type_name}', id {object_id}")]
+pub struct ObjectBorrowedError {
+    pub object_id: ObjectId,
+    pub type_name: &'static str,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Error, Debug)]
 #[error(
     "invalid type for {type_name}::{attr_name}: expected equivalent of {expected_type:?}, \
@@ -52,6 +86,22 @@ pub struct UnexpectedTypeError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// A field that can't be written to the storage, e.g. a `Json` field that fails to serialize.
+#[derive(Error, Debug)]
+#[error(
+    "invalid value of {type_name}::{attr_name}: {reason} \
+    (table: {table_name}, column: {column_name})"
+)]
+pub struct InvalidValueError {
+    pub type_name: &'static str,
+    pub attr_name: &'static str,
+    pub table_name: &'static str,
+    pub column_name: &'static str,
+    pub reason: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Error, Debug)]
 #[error(
     "missing a column for {type_name}::{attr_name} \
@@ -66,4 +116,34 @@ pub struct MissingColumnError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// A reference to an object that does not exist, found on commit. Either the object has been
+/// deleted while its `OnDelete` policy is `Restrict`, or it has never existed.
+#[derive(Error, Debug)]
+#[error(
+    "reference to a missing object in table {table_name}, column {column_name}, id {object_id} \
+    (references table: {target_table})"
+)]
+pub struct DanglingReferenceError {
+    pub table_name: String,
+    pub column_name: String,
+    pub object_id: ObjectId,
+    pub target_table: String,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// An object with the same values of fields declared `#[unique]` as another object.
+#[derive(Error, Debug)]
+#[error("duplicate value for {type_name}::{attr_names:?}: {values:?} (table: {table_name})")]
+pub struct UniqueViolationError {
+    pub type_name: &'static str,
+    pub table_name: &'static str,
+    /// Fields of the unique constraint, more than one for a composite one.
+    pub attr_names: Vec<&'static str>,
+    /// Duplicate values, in the order of `attr_names`.
+    pub values: Vec<Value<'static>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,75 @@
+use crate::data::{AsDataType, ConversionError, DataType, FromValue, ToValue, Value};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::{
+    borrow::Cow,
+    ops::{Deref, DerefMut},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A field of any serializable type, stored as JSON text.
+///
+/// The value is serialized on every write and deserialized when the object is loaded.
+/// Stored text that does not match `T` is reported as `Error::UnexpectedType`, and a value
+/// that fails to serialize as `Error::InvalidValue`. NB: JSON columns can't be used in
+/// query filters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Json<T>(pub T);
+
+impl<T> Json<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Json<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Json<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> From<T> for Json<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<T> AsDataType for Json<T> {
+    const DATA_TYPE: DataType = DataType::Json;
+}
+
+impl<T: Serialize> ToValue for Json<T> {
+    fn to_value(&self) -> Result<Value<'_>, ConversionError> {
+        match serde_json::to_string(&self.0) {
+            Ok(text) => Ok(Value::String(Cow::Owned(text))),
+            Err(err) => Err(ConversionError(format!(
+                "JSON that fails to serialize: {}",
+                err
+            ))),
+        }
+    }
+}
+
+impl<T: DeserializeOwned> FromValue for Json<T> {
+    fn from_value(value: Value<'static>) -> Result<Self, ConversionError> {
+        let Value::String(text) = value else {
+            return Err(ConversionError(format!("{:?}", value)));
+        };
+        match serde_json::from_str(&text) {
+            Ok(value) => Ok(Self(value)),
+            Err(err) => Err(ConversionError(format!("invalid JSON: {}", err))),
+        }
+    }
+}
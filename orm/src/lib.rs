@@ -1,17 +1,33 @@
 #![forbid(unsafe_code)]
 
+// NB: makes the code generated by `orm-derive` work inside this crate.
+extern crate self as orm;
+
+mod audit;
 mod connection;
 mod error;
+#[cfg(feature = "json")]
+mod json;
+mod memory;
+mod migration;
+mod relation;
+mod retry;
 mod transaction;
 
 pub mod data;
 pub mod object;
+pub mod query;
 pub mod storage;
 
+pub use audit::{AuditEntry, Change, ChangeKind, FieldChange};
 pub use connection::Connection;
-pub use data::ObjectId;
+pub use data::{AsDataType, ObjectId};
 pub use error::{Error, Result};
+#[cfg(feature = "json")]
+pub use json::Json;
 pub use object::Object;
-pub use transaction::{ObjectState, Transaction, Tx};
+pub use relation::Ref;
+pub use retry::RetryPolicy;
+pub use transaction::{ObjectState, Savepoint, Transaction, Tx};
 
-pub use orm_derive::Object;
+pub use orm_derive::{Enum, Object};
@@ -0,0 +1,865 @@
+use crate::{
+    connection::StorageConnection,
+    data::{DataType, Value},
+    error::{
+        DanglingReferenceError, Error, MissingColumnError, NotFoundError, Result,
+        UnexpectedTypeError, UniqueViolationError,
+    },
+    object::{Field, Index, OnDelete, Schema},
+    query::{CompareOp, Expr, Query},
+    storage::{Affinity, Row, StorageTransaction, TableColumn},
+    ObjectId,
+};
+
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Tables kept in memory, shared by all the connections to them.
+///
+/// Every transaction works with a snapshot of the tables taken when it starts, so it never
+/// sees changes committed after that. Writing a row locks it until the transaction ends, and
+/// altering a table locks the whole table. A transaction fails with `LockConflict` instead of
+/// waiting for a lock, as well as when it writes a row that has changed since its snapshot.
+#[derive(Default)]
+struct Database {
+    /// Number of commits so far.
+    version: u64,
+    next_tx_id: u64,
+    tables: HashMap<String, Arc<Table>>,
+    next_ids: HashMap<String, i64>,
+    locks: HashMap<String, TableLocks>,
+}
+
+#[derive(Clone)]
+struct Table {
+    columns: Vec<ColumnDef>,
+    indexes: Vec<IndexDef>,
+    rows: BTreeMap<i64, StoredRow>,
+    /// Version of the last commit that changed the table.
+    version: u64,
+    /// Version of the last commit that changed the columns.
+    schema_version: u64,
+}
+
+#[derive(Clone)]
+struct ColumnDef {
+    name: String,
+    data_type: DataType,
+    /// Referenced table and what happens once a referenced row is deleted.
+    reference: Option<(String, OnDelete)>,
+}
+
+/// NB: rows are always scanned, so only unique indexes matter beyond their names.
+#[derive(Clone)]
+struct IndexDef {
+    name: String,
+    columns: Vec<String>,
+    unique: bool,
+    /// What `UniqueViolationError` reports.
+    type_name: &'static str,
+    table_name: &'static str,
+    attr_names: Vec<&'static str>,
+}
+
+#[derive(Clone)]
+struct StoredRow {
+    /// NB: booleans are stored as integers, as SQLite does.
+    values: Vec<Value<'static>>,
+    /// Version of the commit that has written the row.
+    version: u64,
+}
+
+/// Owners of the locks on a table.
+#[derive(Default)]
+struct TableLocks {
+    table: Option<u64>,
+    rows: HashMap<i64, u64>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub(crate) struct MemoryConnection {
+    db: Arc<Mutex<Database>>,
+}
+
+impl MemoryConnection {
+    pub fn new() -> Self {
+        Self { db: Arc::default() }
+    }
+}
+
+impl StorageConnection for MemoryConnection {
+    fn new_transaction(&mut self) -> Result<Box<dyn StorageTransaction + '_>> {
+        let mut db = self.db.lock().unwrap();
+        db.next_tx_id += 1;
+        Ok(Box::new(MemoryTransaction {
+            db: self.db.clone(),
+            id: db.next_tx_id,
+            snapshot: db.version,
+            state: RefCell::new(TxState {
+                tables: db.tables.clone(),
+                ..TxState::default()
+            }),
+        }))
+    }
+
+    fn try_clone(&self) -> Result<Box<dyn StorageConnection>> {
+        Ok(Box::new(Self {
+            db: self.db.clone(),
+        }))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct MemoryTransaction {
+    db: Arc<Mutex<Database>>,
+    id: u64,
+    /// Version of the database the transaction sees.
+    snapshot: u64,
+    state: RefCell<TxState>,
+}
+
+#[derive(Default)]
+struct TxState {
+    /// The snapshot with the changes made by the transaction.
+    tables: HashMap<String, Arc<Table>>,
+    /// Rows inserted, updated or deleted by the transaction.
+    written: BTreeSet<(String, i64)>,
+    /// Tables created or altered by the transaction.
+    altered: HashSet<String>,
+    /// Tables the transaction holds any locks on.
+    locked: HashSet<String>,
+    /// Changes made before each savepoint, innermost last. Locks are kept on rollback.
+    savepoints: Vec<(String, SavedState)>,
+    is_finished: bool,
+}
+
+struct SavedState {
+    tables: HashMap<String, Arc<Table>>,
+    written: BTreeSet<(String, i64)>,
+    altered: HashSet<String>,
+}
+
+impl TxState {
+    fn table(&self, name: &str) -> Result<&Table> {
+        match self.tables.get(name) {
+            Some(table) => Ok(table),
+            None => Err(Error::Storage(format!("no such table: {}", name).into())),
+        }
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize> {
+        match self.savepoints.iter().rposition(|(saved, _)| saved == name) {
+            Some(index) => Ok(index),
+            None => Err(Error::Storage(
+                format!("no such savepoint: {}", name).into(),
+            )),
+        }
+    }
+
+    fn table_mut(&mut self, name: &str) -> Result<&mut Table> {
+        match self.tables.get_mut(name) {
+            Some(table) => Ok(Arc::make_mut(table)),
+            None => Err(Error::Storage(format!("no such table: {}", name).into())),
+        }
+    }
+}
+
+impl MemoryTransaction {
+    /// Lock a row before writing it.
+    fn lock_row(&self, db: &mut Database, state: &mut TxState, table: &str, id: i64) -> Result<()> {
+        let locks = db.locks.entry(table.to_string()).or_default();
+        if locks.table.is_some_and(|owner| owner != self.id) {
+            return Err(Error::LockConflict);
+        }
+        match locks.rows.get(&id) {
+            Some(&owner) if owner == self.id => return Ok(()),
+            Some(_) => return Err(Error::LockConflict),
+            None => {}
+        }
+
+        // NB: writing a row that has changed since the snapshot would lose the change.
+        if let Some(shared) = db.tables.get(table) {
+            let is_in_snapshot = state.table(table)?.rows.contains_key(&id);
+            let is_changed = match shared.rows.get(&id) {
+                Some(row) => row.version > self.snapshot,
+                None => is_in_snapshot,
+            };
+            if is_changed || shared.schema_version > self.snapshot {
+                return Err(Error::LockConflict);
+            }
+        }
+
+        locks.rows.insert(id, self.id);
+        state.locked.insert(table.to_string());
+        Ok(())
+    }
+
+    /// Lock a table before creating or altering it.
+    fn lock_table(&self, db: &mut Database, state: &mut TxState, table: &str) -> Result<()> {
+        let locks = db.locks.entry(table.to_string()).or_default();
+        let is_locked = locks.table.is_some_and(|owner| owner != self.id)
+            || locks.rows.values().any(|&owner| owner != self.id);
+        let is_changed = match (db.tables.get(table), state.tables.get(table)) {
+            (Some(shared), Some(_)) => shared.version > self.snapshot,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if is_locked || is_changed {
+            return Err(Error::LockConflict);
+        }
+
+        locks.table = Some(self.id);
+        state.locked.insert(table.to_string());
+        state.altered.insert(table.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, db: &mut Database, state: &mut TxState, table: &str, id: i64) -> Result<()> {
+        self.lock_row(db, state, table, id)?;
+        state.written.insert((table.to_string(), id));
+        if state.table_mut(table)?.rows.remove(&id).is_none() {
+            return Ok(());
+        }
+
+        let referrers: Vec<_> = state
+            .tables
+            .iter()
+            .flat_map(|(name, referrer)| {
+                referrer
+                    .columns
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(column, def)| match &def.reference {
+                        Some((target, on_delete)) if target == table => {
+                            Some((name.clone(), column, *on_delete))
+                        }
+                        _ => None,
+                    })
+            })
+            .collect();
+
+        // NB: broken references of `OnDelete::Restrict` are reported on commit, as the
+        // referring objects may still be deleted.
+        for (name, column, on_delete) in referrers {
+            let ids: Vec<_> = state.tables[&name]
+                .rows
+                .iter()
+                .filter(|(_, row)| row.values[column] == Value::Int64(id))
+                .map(|(&id, _)| id)
+                .collect();
+            for referrer_id in ids {
+                match on_delete {
+                    OnDelete::Restrict => {}
+                    OnDelete::Cascade => self.delete(db, state, &name, referrer_id)?,
+                    OnDelete::Nullify => {
+                        self.lock_row(db, state, &name, referrer_id)?;
+                        state.written.insert((name.clone(), referrer_id));
+                        let row = state.table_mut(&name)?.rows.get_mut(&referrer_id).unwrap();
+                        row.values[column] = Value::Null;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_references(&self, state: &TxState) -> Result<()> {
+        let mut names: Vec<_> = state.tables.keys().collect();
+        names.sort();
+        for name in names {
+            let table = &state.tables[name];
+            for (column, def) in table.columns.iter().enumerate() {
+                let Some((target, _)) = &def.reference else {
+                    continue;
+                };
+                for (&id, row) in &table.rows {
+                    let Value::Int64(target_id) = row.values[column] else {
+                        continue;
+                    };
+                    let exists = state
+                        .tables
+                        .get(target)
+                        .is_some_and(|target| target.rows.contains_key(&target_id));
+                    if !exists {
+                        return Err(Error::DanglingReference(Box::new(DanglingReferenceError {
+                            table_name: name.clone(),
+                            column_name: def.name.clone(),
+                            object_id: id.into(),
+                            target_table: target.clone(),
+                        })));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn release_locks(&self, db: &mut Database, state: &mut TxState) {
+        for name in state.locked.drain() {
+            let locks = db.locks.get_mut(&name).unwrap();
+            if locks.table == Some(self.id) {
+                locks.table = None;
+            }
+            locks.rows.retain(|_, owner| *owner != self.id);
+            if locks.table.is_none() && locks.rows.is_empty() {
+                db.locks.remove(&name);
+            }
+        }
+        state.is_finished = true;
+    }
+}
+
+impl StorageTransaction for MemoryTransaction {
+    fn table_exists(&self, table: &str) -> Result<bool> {
+        Ok(self.state.borrow().tables.contains_key(table))
+    }
+
+    fn create_table(&self, schema: &Schema) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        self.lock_table(&mut db, state, schema.table_name)?;
+        let table = Table {
+            columns: schema.fields.iter().map(ColumnDef::new).collect(),
+            indexes: schema
+                .indexes
+                .iter()
+                .map(|index| IndexDef::new(schema, index))
+                .collect(),
+            rows: BTreeMap::new(),
+            version: 0,
+            schema_version: 0,
+        };
+        state
+            .tables
+            .insert(schema.table_name.to_string(), Arc::new(table));
+        Ok(())
+    }
+
+    fn index_exists(&self, name: &str) -> Result<bool> {
+        let state = self.state.borrow();
+        let exists = state
+            .tables
+            .values()
+            .any(|table| table.indexes.iter().any(|index| index.name == name));
+        Ok(exists)
+    }
+
+    fn create_index(&self, schema: &Schema, index: &Index) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        self.lock_table(&mut db, state, schema.table_name)?;
+        let table = state.table_mut(schema.table_name)?;
+        let index = IndexDef::new(schema, index);
+        for (&id, row) in &table.rows {
+            table.check_unique(&index, id, &row.values, table.rows.iter())?;
+        }
+        table.indexes.push(index);
+        Ok(())
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<TableColumn>> {
+        let state = self.state.borrow();
+        let id = TableColumn {
+            name: "id".to_string(),
+            affinity: Affinity::Integer,
+        };
+        let columns = state.table(table)?.columns.iter().map(|def| TableColumn {
+            name: def.name.clone(),
+            affinity: Affinity::of(def.data_type.sql_type()),
+        });
+        Ok([id].into_iter().chain(columns).collect())
+    }
+
+    fn add_column(&self, schema: &Schema, field: &Field, default: &Value) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        self.lock_table(&mut db, state, schema.table_name)?;
+        let table = state.table_mut(schema.table_name)?;
+        table.columns.push(ColumnDef::new(field));
+        for row in table.rows.values_mut() {
+            row.values.push(to_stored(default));
+        }
+        Ok(())
+    }
+
+    fn rename_column(&self, schema: &Schema, from: &str, to: &str) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        self.lock_table(&mut db, state, schema.table_name)?;
+        let table = state.table_mut(schema.table_name)?;
+        if let Some(def) = table.columns.iter_mut().find(|def| def.name == from) {
+            def.name = to.to_string();
+        }
+        for index in &mut table.indexes {
+            for column in &mut index.columns {
+                if column == from {
+                    *column = to.to_string();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn drop_column(&self, schema: &Schema, column: &str) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        self.lock_table(&mut db, state, schema.table_name)?;
+        let table = state.table_mut(schema.table_name)?;
+        if let Some(index) = table.columns.iter().position(|def| def.name == column) {
+            table.columns.remove(index);
+            for row in table.rows.values_mut() {
+                row.values.remove(index);
+            }
+        }
+        table
+            .indexes
+            .retain(|index| !index.columns.iter().any(|name| name == column));
+        Ok(())
+    }
+
+    fn reserve_id(&self, schema: &Schema) -> Result<ObjectId> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        state.table(schema.table_name)?;
+
+        let next_id = db
+            .next_ids
+            .entry(schema.table_name.to_string())
+            .or_insert(1);
+        let id = *next_id;
+        *next_id += 1;
+        self.lock_row(&mut db, state, schema.table_name, id)?;
+        Ok(id.into())
+    }
+
+    fn insert_rows(&self, schema: &Schema, rows: &[(ObjectId, Row)]) -> Result<()> {
+        let state = &mut *self.state.borrow_mut();
+        let table = state.table_mut(schema.table_name)?;
+        let columns = column_indices(schema, table)?;
+        for (i, (id, row)) in rows.iter().enumerate() {
+            let id = id.into_i64();
+            let mut values = vec![Value::Null; table.columns.len()];
+            for (&column, value) in columns.iter().zip(row) {
+                values[column] = to_stored(value);
+            }
+            for index in &table.indexes {
+                if let Err(err) = table.check_unique(index, id, &values, table.rows.iter()) {
+                    for (id, _) in &rows[..i] {
+                        table.rows.remove(&id.into_i64());
+                    }
+                    return Err(err);
+                }
+            }
+            table.rows.insert(id, StoredRow { values, version: 0 });
+        }
+        for (id, _) in rows {
+            state
+                .written
+                .insert((schema.table_name.to_string(), id.into_i64()));
+        }
+        Ok(())
+    }
+
+    fn update_rows(&self, schema: &Schema, rows: &[(ObjectId, Row)]) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        let columns = column_indices(schema, state.table(schema.table_name)?)?;
+        for (id, row) in rows {
+            let id = id.into_i64();
+            self.lock_row(&mut db, state, schema.table_name, id)?;
+
+            let table = state.table_mut(schema.table_name)?;
+            let Some(stored) = table.rows.get(&id) else {
+                continue;
+            };
+            let mut values = stored.values.clone();
+            for (&column, value) in columns.iter().zip(row) {
+                values[column] = to_stored(value);
+            }
+            for index in &table.indexes {
+                table.check_unique(index, id, &values, table.rows.iter())?;
+            }
+            table.rows.get_mut(&id).unwrap().values = values;
+            state.written.insert((schema.table_name.to_string(), id));
+        }
+        Ok(())
+    }
+
+    fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
+        let state = self.state.borrow();
+        let table = state.table(schema.table_name)?;
+        let columns = column_indices(schema, table)?;
+        match table.rows.get(&id.into_i64()) {
+            Some(row) => read_row(schema, &columns, row),
+            None => Err(Error::NotFound(Box::new(NotFoundError {
+                object_id: id,
+                type_name: schema.type_name,
+            }))),
+        }
+    }
+
+    fn select_rows(&self, schema: &Schema, query: &Query) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let state = self.state.borrow();
+        let table = state.table(schema.table_name)?;
+        let columns = column_indices(schema, table)?;
+
+        let mut rows: Vec<_> = table
+            .rows
+            .iter()
+            .filter(|(id, _)| !query.exclude.contains(&(**id).into()))
+            .filter(|(_, row)| match &query.filter {
+                Some(filter) => eval(filter, &columns, row) == Some(true),
+                None => true,
+            })
+            .collect();
+        // NB: the sort is stable, so rows are ordered by id otherwise.
+        rows.sort_by(|(_, lhs), (_, rhs)| {
+            query
+                .order
+                .iter()
+                .map(|&(field, descending)| {
+                    let ordering =
+                        compare(&lhs.values[columns[field]], &rhs.values[columns[field]]);
+                    if descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let limit = query.limit.map_or(usize::MAX, |limit| limit as usize);
+        rows.into_iter()
+            .skip(query.offset as usize)
+            .take(limit)
+            .map(|(&id, row)| Ok((id.into(), read_row(schema, &columns, row)?)))
+            .collect()
+    }
+
+    fn delete_rows(&self, schema: &Schema, ids: &[ObjectId]) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        for id in ids {
+            self.delete(&mut db, state, schema.table_name, id.into_i64())?;
+        }
+        Ok(())
+    }
+
+    fn commit(&self) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        let state = &mut *self.state.borrow_mut();
+        if !state.written.is_empty() || !state.altered.is_empty() {
+            self.check_references(state)?;
+        }
+        // NB: rows committed by others since the snapshot may duplicate the written ones.
+        for (name, id) in &state.written {
+            let (Some(shared), Some(row)) = (db.tables.get(name), state.tables[name].rows.get(id))
+            else {
+                continue;
+            };
+            let others = shared
+                .rows
+                .iter()
+                .filter(|(&other_id, _)| !state.written.contains(&(name.clone(), other_id)));
+            for index in &shared.indexes {
+                shared.check_unique(index, *id, &row.values, others.clone())?;
+            }
+        }
+
+        db.version += 1;
+        let version = db.version;
+        for name in &state.altered {
+            let mut table = state.tables.remove(name).unwrap();
+            let table_mut = Arc::make_mut(&mut table);
+            table_mut.version = version;
+            table_mut.schema_version = version;
+            db.tables.insert(name.clone(), table);
+        }
+        for (name, id) in &state.written {
+            let table_mut = match db.tables.get_mut(name) {
+                Some(table) => Arc::make_mut(table),
+                None => continue,
+            };
+            table_mut.version = version;
+            if state.altered.contains(name) {
+                if let Some(row) = table_mut.rows.get_mut(id) {
+                    row.version = version;
+                }
+                continue;
+            }
+            match state.tables[name].rows.get(id) {
+                Some(row) => {
+                    let row = StoredRow {
+                        values: row.values.clone(),
+                        version,
+                    };
+                    table_mut.rows.insert(*id, row);
+                }
+                None => {
+                    table_mut.rows.remove(id);
+                }
+            }
+        }
+
+        self.release_locks(&mut db, state);
+        Ok(())
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        let state = &mut *self.state.borrow_mut();
+        let saved = SavedState {
+            tables: state.tables.clone(),
+            written: state.written.clone(),
+            altered: state.altered.clone(),
+        };
+        state.savepoints.push((name.to_string(), saved));
+        Ok(())
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        let state = &mut *self.state.borrow_mut();
+        let index = state.find_savepoint(name)?;
+        state.savepoints.truncate(index);
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        let state = &mut *self.state.borrow_mut();
+        let index = state.find_savepoint(name)?;
+        let (_, saved) = state.savepoints.drain(index..).next().unwrap();
+        state.tables = saved.tables;
+        state.written = saved.written;
+        state.altered = saved.altered;
+        Ok(())
+    }
+
+    fn rollback(&self) -> Result<()> {
+        let mut db = self.db.lock().unwrap();
+        self.release_locks(&mut db, &mut self.state.borrow_mut());
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransaction {
+    fn drop(&mut self) {
+        if !self.state.get_mut().is_finished {
+            let _ = self.rollback();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl Table {
+    /// Check that no other row has the same values of a unique index as the given one.
+    fn check_unique<'r>(
+        &self,
+        index: &IndexDef,
+        id: i64,
+        values: &[Value<'static>],
+        rows: impl Iterator<Item = (&'r i64, &'r StoredRow)>,
+    ) -> Result<()> {
+        if !index.unique {
+            return Ok(());
+        }
+        let columns: Vec<_> = index
+            .columns
+            .iter()
+            .filter_map(|name| self.columns.iter().position(|def| def.name == *name))
+            .collect();
+        // NB: as in SQL, NULLs are distinct from each other.
+        if columns.iter().any(|&column| values[column] == Value::Null) {
+            return Ok(());
+        }
+        let is_duplicate = |row: &StoredRow| {
+            columns
+                .iter()
+                .all(|&column| compare(&row.values[column], &values[column]).is_eq())
+        };
+        match rows
+            .filter(|(&other_id, _)| other_id != id)
+            .find(|(_, row)| is_duplicate(row))
+        {
+            Some(_) => Err(Error::UniqueViolation(Box::new(UniqueViolationError {
+                type_name: index.type_name,
+                table_name: index.table_name,
+                attr_names: index.attr_names.clone(),
+                values: columns
+                    .iter()
+                    .map(|&column| values[column].clone())
+                    .collect(),
+            }))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl IndexDef {
+    fn new(schema: &Schema, index: &Index) -> Self {
+        Self {
+            name: index.name(schema),
+            columns: index
+                .fields
+                .iter()
+                .map(|&field| schema.fields[field].column_name.to_string())
+                .collect(),
+            unique: index.unique,
+            type_name: schema.type_name,
+            table_name: schema.table_name,
+            attr_names: index.attr_names(schema),
+        }
+    }
+}
+
+impl ColumnDef {
+    fn new(field: &Field) -> Self {
+        Self {
+            name: field.column_name.to_string(),
+            data_type: field.data_type,
+            reference: field.reference.as_ref().map(|reference| {
+                let target = (reference.target)().table_name.to_string();
+                (target, reference.on_delete)
+            }),
+        }
+    }
+}
+
+/// Positions of the columns of the fields in the table.
+fn column_indices(schema: &Schema, table: &Table) -> Result<Vec<usize>> {
+    schema
+        .fields
+        .iter()
+        .map(|field| {
+            let index = table
+                .columns
+                .iter()
+                .position(|def| def.name.eq_ignore_ascii_case(field.column_name));
+            index.ok_or_else(|| {
+                Error::MissingColumn(Box::new(MissingColumnError {
+                    type_name: schema.type_name,
+                    attr_name: field.attr_name,
+                    table_name: schema.table_name,
+                    column_name: field.column_name,
+                }))
+            })
+        })
+        .collect()
+}
+
+fn to_stored(value: &Value) -> Value<'static> {
+    match value {
+        Value::Bool(x) => Value::Int64(*x as i64),
+        value => value.clone().into_owned(),
+    }
+}
+
+/// Read the values of the fields, converting them the same way SQLite does.
+fn read_row(schema: &Schema, columns: &[usize], row: &StoredRow) -> Result<Row<'static>> {
+    schema
+        .fields
+        .iter()
+        .zip(columns)
+        .map(|(field, &column)| {
+            let value = &row.values[column];
+            let converted = match (field.data_type, value) {
+                (_, Value::Null) if field.nullable => Some(Value::Null),
+                (DataType::String | DataType::Json, Value::String(_))
+                | (DataType::Bytes, Value::Bytes(_))
+                | (DataType::Int64 | DataType::Ref | DataType::Timestamp, Value::Int64(_))
+                | (DataType::Float64, Value::Float64(_)) => Some(value.clone()),
+                (DataType::Float64, Value::Int64(x)) => Some(Value::Float64(*x as f64)),
+                (DataType::Bool, Value::Int64(x)) => Some(Value::Bool(*x != 0)),
+                _ => None,
+            };
+            converted.ok_or_else(|| {
+                Error::UnexpectedType(Box::new(UnexpectedTypeError {
+                    type_name: schema.type_name,
+                    attr_name: field.attr_name,
+                    table_name: schema.table_name,
+                    column_name: field.column_name,
+                    expected_type: field.data_type,
+                    got_type: storage_class(value).to_string(),
+                }))
+            })
+        })
+        .collect()
+}
+
+/// Name of the type of a stored value, as reported by SQLite.
+fn storage_class(value: &Value) -> &'static str {
+    match value {
+        Value::String(_) => "Text",
+        Value::Bytes(_) => "Blob",
+        Value::Int64(_) | Value::Bool(_) => "Integer",
+        Value::Float64(_) => "Real",
+        Value::Null => "Null",
+    }
+}
+
+/// Evaluate a filter the way SQL does, `None` standing for an unknown result.
+fn eval(expr: &Expr, columns: &[usize], row: &StoredRow) -> Option<bool> {
+    match expr {
+        Expr::Compare { field, op, value } => {
+            let lhs = &row.values[columns[*field]];
+            match (op, value) {
+                (CompareOp::Eq, Value::Null) => Some(*lhs == Value::Null),
+                (CompareOp::Ne, Value::Null) => Some(*lhs != Value::Null),
+                _ if *lhs == Value::Null || *value == Value::Null => None,
+                _ => {
+                    let ordering = compare(lhs, &to_stored(value));
+                    Some(match op {
+                        CompareOp::Eq => ordering.is_eq(),
+                        CompareOp::Ne => ordering.is_ne(),
+                        CompareOp::Lt => ordering.is_lt(),
+                        CompareOp::Le => ordering.is_le(),
+                        CompareOp::Gt => ordering.is_gt(),
+                        CompareOp::Ge => ordering.is_ge(),
+                    })
+                }
+            }
+        }
+        Expr::And(lhs, rhs) => match (eval(lhs, columns, row), eval(rhs, columns, row)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None,
+        },
+        Expr::Or(lhs, rhs) => match (eval(lhs, columns, row), eval(rhs, columns, row)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None,
+        },
+        Expr::Not(inner) => eval(inner, columns, row).map(|result| !result),
+    }
+}
+
+/// Order of stored values: nulls, numbers, strings and then bytes, as in SQLite.
+fn compare(lhs: &Value, rhs: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Int64(_) | Value::Float64(_) | Value::Bool(_) => 1,
+            Value::String(_) => 2,
+            Value::Bytes(_) => 3,
+        }
+    }
+
+    match (lhs, rhs) {
+        (Value::Int64(lhs), Value::Int64(rhs)) => lhs.cmp(rhs),
+        (Value::Int64(lhs), Value::Float64(rhs)) => (*lhs as f64).total_cmp(rhs),
+        (Value::Float64(lhs), Value::Int64(rhs)) => lhs.total_cmp(&(*rhs as f64)),
+        (Value::Float64(lhs), Value::Float64(rhs)) => lhs.total_cmp(rhs),
+        (Value::String(lhs), Value::String(rhs)) => lhs.cmp(rhs),
+        (Value::Bytes(lhs), Value::Bytes(rhs)) => lhs.cmp(rhs),
+        _ => rank(lhs).cmp(&rank(rhs)),
+    }
+}
@@ -0,0 +1,108 @@
+use crate::{
+    data::Value,
+    error::{Error, MissingColumnError, Result, UnexpectedTypeError},
+    object::{Field, Index, Schema},
+    storage::{StorageTransaction, TableColumn},
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+enum Step<'a> {
+    Add(&'a Field, Value<'static>),
+    Rename(&'a str, &'a Field),
+    Drop(&'a str),
+    Index(&'a Index),
+}
+
+/// Bring an existing table in line with the schema.
+///
+/// A field without a column is filled with its default, or takes over the column it has been
+/// renamed from. Columns listed as dropped are removed, and missing indexes are created.
+/// Everything is checked before the table is altered, so a column of an incompatible type or
+/// a field that cannot be filled leaves the table intact.
+pub(crate) fn migrate_table(storage: &dyn StorageTransaction, schema: &Schema) -> Result<()> {
+    let columns = storage.table_columns(schema.table_name)?;
+    let find = |name: &str| {
+        columns
+            .iter()
+            .find(|column| column.name.eq_ignore_ascii_case(name))
+    };
+
+    let mut steps = vec![];
+    for (index, field) in schema.fields.iter().enumerate() {
+        let column = match find(field.column_name) {
+            Some(column) => column,
+            None => match field.renamed_from.and_then(find) {
+                Some(column) => {
+                    steps.push(Step::Rename(&column.name, field));
+                    column
+                }
+                None => match field
+                    .default_value()
+                    .map_err(|err| schema.invalid_value(index, err))?
+                {
+                    Some(default) => {
+                        steps.push(Step::Add(field, default));
+                        continue;
+                    }
+                    None => return Err(missing_column(schema, field)),
+                },
+            },
+        };
+        check_type(schema, field, column)?;
+    }
+
+    for &name in schema.dropped_columns {
+        // NB: a column may be listed as dropped once it has been renamed or reused.
+        let is_kept = schema
+            .fields
+            .iter()
+            .any(|field| field.column_name.eq_ignore_ascii_case(name))
+            || steps.iter().any(
+                |step| matches!(step, Step::Rename(from, _) if from.eq_ignore_ascii_case(name)),
+            );
+        match find(name) {
+            Some(column) if !is_kept => steps.push(Step::Drop(&column.name)),
+            _ => {}
+        }
+    }
+
+    for index in schema.indexes {
+        if !storage.index_exists(&index.name(schema))? {
+            steps.push(Step::Index(index));
+        }
+    }
+
+    for step in steps {
+        match step {
+            Step::Add(field, default) => storage.add_column(schema, field, &default)?,
+            Step::Rename(from, field) => storage.rename_column(schema, from, field.column_name)?,
+            Step::Drop(column) => storage.drop_column(schema, column)?,
+            Step::Index(index) => storage.create_index(schema, index)?,
+        }
+    }
+    Ok(())
+}
+
+fn check_type(schema: &Schema, field: &Field, column: &TableColumn) -> Result<()> {
+    if column.affinity.accepts(field.data_type) {
+        return Ok(());
+    }
+    Err(Error::UnexpectedType(Box::new(UnexpectedTypeError {
+        type_name: schema.type_name,
+        attr_name: field.attr_name,
+        table_name: schema.table_name,
+        column_name: field.column_name,
+        expected_type: field.data_type,
+        got_type: column.affinity.name().to_string(),
+    })))
+}
+
+fn missing_column(schema: &Schema, field: &Field) -> Error {
+    Error::MissingColumn(Box::new(MissingColumnError {
+        type_name: schema.type_name,
+        attr_name: field.attr_name,
+        table_name: schema.table_name,
+        column_name: field.column_name,
+    }))
+}
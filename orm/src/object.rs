@@ -1,17 +1,198 @@
-use crate::{data::DataType, storage::Row};
+use crate::{
+    data::{AsDataType, ConversionError, DataType, Value},
+    error::{Error, InvalidValueError, Result, UnexpectedTypeError},
+    storage::Row,
+};
 
 use std::any::Any;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub trait Object: Any {
-    // TODO: your code here.
+/// A type stored in its own table, one object per row. Implemented by `#[derive(Object)]`.
+pub trait Object: Any + Sized {
+    const SCHEMA: Schema;
+
+    /// Typed handles of the fields, used to build queries, see `Transaction::select`.
+    type Columns;
+    const COLUMNS: Self::Columns;
+
+    /// Values of the fields, in the order of `SCHEMA.fields`. Fails with
+    /// `Error::InvalidValue` if a field can't be stored.
+    fn to_row(&self) -> Result<Row<'_>>;
+    /// Build an object from the values of its fields. The storage guarantees that they have
+    /// the types given by the schema, and a value that still doesn't fit the field, e.g. an
+    /// unknown variant of an enum, fails with `Error::UnexpectedType`.
+    fn from_row(row: Row<'static>) -> Result<Self>;
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Debug)]
 pub struct Schema {
-    // TODO: your code here.
+    pub type_name: &'static str,
+    pub table_name: &'static str,
+    pub fields: &'static [Field],
+    /// Columns that are dropped from an existing table, see `#[orm(drop_column = "...")]`.
+    pub dropped_columns: &'static [&'static str],
+    /// Indexes declared by `#[unique]` and `#[index]`.
+    pub indexes: &'static [Index],
+}
+
+#[derive(Debug)]
+pub struct Field {
+    pub attr_name: &'static str,
+    pub column_name: &'static str,
+    pub data_type: DataType,
+    /// Whether the column may hold NULL, as for `Option` fields and references.
+    pub nullable: bool,
+    pub reference: Option<Reference>,
+    /// Column that is renamed to `column_name` in an existing table.
+    pub renamed_from: Option<&'static str>,
+    /// Value of the column added to an existing table, see `#[orm(default = ...)]`.
+    pub default: Option<fn() -> std::result::Result<Value<'static>, ConversionError>>,
+}
+
+impl Field {
+    /// Value the column is filled with when it is added to an existing table. Nullable fields,
+    /// including references, are null unless declared otherwise, other fields need a declared
+    /// default.
+    pub(crate) fn default_value(
+        &self,
+    ) -> std::result::Result<Option<Value<'static>>, ConversionError> {
+        match self.default {
+            Some(default) => default().map(Some),
+            None if self.nullable => Ok(Some(Value::Null)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Target of a field of type `Ref`.
+#[derive(Debug)]
+pub struct Reference {
+    pub target: fn() -> &'static Schema,
+    pub on_delete: OnDelete,
+}
+
+/// What happens to a reference once the object it refers to is deleted. The policy is
+/// enforced when the transaction is committed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OnDelete {
+    /// The commit fails, unless the referring object is deleted as well.
+    #[default]
+    Restrict,
+    /// The referring object is deleted too.
+    Cascade,
+    /// The reference is set to null.
+    Nullify,
+}
+
+impl Reference {
+    /// Reference of a field of type `V`, if it is a `Ref`.
+    #[doc(hidden)]
+    pub const fn of<V: AsDataType>(on_delete: OnDelete) -> Option<Self> {
+        match V::TARGET {
+            Some(target) => Some(Self { target, on_delete }),
+            None => None,
+        }
+    }
+}
+
+/// An index over one or more fields, which may also be a unique constraint.
+#[derive(Debug)]
+pub struct Index {
+    /// Positions of the fields in `Schema::fields`.
+    pub fields: &'static [usize],
+    pub unique: bool,
+}
+
+impl Index {
+    /// Name of the index in the storage, unique per table and set of columns.
+    pub(crate) fn name(&self, schema: &Schema) -> String {
+        let mut name = schema.table_name.to_string();
+        for &field in self.fields {
+            name.push('_');
+            name.push_str(schema.fields[field].column_name);
+        }
+        name.push_str(if self.unique { "_unique" } else { "_index" });
+        name
+    }
+
+    pub(crate) fn attr_names(&self, schema: &Schema) -> Vec<&'static str> {
+        self.fields
+            .iter()
+            .map(|&field| schema.fields[field].attr_name)
+            .collect()
+    }
+}
+
+impl Schema {
+    pub(crate) fn find_column(&self, column_name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|field| field.column_name == column_name)
+    }
+
+    /// Error for a value of the field at `index` that is read from the storage but can't be
+    /// converted into the field.
+    #[doc(hidden)]
+    pub fn unexpected_value(&self, index: usize, err: ConversionError) -> Error {
+        let field = &self.fields[index];
+        Error::UnexpectedType(Box::new(UnexpectedTypeError {
+            type_name: self.type_name,
+            attr_name: field.attr_name,
+            table_name: self.table_name,
+            column_name: field.column_name,
+            expected_type: field.data_type,
+            got_type: err.0,
+        }))
+    }
+
+    /// Error for the field at `index` that can't be converted into a value to store.
+    #[doc(hidden)]
+    pub fn invalid_value(&self, index: usize, err: ConversionError) -> Error {
+        let field = &self.fields[index];
+        Error::InvalidValue(Box::new(InvalidValueError {
+            type_name: self.type_name,
+            attr_name: field.attr_name,
+            table_name: self.table_name,
+            column_name: field.column_name,
+            reason: err.0,
+        }))
+    }
 }
 
-// TODO: your code here.
+////////////////////////////////////////////////////////////////////////////////
+
+/// Object-safe part of `Object`, used by the transaction to handle objects of any type.
+pub(crate) trait Store: Any {
+    fn schema(&self) -> &'static Schema;
+    fn to_row(&self) -> Result<Row<'_>>;
+    /// Replace the object with the one stored in the row.
+    fn load_row(&mut self, row: Row<'static>) -> Result<()>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Object> Store for T {
+    fn schema(&self) -> &'static Schema {
+        &T::SCHEMA
+    }
+
+    fn to_row(&self) -> Result<Row<'_>> {
+        Object::to_row(self)
+    }
+
+    fn load_row(&mut self, row: Row<'static>) -> Result<()> {
+        *self = T::from_row(row)?;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
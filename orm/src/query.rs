@@ -0,0 +1,249 @@
+use crate::{data::Value, object::Object, ObjectId, Result, Transaction, Tx};
+
+use std::{marker::PhantomData, ops};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A field of `T` holding values of type `V`. Handles of all the fields of an object are
+/// generated by `#[derive(Object)]`, see `Object::COLUMNS`.
+pub struct Column<T, V> {
+    index: usize,
+    marker: PhantomData<fn() -> (T, V)>,
+}
+
+impl<T, V> Column<T, V> {
+    /// Refer to the field described by `T::SCHEMA.fields[index]`.
+    #[doc(hidden)]
+    pub const fn new(index: usize) -> Self {
+        Self {
+            index,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn asc(self) -> Order<T> {
+        Order::new(self.index, false)
+    }
+
+    pub fn desc(self) -> Order<T> {
+        Order::new(self.index, true)
+    }
+}
+
+impl<T, V: Into<Value<'static>>> Column<T, V> {
+    pub fn eq(self, value: impl Into<V>) -> Filter<T> {
+        self.compare(CompareOp::Eq, value)
+    }
+
+    pub fn ne(self, value: impl Into<V>) -> Filter<T> {
+        self.compare(CompareOp::Ne, value)
+    }
+
+    pub fn lt(self, value: impl Into<V>) -> Filter<T> {
+        self.compare(CompareOp::Lt, value)
+    }
+
+    pub fn le(self, value: impl Into<V>) -> Filter<T> {
+        self.compare(CompareOp::Le, value)
+    }
+
+    pub fn gt(self, value: impl Into<V>) -> Filter<T> {
+        self.compare(CompareOp::Gt, value)
+    }
+
+    pub fn ge(self, value: impl Into<V>) -> Filter<T> {
+        self.compare(CompareOp::Ge, value)
+    }
+
+    fn compare(self, op: CompareOp, value: impl Into<V>) -> Filter<T> {
+        Filter::new(Expr::Compare {
+            field: self.index,
+            op,
+            value: value.into().into(),
+        })
+    }
+}
+
+impl<T, V> Clone for Column<T, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, V> Copy for Column<T, V> {}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A condition on the fields of `T`. Conditions are combined with `&`, `|` and `!`.
+pub struct Filter<T> {
+    expr: Expr,
+    marker: PhantomData<fn() -> T>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Expr {
+    Compare {
+        field: usize,
+        op: CompareOp,
+        value: Value<'static>,
+    },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub(crate) fn sql(self) -> &'static str {
+        match self {
+            Self::Eq => "=",
+            Self::Ne => "<>",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+}
+
+impl<T> Filter<T> {
+    fn new(expr: Expr) -> Self {
+        Self {
+            expr,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn and(self, other: Self) -> Self {
+        Self::new(Expr::And(Box::new(self.expr), Box::new(other.expr)))
+    }
+
+    pub fn or(self, other: Self) -> Self {
+        Self::new(Expr::Or(Box::new(self.expr), Box::new(other.expr)))
+    }
+}
+
+impl<T> ops::BitAnd for Filter<T> {
+    type Output = Self;
+
+    fn bitand(self, other: Self) -> Self {
+        self.and(other)
+    }
+}
+
+impl<T> ops::BitOr for Filter<T> {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.or(other)
+    }
+}
+
+impl<T> ops::Not for Filter<T> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self::new(Expr::Not(Box::new(self.expr)))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Sort key of a query. A column by itself sorts in ascending order.
+pub struct Order<T> {
+    field: usize,
+    descending: bool,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Order<T> {
+    fn new(field: usize, descending: bool) -> Self {
+        Self {
+            field,
+            descending,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, V> From<Column<T, V>> for Order<T> {
+    fn from(column: Column<T, V>) -> Self {
+        column.asc()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Untyped description of a query, as passed to the storage. Fields are referred to by
+/// their index in the schema.
+#[derive(Debug, Default)]
+pub(crate) struct Query {
+    pub filter: Option<Expr>,
+    /// Pairs of a field and whether the order is descending.
+    pub order: Vec<(usize, bool)>,
+    pub limit: Option<u64>,
+    pub offset: u64,
+    /// Objects removed in the transaction, which are still in the storage until commit.
+    pub exclude: Vec<ObjectId>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Query builder returned by `Transaction::select`.
+pub struct Select<'t, 'a, T> {
+    tx: &'t Transaction<'a>,
+    query: Query,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<'t, 'a, T: Object> Select<'t, 'a, T> {
+    pub(crate) fn new(tx: &'t Transaction<'a>) -> Self {
+        Self {
+            tx,
+            query: Query::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Only select objects matching the filter. Several filters must all match.
+    pub fn filter(mut self, filter: impl FnOnce(&T::Columns) -> Filter<T>) -> Self {
+        let expr = filter(&T::COLUMNS).expr;
+        self.query.filter = Some(match self.query.filter.take() {
+            Some(prev) => Expr::And(Box::new(prev), Box::new(expr)),
+            None => expr,
+        });
+        self
+    }
+
+    /// Sort objects by a field. Calls add keys of decreasing priority.
+    pub fn order_by<O: Into<Order<T>>>(mut self, order: impl FnOnce(&T::Columns) -> O) -> Self {
+        let order = order(&T::COLUMNS).into();
+        self.query.order.push((order.field, order.descending));
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.query.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.query.offset = offset;
+        self
+    }
+
+    /// Run the query. See `Transaction::select` for how it interacts with the objects
+    /// of the transaction.
+    pub fn fetch(self) -> Result<Vec<Tx<'t, T>>> {
+        self.tx.fetch(self.query)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    data::{AsDataType, ConversionError, DataType, FromValue, ToValue, Value},
+    object::{Object, Schema},
+    ObjectId, Result, Transaction, Tx,
+};
+
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A reference to an object of type `T`, stored as its id.
+///
+/// The referenced object is only loaded by `get`. What happens to the reference once the
+/// object is deleted is declared on the field by `#[on_delete("...")]`, see `OnDelete`.
+/// A reference is null if it has been nullified or never set.
+pub struct Ref<T> {
+    id: Option<ObjectId>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Ref<T> {
+    pub fn new(id: ObjectId) -> Self {
+        Self {
+            id: Some(id),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn null() -> Self {
+        Self {
+            id: None,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> Option<ObjectId> {
+        self.id
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.id.is_none()
+    }
+}
+
+impl<T: Object> Ref<T> {
+    /// Load the referenced object, unless the reference is null.
+    pub fn get<'t>(&self, tx: &'t Transaction<'_>) -> Result<Option<Tx<'t, T>>> {
+        self.id.map(|id| tx.get(id)).transpose()
+    }
+}
+
+impl<T: Object> From<&Tx<'_, T>> for Ref<T> {
+    fn from(tx: &Tx<'_, T>) -> Self {
+        Self::new(tx.id())
+    }
+}
+
+impl<T> Default for Ref<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Ref<T> {}
+
+impl<T> PartialEq for Ref<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Ref<T> {}
+
+impl<T> Hash for Ref<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for Ref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "Ref({})", id),
+            None => f.write_str("Ref(null)"),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn schema_of<T: Object>() -> &'static Schema {
+    &T::SCHEMA
+}
+
+impl<T: Object> AsDataType for Ref<T> {
+    const DATA_TYPE: DataType = DataType::Ref;
+    const NULLABLE: bool = true;
+    const TARGET: Option<fn() -> &'static Schema> = Some(schema_of::<T>);
+}
+
+impl<T> From<&Ref<T>> for Value<'_> {
+    fn from(r: &Ref<T>) -> Self {
+        match r.id {
+            Some(id) => Value::Int64(id.into_i64()),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T> From<Ref<T>> for Value<'_> {
+    fn from(r: Ref<T>) -> Self {
+        (&r).into()
+    }
+}
+
+impl<T> From<Value<'_>> for Ref<T> {
+    fn from(value: Value<'_>) -> Self {
+        match value {
+            Value::Int64(id) => Self::new(id.into()),
+            Value::Null => Self::null(),
+            value => panic!("expected a reference, got {:?}", value),
+        }
+    }
+}
+
+impl<T> FromValue for Ref<T> {
+    fn from_value(value: Value<'static>) -> std::result::Result<Self, ConversionError> {
+        match value {
+            Value::Int64(id) => Ok(Self::new(id.into())),
+            Value::Null => Ok(Self::null()),
+            value => Err(ConversionError(format!("{:?}", value))),
+        }
+    }
+}
+
+impl<T> ToValue for Ref<T> {
+    fn to_value(&self) -> std::result::Result<Value<'_>, ConversionError> {
+        Ok(self.into())
+    }
+}
@@ -1,15 +1,21 @@
 use crate::{
     data::{DataType, Value},
     error::{
-        Result,
+        DanglingReferenceError, Error, MissingColumnError, NotFoundError, Result,
+        UnexpectedTypeError, UniqueViolationError,
     },
-    object::Schema,
+    object::{Field, Index, OnDelete, Schema},
+    query::{CompareOp, Expr, Query},
     ObjectId,
 };
 
-use rusqlite::OptionalExtension;
+use rusqlite::{
+    params_from_iter,
+    types::{ToSqlOutput, ValueRef},
+    OptionalExtension, ToSql,
+};
 
-use std::{borrow::Cow, fmt::Write};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, fmt::Write, iter, ops::Deref, slice};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -18,57 +24,772 @@ pub type RowSlice<'a> = [Value<'a>];
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Column of an existing table.
+#[derive(Debug)]
+pub(crate) struct TableColumn {
+    pub name: String,
+    pub affinity: Affinity,
+}
+
+/// Kind of values a column holds, see https://www.sqlite.org/datatype3.html.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Affinity {
+    Integer,
+    Real,
+    Text,
+    Blob,
+    Numeric,
+}
+
+impl Affinity {
+    /// Affinity of a column with the given declared type.
+    pub fn of(sql_type: &str) -> Self {
+        let sql_type = sql_type.to_ascii_uppercase();
+        if sql_type.contains("INT") {
+            Self::Integer
+        } else if ["CHAR", "CLOB", "TEXT"]
+            .iter()
+            .any(|s| sql_type.contains(s))
+        {
+            Self::Text
+        } else if sql_type.contains("BLOB") || sql_type.is_empty() {
+            Self::Blob
+        } else if ["REAL", "FLOA", "DOUB"]
+            .iter()
+            .any(|s| sql_type.contains(s))
+        {
+            Self::Real
+        } else {
+            Self::Numeric
+        }
+    }
+
+    /// Whether values of the column can always be read as `data_type`.
+    pub fn accepts(self, data_type: DataType) -> bool {
+        match data_type {
+            DataType::String | DataType::Json => self == Self::Text,
+            DataType::Bytes => self == Self::Blob,
+            DataType::Int64 | DataType::Bool | DataType::Ref | DataType::Timestamp => {
+                matches!(self, Self::Integer | Self::Numeric)
+            }
+            DataType::Float64 => matches!(self, Self::Integer | Self::Real | Self::Numeric),
+        }
+    }
+
+    /// Name of the affinity, as used by `UnexpectedTypeError`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Integer => "Integer",
+            Self::Real => "Real",
+            Self::Text => "Text",
+            Self::Blob => "Blob",
+            Self::Numeric => "Numeric",
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub(crate) trait StorageTransaction {
     fn table_exists(&self, table: &str) -> Result<bool>;
+    /// Create the table along with its indexes.
     fn create_table(&self, schema: &Schema) -> Result<()>;
+    fn index_exists(&self, name: &str) -> Result<bool>;
+    fn create_index(&self, schema: &Schema, index: &Index) -> Result<()>;
+
+    fn table_columns(&self, table: &str) -> Result<Vec<TableColumn>>;
+    fn add_column(&self, schema: &Schema, field: &Field, default: &Value) -> Result<()>;
+    fn rename_column(&self, schema: &Schema, from: &str, to: &str) -> Result<()>;
+    /// Drop the column along with the indexes over it.
+    fn drop_column(&self, schema: &Schema, column: &str) -> Result<()>;
 
-    fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId>;
-    fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()>;
+    /// Allocate the id of a row, which is inserted later by `insert_rows`.
+    fn reserve_id(&self, schema: &Schema) -> Result<ObjectId>;
+    /// Insert all the rows, or none of them if any fails: the objects are only marked as
+    /// stored once the whole batch is.
+    fn insert_rows(&self, schema: &Schema, rows: &[(ObjectId, Row)]) -> Result<()>;
+    fn update_rows(&self, schema: &Schema, rows: &[(ObjectId, Row)]) -> Result<()>;
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>>;
-    fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()>;
+    fn select_rows(&self, schema: &Schema, query: &Query) -> Result<Vec<(ObjectId, Row<'static>)>>;
+    fn delete_rows(&self, schema: &Schema, ids: &[ObjectId]) -> Result<()>;
+
+    /// Start a savepoint, which can be nested in other ones.
+    fn savepoint(&self, name: &str) -> Result<()>;
+    /// Forget the savepoint and the ones started after it, keeping their changes.
+    fn release_savepoint(&self, name: &str) -> Result<()>;
+    /// Undo the changes made since the savepoint started, and forget it.
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()>;
 
     fn commit(&self) -> Result<()>;
     fn rollback(&self) -> Result<()>;
 }
 
-impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
+/// Maximum number of parameters of a statement in older versions of SQLite.
+const MAX_PARAMS: usize = 999;
+
+/// Savepoint that makes `insert_rows` all or nothing.
+const INSERT_SAVEPOINT: &str = "orm_insert_rows";
+
+pub(crate) struct SqliteTransaction<'a> {
+    inner: rusqlite::Transaction<'a>,
+    /// Ids to be given to the next inserted rows, by table.
+    next_ids: RefCell<HashMap<&'static str, i64>>,
+}
+
+impl<'a> SqliteTransaction<'a> {
+    pub fn new(inner: rusqlite::Transaction<'a>) -> Self {
+        Self {
+            inner,
+            next_ids: RefCell::default(),
+        }
+    }
+
+    /// Id of the last row ever inserted into the table.
+    fn last_id(&self, schema: &Schema) -> Result<i64> {
+        let table = quote(schema.table_name);
+        // NB: any write keeps other connections from inserting rows until the transaction
+        // ends, so ids can be counted here from now on.
+        self.execute(&format!("UPDATE {} SET id = id WHERE 0", table), [])?;
+
+        let sql = format!("SELECT coalesce(max(id), 0) FROM {}", table);
+        let mut last_id: i64 = self.query_row(&sql, [], |row| row.get(0))?;
+        // NB: ids of deleted rows are not reused, see https://www.sqlite.org/autoinc.html.
+        if self.table_exists("sqlite_sequence")? {
+            let seq = self
+                .query_row(
+                    "SELECT seq FROM sqlite_sequence WHERE name = ?",
+                    [schema.table_name],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?;
+            last_id = last_id.max(seq.unwrap_or(0));
+        }
+        Ok(last_id)
+    }
+
+    /// Insert the rows a chunk at a time. NB: rows inserted before a failure stay.
+    fn insert_chunks(&self, schema: &Schema, rows: &[(ObjectId, Row)]) -> Result<()> {
+        let columns: Vec<_> = iter::once("id".to_string())
+            .chain(schema.fields.iter().map(|field| quote(field.column_name)))
+            .collect();
+        let placeholders = format!("({})", vec!["?"; columns.len()].join(", "));
+
+        for chunk in rows.chunks(MAX_PARAMS / columns.len()) {
+            let sql = format!(
+                "INSERT INTO {}({}) VALUES {}",
+                quote(schema.table_name),
+                columns.join(", "),
+                vec![placeholders.as_str(); chunk.len()].join(", "),
+            );
+            let ids: Vec<_> = chunk
+                .iter()
+                .map(|(id, _)| Value::Int64(id.into_i64()))
+                .collect();
+            let params = ids
+                .iter()
+                .zip(chunk)
+                .flat_map(|(id, (_, row))| iter::once(id).chain(row));
+
+            let res = self
+                .prepare_cached(&sql)
+                .and_then(|mut stmt| stmt.execute(params_from_iter(params)));
+            match res {
+                Ok(_) => {}
+                // NB: the failed statement has no effect, so look for the duplicate row by row.
+                Err(err) if is_unique_violation(&err) && chunk.len() > 1 => {
+                    for row in chunk {
+                        self.insert_chunks(schema, slice::from_ref(row))?;
+                    }
+                }
+                Err(err) => return Err(convert_write_error(err, schema, &chunk[0].1)),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Deref for SqliteTransaction<'a> {
+    type Target = rusqlite::Transaction<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl StorageTransaction for SqliteTransaction<'_> {
     fn table_exists(&self, table: &str) -> Result<bool> {
-        // TODO: your code here.
-        unimplemented!()
+        let exists = self
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+                [table],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        Ok(exists)
     }
 
     fn create_table(&self, schema: &Schema) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+        let mut sql = format!(
+            "CREATE TABLE {}(id INTEGER PRIMARY KEY AUTOINCREMENT",
+            quote(schema.table_name)
+        );
+        for field in schema.fields {
+            write!(sql, ", {}", column_definition(field)).unwrap();
+        }
+        sql.push(')');
+
+        self.execute(&sql, [])
+            .map_err(|err| convert_error(err, schema, None))?;
+        for index in schema.indexes {
+            self.create_index(schema, index)?;
+        }
+        Ok(())
+    }
+
+    fn index_exists(&self, name: &str) -> Result<bool> {
+        let exists = self
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = ?",
+                [name],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        Ok(exists)
+    }
+
+    fn create_index(&self, schema: &Schema, index: &Index) -> Result<()> {
+        let columns = index_columns(schema, index);
+        let sql = format!(
+            "CREATE {}INDEX {} ON {}({})",
+            if index.unique { "UNIQUE " } else { "" },
+            quote(&index.name(schema)),
+            quote(schema.table_name),
+            columns
+        );
+        match self.execute(&sql, []) {
+            Ok(_) => Ok(()),
+            Err(err) if is_unique_violation(&err) => {
+                // NB: existing rows violate the constraint, so report the first duplicate.
+                let conditions = index
+                    .fields
+                    .iter()
+                    .map(|&field| {
+                        format!("{} IS NOT NULL", quote(schema.fields[field].column_name))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" AND ");
+                let sql = format!(
+                    "SELECT {columns} FROM {} WHERE {} GROUP BY {columns} HAVING COUNT(*) > 1",
+                    quote(schema.table_name),
+                    conditions,
+                );
+                let values = self.query_row(&sql, [], |row| {
+                    (0..index.fields.len())
+                        .map(|i| Ok(row.get_ref(i)?.into()))
+                        .collect()
+                })?;
+                Err(unique_violation(schema, index, values))
+            }
+            Err(err) => Err(convert_error(err, schema, None)),
+        }
+    }
+
+    fn table_columns(&self, table: &str) -> Result<Vec<TableColumn>> {
+        let mut stmt = self.prepare("SELECT name, type FROM pragma_table_info(?)")?;
+        let columns = stmt
+            .query_map([table], |row| {
+                Ok(TableColumn {
+                    name: row.get(0)?,
+                    affinity: Affinity::of(&row.get::<_, String>(1)?),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(columns)
+    }
+
+    fn add_column(&self, schema: &Schema, field: &Field, default: &Value) -> Result<()> {
+        let table = quote(schema.table_name);
+        let sql = format!(
+            "ALTER TABLE {} ADD COLUMN {}",
+            table,
+            column_definition(field)
+        );
+        self.execute(&sql, [])
+            .map_err(|err| convert_error(err, schema, None))?;
+
+        // NB: SQLite only accepts a literal as the default of a column, so fill it separately.
+        if *default != Value::Null {
+            let sql = format!("UPDATE {} SET {} = ?", table, quote(field.column_name));
+            self.execute(&sql, [default])
+                .map_err(|err| convert_error(err, schema, None))?;
+        }
+        Ok(())
+    }
+
+    fn rename_column(&self, schema: &Schema, from: &str, to: &str) -> Result<()> {
+        let sql = format!(
+            "ALTER TABLE {} RENAME COLUMN {} TO {}",
+            quote(schema.table_name),
+            quote(from),
+            quote(to)
+        );
+        self.execute(&sql, [])
+            .map_err(|err| convert_error(err, schema, None))?;
+        Ok(())
+    }
+
+    fn drop_column(&self, schema: &Schema, column: &str) -> Result<()> {
+        // NB: SQLite refuses to drop a column that is still indexed.
+        let indexes = self
+            .prepare(
+                "SELECT DISTINCT list.name FROM pragma_index_list(?1) AS list \
+                JOIN pragma_index_info(list.name) AS info \
+                WHERE list.origin = 'c' AND info.name = ?2 COLLATE NOCASE",
+            )?
+            .query_map([schema.table_name, column], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for index in indexes {
+            self.execute(&format!("DROP INDEX {}", quote(&index)), [])
+                .map_err(|err| convert_error(err, schema, None))?;
+        }
+
+        let sql = format!(
+            "ALTER TABLE {} DROP COLUMN {}",
+            quote(schema.table_name),
+            quote(column)
+        );
+        self.execute(&sql, [])
+            .map_err(|err| convert_error(err, schema, None))?;
+        Ok(())
+    }
+
+    fn reserve_id(&self, schema: &Schema) -> Result<ObjectId> {
+        let mut next_ids = self.next_ids.borrow_mut();
+        let next_id = match next_ids.get_mut(schema.table_name) {
+            Some(next_id) => next_id,
+            None => next_ids
+                .entry(schema.table_name)
+                .or_insert(self.last_id(schema)? + 1),
+        };
+        let id = *next_id;
+        *next_id += 1;
+        Ok(id.into())
     }
 
-    fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId> {
-        // TODO: your code here.
-        unimplemented!()
+    fn insert_rows(&self, schema: &Schema, rows: &[(ObjectId, Row)]) -> Result<()> {
+        self.savepoint(INSERT_SAVEPOINT)?;
+        match self.insert_chunks(schema, rows) {
+            Ok(()) => self.release_savepoint(INSERT_SAVEPOINT),
+            Err(err) => {
+                self.rollback_to_savepoint(INSERT_SAVEPOINT)?;
+                Err(err)
+            }
+        }
     }
 
-    fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+    fn update_rows(&self, schema: &Schema, rows: &[(ObjectId, Row)]) -> Result<()> {
+        if schema.fields.is_empty() {
+            return Ok(());
+        }
+
+        let assignments = schema
+            .fields
+            .iter()
+            .map(|field| format!("{} = ?", quote(field.column_name)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sql = format!(
+            "UPDATE {} SET {} WHERE id = ?",
+            quote(schema.table_name),
+            assignments
+        );
+
+        let mut stmt = self
+            .prepare_cached(&sql)
+            .map_err(|err| convert_error(err, schema, None))?;
+        for (id, row) in rows {
+            let id = Value::Int64(id.into_i64());
+            stmt.execute(params_from_iter(row.iter().chain([&id])))
+                .map_err(|err| convert_write_error(err, schema, row))?;
+        }
+        Ok(())
     }
 
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
-        // TODO: your code here.
-        unimplemented!()
+        let sql = format!(
+            "SELECT {} FROM {} WHERE id = ?",
+            select_list(schema),
+            quote(schema.table_name),
+        );
+
+        self.query_row(&sql, [id.into_i64()], |row| read_row(row, schema))
+            .map_err(|err| convert_error(err, schema, Some(id)))
+    }
+
+    fn select_rows(&self, schema: &Schema, query: &Query) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let mut sql = format!(
+            "SELECT {} FROM {}",
+            select_list(schema),
+            quote(schema.table_name),
+        );
+
+        let mut params = vec![];
+        if let Some(filter) = &query.filter {
+            sql.push_str(" WHERE ");
+            write_expr(&mut sql, &mut params, schema, filter);
+        }
+        let exclude: Vec<_> = query
+            .exclude
+            .iter()
+            .map(|id| Value::Int64(id.into_i64()))
+            .collect();
+        if !exclude.is_empty() {
+            sql.push_str(if query.filter.is_some() {
+                " AND "
+            } else {
+                " WHERE "
+            });
+            write!(sql, "id NOT IN ({})", vec!["?"; exclude.len()].join(", ")).unwrap();
+            params.extend(&exclude);
+        }
+        for (i, &(field, descending)) in query.order.iter().enumerate() {
+            sql.push_str(if i == 0 { " ORDER BY " } else { ", " });
+            sql.push_str(&quote(schema.fields[field].column_name));
+            if descending {
+                sql.push_str(" DESC");
+            }
+        }
+        // NB: SQLite only allows an offset after a limit, a negative one meaning no limit.
+        match query.limit {
+            Some(limit) => write!(sql, " LIMIT {}", limit).unwrap(),
+            None if query.offset > 0 => sql.push_str(" LIMIT -1"),
+            None => {}
+        }
+        if query.offset > 0 {
+            write!(sql, " OFFSET {}", query.offset).unwrap();
+        }
+
+        let select = || -> rusqlite::Result<_> {
+            let mut stmt = self.prepare(&sql)?;
+            let rows = stmt.query_map(params_from_iter(params), |row| {
+                let id = row.get::<_, i64>(schema.fields.len())?;
+                Ok((id.into(), read_row(row, schema)?))
+            })?;
+            rows.collect()
+        };
+        select().map_err(|err| convert_error(err, schema, None))
     }
 
-    fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+    fn delete_rows(&self, schema: &Schema, ids: &[ObjectId]) -> Result<()> {
+        for chunk in ids.chunks(MAX_PARAMS) {
+            let sql = format!(
+                "DELETE FROM {} WHERE id IN ({})",
+                quote(schema.table_name),
+                vec!["?"; chunk.len()].join(", ")
+            );
+            let mut stmt = self.prepare_cached(&sql)?;
+            stmt.execute(params_from_iter(chunk.iter().map(|id| id.into_i64())))
+                .map_err(|err| convert_error(err, schema, None))?;
+        }
+        Ok(())
     }
 
     fn commit(&self) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+        // NB: SQLite would refuse to commit anyway, but without saying which reference is broken.
+        let violation = self
+            .query_row(
+                "SELECT c.`table`, c.rowid, c.parent, f.`from` \
+                FROM pragma_foreign_key_check() AS c \
+                JOIN pragma_foreign_key_list(c.`table`) AS f ON f.id = c.fkid \
+                LIMIT 1",
+                [],
+                |row| {
+                    Ok(DanglingReferenceError {
+                        table_name: row.get(0)?,
+                        object_id: row.get::<_, i64>(1)?.into(),
+                        target_table: row.get(2)?,
+                        column_name: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+        if let Some(violation) = violation {
+            return Err(Error::DanglingReference(Box::new(violation)));
+        }
+
+        self.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn savepoint(&self, name: &str) -> Result<()> {
+        self.execute_batch(&format!("SAVEPOINT {}", quote(name)))?;
+        Ok(())
+    }
+
+    fn release_savepoint(&self, name: &str) -> Result<()> {
+        self.execute_batch(&format!("RELEASE {}", quote(name)))?;
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&self, name: &str) -> Result<()> {
+        // NB: `ROLLBACK TO` keeps the savepoint itself.
+        let name = quote(name);
+        self.execute_batch(&format!("ROLLBACK TO {0}; RELEASE {0}", name))?;
+        Ok(())
     }
 
     fn rollback(&self) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+        self.execute_batch("ROLLBACK")?;
+        Ok(())
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl ToSql for Value<'_> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Self::String(s) => ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes())),
+            Self::Bytes(b) => ToSqlOutput::Borrowed(ValueRef::Blob(b)),
+            Self::Int64(x) => ToSqlOutput::from(*x),
+            Self::Float64(x) => ToSqlOutput::from(*x),
+            Self::Bool(x) => ToSqlOutput::from(*x),
+            Self::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+        })
+    }
+}
+
+impl From<ValueRef<'_>> for Value<'static> {
+    fn from(value: ValueRef<'_>) -> Self {
+        match value {
+            ValueRef::Null => Value::Null,
+            ValueRef::Integer(x) => Value::Int64(x),
+            ValueRef::Real(x) => Value::Float64(x),
+            ValueRef::Text(s) => Value::String(Cow::Owned(String::from_utf8_lossy(s).into_owned())),
+            ValueRef::Blob(b) => Value::Bytes(Cow::Owned(b.to_vec())),
+        }
+    }
+}
+
+/// Read the values of the fields, selected by `select_list`.
+fn read_row(row: &rusqlite::Row, schema: &Schema) -> rusqlite::Result<Row<'static>> {
+    schema
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            if field.nullable && row.get_ref(i)? == ValueRef::Null {
+                return Ok(Value::Null);
+            }
+            Ok(match field.data_type {
+                DataType::String | DataType::Json => Value::String(Cow::Owned(row.get(i)?)),
+                DataType::Bytes => Value::Bytes(Cow::Owned(row.get(i)?)),
+                DataType::Int64 | DataType::Ref | DataType::Timestamp => Value::Int64(row.get(i)?),
+                DataType::Float64 => Value::Float64(row.get(i)?),
+                DataType::Bool => Value::Bool(row.get(i)?),
+            })
+        })
+        .collect()
+}
+
+fn write_expr<'q>(
+    sql: &mut String,
+    params: &mut Vec<&'q Value<'static>>,
+    schema: &Schema,
+    expr: &'q Expr,
+) {
+    match expr {
+        Expr::Compare { field, op, value } => {
+            let column_name = quote(schema.fields[*field].column_name);
+            match (op, value) {
+                (CompareOp::Eq, Value::Null) => write!(sql, "{} IS NULL", column_name).unwrap(),
+                (CompareOp::Ne, Value::Null) => write!(sql, "{} IS NOT NULL", column_name).unwrap(),
+                _ => {
+                    write!(sql, "{} {} ?", column_name, op.sql()).unwrap();
+                    params.push(value);
+                }
+            }
+        }
+        Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
+            sql.push('(');
+            write_expr(sql, params, schema, lhs);
+            sql.push_str(if let Expr::And(..) = expr {
+                " AND "
+            } else {
+                " OR "
+            });
+            write_expr(sql, params, schema, rhs);
+            sql.push(')');
+        }
+        Expr::Not(inner) => {
+            sql.push_str("NOT (");
+            write_expr(sql, params, schema, inner);
+            sql.push(')');
+        }
+    }
+}
+
+/// Columns of the fields followed by the id. NB: the id goes last, so that a missing column
+/// of a field is the first one reported.
+fn select_list(schema: &Schema) -> String {
+    let mut list = column_list(schema);
+    if !list.is_empty() {
+        list.push_str(", ");
+    }
+    list.push_str("id");
+    list
+}
+
+/// Definition of the column of a field, as used by `CREATE TABLE` and `ADD COLUMN`.
+fn column_definition(field: &Field) -> String {
+    let mut sql = format!(
+        "{} {}",
+        quote(field.column_name),
+        field.data_type.sql_type()
+    );
+    // NB: a deferred foreign key is only checked on commit, so objects may refer to each other
+    // in any order in the meantime. Restrict is a check as well.
+    if let Some(reference) = &field.reference {
+        let on_delete = match reference.on_delete {
+            OnDelete::Restrict => "NO ACTION",
+            OnDelete::Cascade => "CASCADE",
+            OnDelete::Nullify => "SET NULL",
+        };
+        write!(
+            sql,
+            " REFERENCES {}(id) ON DELETE {} DEFERRABLE INITIALLY DEFERRED",
+            quote((reference.target)().table_name),
+            on_delete
+        )
+        .unwrap();
+    }
+    sql
+}
+
+fn index_columns(schema: &Schema, index: &Index) -> String {
+    index
+        .fields
+        .iter()
+        .map(|&field| quote(schema.fields[field].column_name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn column_list(schema: &Schema) -> String {
+    schema
+        .fields
+        .iter()
+        .map(|field| quote(field.column_name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Quote an identifier, so that it may contain any characters. NB: unlike double quotes,
+/// backticks never turn an unknown column into a string literal.
+fn quote(ident: &str) -> String {
+    format!("`{}`", ident.replace('`', "``"))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Convert an error, adding the context `rusqlite` doesn't know about.
+fn convert_error(err: rusqlite::Error, schema: &Schema, id: Option<ObjectId>) -> Error {
+    match err {
+        rusqlite::Error::QueryReturnedNoRows => match id {
+            Some(object_id) => Error::NotFound(Box::new(NotFoundError {
+                object_id,
+                type_name: schema.type_name,
+            })),
+            None => err.into(),
+        },
+        rusqlite::Error::InvalidColumnType(_, ref column_name, ref got_type) => {
+            match schema.find_column(column_name) {
+                Some(field) => Error::UnexpectedType(Box::new(UnexpectedTypeError {
+                    type_name: schema.type_name,
+                    attr_name: field.attr_name,
+                    table_name: schema.table_name,
+                    column_name: field.column_name,
+                    expected_type: field.data_type,
+                    got_type: got_type.to_string(),
+                })),
+                None => err.into(),
+            }
+        }
+        rusqlite::Error::SqliteFailure(_, Some(ref message)) => {
+            let missing_column = ["no such column: ", "has no column named "]
+                .iter()
+                .find_map(|pattern| {
+                    let (_, column_name) = message.split_once(pattern)?;
+                    schema.find_column(column_name.trim())
+                });
+            match missing_column {
+                Some(field) => Error::MissingColumn(Box::new(MissingColumnError {
+                    type_name: schema.type_name,
+                    attr_name: field.attr_name,
+                    table_name: schema.table_name,
+                    column_name: field.column_name,
+                })),
+                None => err.into(),
+            }
+        }
+        err => err.into(),
+    }
+}
+
+fn is_unique_violation(err: &rusqlite::Error) -> bool {
+    // NB: the extended code is not exported by the bindings, see
+    // https://www.sqlite.org/rescode.html#constraint_unique.
+    const SQLITE_CONSTRAINT_UNIQUE: i32 = rusqlite::ffi::SQLITE_CONSTRAINT | (8 << 8);
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(err, _) if err.extended_code == SQLITE_CONSTRAINT_UNIQUE
+    )
+}
+
+/// Convert an error of writing the row, which may violate a unique constraint.
+fn convert_write_error(err: rusqlite::Error, schema: &Schema, row: &RowSlice) -> Error {
+    // NB: the message lists the columns of the constraint, e.g.
+    // "UNIQUE constraint failed: user.first_name, user.last_name".
+    let index = match &err {
+        rusqlite::Error::SqliteFailure(_, Some(message)) if is_unique_violation(&err) => {
+            message.split_once(": ").and_then(|(_, columns)| {
+                let columns: Vec<_> = columns
+                    .split(", ")
+                    .map(|column| column.rsplit_once('.').map_or(column, |(_, name)| name))
+                    .collect();
+                schema.indexes.iter().find(|index| {
+                    index.unique
+                        && index.fields.len() == columns.len()
+                        && index
+                            .fields
+                            .iter()
+                            .zip(&columns)
+                            .all(|(&field, column)| schema.fields[field].column_name == *column)
+                })
+            })
+        }
+        _ => None,
+    };
+    match index {
+        Some(index) => {
+            let values = index
+                .fields
+                .iter()
+                .map(|&field| row[field].clone().into_owned())
+                .collect();
+            unique_violation(schema, index, values)
+        }
+        None => convert_error(err, schema, None),
+    }
+}
+
+fn unique_violation(schema: &Schema, index: &Index, values: Vec<Value<'static>>) -> Error {
+    Error::UniqueViolation(Box::new(UniqueViolationError {
+        type_name: schema.type_name,
+        table_name: schema.table_name,
+        attr_names: index.attr_names(schema),
+        values,
+    }))
+}
@@ -1,16 +1,17 @@
 use crate::{
-    data::ObjectId,
+    audit::{Change, CommitHooks},
+    data::{ObjectId, Value},
     error::*,
-    object::{
-        Object,
-    },
-    storage::StorageTransaction,
+    migration,
+    object::{Object, Schema, Store},
+    query::{Query, Select},
+    storage::{Row, StorageTransaction},
 };
 
 use std::{
     any::{Any, TypeId},
     cell::{Cell, Ref, RefCell, RefMut},
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     marker::PhantomData,
     rc::Rc,
 };
@@ -19,77 +20,499 @@ use std::{
 
 pub struct Transaction<'a> {
     inner: Box<dyn StorageTransaction + 'a>,
-    // TODO: your code here.
+    objects: RefCell<HashMap<(TypeId, ObjectId), Rc<ObjectCell>>>,
+    /// Tables known to exist.
+    tables: RefCell<HashSet<&'static str>>,
+    hooks: &'a CommitHooks,
+    /// Active savepoints, innermost last.
+    savepoints: RefCell<Vec<CacheSnapshot>>,
+    next_savepoint_id: Cell<u64>,
 }
 
 impl<'a> Transaction<'a> {
-    pub(crate) fn new(inner: Box<dyn StorageTransaction + 'a>) -> Self {
+    pub(crate) fn new(inner: Box<dyn StorageTransaction + 'a>, hooks: &'a CommitHooks) -> Self {
         Self {
             inner,
-            // TODO: your code here.
+            objects: RefCell::default(),
+            tables: RefCell::default(),
+            hooks,
+            savepoints: RefCell::default(),
+            next_savepoint_id: Cell::new(0),
         }
     }
 
+    /// Create a new object. It is only written to the storage on the next flush, along with
+    /// the other objects created since, so e.g. uniqueness is checked then.
     pub fn create<T: Object>(&self, obj: T) -> Result<Tx<'_, T>> {
-        // TODO: your code here.
-        unimplemented!()
+        self.ensure_table(&T::SCHEMA)?;
+        let id = self.inner.reserve_id(&T::SCHEMA)?;
+        Ok(self.insert_object(id, obj, None))
     }
 
     pub fn get<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
-        // TODO: your code here.
-        unimplemented!()
+        if let Some(cell) = self.objects.borrow().get(&(TypeId::of::<T>(), id)) {
+            if let ObjectState::Removed = cell.state.get() {
+                return Err(Error::NotFound(Box::new(NotFoundError {
+                    object_id: id,
+                    type_name: T::SCHEMA.type_name,
+                })));
+            }
+            return Ok(Tx::new(id, cell.clone()));
+        }
+
+        self.ensure_table(&T::SCHEMA)?;
+        let row = self.inner.select_row(id, &T::SCHEMA)?;
+        Ok(self.insert_object(id, T::from_row(row.clone())?, Some(row)))
     }
 
+    /// Build a query selecting objects of type `T`, e.g.
+    /// `tx.select::<User>().filter(|u| u.name.eq("John")).limit(10).fetch()`.
+    ///
+    /// Changes made in this transaction are written to the storage before the query runs, so
+    /// it sees the objects as they are in memory. Objects that have already been loaded are
+    /// returned as the same `Tx` handles, removed ones are skipped. NB: an object that is
+    /// mutably borrowed while the query runs is seen as it was before that borrow, and a new
+    /// one can't be written at all, so the query fails with `Error::ObjectBorrowed`.
+    pub fn select<T: Object>(&self) -> Select<'_, 'a, T> {
+        Select::new(self)
+    }
+
+    /// Write all the changes to the storage and commit them. Removed objects are only
+    /// deleted here, applying the `OnDelete` policies of the references to them.
+    ///
+    /// The observers registered with `Connection::on_commit` are called once the commit
+    /// has succeeded.
     pub fn commit(self) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+        let changes = if self.hooks.is_tracking() {
+            self.changes()?
+        } else {
+            vec![]
+        };
+        for entry in self.hooks.audit_entries(&changes) {
+            self.create(entry)?;
+        }
+        self.flush()?;
+
+        let mut removed = BTreeMap::<TypeId, (&'static Schema, Vec<ObjectId>)>::new();
+        for (&(type_id, id), cell) in self.objects.borrow().iter() {
+            if cell.state.get() == ObjectState::Removed && cell.is_stored.get() {
+                let schema = cell.object.borrow().schema();
+                removed
+                    .entry(type_id)
+                    .or_insert((schema, vec![]))
+                    .1
+                    .push(id);
+            }
+        }
+        for (schema, mut ids) in removed.into_values() {
+            ids.sort_by_key(|id| id.into_i64());
+            self.inner.delete_rows(schema, &ids)?;
+        }
+        self.inner.commit()?;
+
+        for observer in &self.hooks.observers {
+            observer(&changes);
+        }
+        Ok(())
+    }
+
+    pub fn rollback(self) -> Result<()> {
+        self.inner.rollback()
+    }
+
+    /// Start a savepoint, e.g. to try an operation and undo only its changes:
+    /// rolling the savepoint back restores both the storage and the objects of this
+    /// transaction, including their states, as they were when it started. Savepoints
+    /// can be nested, and a dropped savepoint is rolled back.
+    ///
+    /// An object that is mutably borrowed can't be copied, so then this fails with
+    /// `Error::ObjectBorrowed`. NB: the objects are copied, so this takes time proportional
+    /// to their number.
+    pub fn savepoint(&self) -> Result<Savepoint<'_, 'a>> {
+        let objects = self
+            .objects
+            .borrow()
+            .iter()
+            .map(|(&key, cell)| {
+                let Ok(obj) = cell.object.try_borrow() else {
+                    return Err(cell.borrowed_error(key.1));
+                };
+                let saved = SavedObject {
+                    state: cell.state.get(),
+                    is_stored: cell.is_stored.get(),
+                    is_dirty: cell.is_dirty.get(),
+                    row: obj.to_row()?.into_iter().map(Value::into_owned).collect(),
+                };
+                Ok((key, saved))
+            })
+            .collect::<Result<_>>()?;
+        let id = self.next_savepoint_id.get();
+        self.next_savepoint_id.set(id + 1);
+        self.inner.savepoint(&savepoint_name(id))?;
+        self.savepoints.borrow_mut().push(CacheSnapshot {
+            id,
+            objects,
+            tables: self.tables.borrow().clone(),
+        });
+
+        Ok(Savepoint { tx: self, id })
+    }
+
+    pub(crate) fn fetch<T: Object>(&self, mut query: Query) -> Result<Vec<Tx<'_, T>>> {
+        self.ensure_table(&T::SCHEMA)?;
+        self.flush()?;
+        query.exclude = self
+            .objects
+            .borrow()
+            .iter()
+            .filter(|(&(type_id, _), cell)| {
+                type_id == TypeId::of::<T>() && matches!(cell.state.get(), ObjectState::Removed)
+            })
+            .map(|(&(_, id), _)| id)
+            .collect();
+
+        let rows = self.inner.select_rows(&T::SCHEMA, &query)?;
+        let txs = rows
+            .into_iter()
+            .map(|(id, row)| {
+                let cached = self.objects.borrow().get(&(TypeId::of::<T>(), id)).cloned();
+                match cached {
+                    Some(cell) => Ok(Tx::new(id, cell)),
+                    None => Ok(self.insert_object(id, T::from_row(row.clone())?, Some(row))),
+                }
+            })
+            .collect::<Result<_>>()?;
+        Ok(txs)
+    }
+
+    /// Write the created and modified objects to the storage, a table at a time. Removed
+    /// objects are kept until commit, so that all the references to them are handled at once.
+    ///
+    /// Objects that are mutably borrowed at the moment are left for the next flush: they
+    /// can't be read, and at least one more flush happens on commit, when no object can be
+    /// borrowed anymore. A new object can't be left out though, or the query that flushes
+    /// would miss it, so it is reported as `Error::ObjectBorrowed`.
+    fn flush(&self) -> Result<()> {
+        let objects = self.objects.borrow();
+
+        // NB: new rows are inserted before the updates, which may refer to them.
+        let mut pending = BTreeMap::<(bool, TypeId), Vec<_>>::new();
+        for (&(type_id, id), cell) in objects.iter() {
+            if cell.state.get() == ObjectState::Removed
+                || (cell.is_stored.get() && !cell.is_dirty.get())
+            {
+                continue;
+            }
+            let Ok(obj) = cell.object.try_borrow() else {
+                if cell.is_stored.get() {
+                    continue;
+                }
+                return Err(cell.borrowed_error(id));
+            };
+            pending
+                .entry((cell.is_stored.get(), type_id))
+                .or_default()
+                .push((id, cell.as_ref(), obj));
+        }
+
+        for ((is_update, _), mut cells) in pending {
+            cells.sort_by_key(|(id, _, _)| id.into_i64());
+            let objs: Vec<_> = cells.iter().map(|(_, _, obj)| obj).collect();
+            let rows: Vec<_> = cells
+                .iter()
+                .map(|(id, _, obj)| Ok((*id, obj.to_row()?)))
+                .collect::<Result<_>>()?;
+
+            let schema = objs[0].schema();
+            if is_update {
+                self.inner.update_rows(schema, &rows)?;
+            } else {
+                self.inner.insert_rows(schema, &rows)?;
+            }
+            for (_, cell, _) in cells {
+                cell.is_stored.set(true);
+                cell.is_dirty.set(false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Index of the savepoint in the stack, unless it has been released or rolled back.
+    fn find_savepoint(&self, id: u64) -> Option<usize> {
+        self.savepoints
+            .borrow()
+            .iter()
+            .position(|snapshot| snapshot.id == id)
+    }
+
+    fn release_savepoint(&self, index: usize) -> Result<()> {
+        let id = self.savepoints.borrow()[index].id;
+        self.inner.release_savepoint(&savepoint_name(id))?;
+        self.savepoints.borrow_mut().truncate(index);
+        Ok(())
+    }
+
+    fn rollback_savepoint(&self, index: usize) -> Result<()> {
+        let id = self.savepoints.borrow()[index].id;
+        self.inner.rollback_to_savepoint(&savepoint_name(id))?;
+        let mut snapshot = self.savepoints.borrow_mut().drain(index..).next().unwrap();
+
+        // NB: every object is restored even if one of them fails, the first error is returned.
+        let mut result = Ok(());
+        self.objects.borrow_mut().retain(|key, cell| {
+            let (state, is_stored, is_dirty, row) = match snapshot.objects.remove(key) {
+                Some(saved) => (saved.state, saved.is_stored, saved.is_dirty, saved.row),
+                // NB: the storage is rolled back, so the object is as it was loaded.
+                None => match &cell.original {
+                    Some(row) => (ObjectState::Clean, true, false, row.clone()),
+                    None => {
+                        cell.state.set(ObjectState::Removed);
+                        cell.is_stored.set(false);
+                        return false;
+                    }
+                },
+            };
+            let Ok(mut obj) = cell.object.try_borrow_mut() else {
+                // NB: the object keeps its current value, which the next flush writes over
+                // the rolled back row.
+                if result.is_ok() {
+                    result = Err(cell.borrowed_error(key.1));
+                }
+                cell.state.set(ObjectState::Modified);
+                cell.is_stored.set(is_stored);
+                cell.is_dirty.set(true);
+                return true;
+            };
+            if let Err(err) = obj.load_row(row) {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+            cell.state.set(state);
+            cell.is_stored.set(is_stored);
+            cell.is_dirty.set(is_dirty);
+            true
+        });
+        *self.tables.borrow_mut() = snapshot.tables;
+        result
+    }
+
+    /// Compare the objects with the rows they were loaded from.
+    fn changes(&self) -> Result<Vec<Change>> {
+        let mut changes: Vec<_> = self
+            .objects
+            .borrow()
+            .iter()
+            .filter_map(|(&(_, id), cell)| {
+                let obj = cell.object.borrow();
+                let current = match cell.state.get() {
+                    ObjectState::Removed => None,
+                    _ => match obj.to_row() {
+                        Ok(row) => Some(row),
+                        Err(err) => return Some(Err(err)),
+                    },
+                };
+                Change::new(obj.schema(), id, cell.original.as_ref(), current).map(Ok)
+            })
+            .collect::<Result<_>>()?;
+        changes.sort_by_key(|change| (change.table_name, change.object_id.into_i64()));
+        Ok(changes)
+    }
+
+    /// Create or migrate the table of the schema, along with the tables it refers to.
+    fn ensure_table(&self, schema: &'static Schema) -> Result<()> {
+        if self.tables.borrow().contains(schema.table_name) {
+            return Ok(());
+        }
+        if self.inner.table_exists(schema.table_name)? {
+            migration::migrate_table(&*self.inner, schema)?;
+        } else {
+            self.inner.create_table(schema)?;
+        }
+        self.tables.borrow_mut().insert(schema.table_name);
+
+        for field in schema.fields {
+            if let Some(reference) = &field.reference {
+                self.ensure_table((reference.target)())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Cache an object, either created or loaded from `original`.
+    fn insert_object<T: Object>(
+        &self,
+        id: ObjectId,
+        obj: T,
+        original: Option<Row<'static>>,
+    ) -> Tx<'_, T> {
+        let cell = Rc::new(ObjectCell {
+            state: Cell::new(ObjectState::Clean),
+            is_stored: Cell::new(original.is_some()),
+            is_dirty: Cell::new(false),
+            original,
+            schema: &T::SCHEMA,
+            object: RefCell::new(Box::new(obj)),
+        });
+        self.objects
+            .borrow_mut()
+            .insert((TypeId::of::<T>(), id), cell.clone());
+        Tx::new(id, cell)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Savepoint of a transaction, see `Transaction::savepoint`.
+pub struct Savepoint<'t, 'a> {
+    tx: &'t Transaction<'a>,
+    id: u64,
+}
+
+impl Savepoint<'_, '_> {
+    /// Keep the changes made since the savepoint started, as a part of the enclosing
+    /// savepoint or transaction.
+    pub fn release(self) -> Result<()> {
+        let index = self.index();
+        self.tx.release_savepoint(index)
     }
 
+    /// Undo the changes made since the savepoint started, including the ones made within
+    /// the savepoints nested in it.
+    ///
+    /// An object that is borrowed at the moment can't be restored: it keeps its current
+    /// value, which is written to the storage on the next flush, and `Error::ObjectBorrowed`
+    /// is returned once the rest is rolled back. A dropped savepoint does the same, ignoring
+    /// the error.
     pub fn rollback(self) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+        let index = self.index();
+        self.tx.rollback_savepoint(index)
     }
+
+    fn index(&self) -> usize {
+        match self.tx.find_savepoint(self.id) {
+            Some(index) => index,
+            None => panic!("the savepoint has already ended with an enclosing one"),
+        }
+    }
+}
+
+impl Drop for Savepoint<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(index) = self.tx.find_savepoint(self.id) {
+            let _ = self.tx.rollback_savepoint(index);
+        }
+    }
+}
+
+fn savepoint_name(id: u64) -> String {
+    format!("orm_savepoint_{}", id)
+}
+
+/// Objects of the transaction at the start of a savepoint.
+struct CacheSnapshot {
+    id: u64,
+    objects: HashMap<(TypeId, ObjectId), SavedObject>,
+    tables: HashSet<&'static str>,
+}
+
+struct SavedObject {
+    state: ObjectState,
+    is_stored: bool,
+    is_dirty: bool,
+    row: Row<'static>,
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ObjectState {
     Clean,
     Modified,
     Removed,
 }
 
-#[derive(Clone)]
+impl ObjectCell {
+    fn borrowed_error(&self, id: ObjectId) -> Error {
+        Error::ObjectBorrowed(Box::new(ObjectBorrowedError {
+            object_id: id,
+            type_name: self.schema.type_name,
+        }))
+    }
+}
+
+struct ObjectCell {
+    state: Cell<ObjectState>,
+    /// Whether the object has been written to the storage.
+    is_stored: Cell<bool>,
+    /// Whether the object may have changed since it was last written to the storage.
+    /// Unlike `state`, this is reset by every flush.
+    is_dirty: Cell<bool>,
+    /// Row the object was loaded from, kept to compute its changes.
+    original: Option<Row<'static>>,
+    /// Schema of the object, known even while it is borrowed.
+    schema: &'static Schema,
+    object: RefCell<Box<dyn Store>>,
+}
+
 pub struct Tx<'a, T> {
-    // TODO: your code here.
+    id: ObjectId,
+    cell: Rc<ObjectCell>,
     lifetime: PhantomData<&'a T>,
 }
 
 impl<'a, T: Any> Tx<'a, T> {
+    fn new(id: ObjectId, cell: Rc<ObjectCell>) -> Self {
+        Self {
+            id,
+            cell,
+            lifetime: PhantomData,
+        }
+    }
+
     pub fn id(&self) -> ObjectId {
-        // TODO: your code here.
-        unimplemented!()
+        self.id
     }
 
     pub fn state(&self) -> ObjectState {
-        // TODO: your code here.
-        unimplemented!()
+        self.cell.state.get()
     }
 
     pub fn borrow(&self) -> Ref<'_, T> {
-        // TODO: your code here.
-        unimplemented!()
+        self.check_not_removed();
+        Ref::map(self.cell.object.borrow(), |obj| {
+            obj.as_any().downcast_ref().unwrap()
+        })
     }
 
     pub fn borrow_mut(&self) -> RefMut<'_, T> {
-        // TODO: your code here.
-        unimplemented!()
+        self.check_not_removed();
+        let obj = self.cell.object.borrow_mut();
+        self.cell.state.set(ObjectState::Modified);
+        self.cell.is_dirty.set(true);
+        RefMut::map(obj, |obj| obj.as_any_mut().downcast_mut().unwrap())
     }
 
     pub fn delete(self) {
-        // TODO: your code here.
-        unimplemented!()
+        if self.cell.object.try_borrow_mut().is_err() {
+            panic!("cannot delete a borrowed object");
+        }
+        self.cell.state.set(ObjectState::Removed);
+    }
+
+    fn check_not_removed(&self) {
+        if let ObjectState::Removed = self.cell.state.get() {
+            panic!("cannot borrow a removed object");
+        }
     }
 }
 
+impl<T> Clone for Tx<'_, T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            cell: self.cell.clone(),
+            lifetime: PhantomData,
+        }
+    }
+}
@@ -1,8 +1,17 @@
-use orm::{data::DataType, Connection, Object, ObjectId, ObjectState, Result, Tx};
+use orm::{
+    data::{DataType, Value},
+    AuditEntry, Change, ChangeKind, Connection, FieldChange, Object, ObjectId, ObjectState, Ref,
+    Result, RetryPolicy, Tx,
+};
 
 use rusqlite::params;
 use tempfile::NamedTempFile;
 
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Object, PartialEq, Clone, Debug)]
@@ -40,10 +49,7 @@ fn fmt_res<T>(res: &Result<T>) -> String {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[test]
-fn test_create() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_create(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let user = User {
         name: "John".into(),
@@ -63,10 +69,7 @@ fn test_create() {
     assert_eq!(*tx_user.borrow(), user);
 }
 
-#[test]
-fn test_update() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_update(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -92,10 +95,7 @@ fn test_update() {
     assert_eq!(tx_user.borrow().balance, 400.);
 }
 
-#[test]
-fn test_delete() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_delete(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -126,10 +126,7 @@ fn test_delete() {
     assert_not_found(res, user_id, "User");
 }
 
-#[test]
-fn test_create_delete() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_create_delete(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -156,11 +153,7 @@ fn test_create_delete() {
     assert_not_found(res, user_id, "User");
 }
 
-#[test]
-#[should_panic(expected = "already borrowed")]
-fn test_double_borrow() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_double_borrow(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -177,11 +170,7 @@ fn test_double_borrow() {
     let _r2 = tx_user_2.borrow_mut();
 }
 
-#[test]
-#[should_panic(expected = "cannot borrow a removed object")]
-fn test_borrow_created_deleted() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_borrow_created_deleted(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -198,11 +187,7 @@ fn test_borrow_created_deleted() {
     tx_user_2.borrow();
 }
 
-#[test]
-#[should_panic(expected = "cannot borrow a removed object")]
-fn test_borrow_deleted() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_borrow_deleted(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -224,11 +209,7 @@ fn test_borrow_deleted() {
     tx_user_2.borrow();
 }
 
-#[test]
-#[should_panic(expected = "cannot delete a borrowed object")]
-fn test_delete_borrowed() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_delete_borrowed(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     let tx_user = tx
         .create(User {
@@ -430,16 +411,13 @@ fn test_conflict() {
     }
 }
 
-#[test]
-fn test_empty_struct() {
+fn test_empty_struct(mut conn: Connection) {
     #[derive(Object)]
     struct Empty {}
 
     #[derive(Object)]
     struct Void;
 
-    let mut conn = Connection::open_in_memory().unwrap();
-
     let tx = conn.new_transaction().unwrap();
     let empty_id = tx.create::<Empty>(Empty {}).unwrap().id();
     let void_id = tx.create::<Void>(Void).unwrap().id();
@@ -465,12 +443,9 @@ fn test_empty_struct() {
     ));
 }
 
-#[test]
-fn test_sql_injection() {
+fn test_sql_injection(mut conn: Connection) {
     let names = ["\"; DROP TABLE user --", "'; DROP TABLE user --"];
 
-    let mut conn = Connection::open_in_memory().unwrap();
-
     for &name in names.iter() {
         let tx = conn.new_transaction().unwrap();
 
@@ -526,10 +501,7 @@ fn test_table_column_names() {
         .unwrap();
 }
 
-#[test]
-fn test_not_found() {
-    let mut conn = Connection::open_in_memory().unwrap();
-
+fn test_not_found(mut conn: Connection) {
     let tx = conn.new_transaction().unwrap();
     match tx.get::<Order>(3523.into()) {
         Err(orm::Error::NotFound(err)) => {
@@ -632,6 +604,1616 @@ fn test_missing_column_renamed() {
     }
 }
 
+fn test_select(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    for (name, visits) in [
+        ("Ann", 3),
+        ("Bob", 10),
+        ("Carl", 7),
+        ("Dora", 10),
+        ("Bob", 1),
+    ] {
+        tx.create(User {
+            name: name.into(),
+            picture: vec![],
+            visits,
+            balance: 0.,
+            is_admin: name.len() == 3,
+        })
+        .unwrap();
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let names = |users: &[Tx<'_, User>]| {
+        users
+            .iter()
+            .map(|user| user.borrow().name.clone())
+            .collect::<Vec<_>>()
+    };
+
+    let bobs = tx
+        .select::<User>()
+        .filter(|u| u.name.eq("Bob"))
+        .order_by(|u| u.visits)
+        .fetch()
+        .unwrap();
+    assert_eq!(names(&bobs), ["Bob", "Bob"]);
+    assert_eq!(bobs[0].borrow().visits, 1);
+
+    let users = tx
+        .select::<User>()
+        .filter(|u| u.visits.ge(5))
+        .filter(|u| u.is_admin.eq(false) | u.name.eq("Bob"))
+        .order_by(|u| u.visits.desc())
+        .order_by(|u| u.name.desc())
+        .fetch()
+        .unwrap();
+    assert_eq!(names(&users), ["Dora", "Bob", "Carl"]);
+
+    let users = tx
+        .select::<User>()
+        .filter(|u| !u.name.lt("Bob"))
+        .order_by(|u| u.name)
+        .limit(2)
+        .offset(1)
+        .fetch()
+        .unwrap();
+    assert_eq!(names(&users), ["Bob", "Carl"]);
+
+    assert!(tx
+        .select::<User>()
+        .filter(|u| u.name.eq("Eve"))
+        .fetch()
+        .unwrap()
+        .is_empty());
+    assert_eq!(tx.select::<User>().fetch().unwrap().len(), 5);
+}
+
+fn test_select_identity_map(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let ann = tx
+        .create(User {
+            name: "Ann".into(),
+            picture: vec![],
+            visits: 3,
+            balance: 0.,
+            is_admin: false,
+        })
+        .unwrap();
+    let bob = tx
+        .create(User {
+            name: "Bob".into(),
+            picture: vec![],
+            visits: 5,
+            balance: 0.,
+            is_admin: false,
+        })
+        .unwrap();
+
+    ann.borrow_mut().visits = 8;
+    let bob_id = bob.id();
+    bob.delete();
+
+    let users = tx
+        .select::<User>()
+        .filter(|u| u.visits.gt(4))
+        .fetch()
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].id(), ann.id());
+
+    users[0].borrow_mut().balance = 20.;
+    assert_eq!(ann.borrow().balance, 20.);
+    assert_not_found(tx.get::<User>(bob_id), bob_id, "User");
+
+    let ann_id = ann.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let users = tx.select::<User>().fetch().unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].id(), ann_id);
+    assert_eq!(users[0].borrow().visits, 8);
+    assert_eq!(users[0].borrow().balance, 20.);
+}
+
+fn test_select_while_borrowed(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let ann_id = tx.create(user("Ann", 3)).unwrap().id();
+    let bob_id = tx.create(user("Bob", 5)).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let ann = tx.get::<User>(ann_id).unwrap();
+    let bob = tx.get::<User>(bob_id).unwrap();
+    ann.borrow_mut().visits = 10;
+    {
+        let mut bob = bob.borrow_mut();
+        bob.visits = 20;
+        // Bob can't be written while borrowed, so the query sees him as he was before.
+        let users = tx
+            .select::<User>()
+            .filter(|u| u.visits.ge(10))
+            .fetch()
+            .unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].id(), ann_id);
+        bob.visits = 30;
+    }
+    assert_eq!(ann.state(), ObjectState::Modified);
+    assert_eq!(bob.state(), ObjectState::Modified);
+
+    let users = tx
+        .select::<User>()
+        .filter(|u| u.visits.ge(30))
+        .fetch()
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].id(), bob_id);
+    assert_eq!(ann.state(), ObjectState::Modified);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<User>(bob_id).unwrap().borrow().visits, 30);
+    assert_eq!(tx.get::<User>(bob_id).unwrap().state(), ObjectState::Clean);
+
+    // A new object can't be left out of the query, so it fails.
+    let carol = tx.create(user("Carol", 40)).unwrap();
+    {
+        let _carol = carol.borrow_mut();
+        match tx.select::<User>().fetch() {
+            Err(orm::Error::ObjectBorrowed(err)) => {
+                assert_eq!(err.object_id, carol.id());
+                assert_eq!(err.type_name, "User");
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("the query has skipped a new object"),
+        }
+    }
+    let users = tx
+        .select::<User>()
+        .filter(|u| u.visits.ge(40))
+        .fetch()
+        .unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].id(), carol.id());
+}
+
+fn test_select_missing_table(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    assert!(tx
+        .select::<Order>()
+        .filter(|o| o.is_tall.eq(true))
+        .fetch()
+        .unwrap()
+        .is_empty());
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object, Debug)]
+struct Customer {
+    name: String,
+}
+
+#[derive(Object)]
+struct Purchase {
+    #[on_delete("cascade")]
+    customer: Ref<Customer>,
+    price: i64,
+}
+
+#[derive(Object)]
+struct Review {
+    #[on_delete("nullify")]
+    author: Ref<Customer>,
+    text: String,
+}
+
+#[derive(Object)]
+struct Invoice {
+    customer: Ref<Customer>,
+}
+
+fn create_customer(conn: &mut Connection, name: &str) -> ObjectId {
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(Customer { name: name.into() }).unwrap().id();
+    tx.commit().unwrap();
+    id
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn test_ref(mut conn: Connection) {
+    let alice_id = create_customer(&mut conn, "Alice");
+    let bob_id = create_customer(&mut conn, "Bob");
+
+    let tx = conn.new_transaction().unwrap();
+    let alice = tx.get::<Customer>(alice_id).unwrap();
+    let purchase = tx
+        .create(Purchase {
+            customer: Ref::from(&alice),
+            price: 10,
+        })
+        .unwrap();
+    tx.create(Purchase {
+        customer: Ref::new(bob_id),
+        price: 20,
+    })
+    .unwrap();
+    let review_id = tx
+        .create(Review {
+            author: Ref::null(),
+            text: "anonymous".into(),
+        })
+        .unwrap()
+        .id();
+    let purchase_id = purchase.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let purchase = tx.get::<Purchase>(purchase_id).unwrap();
+    let customer = purchase.borrow().customer.get(&tx).unwrap().unwrap();
+    assert_eq!(customer.id(), alice_id);
+    customer.borrow_mut().name = "Alice Smith".into();
+    assert_eq!(
+        tx.get::<Customer>(alice_id).unwrap().borrow().name,
+        "Alice Smith"
+    );
+
+    let review = tx.get::<Review>(review_id).unwrap();
+    assert!(review.borrow().author.is_null());
+    assert!(review.borrow().author.get(&tx).unwrap().is_none());
+
+    let purchases = tx
+        .select::<Purchase>()
+        .filter(|p| p.customer.eq(Ref::new(bob_id)))
+        .fetch()
+        .unwrap();
+    assert_eq!(purchases.len(), 1);
+    assert_eq!(purchases[0].borrow().price, 20);
+
+    let reviews = tx
+        .select::<Review>()
+        .filter(|r| r.author.eq(Ref::null()))
+        .fetch()
+        .unwrap();
+    assert_eq!(reviews.len(), 1);
+    assert_eq!(reviews[0].id(), review_id);
+}
+
+fn test_on_delete_cascade_nullify(mut conn: Connection) {
+    let alice_id = create_customer(&mut conn, "Alice");
+
+    let tx = conn.new_transaction().unwrap();
+    let purchase_id = tx
+        .create(Purchase {
+            customer: Ref::new(alice_id),
+            price: 10,
+        })
+        .unwrap()
+        .id();
+    let review_id = tx
+        .create(Review {
+            author: Ref::new(alice_id),
+            text: "great".into(),
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Customer>(alice_id).unwrap().delete();
+    // NB: the policies are only applied on commit.
+    assert!(tx.get::<Purchase>(purchase_id).is_ok());
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert!(matches!(
+        tx.get::<Purchase>(purchase_id),
+        Err(orm::Error::NotFound(_))
+    ));
+    let review = tx.get::<Review>(review_id).unwrap();
+    assert!(review.borrow().author.is_null());
+    assert_eq!(review.borrow().text, "great");
+}
+
+fn test_on_delete_restrict(mut conn: Connection) {
+    let alice_id = create_customer(&mut conn, "Alice");
+
+    let tx = conn.new_transaction().unwrap();
+    let invoice_id = tx
+        .create(Invoice {
+            customer: Ref::new(alice_id),
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Customer>(alice_id).unwrap().delete();
+    match tx.commit() {
+        Err(orm::Error::DanglingReference(err)) => {
+            assert_eq!(err.table_name, "Invoice");
+            assert_eq!(err.column_name, "customer");
+            assert_eq!(err.object_id, invoice_id);
+            assert_eq!(err.target_table, "Customer");
+        }
+        res => panic!("expected DanglingReference, got {}", fmt_res(&res)),
+    }
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<Customer>(alice_id).unwrap().delete();
+    tx.get::<Invoice>(invoice_id).unwrap().delete();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.create(Invoice {
+        customer: Ref::new(alice_id),
+    })
+    .unwrap();
+    assert!(matches!(tx.commit(), Err(orm::Error::DanglingReference(_))));
+}
+
+fn test_select_skips_removed(mut conn: Connection) {
+    for name in ["Alice", "Bob", "Carol"] {
+        create_customer(&mut conn, name);
+    }
+
+    let tx = conn.new_transaction().unwrap();
+    let customers = tx
+        .select::<Customer>()
+        .order_by(|c| c.name)
+        .fetch()
+        .unwrap();
+    customers[0].clone().delete();
+
+    let customers = tx
+        .select::<Customer>()
+        .order_by(|c| c.name)
+        .limit(1)
+        .fetch()
+        .unwrap();
+    assert_eq!(customers[0].borrow().name, "Bob");
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object)]
+#[table_name("profile")]
+struct ProfileV1 {
+    name: String,
+    nick: String,
+    legacy: i64,
+}
+
+#[derive(Object)]
+#[table_name("profile")]
+#[orm(drop_column = "legacy")]
+struct ProfileV2 {
+    name: String,
+    #[orm(renamed_from = "nick")]
+    handle: String,
+    #[orm(default = 18)]
+    age: i64,
+    #[orm(default = "unknown")]
+    city: String,
+    friend: Ref<Customer>,
+}
+
+#[derive(Object)]
+#[table_name("profile")]
+struct ProfileV3 {
+    #[orm(default = 0)]
+    name: i64,
+    #[orm(default = false)]
+    is_new: bool,
+}
+
+fn profile_columns(path: &std::path::Path) -> Vec<String> {
+    let sqlite_conn = rusqlite::Connection::open(path).unwrap();
+    let mut stmt = sqlite_conn
+        .prepare("SELECT name FROM pragma_table_info('profile')")
+        .unwrap();
+    let columns = stmt
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    columns
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn test_migration(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(ProfileV1 {
+            name: "Alice".into(),
+            nick: "al".into(),
+            legacy: 5,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let profile = tx.get::<ProfileV2>(id).unwrap();
+    assert_eq!(profile.borrow().name, "Alice");
+    assert_eq!(profile.borrow().handle, "al");
+    assert_eq!(profile.borrow().age, 18);
+    assert_eq!(profile.borrow().city, "unknown");
+    assert!(profile.borrow().friend.is_null());
+    profile.borrow_mut().age += 1;
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<ProfileV2>(id).unwrap().borrow().age, 19);
+}
+
+fn test_migration_unexpected_type(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let id = tx
+        .create(ProfileV1 {
+            name: "Bob".into(),
+            nick: "bobby".into(),
+            legacy: 0,
+        })
+        .unwrap()
+        .id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    match tx.get::<ProfileV3>(id) {
+        Err(orm::Error::UnexpectedType(err)) => {
+            assert_eq!(err.type_name, "ProfileV3");
+            assert_eq!(err.table_name, "profile");
+            assert_eq!(err.attr_name, "name");
+            assert_eq!(err.expected_type, DataType::Int64);
+            assert_eq!(err.got_type, "Text");
+        }
+        res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.select::<ProfileV1>().fetch().unwrap().len(), 1);
+}
+
+#[test]
+fn test_migration_columns() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    test_migration_unexpected_type(Connection::open_sqlite_file(&path).unwrap());
+    assert_eq!(profile_columns(&path), ["id", "name", "nick", "legacy"]);
+
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    test_migration(Connection::open_sqlite_file(&path).unwrap());
+    assert_eq!(
+        profile_columns(&path),
+        ["id", "name", "handle", "age", "city", "friend"]
+    );
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object)]
+#[table_name("member")]
+#[unique(first_name, last_name)]
+struct Member {
+    #[unique]
+    login: String,
+    #[index]
+    city: String,
+    first_name: String,
+    last_name: String,
+    nickname: Option<String>,
+}
+
+#[derive(Object)]
+#[table_name("member")]
+struct MemberV1 {
+    login: String,
+    city: String,
+    first_name: String,
+    last_name: String,
+    nickname: Option<String>,
+}
+
+fn member(login: &str, first_name: &str, last_name: &str) -> Member {
+    Member {
+        login: login.into(),
+        city: "Paris".into(),
+        first_name: first_name.into(),
+        last_name: last_name.into(),
+        nickname: None,
+    }
+}
+
+fn assert_unique_violation<T>(res: Result<T>, attr_names: &[&str], values: &[&str]) {
+    match res {
+        Err(orm::Error::UniqueViolation(err)) => {
+            assert_eq!(err.type_name, "Member");
+            assert_eq!(err.table_name, "member");
+            assert_eq!(err.attr_names, attr_names);
+            let values: Vec<_> = values
+                .iter()
+                .map(|&value| Value::String(value.to_string().into()))
+                .collect();
+            assert_eq!(err.values, values);
+        }
+        res => panic!("expected Error::UniqueViolation, got {}", fmt_res(&res)),
+    }
+}
+
+fn test_unique(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("alice", "Alice", "Smith")).unwrap();
+    tx.create(member("bob", "Bob", "Smith")).unwrap();
+    tx.commit().unwrap();
+
+    // NB: new objects are only checked when they are written.
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("alice", "Alicia", "Jones")).unwrap();
+    assert_unique_violation(tx.commit(), &["login"], &["alice"]);
+
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("erin", "Erin", "Brown")).unwrap();
+    tx.create(member("erin", "Erin", "Green")).unwrap();
+    assert_unique_violation(tx.select::<Member>().fetch(), &["login"], &["erin"]);
+    tx.rollback().unwrap();
+
+    // NB: NULLs never collide, and neither do non-unique indexes.
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("carol", "Carol", "Smith")).unwrap();
+    tx.create(member("dave", "Dave", "Smith")).unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let bob = tx
+        .select::<Member>()
+        .filter(|m| m.login.eq("bob"))
+        .fetch()
+        .unwrap();
+    bob[0].borrow_mut().first_name = "Alice".into();
+    assert_unique_violation(
+        tx.commit(),
+        &["first_name", "last_name"],
+        &["Alice", "Smith"],
+    );
+
+    let tx = conn.new_transaction().unwrap();
+    let members = tx.select::<Member>().order_by(|m| m.login).fetch().unwrap();
+    let logins: Vec<_> = members.iter().map(|m| m.borrow().login.clone()).collect();
+    assert_eq!(logins, ["alice", "bob", "carol", "dave"]);
+    assert_eq!(members[1].borrow().first_name, "Bob");
+
+    // Swapping values through a third one is fine.
+    members[0].borrow_mut().login = "tmp".into();
+    tx.select::<Member>().fetch().unwrap();
+    members[1].borrow_mut().login = "alice".into();
+    tx.select::<Member>().fetch().unwrap();
+    members[0].borrow_mut().login = "bob".into();
+    tx.commit().unwrap();
+}
+
+fn test_unique_fix_duplicate(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("alice", "Alice", "Smith")).unwrap();
+    tx.commit().unwrap();
+
+    // NB: rows written before the duplicate one must not stay behind once it fails.
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("bob", "Bob", "Smith")).unwrap();
+    let duplicate = tx.create(member("alice", "Alicia", "Jones")).unwrap();
+    tx.create(member("carol", "Carol", "Smith")).unwrap();
+    assert_unique_violation(tx.select::<Member>().fetch(), &["login"], &["alice"]);
+    duplicate.borrow_mut().login = "alicia".into();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let members = tx.select::<Member>().order_by(|m| m.login).fetch().unwrap();
+    let logins: Vec<_> = members.iter().map(|m| m.borrow().login.clone()).collect();
+    assert_eq!(logins, ["alice", "alicia", "bob", "carol"]);
+}
+
+fn test_unique_migration(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    tx.create(MemberV1 {
+        login: "alice".into(),
+        city: "Paris".into(),
+        first_name: "Alice".into(),
+        last_name: "Smith".into(),
+        nickname: None,
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("bob", "Bob", "Smith")).unwrap();
+    tx.create(member("alice", "Alicia", "Jones")).unwrap();
+    assert_unique_violation(tx.commit(), &["login"], &["alice"]);
+}
+
+#[derive(Object)]
+#[table_name("member")]
+#[orm(drop_column = "login")]
+struct MemberV2 {
+    city: String,
+    first_name: String,
+    last_name: String,
+    nickname: Option<String>,
+}
+
+fn test_unique_migration_drop_column(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    tx.create(member("alice", "Alice", "Smith")).unwrap();
+    tx.commit().unwrap();
+
+    // NB: the unique index over the dropped column goes along with it.
+    let tx = conn.new_transaction().unwrap();
+    tx.create(MemberV2 {
+        city: "Paris".into(),
+        first_name: "Bob".into(),
+        last_name: "Smith".into(),
+        nickname: None,
+    })
+    .unwrap();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let members = tx
+        .select::<MemberV2>()
+        .order_by(|m| m.first_name)
+        .fetch()
+        .unwrap();
+    let names: Vec<_> = members
+        .iter()
+        .map(|m| m.borrow().first_name.clone())
+        .collect();
+    assert_eq!(names, ["Alice", "Bob"]);
+}
+
+#[test]
+fn test_unique_migration_duplicates() {
+    for mut conn in [
+        Connection::open_in_memory().unwrap(),
+        Connection::open_memory_native().unwrap(),
+    ] {
+        let tx = conn.new_transaction().unwrap();
+        for first_name in ["Alice", "Alicia"] {
+            tx.create(MemberV1 {
+                login: "alice".into(),
+                city: "Paris".into(),
+                first_name: first_name.into(),
+                last_name: "Smith".into(),
+                nickname: None,
+            })
+            .unwrap();
+        }
+        tx.commit().unwrap();
+
+        let tx = conn.new_transaction().unwrap();
+        assert_unique_violation(tx.select::<Member>().fetch(), &["login"], &["alice"]);
+    }
+}
+
+#[test]
+fn test_native_unique_concurrent() {
+    let mut conn_one = Connection::open_memory_native().unwrap();
+    let mut conn_two = conn_one.try_clone().unwrap();
+    let tx = conn_one.new_transaction().unwrap();
+    tx.create(member("alice", "Alice", "Smith")).unwrap();
+    tx.commit().unwrap();
+
+    let tx_one = conn_one.new_transaction().unwrap();
+    let tx_two = conn_two.new_transaction().unwrap();
+    tx_one.create(member("bob", "Bob", "Smith")).unwrap();
+    tx_two.create(member("bob", "Robert", "Jones")).unwrap();
+    tx_one.commit().unwrap();
+    assert_unique_violation(tx_two.commit(), &["login"], &["bob"]);
+}
+
+#[derive(orm::Enum, Clone, Copy, Debug, PartialEq)]
+enum Status {
+    Open,
+    #[orm(rename = "in progress")]
+    InProgress,
+    Done,
+}
+
+#[derive(orm::Enum, Clone, Copy, Debug, PartialEq)]
+#[orm(repr = "integer")]
+enum Priority {
+    Low = 1,
+    High = 10,
+}
+
+#[derive(Object, Debug, PartialEq)]
+struct Task {
+    title: String,
+    status: Status,
+    priority: Priority,
+    due: Option<SystemTime>,
+    created: SystemTime,
+    estimate: Option<f64>,
+    note: Option<String>,
+    is_flagged: Option<bool>,
+}
+
+fn task(title: &str, status: Status, due: Option<SystemTime>) -> Task {
+    Task {
+        title: title.into(),
+        status,
+        priority: Priority::Low,
+        due,
+        created: UNIX_EPOCH + Duration::from_micros(1_600_000_000_123_456),
+        estimate: None,
+        note: None,
+        is_flagged: None,
+    }
+}
+
+fn test_nullable(mut conn: Connection) {
+    let before_epoch = UNIX_EPOCH - Duration::from_secs(86_400);
+    let tx = conn.new_transaction().unwrap();
+    let write_id = tx
+        .create(Task {
+            priority: Priority::High,
+            estimate: Some(2.5),
+            note: Some("draft first".into()),
+            is_flagged: Some(false),
+            ..task("write", Status::InProgress, Some(before_epoch))
+        })
+        .unwrap()
+        .id();
+    let review_id = tx.create(task("review", Status::Open, None)).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let write = tx.get::<Task>(write_id).unwrap();
+    assert_eq!(write.borrow().status, Status::InProgress);
+    assert_eq!(write.borrow().priority, Priority::High);
+    assert_eq!(write.borrow().due, Some(before_epoch));
+    assert_eq!(write.borrow().estimate, Some(2.5));
+    assert_eq!(write.borrow().note.as_deref(), Some("draft first"));
+    assert_eq!(write.borrow().is_flagged, Some(false));
+    let review = tx.get::<Task>(review_id).unwrap();
+    assert_eq!(*review.borrow(), task("review", Status::Open, None));
+
+    let undated = tx
+        .select::<Task>()
+        .filter(|t| t.due.eq(None))
+        .fetch()
+        .unwrap();
+    assert_eq!(undated.len(), 1);
+    assert_eq!(undated[0].id(), review_id);
+    let open = tx
+        .select::<Task>()
+        .filter(|t| t.status.eq(Status::Open) | t.priority.eq(Priority::High))
+        .order_by(|t| t.priority.desc())
+        .fetch()
+        .unwrap();
+    assert_eq!(open.len(), 2);
+    assert_eq!(open[0].id(), write_id);
+
+    write.borrow_mut().note = None;
+    review.borrow_mut().due = Some(UNIX_EPOCH);
+    let dated = tx
+        .select::<Task>()
+        .filter(|t| t.due.lt(Some(UNIX_EPOCH + Duration::from_secs(1))))
+        .order_by(|t| t.due)
+        .fetch()
+        .unwrap();
+    assert_eq!(dated.len(), 2);
+    assert_eq!(dated[0].id(), write_id);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<Task>(write_id).unwrap().borrow().note, None);
+    assert_eq!(
+        tx.get::<Task>(review_id).unwrap().borrow().due,
+        Some(UNIX_EPOCH)
+    );
+}
+
+#[test]
+fn test_column_types_unexpected_type() {
+    fn check(create_sql: &str, attr_name: &str, expected_type: DataType, got_type: &str) {
+        let path = NamedTempFile::new().unwrap().into_temp_path();
+        let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+        sqlite_conn.execute(create_sql, []).unwrap();
+        sqlite_conn.close().unwrap();
+
+        let mut conn = Connection::open_sqlite_file(&path).unwrap();
+        let tx = conn.new_transaction().unwrap();
+        match tx.get::<Task>(1.into()) {
+            Err(orm::Error::UnexpectedType(err)) => {
+                assert_eq!(err.table_name, "Task");
+                assert_eq!(err.attr_name, attr_name);
+                assert_eq!(err.expected_type, expected_type);
+                assert_eq!(err.got_type, got_type);
+            }
+            res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+        }
+    }
+
+    let columns = [
+        ("title", "TEXT"),
+        ("status", "TEXT"),
+        ("priority", "BIGINT"),
+        ("due", "TIMESTAMP"),
+        ("created", "TIMESTAMP"),
+        ("estimate", "REAL"),
+        ("note", "TEXT"),
+        ("is_flagged", "TINYINT"),
+    ];
+    let create_sql = |column: &str, sql_type: &str| {
+        let columns: Vec<_> = columns
+            .iter()
+            .map(|&(name, default)| match name == column {
+                true => format!("{} {}", name, sql_type),
+                false => format!("{} {}", name, default),
+            })
+            .collect();
+        format!(
+            "CREATE TABLE Task (id INTEGER PRIMARY KEY, {})",
+            columns.join(", ")
+        )
+    };
+
+    check(
+        &create_sql("status", "INTEGER"),
+        "status",
+        DataType::String,
+        "Integer",
+    );
+    check(
+        &create_sql("priority", "TEXT"),
+        "priority",
+        DataType::Int64,
+        "Text",
+    );
+    check(
+        &create_sql("due", "TEXT"),
+        "due",
+        DataType::Timestamp,
+        "Text",
+    );
+    check(
+        &create_sql("note", "BLOB"),
+        "note",
+        DataType::String,
+        "Blob",
+    );
+    check(
+        &create_sql("is_flagged", "REAL"),
+        "is_flagged",
+        DataType::Bool,
+        "Real",
+    );
+}
+
+#[test]
+fn test_unknown_enum_variant() {
+    fn check(update_sql: &str, attr_name: &str, expected_type: DataType, got_type: &str) {
+        let path = NamedTempFile::new().unwrap().into_temp_path();
+        let mut conn = Connection::open_sqlite_file(&path).unwrap();
+        let tx = conn.new_transaction().unwrap();
+        let id = tx.create(task("write", Status::Open, None)).unwrap().id();
+        tx.commit().unwrap();
+
+        let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+        sqlite_conn.execute(update_sql, []).unwrap();
+        sqlite_conn.close().unwrap();
+
+        let tx = conn.new_transaction().unwrap();
+        match tx.get::<Task>(id) {
+            Err(orm::Error::UnexpectedType(err)) => {
+                assert_eq!(err.attr_name, attr_name);
+                assert_eq!(err.expected_type, expected_type);
+                assert_eq!(err.got_type, got_type);
+            }
+            res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+        }
+        match tx.select::<Task>().fetch() {
+            Err(orm::Error::UnexpectedType(err)) => assert_eq!(err.attr_name, attr_name),
+            res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+        }
+    }
+
+    check(
+        "UPDATE Task SET status = 'archived'",
+        "status",
+        DataType::String,
+        "unknown variant of Status: \"archived\"",
+    );
+    check(
+        "UPDATE Task SET priority = 5",
+        "priority",
+        DataType::Int64,
+        "unknown variant of Priority: 5",
+    );
+}
+
+#[cfg(feature = "json")]
+mod json {
+    use super::*;
+    use orm::Json;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Settings {
+        theme: String,
+        font_size: u32,
+        shortcuts: Vec<String>,
+    }
+
+    #[derive(Object)]
+    struct Account {
+        login: String,
+        settings: Json<Settings>,
+        extra: Option<Json<Vec<i64>>>,
+    }
+
+    pub(super) fn test_json(mut conn: Connection) {
+        let settings = Settings {
+            theme: "dark".into(),
+            font_size: 12,
+            shortcuts: vec!["ctrl+s".into()],
+        };
+        let tx = conn.new_transaction().unwrap();
+        let id = tx
+            .create(Account {
+                login: "alice".into(),
+                settings: Json(settings.clone()),
+                extra: None,
+            })
+            .unwrap()
+            .id();
+        tx.commit().unwrap();
+
+        let tx = conn.new_transaction().unwrap();
+        let account = tx.get::<Account>(id).unwrap();
+        assert_eq!(*account.borrow().settings, settings);
+        assert!(account.borrow().extra.is_none());
+        account.borrow_mut().settings.font_size = 14;
+        account.borrow_mut().extra = Some(Json(vec![1, 2, 3]));
+        tx.commit().unwrap();
+
+        let tx = conn.new_transaction().unwrap();
+        let account = tx.get::<Account>(id).unwrap();
+        assert_eq!(account.borrow().settings.font_size, 14);
+        assert_eq!(account.borrow().extra, Some(Json(vec![1, 2, 3])));
+    }
+
+    #[test]
+    fn test_json_unexpected_type() {
+        let path = NamedTempFile::new().unwrap().into_temp_path();
+        let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+        sqlite_conn
+            .execute(
+                "CREATE TABLE Account (id INTEGER PRIMARY KEY, login TEXT, settings BIGINT, extra TEXT)",
+                [],
+            )
+            .unwrap();
+        sqlite_conn.close().unwrap();
+
+        let mut conn = Connection::open_sqlite_file(&path).unwrap();
+        let tx = conn.new_transaction().unwrap();
+        match tx.get::<Account>(1.into()) {
+            Err(orm::Error::UnexpectedType(err)) => {
+                assert_eq!(err.attr_name, "settings");
+                assert_eq!(err.expected_type, DataType::Json);
+                assert_eq!(err.got_type, "Integer");
+            }
+            res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+        }
+    }
+
+    #[test]
+    fn test_json_invalid_text() {
+        let path = NamedTempFile::new().unwrap().into_temp_path();
+        let sqlite_conn = rusqlite::Connection::open(&path).unwrap();
+        sqlite_conn
+            .execute(
+                "CREATE TABLE Account (id INTEGER PRIMARY KEY, login TEXT, settings TEXT, extra TEXT)",
+                [],
+            )
+            .unwrap();
+        sqlite_conn
+            .execute(
+                "INSERT INTO Account VALUES (1, 'alice', '{\"theme\": 42}', NULL)",
+                [],
+            )
+            .unwrap();
+        sqlite_conn.close().unwrap();
+
+        let mut conn = Connection::open_sqlite_file(&path).unwrap();
+        let tx = conn.new_transaction().unwrap();
+        match tx.get::<Account>(1.into()) {
+            Err(orm::Error::UnexpectedType(err)) => {
+                assert_eq!(err.attr_name, "settings");
+                assert_eq!(err.expected_type, DataType::Json);
+                assert!(
+                    err.got_type.starts_with("invalid JSON: "),
+                    "{}",
+                    err.got_type
+                );
+            }
+            res => panic!("expected Error::UnexpectedType, got {}", fmt_res(&res)),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+use json::test_json;
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn test_batch(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let users: Vec<_> = (0..1000)
+        .map(|i| {
+            tx.create(User {
+                name: format!("user{}", i),
+                picture: vec![],
+                visits: i,
+                balance: 0.,
+                is_admin: false,
+            })
+            .unwrap()
+        })
+        .collect();
+    for user in users.iter().step_by(2) {
+        user.borrow_mut().visits *= 10;
+    }
+    let ids: Vec<_> = users.iter().map(|user| user.id()).collect();
+    for user in users.into_iter().step_by(3) {
+        user.delete();
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let users = tx.select::<User>().order_by(|u| u.visits).fetch().unwrap();
+    assert_eq!(users.len(), 666);
+    for (i, &id) in ids.iter().enumerate() {
+        let res = tx.get::<User>(id);
+        if i % 3 == 0 {
+            assert_not_found(res, id, "User");
+            continue;
+        }
+        let user = res.unwrap();
+        let visits = if i % 2 == 0 { i * 10 } else { i };
+        assert_eq!(user.borrow().visits, visits as i64);
+        assert_eq!(user.borrow().name, format!("user{}", i));
+        user.borrow_mut().visits = 0;
+    }
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    let users = tx
+        .select::<User>()
+        .filter(|u| u.visits.eq(0))
+        .fetch()
+        .unwrap();
+    assert_eq!(users.len(), 666);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn user(name: &str, visits: i64) -> User {
+    User {
+        name: name.into(),
+        picture: vec![0xca, 0xfe],
+        visits,
+        balance: 0.5,
+        is_admin: false,
+    }
+}
+
+fn test_on_commit(mut conn: Connection) {
+    let log = Arc::new(Mutex::new(vec![]));
+    let observer_log = log.clone();
+    conn.on_commit(move |changes: &[Change]| {
+        observer_log.lock().unwrap().push(changes.to_vec());
+    });
+    let field = |attr_name, old_value, new_value| FieldChange {
+        attr_name,
+        old_value,
+        new_value,
+    };
+
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(user("John", 1)).unwrap().id();
+    tx.create(user("Jane", 1)).unwrap().delete();
+    tx.commit().unwrap();
+    assert_eq!(
+        log.lock().unwrap().pop().unwrap(),
+        [Change {
+            type_name: "User",
+            table_name: "User",
+            object_id: id,
+            kind: ChangeKind::Create,
+            fields: vec![
+                field("name", None, Some(Value::String("John".into()))),
+                field("picture", None, Some(Value::Bytes(vec![0xca, 0xfe].into()))),
+                field("visits", None, Some(Value::Int64(1))),
+                field("balance", None, Some(Value::Float64(0.5))),
+                field("is_admin", None, Some(Value::Bool(false))),
+            ],
+        }]
+    );
+
+    let tx = conn.new_transaction().unwrap();
+    let john = tx.get::<User>(id).unwrap();
+    john.borrow_mut().visits += 1;
+    tx.rollback().unwrap();
+    assert!(log.lock().unwrap().is_empty());
+
+    // NB: only the fields that differ from the loaded row are reported.
+    let tx = conn.new_transaction().unwrap();
+    let john = tx.get::<User>(id).unwrap();
+    john.borrow_mut().visits += 1;
+    john.borrow_mut().is_admin = false;
+    tx.select::<User>().fetch().unwrap();
+    john.borrow_mut().visits += 1;
+    tx.commit().unwrap();
+    assert_eq!(
+        log.lock().unwrap().pop().unwrap(),
+        [Change {
+            type_name: "User",
+            table_name: "User",
+            object_id: id,
+            kind: ChangeKind::Update,
+            fields: vec![field(
+                "visits",
+                Some(Value::Int64(1)),
+                Some(Value::Int64(3))
+            )],
+        }]
+    );
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<User>(id).unwrap().borrow_mut();
+    tx.commit().unwrap();
+    assert_eq!(log.lock().unwrap().pop().unwrap(), []);
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<User>(id).unwrap().delete();
+    tx.commit().unwrap();
+    let changes = log.lock().unwrap().pop().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].kind, ChangeKind::Delete);
+    assert_eq!(
+        changes[0].fields[2],
+        field("visits", Some(Value::Int64(3)), None)
+    );
+}
+
+fn test_audit_log(mut conn: Connection) {
+    conn.enable_audit_log();
+    let audit_entries = |conn: &mut Connection| {
+        let tx = conn.new_transaction().unwrap();
+        let entries = tx.select::<AuditEntry>().fetch().unwrap();
+        let mut entries: Vec<_> = entries.iter().map(|e| e.borrow().clone()).collect();
+        entries.sort_by_key(|e| {
+            (
+                e.committed_at,
+                e.kind != ChangeKind::Create,
+                e.attr_name.clone(),
+            )
+        });
+        entries
+    };
+
+    let tx = conn.new_transaction().unwrap();
+    let id = tx.create(user("John", 1)).unwrap().id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<User>(id).unwrap().borrow_mut().visits += 1;
+    tx.rollback().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    tx.get::<User>(id).unwrap().borrow_mut().visits += 1;
+    tx.commit().unwrap();
+
+    let entries = audit_entries(&mut conn);
+    let texts: Vec<_> = entries
+        .iter()
+        .map(|e| {
+            assert_eq!(e.type_name, "User");
+            assert_eq!(e.object_id, id.into_i64());
+            (
+                e.kind,
+                e.attr_name.as_str(),
+                e.old_value.as_deref(),
+                e.new_value.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        texts,
+        [
+            (ChangeKind::Create, "balance", None, Some("0.5")),
+            (ChangeKind::Create, "is_admin", None, Some("false")),
+            (ChangeKind::Create, "name", None, Some("John")),
+            (ChangeKind::Create, "picture", None, Some("xcafe")),
+            (ChangeKind::Create, "visits", None, Some("1")),
+            (ChangeKind::Update, "visits", Some("1"), Some("2")),
+        ]
+    );
+    assert!(entries[0].committed_at <= entries[5].committed_at);
+
+    // Reading the log changes nothing.
+    assert_eq!(audit_entries(&mut conn).len(), 6);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn test_savepoint(mut conn: Connection) {
+    let count_users = |tx: &orm::Transaction| tx.select::<User>().fetch().unwrap().len();
+
+    let tx = conn.new_transaction().unwrap();
+    let john = tx.create(user("John", 1)).unwrap();
+    let sp = tx.savepoint().unwrap();
+    john.borrow_mut().visits = 2;
+    let jane = tx.create(user("Jane", 1)).unwrap();
+    assert_eq!(count_users(&tx), 2);
+    sp.rollback().unwrap();
+    assert_eq!(john.borrow().visits, 1);
+    assert_eq!(john.state(), ObjectState::Clean);
+    assert_eq!(jane.state(), ObjectState::Removed);
+    assert_eq!(count_users(&tx), 1);
+
+    // A table created within a savepoint is dropped along with it.
+    let sp = tx.savepoint().unwrap();
+    tx.create(Customer {
+        name: "Alice".into(),
+    })
+    .unwrap();
+    tx.select::<Customer>().fetch().unwrap();
+    sp.rollback().unwrap();
+    tx.create(Customer { name: "Bob".into() }).unwrap();
+
+    let outer = tx.savepoint().unwrap();
+    john.borrow_mut().visits = 3;
+    let inner = tx.savepoint().unwrap();
+    john.borrow_mut().visits = 4;
+    john.clone().delete();
+    tx.create(user("Bob", 1)).unwrap();
+    assert_eq!(count_users(&tx), 1);
+    inner.rollback().unwrap();
+    assert_eq!(john.state(), ObjectState::Modified);
+    assert_eq!(john.borrow().visits, 3);
+    assert_eq!(count_users(&tx), 1);
+
+    let inner = tx.savepoint().unwrap();
+    john.borrow_mut().visits = 5;
+    outer.release().unwrap();
+    // NB: releasing the outer savepoint has released the inner one, so dropping it is a no-op.
+    drop(inner);
+    assert_eq!(john.borrow().visits, 5);
+
+    {
+        let _sp = tx.savepoint().unwrap();
+        john.borrow_mut().visits = 6;
+    }
+    assert_eq!(john.borrow().visits, 5);
+    let john_id = john.id();
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(count_users(&tx), 1);
+    assert_eq!(tx.select::<Customer>().fetch().unwrap().len(), 1);
+
+    // Objects loaded within a savepoint go back to their stored values.
+    let sp = tx.savepoint().unwrap();
+    let john = tx.get::<User>(john_id).unwrap();
+    assert_eq!(john.borrow().visits, 5);
+    john.borrow_mut().visits = 7;
+    assert_eq!(count_users(&tx), 1);
+    sp.rollback().unwrap();
+    assert_eq!(john.state(), ObjectState::Clean);
+    assert_eq!(john.borrow().visits, 5);
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<User>(john_id).unwrap().borrow().visits, 5);
+}
+
+fn test_savepoint_borrowed(mut conn: Connection) {
+    let tx = conn.new_transaction().unwrap();
+    let john = tx.create(user("John", 1)).unwrap();
+    let ann = tx.create(user("Ann", 2)).unwrap();
+    {
+        let _john = john.borrow_mut();
+        assert!(matches!(tx.savepoint(), Err(orm::Error::ObjectBorrowed(_))));
+    }
+
+    // A borrowed object keeps its value, the others are restored.
+    let sp = tx.savepoint().unwrap();
+    john.borrow_mut().visits = 10;
+    ann.borrow_mut().visits = 20;
+    {
+        let _john = john.borrow();
+        assert!(matches!(sp.rollback(), Err(orm::Error::ObjectBorrowed(_))));
+    }
+    assert_eq!(john.borrow().visits, 10);
+    assert_eq!(ann.borrow().visits, 2);
+
+    // The same goes for a savepoint dropped while an object is borrowed.
+    let sp = tx.savepoint().unwrap();
+    ann.borrow_mut().visits = 30;
+    let ann_ref = ann.borrow();
+    drop(sp);
+    assert_eq!(ann_ref.visits, 30);
+    drop(ann_ref);
+    let (john_id, ann_id) = (john.id(), ann.id());
+    tx.commit().unwrap();
+
+    let tx = conn.new_transaction().unwrap();
+    assert_eq!(tx.get::<User>(john_id).unwrap().borrow().visits, 10);
+    assert_eq!(tx.get::<User>(ann_id).unwrap().borrow().visits, 30);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_try_clone() {
+    let path = NamedTempFile::new().unwrap().into_temp_path();
+    let mut conn = Connection::open_sqlite_file(&path).unwrap();
+    let id = create_customer(&mut conn, "Alice");
+
+    let mut conn_two = conn.try_clone().unwrap();
+    let tx = conn_two.new_transaction().unwrap();
+    assert_eq!(tx.get::<Customer>(id).unwrap().borrow().name, "Alice");
+
+    assert!(matches!(
+        Connection::open_in_memory().unwrap().try_clone(),
+        Err(orm::Error::Storage(_))
+    ));
+}
+
+#[test]
+fn test_native_snapshot_isolation() {
+    let mut conn_one = Connection::open_memory_native().unwrap();
+    let mut conn_two = conn_one.try_clone().unwrap();
+    let alice_id = create_customer(&mut conn_one, "Alice");
+
+    let tx_one = conn_one.new_transaction().unwrap();
+    assert_eq!(tx_one.select::<Customer>().fetch().unwrap().len(), 1);
+
+    let bob_id = create_customer(&mut conn_two, "Bob");
+    let tx_two = conn_two.new_transaction().unwrap();
+    tx_two.get::<Customer>(alice_id).unwrap().borrow_mut().name = "Alicia".into();
+    tx_two.commit().unwrap();
+
+    assert_eq!(tx_one.select::<Customer>().fetch().unwrap().len(), 1);
+    assert!(matches!(
+        tx_one.get::<Customer>(bob_id),
+        Err(orm::Error::NotFound(_))
+    ));
+    assert_eq!(
+        tx_one.get::<Customer>(alice_id).unwrap().borrow().name,
+        "Alice"
+    );
+    tx_one.commit().unwrap();
+
+    let tx_one = conn_one.new_transaction().unwrap();
+    assert_eq!(
+        tx_one.get::<Customer>(alice_id).unwrap().borrow().name,
+        "Alicia"
+    );
+    assert_eq!(tx_one.select::<Customer>().fetch().unwrap().len(), 2);
+}
+
+#[test]
+fn test_native_conflict() {
+    let mut conn_one = Connection::open_memory_native().unwrap();
+    let mut conn_two = conn_one.try_clone().unwrap();
+    let alice_id = create_customer(&mut conn_one, "Alice");
+    let bob_id = create_customer(&mut conn_one, "Bob");
+
+    // Different rows of the same table are written independently.
+    let tx_one = conn_one.new_transaction().unwrap();
+    let tx_two = conn_two.new_transaction().unwrap();
+    tx_one.get::<Customer>(alice_id).unwrap().borrow_mut().name = "Alicia".into();
+    tx_two.get::<Customer>(bob_id).unwrap().borrow_mut().name = "Robert".into();
+    tx_one
+        .create(Customer {
+            name: "Carol".into(),
+        })
+        .unwrap();
+    tx_two
+        .create(Customer {
+            name: "Dave".into(),
+        })
+        .unwrap();
+    tx_one.commit().unwrap();
+    tx_two.commit().unwrap();
+
+    // A row locked by another transaction.
+    let tx_one = conn_one.new_transaction().unwrap();
+    let tx_two = conn_two.new_transaction().unwrap();
+    tx_one.get::<Customer>(alice_id).unwrap().borrow_mut().name = "Ali".into();
+    // NB: a query writes the changes to the storage.
+    tx_one.select::<Customer>().fetch().unwrap();
+    tx_two.get::<Customer>(alice_id).unwrap().borrow_mut().name = "Al".into();
+    let res = tx_two.commit();
+    assert!(
+        matches!(res, Err(orm::Error::LockConflict)),
+        "got {}",
+        fmt_res(&res)
+    );
+    tx_one.rollback().unwrap();
+
+    // A row changed since the snapshot was taken.
+    let tx_one = conn_one.new_transaction().unwrap();
+    let tx_two = conn_two.new_transaction().unwrap();
+    tx_two.get::<Customer>(bob_id).unwrap();
+    tx_one.get::<Customer>(bob_id).unwrap().borrow_mut().name = "Bobby".into();
+    tx_one.commit().unwrap();
+    tx_two.get::<Customer>(bob_id).unwrap().borrow_mut().name = "Rob".into();
+    let res = tx_two.commit();
+    assert!(
+        matches!(res, Err(orm::Error::LockConflict)),
+        "got {}",
+        fmt_res(&res)
+    );
+
+    let tx = conn_one.new_transaction().unwrap();
+    assert_eq!(
+        tx.get::<Customer>(alice_id).unwrap().borrow().name,
+        "Alicia"
+    );
+    assert_eq!(tx.get::<Customer>(bob_id).unwrap().borrow().name, "Bobby");
+}
+
+#[test]
+fn test_native_conflict_create_table() {
+    let mut conn_one = Connection::open_memory_native().unwrap();
+    let mut conn_two = conn_one.try_clone().unwrap();
+
+    let tx_one = conn_one.new_transaction().unwrap();
+    let tx_two = conn_two.new_transaction().unwrap();
+    tx_one
+        .create(Customer {
+            name: "Alice".into(),
+        })
+        .unwrap();
+    let res = tx_two.create(Customer { name: "Bob".into() });
+    assert!(
+        matches!(res, Err(orm::Error::LockConflict)),
+        "got {}",
+        fmt_res(&res)
+    );
+}
+
+#[test]
+fn test_transact_retry() {
+    let policy =
+        RetryPolicy::new(3).with_backoff(Duration::from_millis(1), Duration::from_millis(2));
+    let mut conn_one = Connection::open_memory_native().unwrap();
+    let mut conn_two = conn_one.try_clone().unwrap();
+    let alice_id = create_customer(&mut conn_one, "Alice");
+
+    let blocker = conn_one.new_transaction().unwrap();
+    blocker.get::<Customer>(alice_id).unwrap().borrow_mut().name = "Ali".into();
+    blocker.select::<Customer>().fetch().unwrap();
+
+    let mut attempts = 0;
+    let res = conn_two.transact(&policy, |tx| {
+        attempts += 1;
+        tx.get::<Customer>(alice_id)?.borrow_mut().name = "Al".into();
+        Ok(())
+    });
+    assert!(
+        matches!(res, Err(orm::Error::RetriesExhausted { attempts: 3 })),
+        "got {}",
+        fmt_res(&res)
+    );
+    assert_eq!(attempts, 3);
+
+    let mut blocker = Some(blocker);
+    let mut attempts = 0;
+    let name = conn_two
+        .transact(&policy, |tx| {
+            attempts += 1;
+            if attempts == 2 {
+                blocker.take().unwrap().rollback()?;
+            }
+            let alice = tx.get::<Customer>(alice_id)?;
+            alice.borrow_mut().name = "Alicia".into();
+            let name = alice.borrow().name.clone();
+            Ok(name)
+        })
+        .unwrap();
+    assert_eq!(name, "Alicia");
+    assert_eq!(attempts, 2);
+
+    let res = conn_two.transact(&policy, |tx| tx.get::<Customer>(100.into()).map(|_| ()));
+    assert!(matches!(res, Err(orm::Error::NotFound(_))));
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+macro_rules! backend_tests {
+    ($($(#[$attr:meta])* $name:ident,)*) => {
+        mod sqlite {
+            use super::*;
+
+            $(
+                #[test]
+                $(#[$attr])*
+                fn $name() {
+                    super::$name(Connection::open_in_memory().unwrap())
+                }
+            )*
+        }
+
+        mod native {
+            use super::*;
+
+            $(
+                #[test]
+                $(#[$attr])*
+                fn $name() {
+                    super::$name(Connection::open_memory_native().unwrap())
+                }
+            )*
+        }
+    };
+}
+
+backend_tests! {
+    test_create,
+    test_update,
+    test_delete,
+    test_create_delete,
+    #[should_panic(expected = "already borrowed")]
+    test_double_borrow,
+    #[should_panic(expected = "cannot borrow a removed object")]
+    test_borrow_created_deleted,
+    #[should_panic(expected = "cannot borrow a removed object")]
+    test_borrow_deleted,
+    #[should_panic(expected = "cannot delete a borrowed object")]
+    test_delete_borrowed,
+    test_empty_struct,
+    test_sql_injection,
+    test_not_found,
+    test_select,
+    test_select_identity_map,
+    test_select_while_borrowed,
+    test_select_missing_table,
+    test_ref,
+    test_on_delete_cascade_nullify,
+    test_on_delete_restrict,
+    test_select_skips_removed,
+    test_migration,
+    test_migration_unexpected_type,
+    test_nullable,
+    test_unique,
+    test_unique_fix_duplicate,
+    test_unique_migration,
+    test_unique_migration_drop_column,
+    test_batch,
+    test_on_commit,
+    test_audit_log,
+    test_savepoint,
+    test_savepoint_borrowed,
+}
+
+#[cfg(feature = "json")]
+mod json_backends {
+    use super::*;
+
+    backend_tests! {
+        test_json,
+    }
+}
+
 #[cfg(feature = "test_lifetimes_create")]
 #[test]
 fn test_lifetimes_create() {
@@ -0,0 +1,61 @@
+// To compare with a revision that predates this file, check it out with `git worktree add` and
+// copy this file there together with the `criterion` dev-dependency and the `[[bench]]` section
+// of Cargo.toml. Then run
+//
+//     CRITERION_HOME=/tmp/criterion cargo bench -- --save-baseline <name>
+//
+// there, followed by the same command with `--baseline <name>` here. Don't share
+// `CARGO_TARGET_DIR` between the trees: their sources have the same relative paths, so cargo
+// may consider the other tree's bench binary up to date and run it instead.
+
+use std::{fs, path::Path};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use ripgzip::{compress, decompress};
+
+fn read_case(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("data")
+        .join("ok")
+        .join(name);
+    fs::read(path).expect("failed to read test case")
+}
+
+fn decompressed_len(compressed: &[u8]) -> usize {
+    let mut output = Vec::new();
+    decompress(compressed, &mut output).expect("failed to decompress test case");
+    output.len()
+}
+
+fn bench_decompress(c: &mut Criterion) {
+    let text = read_case("06-war-and-peace.txt.gz");
+    let mut recompressed = Vec::new();
+    let mut data = Vec::new();
+    decompress(&text[..], &mut data).unwrap();
+    compress(&data[..], &mut recompressed).unwrap();
+
+    let cases = [
+        ("war_and_peace", text),
+        ("war_and_peace_ripgzip", recompressed),
+        ("app", read_case("05-app.gz")),
+        ("photo", read_case("03-photo.jpg.gz")),
+    ];
+
+    let mut group = c.benchmark_group("decompress");
+    group.sample_size(20);
+    for (name, compressed) in cases.iter() {
+        let len = decompressed_len(compressed);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                let mut output = Vec::with_capacity(len);
+                decompress(black_box(&compressed[..]), &mut output).unwrap();
+                output
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decompress);
+criterion_main!(benches);
@@ -0,0 +1,248 @@
+use std::{
+    io::{self, BufRead, Read, Write},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use anyhow::Result;
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+    decoder, encoder, resumable::is_would_block, CompressOptions, Format, GzipDecoder, GzipEncoder,
+    MemberHeader,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of compressed input read from the underlying reader at once.
+const INPUT_CHUNK_SIZE: usize = 1 << 15;
+
+/// Decompresses data read from an `AsyncRead`, see `GzipDecoder`.
+///
+/// Input is decoded as soon as it arrives: once it runs out, the decoder returns `Pending`
+/// and resumes from the very same bit when polled with more input available. Decoding itself
+/// happens inside `poll_read`, producing at most a buffer of output at a time.
+pub struct AsyncGzipDecoder<R> {
+    reader: R,
+    decoder: GzipDecoder<InputBuffer>,
+}
+
+/// Input that has been read from the underlying reader, but not decoded yet. Fails with
+/// `WouldBlock` once it runs out, unless the underlying reader has ended.
+struct InputBuffer {
+    buffer: Vec<u8>,
+    pos: usize,
+    end: usize,
+    at_eof: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncGzipDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_format(reader, Format::Gzip)
+    }
+
+    pub fn with_format(reader: R, format: Format) -> Self {
+        let input = InputBuffer {
+            buffer: vec![0; INPUT_CHUNK_SIZE],
+            pos: 0,
+            end: 0,
+            at_eof: false,
+        };
+        Self {
+            reader,
+            decoder: GzipDecoder::with_format(input, format),
+        }
+    }
+
+    /// Return the header of the gzip member being decoded, see `GzipDecoder::header`.
+    pub fn header(&self) -> Option<&MemberHeader> {
+        self.decoder.header()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Decode until some output is available or the stream has ended.
+    fn poll_decode(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.decoder.buffer().is_empty() {
+            match self.decoder.fill() {
+                Ok(_) => break,
+                Err(err) if is_would_block(&err) => ready!(self.poll_input(cx))?,
+                Err(err) => return Poll::Ready(Err(decoder::into_io_error(err))),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Read more input from the underlying reader.
+    fn poll_input(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let input = self
+            .decoder
+            .get_mut()
+            .expect("decoder cannot run out of input after failing");
+        // NB: the decoder only relies on the input it has not consumed yet.
+        input.buffer.copy_within(input.pos..input.end, 0);
+        input.end -= input.pos;
+        input.pos = 0;
+        if input.end == input.buffer.len() {
+            input.buffer.resize(input.end + INPUT_CHUNK_SIZE, 0);
+        }
+
+        let mut buf = ReadBuf::new(&mut input.buffer[input.end..]);
+        ready!(Pin::new(&mut self.reader).poll_read(cx, &mut buf))?;
+        let read = buf.filled().len();
+        input.end += read;
+        if read == 0 {
+            input.at_eof = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncGzipDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(this.poll_decode(cx))?;
+        let available = this.decoder.buffer();
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        this.decoder.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncBufRead for AsyncGzipDecoder<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        ready!(this.poll_decode(cx))?;
+        Poll::Ready(Ok(this.decoder.buffer()))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().decoder.consume(amt)
+    }
+}
+
+impl Read for InputBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for InputBuffer {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.end && !self.at_eof {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(&self.buffer[self.pos..self.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.end);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Compresses data written to it into an `AsyncWrite`, see `GzipEncoder`.
+///
+/// Compression happens inside `poll_write`, a chunk of input at a time. `poll_flush` only
+/// writes out the chunks compressed so far, and the stream is completed by `poll_shutdown`.
+pub struct AsyncGzipEncoder<W> {
+    writer: W,
+    /// `None` once the stream has been completed.
+    encoder: Option<GzipEncoder<Vec<u8>>>,
+    /// Compressed data that hasn't been written to `writer` yet.
+    output: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncGzipEncoder<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        Self::with_format(writer, Format::Gzip)
+    }
+
+    pub fn with_format(writer: W, format: Format) -> Result<Self> {
+        Self::with_options(writer, format, &CompressOptions::default())
+    }
+
+    /// Create an encoder with the given settings, see `GzipEncoder::with_options`.
+    pub fn with_options(writer: W, format: Format, options: &CompressOptions) -> Result<Self> {
+        let mut encoder = GzipEncoder::with_options(Vec::new(), format, options)?;
+        let output = std::mem::take(encoder.get_mut());
+        Ok(Self {
+            writer,
+            encoder: Some(encoder),
+            output,
+            written: 0,
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write all the compressed data to the underlying writer.
+    fn poll_output(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let buf = &self.output[self.written..];
+            let len = ready!(Pin::new(&mut self.writer).poll_write(cx, buf))?;
+            if len == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += len;
+        }
+        self.output.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncGzipEncoder<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_output(cx))?;
+        let Some(encoder) = &mut this.encoder else {
+            return Poll::Ready(Err(io::Error::other("encoder has been shut down")));
+        };
+        let len = encoder.write(buf)?;
+        this.output.append(encoder.get_mut());
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_output(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_output(cx))?;
+        if let Some(encoder) = this.encoder.take() {
+            this.output = encoder.finish().map_err(encoder::into_io_error)?;
+            ready!(this.poll_output(cx))?;
+        }
+        Pin::new(&mut this.writer).poll_shutdown(cx)
+    }
+}
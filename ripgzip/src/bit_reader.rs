@@ -1,9 +1,4 @@
-use std::{
-    fmt,
-    io::{self, BufRead},
-};
-
-use byteorder::ReadBytesExt;
+use std::io::{self, BufRead};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -15,52 +10,191 @@ pub struct BitSequence {
 
 impl BitSequence {
     pub fn new(bits: u16, len: u8) -> Self {
+        assert!(len <= 16, "bit sequence is too long: {}", len);
         // NB: make sure to zero unused bits so that Eq and Hash work as expected.
-        // TODO: your code here.
-        unimplemented!()
+        let mask = ((1u32 << len) - 1) as u16;
+        Self {
+            bits: bits & mask,
+            len,
+        }
     }
 
     pub fn bits(&self) -> u16 {
-        // TODO: your code here.
-        unimplemented!()
+        self.bits
     }
 
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u8 {
-        // TODO: your code here.
-        unimplemented!()
+        self.len
     }
 
+    /// Append `other` to the end of `self`, i.e. `other` becomes the lowest bits of the result.
+    #[cfg(test)]
     pub fn concat(self, other: Self) -> Self {
         // NB: result must not be larger than 16 bits.
-        // TODO: your code here.
-        unimplemented!()
+        let len = self.len + other.len;
+        assert!(len <= 16, "bit sequence is too long: {}", len);
+        let bits = ((self.bits as u32) << other.len) | other.bits as u32;
+        Self::new(bits as u16, len)
+    }
+
+    /// Reverse the order of bits, e.g. to emit a Huffman code starting from its first bit.
+    pub fn reversed(self) -> Self {
+        if self.len == 0 {
+            return self;
+        }
+        Self::new(self.bits.reverse_bits() >> (16 - self.len), self.len)
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// Reads bits LSB-first, as required by RFC 1951.
+///
+/// Bytes are loaded into a 64-bit buffer straight from `fill_buf` and consumed from the
+/// underlying stream only once all of their bits have been used. This way the reader can look
+/// ahead without ever losing bytes that follow the bit stream, see `borrow_reader_from_boundary`.
+///
+/// If the stream fails with `WouldBlock`, no bits are consumed, so the same read can be
+/// repeated once more input is available.
 pub struct BitReader<T> {
     stream: T,
-    // TODO: your code here.
+    buffer: u64,
+    buffer_len: u8,
+    /// Number of bytes at the front of the stream that are loaded into the buffer but have
+    /// not been consumed yet. They hold the highest bits of the buffer.
+    pending: usize,
 }
 
 impl<T: BufRead> BitReader<T> {
     pub fn new(stream: T) -> Self {
-        // TODO: your code here.
-        unimplemented!()
+        Self {
+            stream,
+            buffer: 0,
+            buffer_len: 0,
+            pending: 0,
+        }
     }
 
     pub fn read_bits(&mut self, len: u8) -> io::Result<BitSequence> {
         // NB: you can only read up to 16 bits at a time.
-        // TODO: your code here.
-        unimplemented!()
+        assert!(len <= 16, "cannot read more than 16 bits at a time");
+        self.require_bits(len)?;
+        let seq = BitSequence::new(self.buffer as u16, len);
+        self.consume_bits(len);
+        Ok(seq)
+    }
+
+    /// Return up to `len` next bits without consuming them. Fewer bits are returned only if
+    /// getting more would require consuming the current chunk of the stream, in which case
+    /// `require_bits` has to be called first.
+    pub fn peek_bits(&mut self, len: u8) -> io::Result<BitSequence> {
+        assert!(len <= 16, "cannot peek more than 16 bits at a time");
+        if self.buffer_len < len {
+            self.refill()?;
+        }
+        Ok(BitSequence::new(
+            self.buffer as u16,
+            len.min(self.buffer_len),
+        ))
+    }
+
+    /// Drop `len` bits that have been returned by `peek_bits` earlier.
+    pub fn consume_bits(&mut self, len: u8) {
+        assert!(
+            len <= self.buffer_len,
+            "cannot consume bits that were not peeked"
+        );
+        self.buffer >>= len;
+        self.buffer_len -= len;
+    }
+
+    /// Make sure that at least `len` bits are buffered, failing with `UnexpectedEof` if the
+    /// stream ends before that.
+    pub fn require_bits(&mut self, len: u8) -> io::Result<()> {
+        assert!(len <= 56, "cannot buffer more than 56 bits");
+        if self.buffer_len >= len {
+            return Ok(());
+        }
+        loop {
+            self.refill()?;
+            if self.buffer_len >= len {
+                return Ok(());
+            }
+            if self.pending == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            // The current chunk is exhausted, so the bytes loaded from it have to be consumed
+            // to get to the next one. Since the caller is about to use all of the buffered bits
+            // and then some, none of these bytes will be needed by the underlying reader.
+            self.stream.consume(self.pending);
+            self.pending = 0;
+        }
+    }
+
+    /// Load as many bytes of the current chunk into the buffer as fit.
+    fn refill(&mut self) -> io::Result<()> {
+        self.release_used();
+        let chunk = self.stream.fill_buf()?;
+        let available = chunk.get(self.pending..).unwrap_or_default();
+        let count = available.len().min((64 - self.buffer_len as usize) / 8);
+        for &byte in &available[..count] {
+            self.buffer |= (byte as u64) << self.buffer_len;
+            self.buffer_len += 8;
+        }
+        self.pending += count;
+        Ok(())
+    }
+
+    /// Consume the pending bytes all bits of which have been used.
+    fn release_used(&mut self) {
+        let used = self
+            .pending
+            .saturating_sub(self.buffer_len.div_ceil(8) as usize);
+        self.stream.consume(used);
+        self.pending -= used;
+    }
+
+    /// Return the number of bits read so far, given the number of bytes consumed from
+    /// the underlying stream.
+    pub fn bit_position(&self, stream_position: u64) -> u64 {
+        (stream_position + self.pending as u64) * 8 - self.buffer_len as u64
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// NB: the stream may only be changed in ways that keep the data returned by `fill_buf`,
+    /// e.g. by appending to it.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Discard the unread bits of the current byte.
+    pub fn align_to_byte(&mut self) {
+        self.consume_bits(self.buffer_len % 8);
     }
 
     /// Discard all the unread bits in the current byte and return a mutable reference
     /// to the underlying reader.
     pub fn borrow_reader_from_boundary(&mut self) -> &mut T {
-        // TODO: your code here.
-        unimplemented!()
+        self.release_used();
+        // Bits that do not belong to pending bytes never make up a whole byte, see
+        // `require_bits`. The whole bytes left are pending, so leave them in the stream.
+        let whole_bytes = (self.buffer_len / 8) as usize;
+        debug_assert!(whole_bytes <= self.pending);
+        self.stream.consume(self.pending - whole_bytes);
+        self.buffer = 0;
+        self.buffer_len = 0;
+        self.pending = 0;
+        &mut self.stream
+    }
+
+    /// Discard all the unread bits in the current byte and return the underlying reader.
+    pub fn into_inner(mut self) -> T {
+        self.borrow_reader_from_boundary();
+        self.stream
     }
 }
 
@@ -70,6 +204,10 @@ impl<T: BufRead> BitReader<T> {
 mod tests {
     use super::*;
 
+    use std::io::{BufReader, Read};
+
+    use byteorder::ReadBytesExt;
+
     #[test]
     fn read_bits() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
@@ -87,6 +225,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn bit_sequence() {
+        let seq = BitSequence::new(0b1111_0110, 4);
+        assert_eq!(seq.bits(), 0b0110);
+        assert_eq!(seq.len(), 4);
+        assert_eq!(
+            seq.concat(BitSequence::new(0b01, 2)),
+            BitSequence::new(0b011001, 6)
+        );
+        assert_eq!(seq.reversed(), BitSequence::new(0b0110, 4));
+        assert_eq!(
+            BitSequence::new(0b0011, 4).reversed(),
+            BitSequence::new(0b1100, 4)
+        );
+        assert_eq!(BitSequence::new(0, 0).reversed(), BitSequence::new(0, 0));
+    }
+
     #[test]
     fn borrow_reader_from_boundary() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
@@ -96,4 +251,42 @@ mod tests {
         assert_eq!(reader.read_bits(8)?, BitSequence::new(0b10101111, 8));
         Ok(())
     }
+
+    #[test]
+    fn peek_bits() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.peek_bits(4)?, BitSequence::new(0b0011, 4));
+        reader.consume_bits(3);
+        assert_eq!(reader.peek_bits(16)?, BitSequence::new(0b1101101101100, 13));
+        reader.consume_bits(13);
+        assert_eq!(reader.peek_bits(1)?, BitSequence::new(0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn small_chunks() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111, 0b00001111, 0b11110000];
+        let mut reader = BitReader::new(BufReader::with_capacity(1, data));
+        assert_eq!(reader.peek_bits(16)?, BitSequence::new(0b01100011, 8));
+        reader.consume_bits(1);
+        assert_eq!(reader.read_bits(12)?, BitSequence::new(0b1101_10110001, 12));
+        assert_eq!(reader.read_bits(4)?, BitSequence::new(0b1110, 4));
+        assert_eq!(reader.borrow_reader_from_boundary().read_u8()?, 0b00001111);
+        assert_eq!(reader.read_bits(4)?, BitSequence::new(0b0000, 4));
+        assert_eq!(reader.into_inner().fill_buf()?, &[] as &[u8]);
+        Ok(())
+    }
+
+    #[test]
+    fn borrow_after_lookahead() -> io::Result<()> {
+        let data: &[u8] = &[0b00000101, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.peek_bits(16)?.len(), 16);
+        reader.consume_bits(3);
+        let mut rest = Vec::new();
+        reader.into_inner().read_to_end(&mut rest)?;
+        assert_eq!(rest, [1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        Ok(())
+    }
 }
@@ -0,0 +1,86 @@
+use std::io::{self, Write};
+
+use crate::bit_reader::BitSequence;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct BitWriter<T> {
+    stream: T,
+    buffer: u64,
+    buffer_len: u8,
+}
+
+impl<T: Write> BitWriter<T> {
+    pub fn new(stream: T) -> Self {
+        Self {
+            stream,
+            buffer: 0,
+            buffer_len: 0,
+        }
+    }
+
+    /// Write the bits of `seq` starting from the least significant one.
+    pub fn write_bits(&mut self, seq: BitSequence) -> io::Result<()> {
+        self.buffer |= (seq.bits() as u64) << self.buffer_len;
+        self.buffer_len += seq.len();
+        if self.buffer_len >= 32 {
+            self.stream.write_all(&(self.buffer as u32).to_le_bytes())?;
+            self.buffer >>= 32;
+            self.buffer_len -= 32;
+        }
+        Ok(())
+    }
+
+    /// Pad the current byte with zero bits, flush all the buffered bits and return a mutable
+    /// reference to the underlying writer.
+    pub fn borrow_writer_from_boundary(&mut self) -> io::Result<&mut T> {
+        let byte_count = (self.buffer_len as usize).div_ceil(8);
+        self.stream
+            .write_all(&self.buffer.to_le_bytes()[..byte_count])?;
+        self.buffer = 0;
+        self.buffer_len = 0;
+        Ok(&mut self.stream)
+    }
+
+    /// Return the underlying writer. Bits that don't make up a whole 32-bit word yet are
+    /// kept in the buffer.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Pad the current byte with zero bits and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<T> {
+        self.borrow_writer_from_boundary()?;
+        Ok(self.stream)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_bits() -> io::Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b1, 1))?;
+        writer.write_bits(BitSequence::new(0b01, 2))?;
+        writer.write_bits(BitSequence::new(0b100, 3))?;
+        writer.write_bits(BitSequence::new(0b1101, 4))?;
+        writer.write_bits(BitSequence::new(0b10110, 5))?;
+        writer.write_bits(BitSequence::new(0b01011111, 8))?;
+        assert_eq!(writer.finish()?, vec![0b01100011, 0b11011011, 0b00101111]);
+        Ok(())
+    }
+
+    #[test]
+    fn borrow_writer_from_boundary() -> io::Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer.borrow_writer_from_boundary()?.write_all(&[0xab])?;
+        writer.write_bits(BitSequence::new(0xffff, 16))?;
+        assert_eq!(writer.finish()?, vec![0b011, 0xab, 0xff, 0xff]);
+        Ok(())
+    }
+}
@@ -0,0 +1,772 @@
+use std::io::{self, BufRead, Read, Write};
+
+use anyhow::{bail, Result};
+use byteorder::WriteBytesExt;
+use log::*;
+
+use crate::{
+    bit_reader::BitReader,
+    deflate::{CompressionType, DeflateReader},
+    error::DecodeError,
+    gzip::{CompressionMethod, GzipReader, MemberHeader, MemberReader},
+    huffman_coding::{
+        fixed_litlen_distance_trees, DistanceToken, HuffmanCoding, LitLenToken, TreeDecoder,
+    },
+    index::Checkpoint,
+    resumable::is_would_block,
+    tracking_writer::TrackingWriter,
+    zlib::{Adler32, ZlibReader, ZlibStreamReader},
+    Format,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The decoder stops filling its buffer once this many bytes are pending.
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// Lazily decompresses a gzip stream, possibly consisting of several members. Zlib and raw
+/// deflate streams are supported as well, see `with_format` and `detect`.
+///
+/// Decoding happens on demand: every `read` or `fill_buf` call decodes just enough
+/// input to return some data, so the stream may be abandoned at any point.
+///
+/// A reader failing with `WouldBlock` does not break the decoder: the error is returned once
+/// the data decoded so far has been read, and decoding continues where it stopped as soon as
+/// more input is available. This is what the async adapters are built upon.
+pub struct GzipDecoder<R> {
+    format: Format,
+    state: State<R>,
+    writer: TrackingWriter<Vec<u8>>,
+    consumed: usize,
+    adler32: Adler32,
+    single_member: bool,
+    /// Uncompressed offset of the current gzip member.
+    member_offset: u64,
+    checkpoint_span: Option<u64>,
+    checkpoints: Vec<Checkpoint>,
+    /// Compressed offset of the current gzip member.
+    member_start: u64,
+    header: Option<MemberHeader>,
+    members: Option<Vec<MemberInfo>>,
+}
+
+/// Summary of a gzip member, see `GzipDecoder::record_members`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemberInfo {
+    pub header: MemberHeader,
+    /// Size of the member, including its header and footer.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub crc32: u32,
+}
+
+enum State<R> {
+    MemberStart(GzipReader<CountingReader<R>>),
+    ZlibStart(ZlibReader<CountingReader<R>>),
+    RawStart(CountingReader<R>),
+    Blocks {
+        reader: DeflateReader<Body<CountingReader<R>>>,
+        block: Block,
+    },
+    /// The deflate stream has ended and the footer of its container follows.
+    Footer(Body<CountingReader<R>>),
+    /// The stream has ended. The reader is kept if the input may be used further.
+    Done(Option<R>),
+    Failed,
+}
+
+/// Counts the bytes consumed from the compressed stream, so that checkpoints can refer
+/// to positions in it.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+/// Compressed data together with the container it is framed by.
+enum Body<R> {
+    Gzip(MemberReader<R>),
+    Zlib(ZlibStreamReader<R>),
+    Raw(R),
+}
+
+enum Block {
+    BetweenBlocks,
+    /// The header of a stored block has been read, but not its length.
+    StoredLength,
+    Uncompressed {
+        remaining: usize,
+    },
+    /// The code trees of a dynamic block are being read.
+    Trees(TreeDecoder),
+    Compressed {
+        litlen_coding: HuffmanCoding<LitLenToken>,
+        distance_coding: HuffmanCoding<DistanceToken>,
+        pending_match: Option<PartialMatch>,
+    },
+}
+
+/// A length/distance pair the reader has run out of input in the middle of.
+#[derive(Clone, Copy)]
+enum PartialMatch {
+    LengthExtra { base: u16, extra_bits: u8 },
+    Distance { len: u16 },
+    DistanceExtra { len: u16, base: u16, extra_bits: u8 },
+}
+
+impl<R: BufRead> GzipDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_format(reader, Format::Gzip)
+    }
+
+    pub fn with_format(reader: R, format: Format) -> Self {
+        let reader = CountingReader {
+            inner: reader,
+            count: 0,
+        };
+        let state = match format {
+            Format::Gzip => State::MemberStart(GzipReader::new(reader)),
+            Format::Zlib => State::ZlibStart(ZlibReader::new(reader)),
+            Format::Raw => State::RawStart(reader),
+        };
+        Self::from_state(format, state)
+    }
+
+    fn from_state(format: Format, state: State<R>) -> Self {
+        Self {
+            format,
+            state,
+            writer: TrackingWriter::new(Vec::with_capacity(BUFFER_SIZE)),
+            consumed: 0,
+            adler32: Adler32::new(),
+            single_member: false,
+            member_offset: 0,
+            checkpoint_span: None,
+            checkpoints: Vec::new(),
+            member_start: 0,
+            header: None,
+            members: None,
+        }
+    }
+
+    /// Create a decoder that stops after the first gzip member, leaving the rest of the
+    /// stream unread.
+    pub(crate) fn single_member(reader: R) -> Self {
+        let mut decoder = Self::new(reader);
+        decoder.single_member = true;
+        decoder
+    }
+
+    /// Continue decoding a gzip stream from `checkpoint`. The reader must be positioned at
+    /// the byte the checkpoint's bit offset falls into.
+    pub(crate) fn resume(reader: R, checkpoint: &Checkpoint) -> Result<Self> {
+        let reader = CountingReader {
+            inner: reader,
+            count: checkpoint.compressed_bits / 8,
+        };
+        let mut bit_reader = BitReader::new(Body::Gzip(MemberReader::resume(reader)));
+        bit_reader.read_bits((checkpoint.compressed_bits % 8) as u8)?;
+
+        let state = State::Blocks {
+            reader: DeflateReader::new(bit_reader),
+            block: Block::BetweenBlocks,
+        };
+        let mut decoder = Self::from_state(Format::Gzip, state);
+        decoder.writer = TrackingWriter::resume(
+            Vec::with_capacity(BUFFER_SIZE),
+            &checkpoint.window,
+            checkpoint.member_size as usize,
+            checkpoint.member_crc32,
+        );
+        decoder.member_offset = checkpoint.uncompressed_offset - checkpoint.member_size;
+        Ok(decoder)
+    }
+
+    /// Create a decoder for the format detected from the first bytes of `reader`.
+    pub fn detect(mut reader: R) -> io::Result<Self> {
+        let format = Format::detect(reader.fill_buf()?);
+        debug!("detected format: {:?}", format);
+        Ok(Self::with_format(reader, format))
+    }
+
+    /// Decode more data into the internal buffer. Returns `false` once the stream has been
+    /// fully decoded and verified and the buffer has been drained.
+    pub(crate) fn fill(&mut self) -> Result<bool> {
+        if self.consumed == self.writer.get_ref().len() {
+            self.writer.get_mut().clear();
+            self.consumed = 0;
+        }
+
+        while self.writer.get_ref().len() < BUFFER_SIZE {
+            if let State::Done(_) = self.state {
+                break;
+            }
+            if let Err(err) = self.step() {
+                if !is_would_block(&err) {
+                    self.state = State::Failed;
+                    return Err(err);
+                }
+                // NB: the state is intact, so decoding resumes from it on the next call.
+                if self.buffer().is_empty() {
+                    return Err(err);
+                }
+                break;
+            }
+        }
+
+        Ok(self.consumed < self.writer.get_ref().len())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        match &mut self.state {
+            State::MemberStart(_) => self.start_member(),
+            State::ZlibStart(zlib_reader) => {
+                let header = zlib_reader.read_header()?;
+                debug!("zlib header: {:?}", header);
+                let State::ZlibStart(zlib_reader) = self.take_state() else {
+                    unreachable!()
+                };
+                self.start_stream(Body::Zlib(zlib_reader.into_stream_reader()));
+                Ok(())
+            }
+            State::RawStart(_) => {
+                let State::RawStart(reader) = self.take_state() else {
+                    unreachable!()
+                };
+                self.start_stream(Body::Raw(reader));
+                Ok(())
+            }
+            State::Blocks { .. } => {
+                let len = self.writer.get_ref().len();
+                let result = self.decode_blocks();
+                if self.format == Format::Zlib {
+                    self.adler32.update(&self.writer.get_ref()[len..]);
+                }
+                result
+            }
+            State::Footer(_) => self.finish_stream(),
+            State::Done(_) => Ok(()),
+            State::Failed => bail!(DecodeError::DecoderFailed),
+        }
+    }
+
+    fn take_state(&mut self) -> State<R> {
+        std::mem::replace(&mut self.state, State::Failed)
+    }
+
+    pub(crate) fn buffer(&self) -> &[u8] {
+        &self.writer.get_ref()[self.consumed..]
+    }
+
+    /// Return the header of the gzip member being decoded, or of the last one once
+    /// the stream has ended.
+    pub fn header(&self) -> Option<&MemberHeader> {
+        self.header.as_ref()
+    }
+
+    /// Start keeping a summary of every gzip member, see `members`.
+    pub fn record_members(&mut self) {
+        self.members.get_or_insert_with(Vec::new);
+    }
+
+    /// Return the members whose headers have been read since calling `record_members`.
+    /// The sizes and the checksum of a member are only filled in once its footer has been
+    /// verified.
+    pub fn members(&self) -> &[MemberInfo] {
+        self.members.as_deref().unwrap_or_default()
+    }
+
+    /// Start recording a checkpoint at the first block boundary after every `span` bytes of
+    /// output, see `GzipIndex`.
+    pub(crate) fn record_checkpoints(&mut self, span: u64) {
+        self.checkpoint_span = Some(span);
+    }
+
+    pub(crate) fn take_checkpoints(&mut self) -> Vec<Checkpoint> {
+        std::mem::take(&mut self.checkpoints)
+    }
+
+    /// Return the underlying reader, unless decoding has failed. Unread bits of the current
+    /// byte are discarded.
+    pub(crate) fn into_inner(self) -> Option<R> {
+        let reader = match self.state {
+            State::MemberStart(gzip_reader) => gzip_reader.into_inner(),
+            State::ZlibStart(zlib_reader) => zlib_reader.into_inner(),
+            State::RawStart(reader) => reader,
+            State::Blocks { reader, .. } => reader.into_inner().into_inner().into_inner(),
+            State::Footer(body) => body.into_inner(),
+            State::Done(reader) => return reader,
+            State::Failed => return None,
+        };
+        Some(reader.inner)
+    }
+
+    /// Return the underlying reader, unless decoding has failed. This is useful to add more
+    /// input after the reader has failed with `WouldBlock`. NB: the data the reader has
+    /// returned from `fill_buf` but that hasn't been consumed must stay in place.
+    pub fn get_mut(&mut self) -> Option<&mut R> {
+        let reader = match &mut self.state {
+            State::MemberStart(gzip_reader) => gzip_reader.get_mut(),
+            State::ZlibStart(zlib_reader) => zlib_reader.get_mut(),
+            State::RawStart(reader) => reader,
+            State::Blocks { reader, .. } => reader.bit_reader_mut().get_mut().get_mut(),
+            State::Footer(body) => body.get_mut(),
+            State::Done(reader) => return reader.as_mut(),
+            State::Failed => return None,
+        };
+        Some(&mut reader.inner)
+    }
+
+    fn start_member(&mut self) -> Result<()> {
+        let State::MemberStart(gzip_reader) = &mut self.state else {
+            unreachable!()
+        };
+        if gzip_reader.is_at_end()? {
+            let State::MemberStart(gzip_reader) = self.take_state() else {
+                unreachable!()
+            };
+            self.state = State::Done(Some(gzip_reader.into_inner().inner));
+            return Ok(());
+        }
+        if !gzip_reader.is_reading_header() {
+            self.member_start = gzip_reader.get_ref().count;
+        }
+        let header = match gzip_reader.next_member() {
+            Some(res) => res?,
+            None => bail!(DecodeError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unexpected end of stream"
+            ))),
+        };
+        debug!("member header: {:?}", header);
+        if let CompressionMethod::Unknown(method) = header.compression_method {
+            bail!(DecodeError::UnsupportedCompressionMethod(method));
+        }
+        if let Some(members) = &mut self.members {
+            members.push(MemberInfo {
+                header: header.clone(),
+                compressed_size: 0,
+                uncompressed_size: 0,
+                crc32: 0,
+            });
+        }
+        self.header = Some(header);
+
+        let State::MemberStart(gzip_reader) = self.take_state() else {
+            unreachable!()
+        };
+        self.start_stream(Body::Gzip(gzip_reader.into_member_reader()));
+        Ok(())
+    }
+
+    fn start_stream(&mut self, body: Body<CountingReader<R>>) {
+        // NB: members are independent, so the window and checksums start afresh, but the
+        // output that hasn't been read yet must be preserved.
+        self.member_offset += self.writer.byte_count() as u64;
+        let buffer = std::mem::replace(&mut self.writer, TrackingWriter::new(Vec::new()));
+        self.writer = TrackingWriter::new(buffer.into_inner());
+        self.adler32 = Adler32::new();
+        self.state = State::Blocks {
+            reader: DeflateReader::new(BitReader::new(body)),
+            block: Block::BetweenBlocks,
+        };
+    }
+
+    fn decode_blocks(&mut self) -> Result<()> {
+        if let State::Blocks {
+            block: Block::BetweenBlocks,
+            ..
+        } = self.state
+        {
+            self.record_checkpoint();
+        }
+
+        let State::Blocks { reader, block } = &mut self.state else {
+            unreachable!()
+        };
+        match block {
+            Block::BetweenBlocks => match reader.next_block() {
+                Some(res) => {
+                    let (block_header, _) = res?;
+                    trace!("block header: {:?}", block_header);
+                    *block = start_block(block_header.compression_type)?;
+                }
+                None => {
+                    let State::Blocks { reader, .. } = self.take_state() else {
+                        unreachable!()
+                    };
+                    self.state = State::Footer(reader.into_inner().into_inner());
+                }
+            },
+            Block::StoredLength => {
+                let bit_reader = reader.bit_reader_mut();
+                bit_reader.align_to_byte();
+                // NB: both fields are buffered first, so that they are read together.
+                bit_reader.require_bits(32)?;
+                let len = bit_reader.read_bits(16)?.bits();
+                let nlen = bit_reader.read_bits(16)?.bits();
+                if len != !nlen {
+                    bail!(DecodeError::NlenMismatch { len, nlen });
+                }
+                *block = match len {
+                    0 => Block::BetweenBlocks,
+                    len => Block::Uncompressed {
+                        remaining: len as usize,
+                    },
+                };
+            }
+            Block::Uncompressed { remaining } => {
+                let reader = reader.bit_reader_mut().borrow_reader_from_boundary();
+                let mut limit = BUFFER_SIZE.saturating_sub(self.writer.get_ref().len());
+                while *remaining > 0 && limit > 0 {
+                    let chunk = reader.fill_buf()?;
+                    if chunk.is_empty() {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                    }
+                    let len = chunk.len().min(*remaining).min(limit);
+                    self.writer.write_all(&chunk[..len])?;
+                    reader.consume(len);
+                    *remaining -= len;
+                    limit -= len;
+                }
+                if *remaining == 0 {
+                    *block = Block::BetweenBlocks;
+                }
+            }
+            Block::Trees(tree_decoder) => {
+                let (litlen_coding, distance_coding) =
+                    tree_decoder.decode(reader.bit_reader_mut())?;
+                *block = Block::Compressed {
+                    litlen_coding,
+                    distance_coding,
+                    pending_match: None,
+                };
+            }
+            Block::Compressed {
+                litlen_coding,
+                distance_coding,
+                pending_match,
+            } => {
+                let finished = decode_symbols(
+                    reader.bit_reader_mut(),
+                    &mut self.writer,
+                    litlen_coding,
+                    distance_coding,
+                    pending_match,
+                )?;
+                if finished {
+                    *block = Block::BetweenBlocks;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_checkpoint(&mut self) {
+        let State::Blocks { reader, .. } = &self.state else {
+            return;
+        };
+        let span = match self.checkpoint_span {
+            Some(span) if !reader.is_finished() => span,
+            _ => return,
+        };
+        let uncompressed_offset = self.member_offset + self.writer.byte_count() as u64;
+        // NB: a block header the reader has run out of input in the middle of is read again,
+        // so the checkpoint before it may have been recorded already.
+        let last = self.checkpoints.last();
+        let last_offset = last.map_or(0, |c| c.uncompressed_offset);
+        if uncompressed_offset < last_offset + span
+            || last.is_some_and(|c| c.uncompressed_offset == uncompressed_offset)
+        {
+            return;
+        }
+
+        let bit_reader = reader.bit_reader();
+        let stream_position = match bit_reader.get_ref() {
+            Body::Gzip(member_reader) => member_reader.get_ref().count,
+            _ => return,
+        };
+        let checkpoint = Checkpoint {
+            compressed_bits: bit_reader.bit_position(stream_position),
+            uncompressed_offset,
+            member_size: self.writer.byte_count() as u64,
+            member_crc32: self.writer.crc32(),
+            window: self.writer.history(),
+        };
+        trace!(
+            "checkpoint: bit {}, offset {}",
+            checkpoint.compressed_bits,
+            checkpoint.uncompressed_offset
+        );
+        self.checkpoints.push(checkpoint);
+    }
+
+    fn finish_stream(&mut self) -> Result<()> {
+        let State::Footer(body) = &mut self.state else {
+            unreachable!()
+        };
+        let member_reader = match body {
+            Body::Gzip(member_reader) => member_reader,
+            Body::Zlib(stream_reader) => {
+                let footer = stream_reader.read_footer()?;
+                debug!("zlib footer: {:?}", footer);
+                if footer.data_adler32 != self.adler32.sum() {
+                    bail!(DecodeError::Adler32Mismatch {
+                        expected: footer.data_adler32,
+                        actual: self.adler32.sum(),
+                    });
+                }
+                let reader = self.take_state_body().into_inner();
+                self.state = State::Done(Some(reader.inner));
+                return Ok(());
+            }
+            Body::Raw(_) => {
+                let reader = self.take_state_body().into_inner();
+                self.state = State::Done(Some(reader.inner));
+                return Ok(());
+            }
+        };
+
+        let footer = member_reader.read_footer()?;
+        debug!("member footer: {:?}", footer);
+
+        let byte_count = self.writer.byte_count() as u32;
+        if footer.data_size != byte_count {
+            bail!(DecodeError::LengthMismatch {
+                expected: footer.data_size,
+                actual: byte_count,
+            });
+        }
+        if footer.data_crc32 != self.writer.crc32() {
+            bail!(DecodeError::Crc32Mismatch {
+                expected: footer.data_crc32,
+                actual: self.writer.crc32(),
+            });
+        }
+
+        if let Some(info) = self.members.as_mut().and_then(|members| members.last_mut()) {
+            info.compressed_size = member_reader.get_ref().count - self.member_start;
+            info.uncompressed_size = self.writer.byte_count() as u64;
+            info.crc32 = footer.data_crc32;
+        }
+
+        let Body::Gzip(member_reader) = self.take_state_body() else {
+            unreachable!()
+        };
+        let gzip_reader = member_reader.into_gzip_reader();
+        self.state = if self.single_member {
+            State::Done(Some(gzip_reader.into_inner().inner))
+        } else {
+            State::MemberStart(gzip_reader)
+        };
+        Ok(())
+    }
+
+    fn take_state_body(&mut self) -> Body<CountingReader<R>> {
+        match self.take_state() {
+            State::Footer(body) => body,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Decode the gzip member at the start of `data`, ignoring whatever follows it. Returns the
+/// decompressed data together with the size of the member, or `None` if the member
+/// decompresses to more than `max_output` bytes.
+pub(crate) fn decode_member(data: &[u8], max_output: usize) -> Result<Option<(Vec<u8>, usize)>> {
+    let mut decoder = GzipDecoder::single_member(data);
+
+    let mut output = Vec::new();
+    while decoder.fill()? {
+        if output.len() + decoder.buffer().len() > max_output {
+            return Ok(None);
+        }
+        output.extend_from_slice(decoder.buffer());
+        let len = decoder.buffer().len();
+        decoder.consume(len);
+    }
+
+    match decoder.state {
+        State::Done(Some(rest)) if rest.len() < data.len() => {
+            Ok(Some((output, data.len() - rest.len())))
+        }
+        _ => bail!("no gzip member found"),
+    }
+}
+
+fn start_block(compression_type: CompressionType) -> Result<Block> {
+    Ok(match compression_type {
+        CompressionType::Uncompressed => Block::StoredLength,
+        CompressionType::FixedTree => {
+            let (litlen_coding, distance_coding) = fixed_litlen_distance_trees()?;
+            Block::Compressed {
+                litlen_coding,
+                distance_coding,
+                pending_match: None,
+            }
+        }
+        CompressionType::DynamicTree => Block::Trees(TreeDecoder::new()),
+        CompressionType::Reserved => bail!(DecodeError::UnsupportedBlockType),
+    })
+}
+
+/// Decode symbols of a compressed block until the end of block or until the buffer is full.
+/// Returns whether the end of block has been reached. If the reader runs out of input in
+/// the middle of a length/distance pair, the part read so far is left in `pending_match`.
+fn decode_symbols<T: BufRead>(
+    bit_reader: &mut BitReader<T>,
+    writer: &mut TrackingWriter<Vec<u8>>,
+    litlen_coding: &HuffmanCoding<LitLenToken>,
+    distance_coding: &HuffmanCoding<DistanceToken>,
+    pending_match: &mut Option<PartialMatch>,
+) -> Result<bool> {
+    while writer.get_ref().len() < BUFFER_SIZE {
+        let mut partial = match pending_match.take() {
+            Some(partial) => partial,
+            None => match litlen_coding.read_symbol(bit_reader)? {
+                LitLenToken::Literal(byte) => {
+                    writer.write_u8(byte)?;
+                    continue;
+                }
+                LitLenToken::EndOfBlock => return Ok(true),
+                LitLenToken::Length { base, extra_bits } => {
+                    PartialMatch::LengthExtra { base, extra_bits }
+                }
+            },
+        };
+        match read_match(bit_reader, distance_coding, &mut partial) {
+            Ok((dist, len)) => writer.write_previous(dist as usize, len as usize)?,
+            Err(err) => {
+                *pending_match = Some(partial);
+                return Err(err);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Read the rest of a length/distance pair, updating `partial` after every field.
+/// Returns the distance and the length.
+fn read_match<T: BufRead>(
+    bit_reader: &mut BitReader<T>,
+    distance_coding: &HuffmanCoding<DistanceToken>,
+    partial: &mut PartialMatch,
+) -> Result<(u16, u16)> {
+    loop {
+        *partial = match *partial {
+            PartialMatch::LengthExtra { base, extra_bits } => PartialMatch::Distance {
+                len: base + bit_reader.read_bits(extra_bits)?.bits(),
+            },
+            PartialMatch::Distance { len } => {
+                let DistanceToken { base, extra_bits } = distance_coding.read_symbol(bit_reader)?;
+                PartialMatch::DistanceExtra {
+                    len,
+                    base,
+                    extra_bits,
+                }
+            }
+            PartialMatch::DistanceExtra {
+                len,
+                base,
+                extra_bits,
+            } => return Ok((base + bit_reader.read_bits(extra_bits)?.bits(), len)),
+        };
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for GzipDecoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // NB: once decoding fails, the data still in the buffer can't be trusted either.
+        if self.buffer().is_empty() || matches!(self.state, State::Failed) {
+            self.fill().map_err(into_io_error)?;
+        }
+        Ok(self.buffer())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.consumed = (self.consumed + amt).min(self.writer.get_ref().len());
+    }
+}
+
+impl<R: BufRead> Body<R> {
+    fn get_mut(&mut self) -> &mut R {
+        match self {
+            Self::Gzip(reader) => reader.get_mut(),
+            Self::Zlib(reader) => reader.get_mut(),
+            Self::Raw(reader) => reader,
+        }
+    }
+
+    fn into_inner(self) -> R {
+        match self {
+            Self::Gzip(reader) => reader.into_inner(),
+            Self::Zlib(reader) => reader.into_inner(),
+            Self::Raw(reader) => reader,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(reader) => reader.read(buf),
+            Self::Zlib(reader) => reader.read(buf),
+            Self::Raw(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Body<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Self::Gzip(reader) => reader.fill_buf(),
+            Self::Zlib(reader) => reader.fill_buf(),
+            Self::Raw(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            Self::Gzip(reader) => reader.consume(amt),
+            Self::Zlib(reader) => reader.consume(amt),
+            Self::Raw(reader) => reader.consume(amt),
+        }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+pub(crate) fn into_io_error(err: anyhow::Error) -> io::Error {
+    match DecodeError::from_anyhow(err) {
+        DecodeError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
@@ -1,8 +1,20 @@
-use std::io::BufRead;
+use std::io::{BufRead, Write};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, WriteBytesExt};
+use log::*;
 
-use crate::bit_reader::BitReader;
+use crate::{
+    bit_reader::{BitReader, BitSequence},
+    bit_writer::BitWriter,
+    error::DecodeError,
+    huffman_coding::{
+        canonical_codes, fixed_distance_lengths, fixed_litlen_lengths, lengths_from_frequencies,
+        DISTANCE_BASES, DISTANCE_EXTRA_BITS, DISTANCE_SYMBOL_COUNT, LENGTH_BASES,
+        LENGTH_EXTRA_BITS, LITLEN_SYMBOL_COUNT, MAX_BITS, TREE_CODE_ORDER,
+    },
+    lz77::Token,
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -12,7 +24,7 @@ pub struct BlockHeader {
     pub compression_type: CompressionType,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     Uncompressed = 0,
     FixedTree = 1,
@@ -22,20 +34,400 @@ pub enum CompressionType {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+impl From<u16> for CompressionType {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::Uncompressed,
+            1 => Self::FixedTree,
+            2 => Self::DynamicTree,
+            _ => Self::Reserved,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub struct DeflateReader<T> {
     bit_reader: BitReader<T>,
-    // TODO: your code here.
+    seen_final: bool,
 }
 
 impl<T: BufRead> DeflateReader<T> {
     pub fn new(bit_reader: BitReader<T>) -> Self {
-        // TODO: your code here.
-        unimplemented!()
+        Self {
+            bit_reader,
+            seen_final: false,
+        }
     }
 
     pub fn next_block(&mut self) -> Option<Result<(BlockHeader, &mut BitReader<T>)>> {
-        // TODO: your code here.
-        unimplemented!()
+        if self.seen_final {
+            return None;
+        }
+        Some(
+            self.read_block_header()
+                .map(|header| (header, &mut self.bit_reader)),
+        )
+    }
+
+    /// Check whether the final block has been started, i.e. no more block headers follow.
+    pub fn is_finished(&self) -> bool {
+        self.seen_final
+    }
+
+    pub fn bit_reader(&self) -> &BitReader<T> {
+        &self.bit_reader
+    }
+
+    /// Access the data of the current block.
+    pub fn bit_reader_mut(&mut self) -> &mut BitReader<T> {
+        &mut self.bit_reader
+    }
+
+    pub fn into_inner(self) -> BitReader<T> {
+        self.bit_reader
+    }
+
+    fn read_block_header(&mut self) -> Result<BlockHeader> {
+        // See RFC 1951, section 3.2.3. NB: the fields are read at once, so that the header
+        // is either read in full or not at all if the reader runs out of input.
+        let bits = self.bit_reader.read_bits(3)?.bits();
+        let is_final = bits & 1 == 1;
+        let compression_type = CompressionType::from(bits >> 1);
+        if compression_type == CompressionType::Reserved {
+            bail!(DecodeError::UnsupportedBlockType);
+        }
+        self.seen_final = is_final;
+        Ok(BlockHeader {
+            is_final,
+            compression_type,
+        })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const END_OF_BLOCK: usize = 256;
+const MAX_STORED_LEN: usize = u16::MAX as usize;
+const MAX_TREE_CODE_BITS: u8 = 7;
+
+pub struct DeflateWriter<T> {
+    bit_writer: BitWriter<T>,
+}
+
+impl<T: Write> DeflateWriter<T> {
+    pub fn new(bit_writer: BitWriter<T>) -> Self {
+        Self { bit_writer }
+    }
+
+    /// Write `data` using whichever block type yields the smallest output. `tokens` must
+    /// expand exactly to `data`.
+    pub fn write_block(&mut self, tokens: &[Token], data: &[u8], is_final: bool) -> Result<()> {
+        let fixed = BlockCoding::new(&fixed_litlen_lengths(), &fixed_distance_lengths())?;
+        let dynamic = DynamicTrees::new(tokens)?;
+
+        let stored_cost = stored_cost(data.len());
+        let fixed_cost = 3 + fixed.data_cost(tokens);
+        let dynamic_cost = 3 + dynamic.header_cost() + dynamic.coding.data_cost(tokens);
+        trace!(
+            "block of {} bytes: stored {} bits, fixed {} bits, dynamic {} bits",
+            data.len(),
+            stored_cost,
+            fixed_cost,
+            dynamic_cost
+        );
+
+        if stored_cost <= fixed_cost.min(dynamic_cost) {
+            self.write_stored(data, is_final)
+        } else if fixed_cost <= dynamic_cost {
+            self.write_header(&BlockHeader {
+                is_final,
+                compression_type: CompressionType::FixedTree,
+            })?;
+            self.write_tokens(tokens, &fixed)
+        } else {
+            self.write_header(&BlockHeader {
+                is_final,
+                compression_type: CompressionType::DynamicTree,
+            })?;
+            self.write_dynamic_trees(&dynamic)?;
+            self.write_tokens(tokens, &dynamic.coding)
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.bit_writer.get_mut()
+    }
+
+    /// Pad the last block to the byte boundary and return the underlying writer.
+    pub fn finish(self) -> Result<T> {
+        Ok(self.bit_writer.finish()?)
+    }
+
+    fn write_header(&mut self, header: &BlockHeader) -> Result<()> {
+        // See RFC 1951, section 3.2.3.
+        self.bit_writer
+            .write_bits(BitSequence::new(header.is_final as u16, 1))?;
+        self.bit_writer
+            .write_bits(BitSequence::new(header.compression_type as u16, 2))?;
+        Ok(())
+    }
+
+    fn write_stored(&mut self, data: &[u8], is_final: bool) -> Result<()> {
+        let pieces: Vec<&[u8]> = if data.is_empty() {
+            vec![data]
+        } else {
+            data.chunks(MAX_STORED_LEN).collect()
+        };
+        for (i, piece) in pieces.iter().enumerate() {
+            self.write_header(&BlockHeader {
+                is_final: is_final && i + 1 == pieces.len(),
+                compression_type: CompressionType::Uncompressed,
+            })?;
+            let writer = self.bit_writer.borrow_writer_from_boundary()?;
+            writer.write_u16::<LittleEndian>(piece.len() as u16)?;
+            writer.write_u16::<LittleEndian>(!(piece.len() as u16))?;
+            writer.write_all(piece)?;
+        }
+        Ok(())
+    }
+
+    fn write_dynamic_trees(&mut self, trees: &DynamicTrees) -> Result<()> {
+        // See RFC 1951, section 3.2.7.
+        let bits = &mut self.bit_writer;
+        bits.write_bits(BitSequence::new((trees.litlen_count - 257) as u16, 5))?;
+        bits.write_bits(BitSequence::new((trees.distance_count - 1) as u16, 5))?;
+        bits.write_bits(BitSequence::new((trees.tree_code_count - 4) as u16, 4))?;
+        for &symbol in TREE_CODE_ORDER.iter().take(trees.tree_code_count) {
+            bits.write_bits(BitSequence::new(trees.tree_lengths[symbol] as u16, 3))?;
+        }
+        for &(symbol, extra) in &trees.encoded_lengths {
+            bits.write_bits(trees.tree_codes[symbol as usize])?;
+            bits.write_bits(extra)?;
+        }
+        Ok(())
+    }
+
+    fn write_tokens(&mut self, tokens: &[Token], coding: &BlockCoding) -> Result<()> {
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => {
+                    self.bit_writer
+                        .write_bits(coding.litlen_codes[byte as usize])?;
+                }
+                Token::Match { len, dist } => {
+                    let (symbol, extra) = length_symbol(len);
+                    self.bit_writer.write_bits(coding.litlen_codes[symbol])?;
+                    self.bit_writer.write_bits(extra)?;
+                    let (symbol, extra) = distance_symbol(dist);
+                    self.bit_writer.write_bits(coding.distance_codes[symbol])?;
+                    self.bit_writer.write_bits(extra)?;
+                }
+            }
+        }
+        self.bit_writer
+            .write_bits(coding.litlen_codes[END_OF_BLOCK])?;
+        Ok(())
     }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Codes of litlen and distance symbols, stored in the order they are emitted.
+struct BlockCoding {
+    litlen_lengths: Vec<u8>,
+    distance_lengths: Vec<u8>,
+    litlen_codes: Vec<BitSequence>,
+    distance_codes: Vec<BitSequence>,
+}
+
+impl BlockCoding {
+    fn new(litlen_lengths: &[u8], distance_lengths: &[u8]) -> Result<Self> {
+        Ok(Self {
+            litlen_lengths: litlen_lengths.to_vec(),
+            distance_lengths: distance_lengths.to_vec(),
+            litlen_codes: emitted_codes(litlen_lengths)?,
+            distance_codes: emitted_codes(distance_lengths)?,
+        })
+    }
+
+    fn data_cost(&self, tokens: &[Token]) -> usize {
+        let mut cost = self.litlen_lengths[END_OF_BLOCK] as usize;
+        for token in tokens {
+            cost += match *token {
+                Token::Literal(byte) => self.litlen_lengths[byte as usize] as usize,
+                Token::Match { len, dist } => {
+                    let (len_symbol, len_extra) = length_symbol(len);
+                    let (dist_symbol, dist_extra) = distance_symbol(dist);
+                    self.litlen_lengths[len_symbol] as usize
+                        + len_extra.len() as usize
+                        + self.distance_lengths[dist_symbol] as usize
+                        + dist_extra.len() as usize
+                }
+            };
+        }
+        cost
+    }
+}
+
+fn emitted_codes(lengths: &[u8]) -> Result<Vec<BitSequence>> {
+    // NB: Huffman codes are packed starting from the most significant bit.
+    Ok(canonical_codes(lengths)?
+        .into_iter()
+        .map(|code| code.map_or(BitSequence::new(0, 0), BitSequence::reversed))
+        .collect())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+struct DynamicTrees {
+    coding: BlockCoding,
+    litlen_count: usize,
+    distance_count: usize,
+    tree_code_count: usize,
+    tree_lengths: Vec<u8>,
+    tree_codes: Vec<BitSequence>,
+    encoded_lengths: Vec<(u8, BitSequence)>,
+}
+
+impl DynamicTrees {
+    fn new(tokens: &[Token]) -> Result<Self> {
+        let mut litlen_frequencies = vec![0; LITLEN_SYMBOL_COUNT];
+        let mut distance_frequencies = vec![0; DISTANCE_SYMBOL_COUNT];
+        litlen_frequencies[END_OF_BLOCK] = 1;
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => litlen_frequencies[byte as usize] += 1,
+                Token::Match { len, dist } => {
+                    litlen_frequencies[length_symbol(len).0] += 1;
+                    distance_frequencies[distance_symbol(dist).0] += 1;
+                }
+            }
+        }
+        // NB: some decoders reject incomplete codes, and a code with a single symbol is
+        // incomplete. Padding the alphabets keeps every code complete.
+        ensure_two_symbols(&mut litlen_frequencies);
+        ensure_two_symbols(&mut distance_frequencies);
+
+        let litlen_lengths = lengths_from_frequencies(&litlen_frequencies, MAX_BITS as u8);
+        let distance_lengths = lengths_from_frequencies(&distance_frequencies, MAX_BITS as u8);
+        let litlen_count = used_prefix_len(&litlen_lengths, 257);
+        let distance_count = used_prefix_len(&distance_lengths, 1);
+
+        let all_lengths: Vec<u8> = litlen_lengths[..litlen_count]
+            .iter()
+            .chain(&distance_lengths[..distance_count])
+            .copied()
+            .collect();
+        let encoded_lengths = run_length_encode(&all_lengths);
+
+        let mut tree_frequencies = vec![0; TREE_CODE_ORDER.len()];
+        for &(symbol, _) in &encoded_lengths {
+            tree_frequencies[symbol as usize] += 1;
+        }
+        ensure_two_symbols(&mut tree_frequencies);
+        let tree_lengths = lengths_from_frequencies(&tree_frequencies, MAX_TREE_CODE_BITS);
+        let tree_code_count = TREE_CODE_ORDER
+            .iter()
+            .rposition(|&symbol| tree_lengths[symbol] != 0)
+            .map_or(0, |pos| pos + 1)
+            .max(4);
+
+        Ok(Self {
+            coding: BlockCoding::new(&litlen_lengths, &distance_lengths)?,
+            litlen_count,
+            distance_count,
+            tree_code_count,
+            tree_codes: emitted_codes(&tree_lengths)?,
+            tree_lengths,
+            encoded_lengths,
+        })
+    }
+
+    fn header_cost(&self) -> usize {
+        let encoded_cost: usize = self
+            .encoded_lengths
+            .iter()
+            .map(|&(symbol, extra)| {
+                self.tree_lengths[symbol as usize] as usize + extra.len() as usize
+            })
+            .sum();
+        5 + 5 + 4 + 3 * self.tree_code_count + encoded_cost
+    }
+}
+
+fn ensure_two_symbols(frequencies: &mut [u32]) {
+    let mut used = frequencies.iter().filter(|&&f| f > 0).count();
+    for frequency in frequencies.iter_mut() {
+        if used >= 2 {
+            break;
+        }
+        if *frequency == 0 {
+            *frequency = 1;
+            used += 1;
+        }
+    }
+}
+
+fn used_prefix_len(lengths: &[u8], min_len: usize) -> usize {
+    lengths
+        .iter()
+        .rposition(|&len| len != 0)
+        .map_or(0, |pos| pos + 1)
+        .max(min_len)
+}
+
+/// Encode code lengths with the code length alphabet, see RFC 1951, section 3.2.7.
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, BitSequence)> {
+    let no_extra = BitSequence::new(0, 0);
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == len).count();
+        i += run;
+
+        let mut left = run;
+        if len == 0 {
+            while left >= 11 {
+                let count = left.min(138);
+                encoded.push((18, BitSequence::new((count - 11) as u16, 7)));
+                left -= count;
+            }
+            if left >= 3 {
+                encoded.push((17, BitSequence::new((left - 3) as u16, 3)));
+                left = 0;
+            }
+        } else {
+            encoded.push((len, no_extra));
+            left -= 1;
+            while left >= 3 {
+                let count = left.min(6);
+                encoded.push((16, BitSequence::new((count - 3) as u16, 2)));
+                left -= count;
+            }
+        }
+        encoded.extend(std::iter::repeat_n((len, no_extra), left));
+    }
+    encoded
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn length_symbol(len: u16) -> (usize, BitSequence) {
+    let index = LENGTH_BASES.partition_point(|&base| base <= len) - 1;
+    let extra = BitSequence::new(len - LENGTH_BASES[index], LENGTH_EXTRA_BITS[index]);
+    (257 + index, extra)
+}
+
+fn distance_symbol(dist: u16) -> (usize, BitSequence) {
+    let index = DISTANCE_BASES.partition_point(|&base| base <= dist) - 1;
+    let extra = BitSequence::new(dist - DISTANCE_BASES[index], DISTANCE_EXTRA_BITS[index]);
+    (index, extra)
+}
 
+fn stored_cost(len: usize) -> usize {
+    // Header, worst case padding, LEN and NLEN of every piece.
+    let piece_count = len.div_ceil(MAX_STORED_LEN).max(1);
+    piece_count * (3 + 7 + 32) + 8 * len
 }
@@ -0,0 +1,201 @@
+use std::io::{self, Write};
+
+use anyhow::{bail, Result};
+use crc::{crc32, Hasher32};
+use log::*;
+
+use crate::{
+    bit_writer::BitWriter,
+    deflate::DeflateWriter,
+    gzip::{CompressionMethod, GzipWriter, MemberFooter, MemberWriter},
+    lz77::{Matcher, Token, DEFAULT_LEVEL, MAX_LEVEL, MIN_LEVEL},
+    zlib::{Adler32, ZlibFooter, ZlibHeader, ZlibStreamWriter, ZlibWriter},
+    CompressOptions, Format, MemberHeader,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of input fed to the LZ77 matcher at once.
+const COMPRESSION_CHUNK_SIZE: usize = 1 << 18;
+/// Maximum number of LZ77 tokens in one deflate block.
+const MAX_BLOCK_TOKENS: usize = 1 << 14;
+
+/// Compresses the data written to it into a gzip member, a zlib stream or raw deflate data.
+///
+/// Input is buffered and compressed in chunks of a fixed size, so `flush` only passes on
+/// the chunks compressed so far. The stream is completed by `finish`, dropping the encoder
+/// without calling it leaves the output truncated.
+pub struct GzipEncoder<W: Write> {
+    deflate_writer: DeflateWriter<Body<W>>,
+    matcher: Matcher,
+    chunk: Vec<u8>,
+    data_size: u64,
+    crc32: crc32::Digest,
+    adler32: Adler32,
+}
+
+/// Compressed data together with the container it is framed by.
+enum Body<W> {
+    Gzip(MemberWriter<W>),
+    Zlib(ZlibStreamWriter<W>),
+    Raw(W),
+}
+
+impl<W: Write> GzipEncoder<W> {
+    pub fn new(writer: W) -> Result<Self> {
+        Self::with_format(writer, Format::Gzip)
+    }
+
+    pub fn with_format(writer: W, format: Format) -> Result<Self> {
+        Self::with_options(writer, format, &CompressOptions::default())
+    }
+
+    /// Create an encoder with the given settings. The header is only written by gzip.
+    pub fn with_options(writer: W, format: Format, options: &CompressOptions) -> Result<Self> {
+        if !(MIN_LEVEL..=MAX_LEVEL).contains(&options.level) {
+            bail!("invalid compression level: {}", options.level);
+        }
+
+        let body = match format {
+            Format::Gzip => {
+                // See RFC 1952, section 2.3.1.
+                let extra_flags = match options.level {
+                    MAX_LEVEL => 2,
+                    MIN_LEVEL => 4,
+                    _ => 0,
+                };
+                let header = MemberHeader {
+                    compression_method: CompressionMethod::Deflate,
+                    extra_flags,
+                    ..options.header.clone()
+                };
+                Body::Gzip(GzipWriter::new(writer).write_member_header(&header)?)
+            }
+            Format::Zlib => {
+                let header = ZlibHeader {
+                    compression_info: 7,
+                    // See RFC 1950, section 2.2.
+                    compression_level: match options.level {
+                        MIN_LEVEL => 0,
+                        level if level < DEFAULT_LEVEL => 1,
+                        DEFAULT_LEVEL => 2,
+                        _ => 3,
+                    },
+                };
+                Body::Zlib(ZlibWriter::new(writer).write_header(&header)?)
+            }
+            Format::Raw => Body::Raw(writer),
+        };
+
+        Ok(Self {
+            deflate_writer: DeflateWriter::new(BitWriter::new(body)),
+            matcher: Matcher::with_level(options.level),
+            chunk: Vec::with_capacity(COMPRESSION_CHUNK_SIZE),
+            data_size: 0,
+            crc32: crc32::Digest::new(crc32::IEEE),
+            adler32: Adler32::new(),
+        })
+    }
+
+    /// Return the underlying writer. Compressed data is written to it as soon as it is
+    /// produced, so it may be drained between writes.
+    pub fn get_mut(&mut self) -> &mut W {
+        match self.deflate_writer.get_mut() {
+            Body::Gzip(member_writer) => member_writer.get_mut(),
+            Body::Zlib(stream_writer) => stream_writer.get_mut(),
+            Body::Raw(writer) => writer,
+        }
+    }
+
+    /// Compress the rest of the input, write the footer and return the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.compress_chunk(true)?;
+        let writer = match self.deflate_writer.finish()? {
+            Body::Gzip(member_writer) => {
+                let footer = MemberFooter {
+                    data_crc32: self.crc32.sum32(),
+                    data_size: self.data_size as u32,
+                };
+                debug!("member footer: {:?}", footer);
+                member_writer.write_footer(&footer)?.into_inner()
+            }
+            Body::Zlib(stream_writer) => stream_writer.write_footer(&ZlibFooter {
+                data_adler32: self.adler32.sum(),
+            })?,
+            Body::Raw(writer) => writer,
+        };
+        Ok(writer)
+    }
+
+    /// Write the buffered input as a sequence of deflate blocks.
+    fn compress_chunk(&mut self, is_last_chunk: bool) -> Result<()> {
+        let chunk = &self.chunk;
+        match self.deflate_writer.get_mut() {
+            Body::Gzip(_) => self.crc32.write(chunk),
+            Body::Zlib(_) => self.adler32.update(chunk),
+            Body::Raw(_) => {}
+        }
+        self.data_size += chunk.len() as u64;
+
+        let tokens = self.matcher.find_matches(chunk);
+        let block_count = tokens.len().div_ceil(MAX_BLOCK_TOKENS);
+        let mut offset = 0;
+        for (i, block) in tokens.chunks(MAX_BLOCK_TOKENS).enumerate() {
+            let len: usize = block.iter().map(Token::byte_len).sum();
+            let is_final = is_last_chunk && i + 1 == block_count;
+            self.deflate_writer
+                .write_block(block, &chunk[offset..offset + len], is_final)?;
+            offset += len;
+        }
+        if is_last_chunk && tokens.is_empty() {
+            self.deflate_writer.write_block(&[], &[], true)?;
+        }
+
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for GzipEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // NB: a full chunk is only known not to be the last one once more data arrives.
+        if self.chunk.len() == COMPRESSION_CHUNK_SIZE {
+            self.compress_chunk(false).map_err(into_io_error)?;
+        }
+        let len = buf.len().min(COMPRESSION_CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Write for Body<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Gzip(writer) => writer.write(buf),
+            Self::Zlib(writer) => writer.write(buf),
+            Self::Raw(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Gzip(writer) => writer.flush(),
+            Self::Zlib(writer) => writer.flush(),
+            Self::Raw(writer) => writer.flush(),
+        }
+    }
+}
+
+pub(crate) fn into_io_error(err: anyhow::Error) -> io::Error {
+    match err.downcast::<io::Error>() {
+        Ok(err) => err,
+        Err(err) => io::Error::other(err),
+    }
+}
@@ -0,0 +1,91 @@
+use std::io;
+
+use thiserror::Error;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A reason a compressed stream can't be decoded.
+///
+/// This is what `decompress` and its variants return. The other decoding functions return
+/// `anyhow` errors, but every failure caused by broken data is one of these and may be
+/// found with `anyhow::Error::downcast_ref`.
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("wrong id values: {id1:#04x} {id2:#04x}")]
+    WrongId { id1: u8, id2: u8 },
+    #[error("unsupported compression method: {0}")]
+    UnsupportedCompressionMethod(u8),
+    #[error("header crc16 check failed: expected {expected:#06x}, got {actual:#06x}")]
+    HeaderCrc16Mismatch { expected: u16, actual: u16 },
+    #[error("unsupported block type")]
+    UnsupportedBlockType,
+    #[error("nlen check failed: len = {len}, nlen = {nlen}")]
+    NlenMismatch { len: u16, nlen: u16 },
+    #[error("invalid {tree} tree: {reason}")]
+    InvalidHuffmanTree { tree: &'static str, reason: String },
+    #[error("invalid {alphabet} symbol: {symbol}")]
+    InvalidSymbol { alphabet: &'static str, symbol: u16 },
+    #[error("code length is too large: {0}")]
+    CodeLengthTooLarge(u8),
+    #[error("code lengths are oversubscribed")]
+    OversubscribedCodeLengths,
+    #[error("invalid huffman code: {code:015b}")]
+    InvalidHuffmanCode { code: u16 },
+    #[error("invalid back reference: distance {distance}, history size {history_size}")]
+    InvalidBackReference {
+        distance: usize,
+        history_size: usize,
+    },
+    #[error("length check failed: expected {expected}, got {actual}")]
+    LengthMismatch { expected: u32, actual: u32 },
+    #[error("crc32 check failed: expected {expected:#010x}, got {actual:#010x}")]
+    Crc32Mismatch { expected: u32, actual: u32 },
+    #[error("unsupported window size: cinfo = {0}")]
+    UnsupportedWindowSize(u8),
+    #[error("zlib header check failed")]
+    ZlibHeaderCheck,
+    #[error("preset dictionaries are not supported")]
+    PresetDictionary,
+    #[error("adler32 check failed: expected {expected:#010x}, got {actual:#010x}")]
+    Adler32Mismatch { expected: u32, actual: u32 },
+    /// The decoder is used again after an error.
+    #[error("decoder has failed earlier")]
+    DecoderFailed,
+    /// Reading the input or writing the output failed. The input failing with
+    /// `UnexpectedEof` means that it has ended too early.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl DecodeError {
+    /// Extract the reason of a decoding failure, wrapping any unexpected error into
+    /// `DecodeError::Io`.
+    pub(crate) fn from_anyhow(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<Self>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        match err.downcast::<io::Error>() {
+            Ok(err) => Self::Io(err),
+            Err(err) => Self::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:#}", err),
+            )),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A failure to decode a gzip member, reported by `decompress_recover`.
+#[derive(Error, Debug)]
+#[error("member #{member_index} at offset {member_offset}: failed at offset {offset}: {error}")]
+pub struct MemberError {
+    /// Number of members (including broken ones) preceding this one.
+    pub member_index: usize,
+    /// Offset of the member's header in the compressed stream.
+    pub member_offset: u64,
+    /// Offset in the compressed stream at which the failure was detected.
+    pub offset: u64,
+    pub error: DecodeError,
+}
@@ -1,9 +1,14 @@
-use std::io::{self, BufRead};
+use std::{
+    borrow::Cow,
+    io::{self, BufRead, Read, Write},
+};
 
 use anyhow::{anyhow, bail, Context, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use crc::{crc32, Hasher32};
 
+use crate::{error::DecodeError, resumable::PartialRead};
+
 ////////////////////////////////////////////////////////////////////////////////
 
 const ID1: u8 = 0x1f;
@@ -11,6 +16,22 @@ const ID2: u8 = 0x8b;
 
 const CM_DEFLATE: u8 = 8;
 
+/// The value of OS in the headers written by this crate.
+pub const OS_UNKNOWN: u8 = 255;
+
+/// Check whether the first two bytes of a stream form a gzip member header.
+pub fn is_gzip_header(id1: u8, id2: u8) -> bool {
+    id1 == ID1 && id2 == ID2
+}
+
+/// Check whether a member header using deflate with no reserved flags may start at the
+/// beginning of `data`, which is allowed to hold just a prefix of the header.
+pub fn is_member_candidate(data: &[u8]) -> bool {
+    const MAGIC: [u8; 3] = [ID1, ID2, CM_DEFLATE];
+    let len = data.len().min(MAGIC.len());
+    data[..len] == MAGIC[..len] && data.get(3).is_none_or(|flags| flags & 0xe0 == 0)
+}
+
 const FTEXT_OFFSET: u8 = 0;
 const FHCRC_OFFSET: u8 = 1;
 const FEXTRA_OFFSET: u8 = 2;
@@ -19,20 +40,87 @@ const FCOMMENT_OFFSET: u8 = 4;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+/// Header of a gzip member, see RFC 1952, section 2.3.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MemberHeader {
     pub compression_method: CompressionMethod,
+    /// Modification time of the original file as a Unix timestamp, zero if not available.
     pub modification_time: u32,
+    /// Contents of the FEXTRA field, see `extra_subfields`.
     pub extra: Option<Vec<u8>>,
-    pub name: Option<String>,
-    pub comment: Option<String>,
+    /// Raw bytes of the FNAME field. RFC 1952 prescribes ISO 8859-1, but in practice it is
+    /// whatever the file system used, see `name_lossy`.
+    pub name: Option<Vec<u8>>,
+    /// Raw bytes of the FCOMMENT field, see `comment_lossy`.
+    pub comment: Option<Vec<u8>>,
     pub extra_flags: u8,
     pub os: u8,
+    /// Whether the header is protected by a CRC16, see `crc16`.
     pub has_crc: bool,
     pub is_text: bool,
 }
 
+/// A deflate member with no optional fields, written by an unknown OS.
+impl Default for MemberHeader {
+    fn default() -> Self {
+        Self {
+            compression_method: CompressionMethod::Deflate,
+            modification_time: 0,
+            extra: None,
+            name: None,
+            comment: None,
+            extra_flags: 0,
+            os: OS_UNKNOWN,
+            has_crc: false,
+            is_text: false,
+        }
+    }
+}
+
 impl MemberHeader {
+    pub fn builder() -> MemberHeaderBuilder {
+        MemberHeaderBuilder::new()
+    }
+
+    /// Parse the FEXTRA field into subfields. Returns an empty list if there is no field.
+    pub fn extra_subfields(&self) -> Result<Vec<ExtraSubfield>> {
+        // See RFC 1952, section 2.3.1.1.
+        let mut data = self.extra.as_deref().unwrap_or_default();
+        let mut subfields = Vec::new();
+        while !data.is_empty() {
+            if data.len() < 4 {
+                bail!("extra subfield header is truncated");
+            }
+            let id = [data[0], data[1]];
+            let len = u16::from_le_bytes([data[2], data[3]]) as usize;
+            data = &data[4..];
+            if data.len() < len {
+                bail!(
+                    "extra subfield {:?} is truncated: {} bytes of {}",
+                    id,
+                    data.len(),
+                    len
+                );
+            }
+            subfields.push(ExtraSubfield {
+                id,
+                data: data[..len].to_vec(),
+            });
+            data = &data[len..];
+        }
+        Ok(subfields)
+    }
+
+    /// The FNAME field decoded for display, with invalid UTF-8 replaced.
+    pub fn name_lossy(&self) -> Option<Cow<'_, str>> {
+        self.name.as_deref().map(String::from_utf8_lossy)
+    }
+
+    /// The FCOMMENT field decoded for display, with invalid UTF-8 replaced.
+    pub fn comment_lossy(&self) -> Option<Cow<'_, str>> {
+        self.comment.as_deref().map(String::from_utf8_lossy)
+    }
+
     pub fn crc16(&self) -> u16 {
         let mut digest = crc32::Digest::new(crc32::IEEE);
 
@@ -46,12 +134,12 @@ impl MemberHeader {
         }
 
         if let Some(name) = &self.name {
-            digest.write(name.as_bytes());
+            digest.write(name);
             digest.write(&[0]);
         }
 
         if let Some(comment) = &self.comment {
-            digest.write(comment.as_bytes());
+            digest.write(comment);
             digest.write(&[0]);
         }
 
@@ -71,7 +159,111 @@ impl MemberHeader {
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, Debug)]
+/// A record of the FEXTRA field: subfield ID (SI1 and SI2) followed by its data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtraSubfield {
+    pub id: [u8; 2],
+    pub data: Vec<u8>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Builds a `MemberHeader` for writing, checking that every field can be encoded.
+#[derive(Clone, Debug)]
+pub struct MemberHeaderBuilder {
+    header: MemberHeader,
+    subfields: Vec<ExtraSubfield>,
+}
+
+impl MemberHeaderBuilder {
+    fn new() -> Self {
+        Self {
+            header: MemberHeader::default(),
+            subfields: Vec::new(),
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<Vec<u8>>) -> Self {
+        self.header.name = Some(name.into());
+        self
+    }
+
+    pub fn comment(mut self, comment: impl Into<Vec<u8>>) -> Self {
+        self.header.comment = Some(comment.into());
+        self
+    }
+
+    pub fn modification_time(mut self, timestamp: u32) -> Self {
+        self.header.modification_time = timestamp;
+        self
+    }
+
+    pub fn os(mut self, os: u8) -> Self {
+        self.header.os = os;
+        self
+    }
+
+    pub fn is_text(mut self, is_text: bool) -> Self {
+        self.header.is_text = is_text;
+        self
+    }
+
+    /// Protect the header with a CRC16 (FHCRC).
+    pub fn header_crc(mut self, has_crc: bool) -> Self {
+        self.header.has_crc = has_crc;
+        self
+    }
+
+    /// Append a subfield to the FEXTRA field.
+    pub fn extra_subfield(mut self, id: [u8; 2], data: impl Into<Vec<u8>>) -> Self {
+        self.subfields.push(ExtraSubfield {
+            id,
+            data: data.into(),
+        });
+        self
+    }
+
+    pub fn build(self) -> Result<MemberHeader> {
+        let mut header = self.header;
+        for field in [&header.name, &header.comment].into_iter().flatten() {
+            if field.contains(&0) {
+                bail!(
+                    "header field contains a zero byte: {:?}",
+                    String::from_utf8_lossy(field)
+                );
+            }
+        }
+
+        if !self.subfields.is_empty() {
+            let mut extra = Vec::new();
+            for subfield in &self.subfields {
+                // NB: SI2 = 0 is reserved for future use.
+                if subfield.id[1] == 0 {
+                    bail!("invalid extra subfield id: {:?}", subfield.id);
+                }
+                let len = u16::try_from(subfield.data.len()).map_err(|_| {
+                    anyhow!(
+                        "extra subfield {:?} is too long: {} bytes",
+                        subfield.id,
+                        subfield.data.len()
+                    )
+                })?;
+                extra.extend_from_slice(&subfield.id);
+                extra.extend_from_slice(&len.to_le_bytes());
+                extra.extend_from_slice(&subfield.data);
+            }
+            if extra.len() > u16::MAX as usize {
+                bail!("extra field is too long: {} bytes", extra.len());
+            }
+            header.extra = Some(extra);
+        }
+        Ok(header)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionMethod {
     Deflate,
     Unknown(u8),
@@ -167,35 +359,268 @@ pub struct MemberFooter {
 
 pub struct GzipReader<T> {
     reader: T,
+    header: PartialRead,
 }
 
 impl<T: BufRead> GzipReader<T> {
     pub fn new(reader: T) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            header: PartialRead::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.reader
     }
 
-    pub fn next_member(mut self) -> Option<Result<(MemberHeader, MemberReader<T>)>> {
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    /// Check whether the stream has ended before the header of the next member.
+    pub fn is_at_end(&mut self) -> io::Result<bool> {
+        Ok(self.header.is_empty() && self.reader.fill_buf()?.is_empty())
+    }
+
+    /// Check whether a member header has been partially read, see `PartialRead`.
+    pub fn is_reading_header(&self) -> bool {
+        !self.header.is_empty()
+    }
+
+    /// Read the header of the next member, see `into_member_reader`. Can be called again
+    /// if the reader fails with `WouldBlock`.
+    pub fn next_member(&mut self) -> Option<Result<MemberHeader>> {
         // See RFC 1952, section 2.3.
-        // TODO: your code here.
-        unimplemented!()
+        match self.is_at_end() {
+            Ok(true) => return None,
+            Ok(false) => {}
+            Err(err) => return Some(Err(err.into())),
+        }
+        Some(self.header.read(&mut self.reader, read_header))
     }
 
-    // TODO: your code here.
+    /// Start reading the data of the member whose header has just been read.
+    pub fn into_member_reader(self) -> MemberReader<T> {
+        MemberReader::resume(self.reader)
+    }
+}
+
+fn read_header(reader: &mut &[u8]) -> Result<MemberHeader> {
+    let id1 = reader.read_u8()?;
+    let id2 = reader.read_u8()?;
+    if id1 != ID1 || id2 != ID2 {
+        bail!(DecodeError::WrongId { id1, id2 });
+    }
+
+    let compression_method = CompressionMethod::from(reader.read_u8()?);
+    if let CompressionMethod::Unknown(method) = compression_method {
+        bail!(DecodeError::UnsupportedCompressionMethod(method));
+    }
+
+    let flags = MemberFlags(reader.read_u8()?);
+    let modification_time = reader.read_u32::<LittleEndian>()?;
+    let extra_flags = reader.read_u8()?;
+    let os = reader.read_u8()?;
+
+    let extra = if flags.has_extra() {
+        let len = reader.read_u16::<LittleEndian>()?;
+        let mut extra = vec![0; len as usize];
+        reader
+            .read_exact(&mut extra)
+            .context("failed to read extra field")?;
+        Some(extra)
+    } else {
+        None
+    };
+
+    let name = if flags.has_name() {
+        Some(read_zero_terminated(reader).context("failed to read name")?)
+    } else {
+        None
+    };
+
+    let comment = if flags.has_comment() {
+        Some(read_zero_terminated(reader).context("failed to read comment")?)
+    } else {
+        None
+    };
+
+    let header = MemberHeader {
+        compression_method,
+        modification_time,
+        extra,
+        name,
+        comment,
+        extra_flags,
+        os,
+        has_crc: flags.has_crc(),
+        is_text: flags.is_text(),
+    };
+
+    if header.has_crc {
+        let crc16 = reader.read_u16::<LittleEndian>()?;
+        if crc16 != header.crc16() {
+            bail!(DecodeError::HeaderCrc16Mismatch {
+                expected: crc16,
+                actual: header.crc16(),
+            });
+        }
+    }
+
+    Ok(header)
+}
+
+fn read_zero_terminated(reader: &mut &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.read_until(0, &mut buf)?;
+    if buf.pop() != Some(0) {
+        return Err(anyhow!(io::Error::from(io::ErrorKind::UnexpectedEof)));
+    }
+    Ok(buf)
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct MemberReader<T> {
     inner: T,
+    footer: PartialRead,
 }
 
 impl<T: BufRead> MemberReader<T> {
-    pub fn inner_mut(&mut self) -> &mut T {
+    /// Continue reading a member from the middle of its compressed data.
+    pub fn resume(inner: T) -> Self {
+        Self {
+            inner,
+            footer: PartialRead::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
         &mut self.inner
     }
 
-    pub fn read_footer(mut self) -> Result<(MemberFooter, GzipReader<T>)> {
-        // TODO: your code here.
-        unimplemented!()
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Read the footer that follows the compressed data. Can be called again if the reader
+    /// fails with `WouldBlock`.
+    pub fn read_footer(&mut self) -> Result<MemberFooter> {
+        self.footer.read(&mut self.inner, |reader| {
+            Ok(MemberFooter {
+                data_crc32: reader.read_u32::<LittleEndian>()?,
+                data_size: reader.read_u32::<LittleEndian>()?,
+            })
+        })
+    }
+
+    /// Continue with the next member once the footer has been read.
+    pub fn into_gzip_reader(self) -> GzipReader<T> {
+        GzipReader::new(self.inner)
+    }
+}
+
+impl<T: BufRead> Read for MemberReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: BufRead> BufRead for MemberReader<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct GzipWriter<T> {
+    writer: T,
+}
+
+impl<T: Write> GzipWriter<T> {
+    pub fn new(writer: T) -> Self {
+        Self { writer }
+    }
+
+    pub fn write_member_header(mut self, header: &MemberHeader) -> Result<MemberWriter<T>> {
+        // See RFC 1952, section 2.3.
+        self.writer.write_all(&[ID1, ID2])?;
+        self.writer.write_u8(header.compression_method.into())?;
+        self.writer.write_u8(header.flags().0)?;
+        self.writer
+            .write_u32::<LittleEndian>(header.modification_time)?;
+        self.writer.write_u8(header.extra_flags)?;
+        self.writer.write_u8(header.os)?;
+
+        if let Some(extra) = &header.extra {
+            if extra.len() > u16::MAX as usize {
+                bail!("extra field is too long: {} bytes", extra.len());
+            }
+            self.writer.write_u16::<LittleEndian>(extra.len() as u16)?;
+            self.writer.write_all(extra)?;
+        }
+
+        for field in [&header.name, &header.comment].into_iter().flatten() {
+            if field.contains(&0) {
+                bail!(
+                    "header field contains a zero byte: {:?}",
+                    String::from_utf8_lossy(field)
+                );
+            }
+            self.writer.write_all(field)?;
+            self.writer.write_u8(0)?;
+        }
+
+        if header.has_crc {
+            self.writer.write_u16::<LittleEndian>(header.crc16())?;
+        }
+
+        Ok(MemberWriter { inner: self.writer })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.writer
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct MemberWriter<T> {
+    inner: T,
+}
+
+impl<T: Write> MemberWriter<T> {
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn write_footer(mut self, footer: &MemberFooter) -> Result<GzipWriter<T>> {
+        self.inner.write_u32::<LittleEndian>(footer.data_crc32)?;
+        self.inner.write_u32::<LittleEndian>(footer.data_size)?;
+        Ok(GzipWriter::new(self.inner))
+    }
+}
+
+impl<T: Write> Write for MemberWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
@@ -1,21 +1,191 @@
-use std::{collections::HashMap, convert::TryFrom, fmt, io::BufRead};
+use std::{cmp::Reverse, collections::BinaryHeap, convert::TryFrom, io::BufRead};
 
 use anyhow::{bail, Result};
 use log::*;
 
-use crate::bit_reader::{BitReader, BitSequence};
+use crate::{
+    bit_reader::{BitReader, BitSequence},
+    error::DecodeError,
+};
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub fn decode_litlen_distance_trees<T: BufRead>(
-    bit_reader: &mut BitReader<T>,
+/// Decodes the code trees of a dynamic block, see RFC 1951, section 3.2.7.
+///
+/// Every field is read in a single call to the bit reader and the progress is kept between
+/// calls to `decode`, so it can be repeated if the reader fails with `WouldBlock`.
+#[derive(Default)]
+pub struct TreeDecoder {
+    /// HLIT, HDIST and HCLEN, converted to counts.
+    counts: Option<(usize, usize, usize)>,
+    tree_code_lengths: [u8; TREE_CODE_ORDER.len()],
+    tree_code_lengths_read: usize,
+    tree_coding: Option<HuffmanCoding<TreeCodeToken>>,
+    /// A token whose extra bits haven't been read yet.
+    pending_token: Option<TreeCodeToken>,
+    lengths: Vec<u8>,
+}
+
+impl TreeDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decode<T: BufRead>(
+        &mut self,
+        bit_reader: &mut BitReader<T>,
+    ) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+        let (litlen_count, distance_count, tree_code_count) = match self.counts {
+            Some(counts) => counts,
+            None => {
+                bit_reader.require_bits(14)?;
+                let counts = (
+                    bit_reader.read_bits(5)?.bits() as usize + 257,
+                    bit_reader.read_bits(5)?.bits() as usize + 1,
+                    bit_reader.read_bits(4)?.bits() as usize + 4,
+                );
+                trace!(
+                    "dynamic trees: hlit = {}, hdist = {}, hclen = {}",
+                    counts.0,
+                    counts.1,
+                    counts.2
+                );
+                *self.counts.insert(counts)
+            }
+        };
+
+        while self.tree_code_lengths_read < tree_code_count {
+            let symbol = TREE_CODE_ORDER[self.tree_code_lengths_read];
+            self.tree_code_lengths[symbol] = bit_reader.read_bits(3)?.bits() as u8;
+            self.tree_code_lengths_read += 1;
+        }
+        let tree_coding = match self.tree_coding.take() {
+            Some(coding) => coding,
+            None => HuffmanCoding::from_lengths(&self.tree_code_lengths)
+                .map_err(invalid_tree("code length"))?,
+        };
+        let tree_coding = &*self.tree_coding.insert(tree_coding);
+
+        // NB: litlen and distance code lengths form a single sequence, so a repeat
+        // token may span both of them.
+        let total_count = litlen_count + distance_count;
+        while self.lengths.len() < total_count {
+            let token = match self.pending_token {
+                Some(token) => token,
+                None => *self
+                    .pending_token
+                    .insert(tree_coding.read_symbol(bit_reader)?),
+            };
+            let (value, count) = match token {
+                TreeCodeToken::Length(len) => (len, 1),
+                TreeCodeToken::CopyPrev => {
+                    let prev = match self.lengths.last() {
+                        Some(&prev) => prev,
+                        None => bail!(DecodeError::InvalidHuffmanTree {
+                            tree: "litlen/distance",
+                            reason: "code length repeat without a previous length".into(),
+                        }),
+                    };
+                    (prev, 3 + bit_reader.read_bits(2)?.bits() as usize)
+                }
+                TreeCodeToken::RepeatZero { base, extra_bits } => {
+                    let extra = bit_reader.read_bits(extra_bits)?.bits();
+                    (0, (base + extra) as usize)
+                }
+            };
+            self.pending_token = None;
+            if self.lengths.len() + count > total_count {
+                bail!(DecodeError::InvalidHuffmanTree {
+                    tree: "litlen/distance",
+                    reason: "code length repeat overflows the number of codes".into(),
+                });
+            }
+            self.lengths.extend(std::iter::repeat_n(value, count));
+        }
+
+        let lengths = &self.lengths;
+        if lengths[256] == 0 {
+            bail!(DecodeError::InvalidHuffmanTree {
+                tree: "litlen",
+                reason: "missing end of block code".into(),
+            });
+        }
+
+        let litlen_coding = HuffmanCoding::from_lengths(&lengths[..litlen_count])
+            .map_err(invalid_tree("litlen"))?;
+        let distance_coding = HuffmanCoding::from_lengths(&lengths[litlen_count..])
+            .map_err(invalid_tree("distance"))?;
+        Ok((litlen_coding, distance_coding))
+    }
+}
+
+fn invalid_tree(tree: &'static str) -> impl FnOnce(anyhow::Error) -> DecodeError {
+    move |err| DecodeError::InvalidHuffmanTree {
+        tree,
+        reason: format!("{:#}", err),
+    }
+}
+
+pub fn fixed_litlen_distance_trees(
 ) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
-    // See RFC 1951, section 3.2.7.
-    // TODO: your code here.
-    unimplemented!()
+    // See RFC 1951, section 3.2.6.
+    Ok((
+        HuffmanCoding::from_lengths(&fixed_litlen_lengths())?,
+        HuffmanCoding::from_lengths(&fixed_distance_lengths())?,
+    ))
 }
 
-// TODO: your code here.
+/// Code lengths of the fixed litlen tree. Symbols 286 and 287 never occur in
+/// compressed data and are left out.
+pub fn fixed_litlen_lengths() -> [u8; LITLEN_SYMBOL_COUNT] {
+    let mut lengths = [0; LITLEN_SYMBOL_COUNT];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    lengths
+}
+
+/// Code lengths of the fixed distance tree. Symbols 30 and 31 never occur in
+/// compressed data and are left out.
+pub fn fixed_distance_lengths() -> [u8; DISTANCE_SYMBOL_COUNT] {
+    [5; DISTANCE_SYMBOL_COUNT]
+}
+
+pub const LITLEN_SYMBOL_COUNT: usize = 286;
+pub const DISTANCE_SYMBOL_COUNT: usize = 30;
+
+/// The order in which code length code lengths are stored, see RFC 1951, section 3.2.7.
+pub const TREE_CODE_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Base lengths of litlen symbols 257..=285.
+pub const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Number of extra bits of litlen symbols 257..=285.
+pub const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances of distance symbols 0..=29.
+pub const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Number of extra bits of distance symbols 0..=29.
+pub const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -31,8 +201,22 @@ impl TryFrom<HuffmanCodeWord> for TreeCodeToken {
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         // See RFC 1951, section 3.2.7.
-        // TODO: your code here.
-        unimplemented!()
+        Ok(match value.0 {
+            len @ 0..=15 => Self::Length(len as u8),
+            16 => Self::CopyPrev,
+            17 => Self::RepeatZero {
+                base: 3,
+                extra_bits: 3,
+            },
+            18 => Self::RepeatZero {
+                base: 11,
+                extra_bits: 7,
+            },
+            symbol => bail!(DecodeError::InvalidSymbol {
+                alphabet: "code length",
+                symbol,
+            }),
+        })
     }
 }
 
@@ -50,8 +234,21 @@ impl TryFrom<HuffmanCodeWord> for LitLenToken {
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         // See RFC 1951, section 3.2.5.
-        // TODO: your code here.
-        unimplemented!()
+        Ok(match value.0 {
+            byte @ 0..=255 => Self::Literal(byte as u8),
+            256 => Self::EndOfBlock,
+            symbol @ 257..=285 => {
+                let index = (symbol - 257) as usize;
+                Self::Length {
+                    base: LENGTH_BASES[index],
+                    extra_bits: LENGTH_EXTRA_BITS[index],
+                }
+            }
+            symbol => bail!(DecodeError::InvalidSymbol {
+                alphabet: "litlen",
+                symbol,
+            }),
+        })
     }
 }
 
@@ -68,52 +265,238 @@ impl TryFrom<HuffmanCodeWord> for DistanceToken {
 
     fn try_from(value: HuffmanCodeWord) -> Result<Self> {
         // See RFC 1951, section 3.2.5.
-        // TODO: your code here.
-        unimplemented!()
+        match value.0 {
+            symbol @ 0..=29 => Ok(Self {
+                base: DISTANCE_BASES[symbol as usize],
+                extra_bits: DISTANCE_EXTRA_BITS[symbol as usize],
+            }),
+            symbol => bail!(DecodeError::InvalidSymbol {
+                alphabet: "distance",
+                symbol,
+            }),
+        }
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-const MAX_BITS: usize = 15;
+pub const MAX_BITS: usize = 15;
+
+/// Number of bits indexing the primary decoding table. Longer codes are resolved with
+/// a secondary table linked from the primary one.
+const PRIMARY_BITS: u8 = 9;
+const PRIMARY_MASK: usize = (1 << PRIMARY_BITS) - 1;
 
 pub struct HuffmanCodeWord(pub u16);
 
+#[derive(Clone, Copy)]
+enum TableEntry<T> {
+    Invalid,
+    Symbol { value: T, len: u8 },
+    Link { offset: usize, bits: u8 },
+}
+
+/// Decodes Huffman codes with lookup tables indexed by the next bits of the stream.
+///
+/// Codes arrive starting from their first bit, while the bit reader returns the earliest
+/// bit as the lowest one, so tables are indexed by bit-reversed codes. Every code shorter
+/// than the index occupies all the entries that start with it.
 pub struct HuffmanCoding<T> {
-    map: HashMap<BitSequence, T>,
+    table: Vec<TableEntry<T>>,
 }
 
 impl<T> HuffmanCoding<T>
 where
     T: Copy + TryFrom<HuffmanCodeWord, Error = anyhow::Error>,
 {
-    pub fn new(map: HashMap<BitSequence, T>) -> Self {
-        Self { map }
-    }
-
+    #[cfg(test)]
     pub fn decode_symbol(&self, seq: BitSequence) -> Option<T> {
-        // TODO: your code here.
-        unimplemented!()
+        match self.lookup(seq.reversed().bits()) {
+            Some((value, len)) if len == seq.len() => Some(value),
+            _ => None,
+        }
     }
 
     pub fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
-        // TODO: your code here.
-        unimplemented!()
+        loop {
+            let seq = bit_reader.peek_bits(MAX_BITS as u8)?;
+            match self.lookup(seq.bits()) {
+                Some((value, len)) if len <= seq.len() => {
+                    bit_reader.consume_bits(len);
+                    return Ok(value);
+                }
+                // NB: near the end of the stream's buffer fewer bits may be available than the
+                // code needs. The lookup still tells apart codes that fit into them.
+                _ if (seq.len() as usize) < MAX_BITS => bit_reader.require_bits(seq.len() + 1)?,
+                _ => bail!(DecodeError::InvalidHuffmanCode {
+                    code: seq.reversed().bits(),
+                }),
+            }
+        }
     }
 
     pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
         // See RFC 1951, section 3.2.2.
-        // TODO: your code here.
-        unimplemented!()
+        let codes = canonical_codes(code_lengths)?
+            .into_iter()
+            .enumerate()
+            .filter_map(|(symbol, code)| Some((symbol, code?.reversed())))
+            .collect::<Vec<_>>();
+
+        let mut secondary_bits = [0u8; 1 << PRIMARY_BITS];
+        for (_, code) in codes.iter().filter(|(_, code)| code.len() > PRIMARY_BITS) {
+            let prefix = code.bits() as usize & PRIMARY_MASK;
+            secondary_bits[prefix] = secondary_bits[prefix].max(code.len() - PRIMARY_BITS);
+        }
+
+        let mut table = vec![TableEntry::Invalid; 1 << PRIMARY_BITS];
+        for (prefix, &bits) in secondary_bits.iter().enumerate().filter(|(_, &b)| b > 0) {
+            let offset = table.len();
+            table[prefix] = TableEntry::Link { offset, bits };
+            table.resize(offset + (1 << bits), TableEntry::Invalid);
+        }
+
+        for (symbol, code) in codes {
+            let entry = TableEntry::Symbol {
+                value: T::try_from(HuffmanCodeWord(symbol as u16))?,
+                len: code.len(),
+            };
+            let index = code.bits() as usize;
+            if code.len() <= PRIMARY_BITS {
+                for i in (index..1 << PRIMARY_BITS).step_by(1 << code.len()) {
+                    table[i] = entry;
+                }
+            } else if let TableEntry::Link { offset, bits } = table[index & PRIMARY_MASK] {
+                let suffix_len = code.len() - PRIMARY_BITS;
+                for i in ((index >> PRIMARY_BITS)..1 << bits).step_by(1 << suffix_len) {
+                    table[offset + i] = entry;
+                }
+            }
+        }
+        Ok(Self { table })
+    }
+
+    /// Find the code that `bits` start with, returning its symbol and length.
+    fn lookup(&self, bits: u16) -> Option<(T, u8)> {
+        let mut entry = self.table[bits as usize & PRIMARY_MASK];
+        if let TableEntry::Link {
+            offset,
+            bits: secondary,
+        } = entry
+        {
+            let index = (bits as usize >> PRIMARY_BITS) & ((1 << secondary) - 1);
+            entry = self.table[offset + index];
+        }
+        match entry {
+            TableEntry::Symbol { value, len } => Some((value, len)),
+            _ => None,
+        }
     }
 }
 
+/// Assign canonical codes to symbols with the given code lengths, see RFC 1951, section 3.2.2.
+/// Symbols with zero code length get no code.
+pub fn canonical_codes(code_lengths: &[u8]) -> Result<Vec<Option<BitSequence>>> {
+    let mut bl_count = [0u16; MAX_BITS + 1];
+    for &len in code_lengths {
+        if len as usize > MAX_BITS {
+            bail!(DecodeError::CodeLengthTooLarge(len));
+        }
+        bl_count[len as usize] += 1;
+    }
+    bl_count[0] = 0;
+
+    let mut next_code = [0u32; MAX_BITS + 1];
+    let mut code = 0u32;
+    for bits in 1..=MAX_BITS {
+        code = (code + bl_count[bits - 1] as u32) << 1;
+        next_code[bits] = code;
+    }
+
+    let mut codes = Vec::with_capacity(code_lengths.len());
+    for &len in code_lengths {
+        if len == 0 {
+            codes.push(None);
+            continue;
+        }
+        let code = next_code[len as usize];
+        if code >= 1 << len {
+            bail!(DecodeError::OversubscribedCodeLengths);
+        }
+        next_code[len as usize] += 1;
+        codes.push(Some(BitSequence::new(code as u16, len)));
+    }
+    Ok(codes)
+}
+
+/// Build code lengths of an optimal prefix code for the given symbol frequencies, with no code
+/// longer than `max_len` bits. Symbols with zero frequency get zero length.
+pub fn lengths_from_frequencies(frequencies: &[u32], max_len: u8) -> Vec<u8> {
+    let mut weights = frequencies.to_vec();
+    loop {
+        let lengths = huffman_lengths(&weights);
+        if lengths.iter().all(|&len| len <= max_len) {
+            return lengths;
+        }
+        // Flatten the distribution until the tree is shallow enough. This converges since
+        // equal weights produce a balanced tree.
+        for weight in weights.iter_mut().filter(|w| **w > 0) {
+            *weight = (*weight).div_ceil(2);
+        }
+    }
+}
+
+fn huffman_lengths(weights: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0; weights.len()];
+    let used: Vec<usize> = (0..weights.len()).filter(|&i| weights[i] > 0).collect();
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // Leaves are nodes 0..used.len(), internal nodes are appended after them.
+    let mut parents = vec![usize::MAX; used.len()];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = used
+        .iter()
+        .enumerate()
+        .map(|(node, &symbol)| Reverse((weights[symbol] as u64, node)))
+        .collect();
+    while heap.len() > 1 {
+        let Reverse((weight_a, a)) = heap.pop().unwrap();
+        let Reverse((weight_b, b)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((weight_a + weight_b, node)));
+    }
+
+    // Parents always have larger indices than their children, so depths can be computed
+    // from the root downwards.
+    let mut depths = vec![0u8; parents.len()];
+    for node in (0..parents.len() - 1).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    for (node, &symbol) in used.iter().enumerate() {
+        lengths[symbol] = depths[node];
+    }
+    lengths
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::io::BufReader;
+
+    use crate::bit_writer::BitWriter;
+
     #[derive(Clone, Copy, Debug, PartialEq)]
     struct Value(u16);
 
@@ -182,4 +565,88 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn long_codes() -> Result<()> {
+        // Symbol i < 15 gets a code of i + 1 ones followed by a zero, the last symbol gets
+        // 15 ones. The codes take both the primary and the secondary tables.
+        let lengths: Vec<u8> = (1..=15).chain([15]).collect();
+        let code = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+        assert_eq!(code.decode_symbol(BitSequence::new(0b0, 1)), Some(Value(0)));
+        assert_eq!(
+            code.decode_symbol(BitSequence::new(0b111_1111_1110, 11)),
+            Some(Value(10))
+        );
+        assert_eq!(
+            code.decode_symbol(BitSequence::new(0x7fff, 15)),
+            Some(Value(15))
+        );
+
+        let mut writer = BitWriter::new(Vec::new());
+        for symbol in [14, 0, 9, 15, 3, 12] {
+            let len = lengths[symbol];
+            let bits = if symbol == 15 { 0x7fff } else { (1 << len) - 2 };
+            writer.write_bits(BitSequence::new(bits, len).reversed())?;
+        }
+        let data = writer.finish()?;
+        let mut reader = BitReader::new(BufReader::with_capacity(3, &data[..]));
+        for symbol in [14, 0, 9, 15, 3, 12] {
+            assert_eq!(code.read_symbol(&mut reader)?, Value(symbol));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lengths_from_frequencies() -> Result<()> {
+        assert_eq!(
+            super::lengths_from_frequencies(&[10, 1, 0, 1, 2], 15),
+            vec![1, 3, 0, 3, 2]
+        );
+        assert_eq!(
+            super::lengths_from_frequencies(&[0, 5, 0], 15),
+            vec![0, 1, 0]
+        );
+        assert_eq!(super::lengths_from_frequencies(&[0, 0], 15), vec![0, 0]);
+
+        let fibonacci: Vec<u32> = (0..30)
+            .scan((1, 1), |state, _| {
+                *state = (state.1, state.0 + state.1);
+                Some(state.0)
+            })
+            .collect();
+        let lengths = super::lengths_from_frequencies(&fibonacci, 7);
+        assert!(lengths.iter().all(|&len| (1..=7).contains(&len)));
+        let kraft: f64 = lengths.iter().map(|&len| 0.5f64.powi(len as i32)).sum();
+        assert!(kraft <= 1.0);
+        HuffmanCoding::<Value>::from_lengths(&lengths)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_lengths() {
+        let err = HuffmanCoding::<LitLenToken>::from_lengths(&[9; 288])
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::InvalidSymbol {
+                alphabet: "litlen",
+                symbol: 286,
+            })
+        ));
+
+        let err = HuffmanCoding::<Value>::from_lengths(&[1, 1, 1])
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::OversubscribedCodeLengths)
+        ));
+        let err = HuffmanCoding::<Value>::from_lengths(&[16]).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<DecodeError>(),
+            Some(DecodeError::CodeLengthTooLarge(16))
+        ));
+    }
 }
@@ -0,0 +1,328 @@
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+use anyhow::{bail, Context, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use log::*;
+
+use crate::{
+    decoder::{into_io_error, GzipDecoder},
+    tracking_writer::HISTORY_SIZE,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const INDEX_MAGIC: &[u8; 8] = b"RGZINDEX";
+const INDEX_VERSION: u32 = 1;
+
+/// A point at a deflate block boundary from which decoding can be resumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Checkpoint {
+    /// Offset of the next block header in the compressed file, in bits.
+    pub compressed_bits: u64,
+    pub uncompressed_offset: u64,
+    /// Number of bytes of the current gzip member decoded before the checkpoint.
+    pub member_size: u64,
+    /// CRC32 of the bytes of the current member decoded before the checkpoint.
+    pub member_crc32: u32,
+    /// Up to `HISTORY_SIZE` bytes of the current member preceding the checkpoint.
+    pub window: Vec<u8>,
+}
+
+/// Checkpoints allowing to start decompressing a gzip file from the middle, in the spirit
+/// of zlib's `examples/zran.c`. An index is built during a full decompression pass and
+/// may be saved next to the file it describes, see `write_to` for the format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GzipIndex {
+    compressed_size: u64,
+    uncompressed_size: u64,
+    span: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl GzipIndex {
+    /// Default distance between checkpoints in uncompressed bytes. Every checkpoint stores
+    /// a window of up to 32 KiB, so the index takes about 3% of the uncompressed size.
+    pub const DEFAULT_SPAN: u64 = 1 << 20;
+
+    /// Decompress a gzip file, recording a checkpoint at the first block boundary after
+    /// every `span` bytes of output.
+    pub fn build<R: Read + Seek>(mut input: R, span: u64) -> Result<Self> {
+        if span == 0 {
+            bail!("checkpoint span must be positive");
+        }
+        let compressed_size = input.seek(SeekFrom::End(0))?;
+        input.seek(SeekFrom::Start(0))?;
+
+        let mut decoder = GzipDecoder::new(BufReader::new(input));
+        decoder.record_checkpoints(span);
+        let mut uncompressed_size = 0;
+        while decoder.fill()? {
+            let len = decoder.buffer().len();
+            uncompressed_size += len as u64;
+            decoder.consume(len);
+        }
+
+        let checkpoints = decoder.take_checkpoints();
+        debug!("built an index with {} checkpoints", checkpoints.len());
+        Ok(Self {
+            compressed_size,
+            uncompressed_size,
+            span,
+            checkpoints,
+        })
+    }
+
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    pub fn span(&self) -> u64 {
+        self.span
+    }
+
+    pub fn checkpoint_count(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Save the index. All integers are little-endian:
+    ///
+    /// ```text
+    /// +---+---+---+---+---+---+---+---+---+---+---+---+
+    /// |    "RGZINDEX" (8 bytes)       | VERSION (= 1) |
+    /// +---+---+---+---+---+---+---+---+---+---+---+---+
+    /// | COMPRESSED SIZE (8 bytes)     | UNCOMPRESSED SIZE (8 bytes)   |
+    /// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+    /// | SPAN (8 bytes)                | COUNT (4)     | (COUNT checkpoints)
+    /// +---+---+---+---+---+---+---+---+---+---+---+---+
+    /// ```
+    ///
+    /// Checkpoints are stored in the order of increasing offsets, each one being
+    ///
+    /// ```text
+    /// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+    /// | BIT OFFSET (8 bytes)          | UNCOMPRESSED OFFSET (8 bytes) |
+    /// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+    /// | MEMBER SIZE (8 bytes)         | MEMBER CRC32  | WINDOW LENGTH |
+    /// +---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+---+
+    /// +=============================+
+    /// | WINDOW (WINDOW LENGTH bytes)|
+    /// +=============================+
+    /// ```
+    ///
+    /// * BIT OFFSET is the position of a deflate block header in the compressed file, in bits.
+    /// * UNCOMPRESSED OFFSET is the position in the decompressed data it corresponds to.
+    /// * MEMBER SIZE and MEMBER CRC32 describe the data decoded since the start of the gzip
+    ///   member the block belongs to, so that the member's footer can still be verified.
+    /// * WINDOW holds the last bytes of that data, at most 32768 of them.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u32::<LittleEndian>(INDEX_VERSION)?;
+        writer.write_u64::<LittleEndian>(self.compressed_size)?;
+        writer.write_u64::<LittleEndian>(self.uncompressed_size)?;
+        writer.write_u64::<LittleEndian>(self.span)?;
+        writer.write_u32::<LittleEndian>(self.checkpoints.len() as u32)?;
+        for checkpoint in &self.checkpoints {
+            writer.write_u64::<LittleEndian>(checkpoint.compressed_bits)?;
+            writer.write_u64::<LittleEndian>(checkpoint.uncompressed_offset)?;
+            writer.write_u64::<LittleEndian>(checkpoint.member_size)?;
+            writer.write_u32::<LittleEndian>(checkpoint.member_crc32)?;
+            writer.write_u32::<LittleEndian>(checkpoint.window.len() as u32)?;
+            writer.write_all(&checkpoint.window)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load an index saved with `write_to`.
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0; INDEX_MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .context("failed to read magic")?;
+        if &magic != INDEX_MAGIC {
+            bail!("not a ripgzip index");
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != INDEX_VERSION {
+            bail!("unsupported index version: {}", version);
+        }
+
+        let compressed_size = reader.read_u64::<LittleEndian>()?;
+        let uncompressed_size = reader.read_u64::<LittleEndian>()?;
+        let span = reader.read_u64::<LittleEndian>()?;
+        let count = reader.read_u32::<LittleEndian>()?;
+        let mut checkpoints = Vec::<Checkpoint>::new();
+        for _ in 0..count {
+            let compressed_bits = reader.read_u64::<LittleEndian>()?;
+            let uncompressed_offset = reader.read_u64::<LittleEndian>()?;
+            let member_size = reader.read_u64::<LittleEndian>()?;
+            let member_crc32 = reader.read_u32::<LittleEndian>()?;
+            let window_len = reader.read_u32::<LittleEndian>()? as usize;
+            if window_len > HISTORY_SIZE || window_len as u64 > member_size {
+                bail!("invalid checkpoint window length: {}", window_len);
+            }
+            if member_size > uncompressed_offset
+                || compressed_bits / 8 >= compressed_size
+                || uncompressed_offset > uncompressed_size
+            {
+                bail!("invalid checkpoint at offset {}", uncompressed_offset);
+            }
+            if let Some(last) = checkpoints.last() {
+                if last.uncompressed_offset > uncompressed_offset {
+                    bail!("checkpoints are out of order");
+                }
+            }
+
+            let mut window = vec![0; window_len];
+            reader
+                .read_exact(&mut window)
+                .context("failed to read checkpoint window")?;
+            checkpoints.push(Checkpoint {
+                compressed_bits,
+                uncompressed_offset,
+                member_size,
+                member_crc32,
+                window,
+            });
+        }
+
+        Ok(Self {
+            compressed_size,
+            uncompressed_size,
+            span,
+            checkpoints,
+        })
+    }
+
+    /// Find the last checkpoint at or before `offset`.
+    fn checkpoint_before(&self, offset: u64) -> Option<&Checkpoint> {
+        let count = self
+            .checkpoints
+            .partition_point(|c| c.uncompressed_offset <= offset);
+        count.checked_sub(1).map(|i| &self.checkpoints[i])
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Random access to the decompressed contents of a gzip file described by a `GzipIndex`.
+///
+/// Seeking is lazy: the next read resumes decoding from the nearest checkpoint before the
+/// new position, unless the current position is already between them.
+pub struct SeekableGzipReader<R> {
+    index: GzipIndex,
+    /// `None` once decoding has failed and the underlying reader is lost.
+    decoder: Option<GzipDecoder<BufReader<R>>>,
+    /// Uncompressed offset of the next byte returned by the decoder.
+    decoder_offset: u64,
+    position: u64,
+}
+
+impl<R: Read + Seek> SeekableGzipReader<R> {
+    pub fn new(mut inner: R, index: GzipIndex) -> Result<Self> {
+        let compressed_size = inner.seek(SeekFrom::End(0))?;
+        if compressed_size != index.compressed_size {
+            bail!(
+                "index does not match the file: expected {} bytes, got {}",
+                index.compressed_size,
+                compressed_size
+            );
+        }
+        inner.seek(SeekFrom::Start(0))?;
+
+        Ok(Self {
+            index,
+            decoder: Some(GzipDecoder::new(BufReader::new(inner))),
+            decoder_offset: 0,
+            position: 0,
+        })
+    }
+
+    pub fn index(&self) -> &GzipIndex {
+        &self.index
+    }
+
+    /// Make the decoder return data starting from the current position.
+    fn sync_decoder(&mut self) -> io::Result<()> {
+        let checkpoint = self.index.checkpoint_before(self.position);
+        let restart_offset = checkpoint.map_or(0, |c| c.uncompressed_offset);
+        if self.position < self.decoder_offset || self.decoder_offset < restart_offset {
+            let mut inner = self
+                .decoder
+                .take()
+                .and_then(GzipDecoder::into_inner)
+                .ok_or_else(failed_error)?
+                .into_inner();
+            let decoder = match checkpoint {
+                Some(checkpoint) => {
+                    trace!("resuming from offset {}", checkpoint.uncompressed_offset);
+                    inner.seek(SeekFrom::Start(checkpoint.compressed_bits / 8))?;
+                    GzipDecoder::resume(BufReader::new(inner), checkpoint).map_err(into_io_error)?
+                }
+                None => {
+                    inner.seek(SeekFrom::Start(0))?;
+                    GzipDecoder::new(BufReader::new(inner))
+                }
+            };
+            self.decoder = Some(decoder);
+            self.decoder_offset = restart_offset;
+        }
+
+        let decoder = self.decoder.as_mut().ok_or_else(failed_error)?;
+        while self.decoder_offset < self.position {
+            let available = decoder.fill_buf()?.len() as u64;
+            if available == 0 {
+                break;
+            }
+            let len = available.min(self.position - self.decoder_offset);
+            decoder.consume(len as usize);
+            self.decoder_offset += len;
+        }
+        if self.decoder_offset != self.position {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file is shorter than the index says",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn failed_error() -> io::Error {
+    io::Error::other("decoding has failed earlier")
+}
+
+impl<R: Read + Seek> Read for SeekableGzipReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.index.uncompressed_size {
+            return Ok(0);
+        }
+        self.sync_decoder()?;
+        let decoder = self.decoder.as_mut().ok_or_else(failed_error)?;
+        let len = decoder.read(buf)?;
+        self.position += len as u64;
+        self.decoder_offset = self.position;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableGzipReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.index.uncompressed_size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
@@ -1,35 +1,175 @@
 #![forbid(unsafe_code)]
 
-use std::io::{copy, BufRead, Read, Write};
+use std::{
+    io::{self, BufRead, Write},
+    str::FromStr,
+};
 
 use anyhow::{bail, Result};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use bit_reader::BitReader;
-use deflate::{CompressionType, DeflateReader};
-use gzip::{CompressionMethod, GzipReader};
-use huffman_coding::{decode_litlen_distance_trees, DistanceToken, HuffmanCoding, LitLenToken};
-use log::*;
-use tracking_writer::TrackingWriter;
+use gzip::is_gzip_header;
+use lz77::{DEFAULT_LEVEL, MAX_LEVEL, MIN_LEVEL};
+use zlib::is_zlib_header;
 
+#[cfg(feature = "tokio")]
+pub use async_io::{AsyncGzipDecoder, AsyncGzipEncoder};
+pub use decoder::{GzipDecoder, MemberInfo};
+pub use encoder::GzipEncoder;
+pub use error::{DecodeError, MemberError};
+pub use gzip::{CompressionMethod, ExtraSubfield, MemberHeader, MemberHeaderBuilder};
+pub use index::{GzipIndex, SeekableGzipReader};
+pub use parallel::{compress_parallel, compress_parallel_with, decompress_parallel};
+pub use recovery::decompress_recover;
+
+#[cfg(feature = "tokio")]
+mod async_io;
 mod bit_reader;
+mod bit_writer;
+mod decoder;
 mod deflate;
+mod encoder;
+mod error;
 mod gzip;
 mod huffman_coding;
+mod index;
+mod lz77;
+mod parallel;
+mod recovery;
+mod resumable;
 mod tracking_writer;
+mod zlib;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Container of a deflate stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// RFC 1952, possibly consisting of several members.
+    Gzip,
+    /// RFC 1950.
+    Zlib,
+    /// RFC 1951 data without any framing.
+    Raw,
+}
+
+impl Format {
+    /// Guess the format from the first bytes of a stream. Anything that starts with neither
+    /// a gzip nor a zlib header is assumed to be raw deflate.
+    pub fn detect(prefix: &[u8]) -> Self {
+        match *prefix {
+            [a, b, ..] if is_gzip_header(a, b) => Self::Gzip,
+            [a, b, ..] if is_zlib_header(a, b) => Self::Zlib,
+            _ => Self::Raw,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "gzip" => Self::Gzip,
+            "zlib" => Self::Zlib,
+            "raw" => Self::Raw,
+            _ => bail!("unknown format: {}", s),
+        })
+    }
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub fn compress<R: BufRead, W: Write>(_input: R, _output: W) -> Result<()> {
-    // NB: you are not required to implement compression.
-    // But if you do, you can get extra score for it :)
-    unimplemented!()
+/// Settings of `compress_with`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompressOptions {
+    /// From 1 (fastest) to 9 (best compression).
+    pub level: u8,
+    /// Header of the gzip member, see `MemberHeader::builder`. The compression method
+    /// and the extra flags are set by the compressor.
+    pub header: MemberHeader,
+}
+
+impl CompressOptions {
+    pub const MIN_LEVEL: u8 = MIN_LEVEL;
+    pub const MAX_LEVEL: u8 = MAX_LEVEL;
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_LEVEL,
+            header: MemberHeader::default(),
+        }
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub fn decompress<R: BufRead, W: Write>(input: R, mut output: W) -> Result<()> {
-    // TODO: your code here.
-    unimplemented!()
+pub fn compress<R: BufRead, W: Write>(input: R, output: W) -> Result<()> {
+    compress_format(input, output, Format::Gzip)
+}
+
+pub fn compress_format<R: BufRead, W: Write>(input: R, output: W, format: Format) -> Result<()> {
+    compress_with(input, output, format, &CompressOptions::default())
 }
 
+/// Compress `input` into `format`. The header is only written by gzip.
+pub fn compress_with<R: BufRead, W: Write>(
+    mut input: R,
+    output: W,
+    format: Format,
+    options: &CompressOptions,
+) -> Result<()> {
+    let mut encoder = GzipEncoder::with_options(output, format, options)?;
+    loop {
+        let chunk = input.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        encoder.write_all(chunk)?;
+        let len = chunk.len();
+        input.consume(len);
+    }
+
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<(), DecodeError> {
+    decompress_format(input, output, Format::Gzip)
+}
+
+pub fn decompress_format<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    format: Format,
+) -> Result<(), DecodeError> {
+    copy_decoded(&mut GzipDecoder::with_format(input, format), output)
+        .map_err(DecodeError::from_anyhow)
+}
+
+/// Decompress `input`, detecting its format from the first bytes, see `Format::detect`.
+pub fn decompress_auto<R: BufRead, W: Write>(input: R, output: W) -> Result<(), DecodeError> {
+    copy_decoded(&mut GzipDecoder::detect(input)?, output).map_err(DecodeError::from_anyhow)
+}
+
+/// Decode a gzip stream, returning a summary of each of its members.
+pub fn list_members<R: BufRead>(input: R) -> Result<Vec<MemberInfo>> {
+    let mut decoder = GzipDecoder::new(input);
+    decoder.record_members();
+    copy_decoded(&mut decoder, io::sink())?;
+    Ok(decoder.members().to_vec())
+}
+
+fn copy_decoded<R: BufRead, W: Write>(decoder: &mut GzipDecoder<R>, mut output: W) -> Result<()> {
+    while decoder.fill()? {
+        output.write_all(decoder.buffer())?;
+        let len = decoder.buffer().len();
+        decoder.consume(len);
+    }
+
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,263 @@
+use crate::tracking_writer::HISTORY_SIZE;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+
+const HASH_BITS: u32 = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const NIL: u32 = u32::MAX;
+
+pub const MIN_LEVEL: u8 = 1;
+pub const MAX_LEVEL: u8 = 9;
+pub const DEFAULT_LEVEL: u8 = 6;
+
+/// Search limits for every compression level, mirroring the ones of zlib.
+const LEVEL_CONFIGS: [LevelConfig; MAX_LEVEL as usize] = [
+    LevelConfig::new(4, 8, 0),
+    LevelConfig::new(8, 16, 0),
+    LevelConfig::new(32, 32, 0),
+    LevelConfig::new(16, 16, 4),
+    LevelConfig::new(32, 32, 16),
+    LevelConfig::new(128, 128, 16),
+    LevelConfig::new(256, 128, 32),
+    LevelConfig::new(1024, 258, 128),
+    LevelConfig::new(4096, 258, 258),
+];
+
+#[derive(Clone, Copy, Debug)]
+struct LevelConfig {
+    /// Maximum number of hash chain entries to check.
+    max_chain: usize,
+    /// A match at least this long is taken without looking any further.
+    nice_match: usize,
+    /// Lazy evaluation is only tried for matches shorter than this.
+    max_lazy: usize,
+}
+
+impl LevelConfig {
+    const fn new(max_chain: usize, nice_match: usize, max_lazy: usize) -> Self {
+        Self {
+            max_chain,
+            nice_match,
+            max_lazy,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+impl Token {
+    /// Number of uncompressed bytes this token stands for.
+    pub fn byte_len(&self) -> usize {
+        match *self {
+            Self::Literal(_) => 1,
+            Self::Match { len, .. } => len as usize,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Finds repeated substrings within a sliding window of `HISTORY_SIZE` bytes. The window
+/// is carried over between calls, so matches may refer to data passed in earlier chunks.
+pub struct Matcher {
+    config: LevelConfig,
+    history: Vec<u8>,
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    inserted: usize,
+}
+
+impl Matcher {
+    /// Create a matcher trading speed for compression ratio according to `level`, which
+    /// ranges from `MIN_LEVEL` (fastest) to `MAX_LEVEL` (best compression).
+    pub fn with_level(level: u8) -> Self {
+        assert!(
+            (MIN_LEVEL..=MAX_LEVEL).contains(&level),
+            "invalid compression level: {}",
+            level
+        );
+        Self {
+            config: LEVEL_CONFIGS[(level - MIN_LEVEL) as usize],
+            history: Vec::with_capacity(HISTORY_SIZE),
+            head: vec![NIL; HASH_SIZE],
+            prev: Vec::new(),
+            inserted: 0,
+        }
+    }
+
+    /// Split `data` into literals and back references. The returned tokens expand exactly
+    /// to `data`.
+    pub fn find_matches(&mut self, data: &[u8]) -> Vec<Token> {
+        let mut buf = std::mem::take(&mut self.history);
+        let start = buf.len();
+        buf.extend_from_slice(data);
+
+        self.head.iter_mut().for_each(|h| *h = NIL);
+        self.prev.clear();
+        self.prev.resize(buf.len(), NIL);
+        self.inserted = 0;
+        self.insert_until(&buf, start);
+
+        let mut tokens = Vec::with_capacity(data.len() / 2);
+        let mut pos = start;
+        while pos < buf.len() {
+            self.insert_until(&buf, pos + 1);
+            let (len, dist) = self.longest_match(&buf, pos);
+            if len < MIN_MATCH {
+                tokens.push(Token::Literal(buf[pos]));
+                pos += 1;
+                continue;
+            }
+
+            // Lazy evaluation: prefer a literal if the next position has a longer match.
+            if len < self.config.max_lazy && pos + 1 < buf.len() {
+                self.insert_until(&buf, pos + 2);
+                let (next_len, _) = self.longest_match(&buf, pos + 1);
+                if next_len > len {
+                    tokens.push(Token::Literal(buf[pos]));
+                    pos += 1;
+                    continue;
+                }
+            }
+
+            tokens.push(Token::Match {
+                len: len as u16,
+                dist: dist as u16,
+            });
+            pos += len;
+        }
+
+        let keep_from = buf.len().saturating_sub(HISTORY_SIZE);
+        buf.drain(..keep_from);
+        self.history = buf;
+        tokens
+    }
+
+    /// Add all the positions before `end` to the hash chains.
+    fn insert_until(&mut self, buf: &[u8], end: usize) {
+        let end = end.min(buf.len().saturating_sub(MIN_MATCH - 1));
+        while self.inserted < end {
+            let pos = self.inserted;
+            let hash = Self::hash(&buf[pos..pos + MIN_MATCH]);
+            self.prev[pos] = self.head[hash];
+            self.head[hash] = pos as u32;
+            self.inserted += 1;
+        }
+    }
+
+    fn longest_match(&self, buf: &[u8], pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > buf.len() {
+            return (0, 0);
+        }
+
+        let max_len = MAX_MATCH.min(buf.len() - pos);
+        let (mut best_len, mut best_dist) = (0, 0);
+        let mut candidate = self.prev[pos];
+        let mut chain = 0;
+        let nice_match = self.config.nice_match.min(max_len);
+        while candidate != NIL && chain < self.config.max_chain {
+            let candidate_pos = candidate as usize;
+            let dist = pos - candidate_pos;
+            if dist > HISTORY_SIZE {
+                break;
+            }
+
+            let len = buf[candidate_pos..]
+                .iter()
+                .zip(&buf[pos..pos + max_len])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len > best_len {
+                best_len = len;
+                best_dist = dist;
+                if len >= nice_match {
+                    break;
+                }
+            }
+
+            candidate = self.prev[candidate_pos];
+            chain += 1;
+        }
+        (best_len, best_dist)
+    }
+
+    fn hash(bytes: &[u8]) -> usize {
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(tokens: &[Token]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => out.push(byte),
+                Token::Match { len, dist } => {
+                    for _ in 0..len {
+                        out.push(out[out.len() - dist as usize]);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn find_matches() {
+        let mut matcher = Matcher::with_level(DEFAULT_LEVEL);
+        let tokens = matcher.find_matches(b"abcabcabcabcx");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match { len: 9, dist: 3 },
+                Token::Literal(b'x'),
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_across_chunks() {
+        let mut matcher = Matcher::with_level(DEFAULT_LEVEL);
+        let first = matcher.find_matches(b"hello, world");
+        let second = matcher.find_matches(b"hello, world");
+        assert_eq!(expand(&first), b"hello, world");
+        assert_eq!(second, vec![Token::Match { len: 12, dist: 12 }]);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data: Vec<u8> = (0..200_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 27) as u8 % 7)
+            .collect();
+        let mut token_counts = Vec::new();
+        for level in MIN_LEVEL..=MAX_LEVEL {
+            let mut matcher = Matcher::with_level(level);
+            let mut tokens = Vec::new();
+            for chunk in data.chunks(50_000) {
+                tokens.extend(matcher.find_matches(chunk));
+            }
+            assert_eq!(expand(&tokens), data, "level {}", level);
+            assert!(tokens.len() < data.len() / 2);
+            token_counts.push(tokens.len());
+        }
+        assert!(token_counts[MAX_LEVEL as usize - 1] < token_counts[0]);
+    }
+}
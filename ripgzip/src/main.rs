@@ -1,11 +1,21 @@
 #![forbid(unsafe_code)]
 
-use std::io::{stdin, stdout};
+use std::{
+    ffi::{OsStr, OsString},
+    fs::{self, File},
+    io::{self, stdin, stdout, BufRead, BufReader, BufWriter, IsTerminal, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use log::*;
 use structopt::StructOpt;
 
-use ripgzip::{compress, decompress};
+use anyhow::{bail, Context, Result};
+use ripgzip::{
+    compress_parallel_with, compress_with, decompress_auto, decompress_format, decompress_parallel,
+    decompress_recover, list_members, CompressOptions, Format, GzipDecoder, MemberHeader,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt()]
@@ -13,13 +23,65 @@ struct Opts {
     /// Decompress data
     #[structopt(short = "d", long = "decompress")]
     decompress: bool,
+    /// Write output to standard output and keep the input files
+    #[structopt(short = "c", long = "stdout")]
+    stdout: bool,
+    /// Keep the input files
+    #[structopt(short = "k", long = "keep")]
+    keep: bool,
+    /// Overwrite existing output files, compress files that already have the suffix and
+    /// read or write compressed data from or to a terminal
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+    /// List every member of the compressed files: sizes, OS, modification time (UTC)
+    /// and name
+    #[structopt(short = "l", long = "list")]
+    list: bool,
+    /// Check the integrity of the compressed files without writing any output
+    #[structopt(short = "t", long = "test")]
+    test: bool,
+    /// Compression level from 1 (fastest) to 9 (best). May also be given as -1 .. -9
+    #[structopt(long = "level", default_value = "6")]
+    level: u8,
+    /// Do not save the original file name and modification time when compressing
+    #[structopt(short = "n", long = "no-name")]
+    no_name: bool,
+    /// Restore the original file name and modification time when decompressing
+    #[structopt(short = "N", long = "name")]
+    name: bool,
+    /// Suffix of compressed files
+    #[structopt(short = "S", long = "suffix", default_value = ".gz")]
+    suffix: String,
+    /// Container format: gzip, zlib or raw. Detected automatically when decompressing
+    /// and gzip by default when compressing
+    #[structopt(long = "format", possible_values = &["gzip", "zlib", "raw"])]
+    format: Option<Format>,
+    /// Number of threads to use. Parallel compression writes a separate gzip member for
+    /// every megabyte of input
+    #[structopt(short = "p", long = "processes", default_value = "1")]
+    processes: usize,
+    /// Skip over broken gzip members instead of stopping at the first one. Every failure
+    /// is reported, and the exit code is 2 if there were any
+    #[structopt(long = "recover")]
+    recover: bool,
     /// Verbose mode (-v, -vv, -vvv, etc)
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: usize,
+    /// Files to process. Standard input is read if there are none or for "-"
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+}
+
+/// Outcome of processing a single file. As in gzip, errors take precedence over warnings
+/// in the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warning,
 }
 
 fn main() {
-    let opts = Opts::from_args();
+    let opts = Opts::from_iter(expand_level_flags(std::env::args_os()));
 
     stderrlog::new()
         .verbosity(1 + opts.verbose)
@@ -27,14 +89,490 @@ fn main() {
         .init()
         .expect("failed to initialize logging");
 
-    let res = if opts.decompress {
-        decompress(stdin().lock(), stdout().lock())
+    if let Err(err) = check_opts(&opts) {
+        error!("{:#}", err);
+        std::process::exit(1);
+    }
+
+    let files = match opts.files.as_slice() {
+        [] => vec![PathBuf::from("-")],
+        files => files.to_vec(),
+    };
+    let mut exit_code = 0;
+    if opts.list {
+        print_list_header();
+    }
+    let mut totals = Totals::default();
+    for path in &files {
+        let res = if path == Path::new("-") {
+            process_stdin(&opts, &mut totals)
+        } else {
+            process_file(&opts, path, &mut totals)
+        };
+        match res {
+            Ok(Status::Ok) => {}
+            Ok(Status::Warning) if exit_code == 0 => exit_code = 2,
+            Ok(Status::Warning) => {}
+            Err(err) => {
+                error!("{}: {:#}", path.display(), err);
+                exit_code = 1;
+            }
+        }
+    }
+    if opts.list && totals.count > 1 {
+        totals.print();
+    }
+    std::process::exit(exit_code);
+}
+
+/// Turn the digits in clusters of short flags, e.g. "-9" or "-c1", into `--level` options.
+fn expand_level_flags(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args = args.into_iter();
+    let mut result = args.next().into_iter().collect::<Vec<_>>();
+    let mut options_ended = false;
+    for arg in args {
+        let cluster = match arg.to_str() {
+            Some(s) if !options_ended && s.len() > 1 && s.starts_with('-') => s,
+            _ => {
+                result.push(arg);
+                continue;
+            }
+        };
+        if cluster.starts_with("--") {
+            options_ended = cluster == "--";
+            result.push(arg);
+            continue;
+        }
+
+        let mut flags = String::from("-");
+        let mut level = None;
+        for (i, c) in cluster.char_indices().skip(1) {
+            match c {
+                '1'..='9' => level = Some(c),
+                // NB: the rest of the cluster is the value of the option.
+                'p' | 'S' => {
+                    flags.push_str(&cluster[i..]);
+                    break;
+                }
+                c => flags.push(c),
+            }
+        }
+        if flags.len() > 1 {
+            result.push(flags.into());
+        }
+        if let Some(level) = level {
+            result.push(format!("--level={}", level).into());
+        }
+    }
+    result
+}
+
+fn check_opts(opts: &Opts) -> Result<()> {
+    if opts.processes == 0 {
+        bail!("number of processes must be positive");
+    }
+    if !(CompressOptions::MIN_LEVEL..=CompressOptions::MAX_LEVEL).contains(&opts.level) {
+        bail!("compression level must be between 1 and 9");
+    }
+    if opts.suffix.is_empty() {
+        bail!("suffix must not be empty");
+    }
+    if opts.recover && opts.format.unwrap_or(Format::Gzip) != Format::Gzip {
+        bail!("recovery is only supported for gzip");
+    }
+    Ok(())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn process_stdin(opts: &Opts, totals: &mut Totals) -> Result<Status> {
+    let input = stdin().lock();
+    let reads_compressed = opts.decompress || opts.list || opts.test;
+    if reads_compressed && !opts.force && io::stdin().is_terminal() {
+        bail!("compressed data not read from a terminal, use -f to force decompression");
+    }
+
+    if opts.list {
+        list(input, "-", totals)
+    } else if opts.test {
+        decompress_stream(opts, input, io::sink())
+    } else if opts.decompress {
+        decompress_stream(opts, input, stdout().lock())
+    } else {
+        check_terminal_output(opts)?;
+        compress_stream(opts, input, stdout().lock(), CompressOptions::default())
+    }
+}
+
+fn process_file(opts: &Opts, path: &Path, totals: &mut Totals) -> Result<Status> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() {
+        warn!("{}: not a regular file, ignored", path.display());
+        return Ok(Status::Warning);
+    }
+    let open = || -> Result<BufReader<File>> { Ok(BufReader::new(File::open(path)?)) };
+
+    if opts.list {
+        let name = strip_suffix(opts, path).unwrap_or_else(|| path.to_path_buf());
+        return list(open()?, &name.to_string_lossy(), totals);
+    }
+    if opts.test {
+        return decompress_stream(opts, open()?, io::sink());
+    }
+
+    if opts.decompress {
+        let original = if opts.name {
+            read_original_header(opts, path)?
+        } else {
+            None
+        };
+        if opts.stdout {
+            return decompress_stream(opts, open()?, stdout().lock());
+        }
+
+        let restored_name = original
+            .as_ref()
+            .and_then(|header| header.name.as_deref())
+            .map(os_str_from_bytes);
+        let restored_name = restored_name
+            .as_deref()
+            .and_then(|name| Path::new(name).file_name());
+        let output_path = match (restored_name, strip_suffix(opts, path)) {
+            (Some(name), _) => path.with_file_name(name),
+            (None, Some(output_path)) => output_path,
+            (None, None) => {
+                warn!("{}: unknown suffix, ignored", path.display());
+                return Ok(Status::Warning);
+            }
+        };
+        let modification_time = match original {
+            Some(header) if header.modification_time != 0 => {
+                UNIX_EPOCH + Duration::from_secs(header.modification_time as u64)
+            }
+            _ => metadata.modified()?,
+        };
+        write_file(opts, path, &output_path, modification_time, |output| {
+            decompress_stream(opts, open()?, output)
+        })
     } else {
-        compress(stdin().lock(), stdout().lock())
+        if !opts.force && path.to_string_lossy().ends_with(&opts.suffix) {
+            warn!(
+                "{}: already has {} suffix, unchanged",
+                path.display(),
+                opts.suffix
+            );
+            return Ok(Status::Warning);
+        }
+
+        let mut options = CompressOptions::default();
+        if !opts.no_name {
+            let modification_time = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs().try_into().unwrap_or(u32::MAX));
+            let mut builder = MemberHeader::builder().modification_time(modification_time);
+            if let Some(name) = path.file_name() {
+                builder = builder.name(os_str_to_bytes(name));
+            }
+            options.header = builder.build()?;
+        }
+        if opts.stdout {
+            check_terminal_output(opts)?;
+            return compress_stream(opts, open()?, stdout().lock(), options);
+        }
+
+        let mut output_path = path.as_os_str().to_owned();
+        output_path.push(&opts.suffix);
+        write_file(
+            opts,
+            path,
+            Path::new(&output_path),
+            metadata.modified()?,
+            |output| compress_stream(opts, open()?, output, options),
+        )
+    }
+}
+
+/// Write `output_path` with `write`. The data goes to a temporary file next to it, which
+/// replaces `output_path` only once it has been written in full. On success, the input file
+/// is removed unless asked to keep it.
+fn write_file(
+    opts: &Opts,
+    input_path: &Path,
+    output_path: &Path,
+    modification_time: SystemTime,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<Status>,
+) -> Result<Status> {
+    if !opts.force && output_path.exists() {
+        warn!("{}: already exists, not overwritten", output_path.display());
+        return Ok(Status::Warning);
+    }
+    // NB: e.g. a name restored with -N may point back at the input.
+    if output_path.exists() && fs::canonicalize(output_path)? == fs::canonicalize(input_path)? {
+        warn!(
+            "{}: output would overwrite the input, ignored",
+            input_path.display()
+        );
+        return Ok(Status::Warning);
+    }
+
+    let temp_path = temp_path(output_path);
+    let file = File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .with_context(|| format!("failed to create {}", temp_path.display()))?;
+
+    let mut output = BufWriter::new(file);
+    let res = write(&mut output).and_then(|status| {
+        let file = output.into_inner().map_err(|err| err.into_error())?;
+        file.set_modified(modification_time)?;
+        file.set_permissions(fs::metadata(input_path)?.permissions())?;
+        drop(file);
+        fs::rename(&temp_path, output_path)
+            .with_context(|| format!("failed to create {}", output_path.display()))?;
+        Ok(status)
+    });
+    match res {
+        Ok(Status::Ok) if !opts.keep => {
+            fs::remove_file(input_path)?;
+            Ok(Status::Ok)
+        }
+        Ok(status) => Ok(status),
+        Err(err) => {
+            if let Err(err) = fs::remove_file(&temp_path) {
+                warn!("failed to remove {}: {}", temp_path.display(), err);
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Return a hidden file name in the directory of `path` for writing it.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+fn check_terminal_output(opts: &Opts) -> Result<()> {
+    if !opts.force && io::stdout().is_terminal() {
+        bail!("compressed data not written to a terminal, use -f to force compression");
+    }
+    Ok(())
+}
+
+/// Convert a file name to the bytes stored in FNAME. Unix names are kept as they are,
+/// elsewhere they are encoded as UTF-8.
+fn os_str_to_bytes(name: &OsStr) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        name.as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        name.to_string_lossy().into_owned().into_bytes()
+    }
+}
+
+/// The inverse of `os_str_to_bytes`.
+fn os_str_from_bytes(name: &[u8]) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        OsStr::from_bytes(name).to_owned()
+    }
+    #[cfg(not(unix))]
+    {
+        String::from_utf8_lossy(name).into_owned().into()
+    }
+}
+
+/// Return the path of the decompressed file, unless `path` has no known suffix.
+fn strip_suffix(opts: &Opts, path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let stem = match name.strip_suffix(&opts.suffix) {
+        Some(stem) => stem.to_owned(),
+        None => format!("{}.tar", name.strip_suffix(".tgz")?),
     };
+    match stem.as_str() {
+        "" | ".tar" => None,
+        _ => Some(path.with_file_name(stem)),
+    }
+}
 
-    if let Err(err) = res {
-        error!("{:#}", err);
-        std::process::exit(1);
+/// Read the header of the first gzip member of `path`, see `Opts::name`.
+fn read_original_header(opts: &Opts, path: &Path) -> Result<Option<MemberHeader>> {
+    let mut input = BufReader::new(File::open(path)?);
+    let format = match opts.format {
+        Some(format) => format,
+        None => Format::detect(input.fill_buf()?),
+    };
+    if format != Format::Gzip {
+        return Ok(None);
     }
+    let mut decoder = GzipDecoder::new(input);
+    decoder.fill_buf()?;
+    Ok(decoder.header().cloned())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn decompress_stream<R: BufRead, W: Write>(opts: &Opts, mut input: R, output: W) -> Result<Status> {
+    if opts.recover {
+        let errors = decompress_recover(input, output)?;
+        for err in &errors {
+            warn!("{}", err);
+        }
+        return Ok(match errors.is_empty() {
+            true => Status::Ok,
+            false => Status::Warning,
+        });
+    }
+
+    let format = match opts.format {
+        Some(format) => format,
+        None => Format::detect(input.fill_buf()?),
+    };
+    match format {
+        Format::Gzip if opts.processes > 1 => decompress_parallel(input, output, opts.processes)?,
+        _ if opts.format.is_none() => decompress_auto(input, output)?,
+        _ => decompress_format(input, output, format)?,
+    }
+    Ok(Status::Ok)
+}
+
+fn compress_stream<R: BufRead, W: Write>(
+    opts: &Opts,
+    input: R,
+    output: W,
+    options: CompressOptions,
+) -> Result<Status> {
+    let options = CompressOptions {
+        level: opts.level,
+        ..options
+    };
+    match opts.format.unwrap_or(Format::Gzip) {
+        Format::Gzip if opts.processes > 1 => {
+            compress_parallel_with(input, output, opts.processes, &options)
+        }
+        _ if opts.processes > 1 => bail!("parallel compression only supports gzip"),
+        format => compress_with(input, output, format, &options),
+    }?;
+    Ok(Status::Ok)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Default)]
+struct Totals {
+    count: usize,
+    compressed_size: u64,
+    uncompressed_size: u64,
+}
+
+impl Totals {
+    fn print(&self) {
+        print_list_row(
+            self.compressed_size,
+            self.uncompressed_size,
+            "",
+            "",
+            "(totals)",
+        );
+    }
+}
+
+fn list<R: BufRead>(input: R, file_name: &str, totals: &mut Totals) -> Result<Status> {
+    for member in list_members(input)? {
+        let header = &member.header;
+        let name = header.name_lossy();
+        let name = name.as_deref().unwrap_or(file_name);
+        let modification_time = match header.modification_time {
+            0 => "-".to_owned(),
+            time => format_time(time),
+        };
+        print_list_row(
+            member.compressed_size,
+            member.uncompressed_size,
+            os_name(header.os),
+            &modification_time,
+            name,
+        );
+        totals.count += 1;
+        totals.compressed_size += member.compressed_size;
+        totals.uncompressed_size += member.uncompressed_size;
+    }
+    Ok(Status::Ok)
+}
+
+fn print_list_header() {
+    println!(
+        "{:>12} {:>12} {:>6}  {:<12} {:<19}  name",
+        "compressed", "uncompressed", "ratio", "os", "modified"
+    );
+}
+
+fn print_list_row(
+    compressed_size: u64,
+    uncompressed_size: u64,
+    os: &str,
+    modification_time: &str,
+    name: &str,
+) {
+    let ratio = match uncompressed_size {
+        0 => 0.,
+        size => (1. - compressed_size as f64 / size as f64) * 100.,
+    };
+    println!(
+        "{:>12} {:>12} {:>5.1}%  {:<12} {:<19}  {}",
+        compressed_size, uncompressed_size, ratio, os, modification_time, name
+    );
+}
+
+/// See RFC 1952, section 2.3.1.
+fn os_name(os: u8) -> &'static str {
+    match os {
+        0 => "FAT",
+        1 => "Amiga",
+        2 => "VMS",
+        3 => "Unix",
+        4 => "VM/CMS",
+        5 => "Atari TOS",
+        6 => "HPFS",
+        7 => "Macintosh",
+        8 => "Z-System",
+        9 => "CP/M",
+        10 => "TOPS-20",
+        11 => "NTFS",
+        12 => "QDOS",
+        13 => "Acorn RISCOS",
+        _ => "unknown",
+    }
+}
+
+/// Format a Unix timestamp as UTC date and time.
+fn format_time(timestamp: u32) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
 }
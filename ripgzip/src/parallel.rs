@@ -0,0 +1,458 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Cursor, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+        Condvar, Mutex,
+    },
+    thread,
+};
+
+use anyhow::{bail, Result};
+use log::*;
+
+use crate::{
+    compress_with, decoder::decode_member, decompress, gzip::is_member_candidate, CompressOptions,
+    Format, GzipDecoder, MemberHeader,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of input compressed into every member by `compress_parallel`.
+const MEMBER_INPUT_SIZE: usize = 1 << 20;
+
+/// Amount of compressed input scanned for members at once by `decompress_parallel`.
+const SEGMENT_SIZE: usize = 1 << 24;
+/// A member that decompresses to more than this is decoded sequentially.
+const MAX_MEMBER_OUTPUT: usize = 1 << 24;
+/// Decoding of further members is held back while this much output is waiting to be written.
+const MAX_BUFFERED_OUTPUT: usize = 1 << 26;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Compress `input` into a multi-member gzip stream, compressing up to `threads` members
+/// concurrently. Members are independent, so any gzip decoder can read the result.
+pub fn compress_parallel<R: BufRead, W: Write>(input: R, output: W, threads: usize) -> Result<()> {
+    compress_parallel_with(input, output, threads, &CompressOptions::default())
+}
+
+/// Same as `compress_parallel`, but with the given settings. The header is only written
+/// in full to the first member, the rest keep just its modification time and OS.
+pub fn compress_parallel_with<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    threads: usize,
+    options: &CompressOptions,
+) -> Result<()> {
+    if threads == 0 {
+        bail!("thread count must be positive");
+    }
+
+    let rest_options = CompressOptions {
+        header: MemberHeader {
+            modification_time: options.header.modification_time,
+            os: options.header.os,
+            ..MemberHeader::default()
+        },
+        ..options.clone()
+    };
+    let batch_size = threads * MEMBER_INPUT_SIZE;
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut is_first_batch = true;
+    loop {
+        batch.clear();
+        (&mut input)
+            .take(batch_size as u64)
+            .read_to_end(&mut batch)?;
+        if batch.is_empty() && !is_first_batch {
+            break;
+        }
+
+        let members = thread::scope(|scope| {
+            let handles = batch
+                .chunks(MEMBER_INPUT_SIZE)
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let options = match i {
+                        0 if is_first_batch => options,
+                        _ => &rest_options,
+                    };
+                    scope.spawn(move || compress_member(chunk, options))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("compression thread panicked"))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        // An empty input still produces a single empty member.
+        if members.is_empty() {
+            output.write_all(&compress_member(&[], options)?)?;
+        }
+        for member in members {
+            output.write_all(&member)?;
+        }
+
+        if batch.len() < batch_size {
+            break;
+        }
+        is_first_batch = false;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+fn compress_member(data: &[u8], options: &CompressOptions) -> Result<Vec<u8>> {
+    let mut member = Vec::with_capacity(data.len() / 2);
+    compress_with(data, &mut member, Format::Gzip, options)?;
+    Ok(member)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompress a multi-member gzip stream, inflating up to `threads` members concurrently.
+///
+/// Member boundaries are not known in advance, so every position in the input that looks
+/// like a member header is decoded speculatively while this thread decodes the member at
+/// the head of the input. The output is then assembled by following the chain of members
+/// that decoded successfully. Members that do not fit into the scanned part of the input or
+/// decompress to too much data are left to the sequential decoder, so a single-member input
+/// is decoded just once and memory use stays bounded. Every member is verified against its
+/// footer just like in `decompress`, and any failure is reported by falling back to it.
+pub fn decompress_parallel<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    threads: usize,
+) -> Result<()> {
+    decompress_segments(input, output, threads, SEGMENT_SIZE)
+}
+
+enum MemberResult {
+    Decoded {
+        data: Vec<u8>,
+        end: usize,
+    },
+    /// The member does not end within the segment or its output is too large to be kept
+    /// in memory, so it has to be decoded sequentially.
+    Incomplete,
+    Invalid,
+}
+
+fn decompress_segments<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    threads: usize,
+    segment_size: usize,
+) -> Result<()> {
+    if threads == 0 {
+        bail!("thread count must be positive");
+    }
+
+    let mut segment = Vec::new();
+    loop {
+        segment.clear();
+        (&mut input)
+            .take(segment_size as u64)
+            .read_to_end(&mut segment)?;
+        if segment.is_empty() {
+            break;
+        }
+
+        if let Some(pos) = decode_segment(&segment, &mut input, &mut output, threads)? {
+            // The stream is broken, so let the sequential decoder report the error.
+            debug!("falling back to sequential decoding at {}", pos);
+            let rest = Cursor::new(&segment[pos..]).chain(input);
+            return Ok(decompress(rest, output)?);
+        }
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// State shared by the threads decoding the candidates of a segment.
+struct Candidates<'a> {
+    segment: &'a [u8],
+    positions: Vec<usize>,
+    next: AtomicUsize,
+    /// Start of the next member to be written. Candidates before it are skipped.
+    chain_pos: AtomicUsize,
+    /// Size of the decoded data that hasn't been written yet.
+    buffered: Mutex<usize>,
+    buffered_changed: Condvar,
+}
+
+impl Candidates<'_> {
+    /// Decode candidates in order until there are none left or the receiver is gone.
+    fn decode(&self, results: Sender<(usize, MemberResult)>) {
+        loop {
+            let i = self.next.fetch_add(1, Ordering::Relaxed);
+            let Some(&pos) = self.positions.get(i) else {
+                break;
+            };
+
+            // NB: the candidate the chain is waiting for is never held back, or it would
+            // wait forever.
+            let mut buffered = self.buffered.lock().unwrap();
+            while *buffered >= MAX_BUFFERED_OUTPUT && pos > self.chain_pos.load(Ordering::Relaxed) {
+                buffered = self.buffered_changed.wait(buffered).unwrap();
+            }
+            drop(buffered);
+            if pos < self.chain_pos.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let result = match decode_member(&self.segment[pos..], MAX_MEMBER_OUTPUT) {
+                Ok(Some((data, len))) => {
+                    *self.buffered.lock().unwrap() += data.len();
+                    MemberResult::Decoded {
+                        data,
+                        end: pos + len,
+                    }
+                }
+                Ok(None) => MemberResult::Incomplete,
+                Err(err) if is_truncated(&err) => MemberResult::Incomplete,
+                Err(_) => MemberResult::Invalid,
+            };
+            if results.send((pos, result)).is_err() {
+                break;
+            }
+        }
+    }
+
+    fn advance(&self, pos: usize, released: usize) {
+        let mut buffered = self.buffered.lock().unwrap();
+        *buffered -= released;
+        self.chain_pos.store(pos, Ordering::Relaxed);
+        self.buffered_changed.notify_all();
+    }
+}
+
+/// Decode the members starting in `segment`, reading the last of them to its end from
+/// `input`. Returns the position of the first member that failed to decode, if any.
+fn decode_segment<R: BufRead, W: Write>(
+    segment: &[u8],
+    input: &mut R,
+    output: &mut W,
+    threads: usize,
+) -> Result<Option<usize>> {
+    // NB: the member at the head is decoded by this thread anyway.
+    let positions = (1..segment.len())
+        .filter(|&pos| is_member_candidate(&segment[pos..]))
+        .collect::<Vec<_>>();
+    debug!("found {} member candidates", positions.len());
+
+    let candidates = Candidates {
+        segment,
+        positions,
+        next: AtomicUsize::new(0),
+        chain_pos: AtomicUsize::new(0),
+        buffered: Mutex::new(0),
+        buffered_changed: Condvar::new(),
+    };
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..(threads - 1).min(candidates.positions.len()) {
+            let sender = sender.clone();
+            scope.spawn(|| candidates.decode(sender));
+        }
+        drop(sender);
+
+        let mut results = BTreeMap::new();
+        let mut pos = 0;
+        let res = loop {
+            if pos >= segment.len() {
+                break Ok(None);
+            }
+            let result = if pos == 0 {
+                MemberResult::Incomplete
+            } else if candidates.positions.binary_search(&pos).is_err() {
+                MemberResult::Invalid
+            } else {
+                loop {
+                    if let Some(result) = results.remove(&pos) {
+                        break result;
+                    }
+                    match receiver.recv() {
+                        Ok((candidate, result)) => {
+                            results.insert(candidate, result);
+                        }
+                        // NB: there are no other threads, so decode it here.
+                        Err(_) => break MemberResult::Incomplete,
+                    }
+                }
+            };
+
+            let (end, released) = match result {
+                MemberResult::Decoded { data, end } => {
+                    if let Err(err) = output.write_all(&data) {
+                        break Err(err.into());
+                    }
+                    (end, data.len())
+                }
+                MemberResult::Incomplete => {
+                    let progress = |len| candidates.advance(pos + len, 0);
+                    match stream_member(&segment[pos..], &mut *input, &mut *output, progress) {
+                        Ok(len) => (pos + len, 0),
+                        Err(err) => break Err(err),
+                    }
+                }
+                MemberResult::Invalid => break Ok(Some(pos)),
+            };
+            pos = end;
+
+            // Drop the results of candidates inside the members decoded so far.
+            let rest = results.split_off(&pos);
+            let skipped = std::mem::replace(&mut results, rest)
+                .into_values()
+                .map(|result| match result {
+                    MemberResult::Decoded { data, .. } => data.len(),
+                    _ => 0,
+                })
+                .sum::<usize>();
+            candidates.advance(pos, released + skipped);
+        };
+
+        // Let the threads skip the remaining candidates.
+        candidates.advance(usize::MAX, 0);
+        res
+    })
+}
+
+/// Decode the member at the start of `segment` as it is read, continuing with `input` if
+/// the member doesn't end within the segment. `progress` is called with the part of
+/// the segment consumed so far. Returns the size of that part once the member has ended.
+fn stream_member<R: BufRead, W: Write>(
+    segment: &[u8],
+    input: R,
+    mut output: W,
+    progress: impl Fn(usize),
+) -> Result<usize> {
+    let mut decoder = GzipDecoder::single_member(Cursor::new(segment).chain(input));
+    while decoder.fill()? {
+        output.write_all(decoder.buffer())?;
+        let len = decoder.buffer().len();
+        decoder.consume(len);
+        if let Some(reader) = decoder.get_mut() {
+            progress(reader.get_ref().0.position() as usize);
+        }
+    }
+    let reader = decoder
+        .into_inner()
+        .expect("the decoder keeps its reader once done");
+    Ok(reader.get_ref().0.position() as usize)
+}
+
+/// Check whether decoding has failed because the input has ended.
+fn is_truncated(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::UnexpectedEof)
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::compress;
+
+    fn sample_data(len: usize) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| b"abcdefgh"[(i.wrapping_mul(2_654_435_761) >> 29) as usize])
+            .collect()
+    }
+
+    fn concat_members(parts: &[&[u8]]) -> Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        for part in parts {
+            compress(*part, &mut compressed)?;
+        }
+        Ok(compressed)
+    }
+
+    #[test]
+    fn candidates() {
+        assert!(is_member_candidate(&[0x1f, 0x8b, 0x08, 0x00, 0x00]));
+        assert!(is_member_candidate(&[0x1f, 0x8b]));
+        assert!(!is_member_candidate(&[0x1f, 0x8b, 0x08, 0x20]));
+        assert!(!is_member_candidate(&[0x1f, 0x8c]));
+    }
+
+    #[test]
+    fn small_segments() -> Result<()> {
+        let parts = [
+            sample_data(5000),
+            Vec::new(),
+            sample_data(20000),
+            sample_data(10),
+        ];
+        let compressed = concat_members(&parts.iter().map(Vec::as_slice).collect::<Vec<_>>())?;
+
+        for segment_size in [1, 100, 1000, 5000, 1 << 20] {
+            let mut output = Vec::new();
+            decompress_segments(&compressed[..], &mut output, 3, segment_size)?;
+            assert_eq!(output, parts.concat(), "segment size {}", segment_size);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn large_members() -> Result<()> {
+        // Both members are too large to be kept in memory, the first one also spans
+        // several segments.
+        let large = vec![0; MAX_MEMBER_OUTPUT + 1];
+        let parts = [sample_data(5000), large.clone(), sample_data(100), large];
+        let compressed = concat_members(&parts.iter().map(Vec::as_slice).collect::<Vec<_>>())?;
+
+        for (threads, segment_size) in [(1, 1 << 20), (3, 1000)] {
+            let mut output = Vec::new();
+            decompress_segments(&compressed[..], &mut output, threads, segment_size)?;
+            assert!(output == parts.concat(), "segment size {}", segment_size);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn errors() -> Result<()> {
+        let mut compressed = concat_members(&[&sample_data(3000), &sample_data(3000)])?;
+        let len = compressed.len();
+        compressed[len - 5] ^= 1;
+
+        let mut output = Vec::new();
+        let err = decompress_segments(&compressed[..], &mut output, 2, 1000).unwrap_err();
+        assert!(format!("{:#}", err).contains("crc32 check failed"));
+
+        compressed.extend_from_slice(b"garbage");
+        let err = decompress_segments(&compressed[..], &mut Vec::new(), 2, 1 << 20).unwrap_err();
+        assert!(format!("{:#}", err).contains("crc32 check failed"));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let data = sample_data(MEMBER_INPUT_SIZE * 5 / 2);
+        let mut compressed = Vec::new();
+        compress_parallel(&data[..], &mut compressed, 4)?;
+
+        let mut output = Vec::new();
+        decompress(&compressed[..], &mut output)?;
+        assert_eq!(output, data);
+
+        output.clear();
+        decompress_segments(&compressed[..], &mut output, 4, 1 << 16)?;
+        assert_eq!(output, data);
+
+        let mut compressed = Vec::new();
+        compress_parallel(&[][..], &mut compressed, 2)?;
+        output.clear();
+        decompress(&compressed[..], &mut output)?;
+        assert!(output.is_empty());
+        Ok(())
+    }
+}
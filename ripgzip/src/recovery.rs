@@ -0,0 +1,207 @@
+use std::io::{self, BufRead, Read, Write};
+
+use anyhow::Result;
+use log::*;
+
+use crate::{
+    decoder::GzipDecoder,
+    error::{DecodeError, MemberError},
+    gzip::is_member_candidate,
+};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Amount of input before the failure point kept for resynchronization. Members starting
+/// earlier than that inside a broken one are skipped.
+const LOOK_BEHIND_SIZE: usize = 1 << 16;
+
+/// Decompress a gzip stream, skipping over members that fail to decode.
+///
+/// Every failure is reported with the index and offset of the member, after which decoding
+/// resumes from the next possible member header. Output of a broken member is written
+/// as far as it could be decoded. Returns all the failures in order; errors reading the
+/// input or writing the output still abort decompression.
+pub fn decompress_recover<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+) -> Result<Vec<MemberError>> {
+    let mut reader = RecoveryReader::new(input);
+    let mut errors = Vec::new();
+    let mut member_index = 0;
+    while !reader.is_at_end()? {
+        let member_offset = reader.offset();
+        reader.mark();
+
+        let mut decoder = GzipDecoder::single_member(&mut reader);
+        let result = loop {
+            match decoder.fill() {
+                Ok(true) => {}
+                Ok(false) => break Ok(()),
+                Err(err) => break Err(err),
+            }
+            output.write_all(decoder.buffer())?;
+            let len = decoder.buffer().len();
+            decoder.consume(len);
+        };
+        // NB: data decoded right before the failure is still buffered.
+        output.write_all(decoder.buffer())?;
+        drop(decoder);
+
+        if let Err(err) = result {
+            if let Some(err) = reader.input_error.take() {
+                return Err(err.into());
+            }
+            let error = MemberError {
+                member_index,
+                member_offset,
+                offset: reader.offset(),
+                error: DecodeError::from_anyhow(err),
+            };
+            debug!("{}", error);
+
+            // NB: the footer has been read in full, so the member's end is known. Otherwise
+            // the next member may start anywhere after the broken one's header.
+            let footer_failed = matches!(
+                error.error,
+                DecodeError::LengthMismatch { .. } | DecodeError::Crc32Mismatch { .. }
+            );
+            errors.push(error);
+            if !footer_failed {
+                reader.rewind(member_offset + 1);
+            }
+            if !reader.skip_to_member()? {
+                break;
+            }
+        }
+        member_index += 1;
+    }
+
+    output.flush()?;
+    Ok(errors)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Buffers the input so that reading may be restarted from a recent offset.
+struct RecoveryReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    /// Position of the next unread byte in `buffer`.
+    pos: usize,
+    /// Offset of the first byte of `buffer` in the stream.
+    buffer_offset: u64,
+    /// Offset before which the input is not needed anymore.
+    mark: u64,
+    /// Error of the underlying reader, passed to the decoder as just its kind.
+    input_error: Option<io::Error>,
+}
+
+impl<R: BufRead> RecoveryReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            pos: 0,
+            buffer_offset: 0,
+            mark: 0,
+            input_error: None,
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        self.buffer_offset + self.pos as u64
+    }
+
+    fn mark(&mut self) {
+        self.mark = self.offset();
+    }
+
+    /// Move back to `offset` or to the earliest offset still buffered after it.
+    fn rewind(&mut self, offset: u64) {
+        let offset = offset.clamp(self.buffer_offset, self.offset());
+        self.pos = (offset - self.buffer_offset) as usize;
+    }
+
+    fn is_at_end(&mut self) -> io::Result<bool> {
+        Ok(self.pos == self.buffer.len() && !self.read_more()?)
+    }
+
+    /// Skip the input up to the next possible member header. Returns `false` if the input
+    /// ends before one is found.
+    fn skip_to_member(&mut self) -> io::Result<bool> {
+        loop {
+            let available = &self.buffer[self.pos..];
+            match (0..available.len()).find(|&i| is_member_candidate(&available[i..])) {
+                // NB: make sure that a partial match is not a false positive.
+                Some(i) if available.len() - i >= 4 => {
+                    self.pos += i;
+                    return Ok(true);
+                }
+                Some(i) => {
+                    self.pos += i;
+                    if !self.read_more()? {
+                        return Ok(true);
+                    }
+                }
+                None => {
+                    self.pos = self.buffer.len();
+                    if !self.read_more()? {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Append the next chunk of the underlying reader to the buffer, dropping the bytes
+    /// that can't be rewound to anymore. Returns `false` at the end of the input.
+    fn read_more(&mut self) -> io::Result<bool> {
+        let keep_from = self
+            .mark
+            .max(self.offset().saturating_sub(LOOK_BEHIND_SIZE as u64));
+        let dropped = (keep_from.saturating_sub(self.buffer_offset) as usize).min(self.pos);
+        self.buffer.drain(..dropped);
+        self.pos -= dropped;
+        self.buffer_offset += dropped as u64;
+
+        let chunk = loop {
+            match self.inner.fill_buf() {
+                Ok(chunk) => break chunk,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        };
+        if chunk.is_empty() {
+            return Ok(false);
+        }
+        let len = chunk.len();
+        self.buffer.extend_from_slice(chunk);
+        self.inner.consume(len);
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Read for RecoveryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for RecoveryReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buffer.len() {
+            if let Err(err) = self.read_more() {
+                let kind = err.kind();
+                self.input_error = Some(err);
+                return Err(kind.into());
+            }
+        }
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buffer.len());
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::{self, BufRead};
+
+use anyhow::Result;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Check whether decoding has stopped only because the reader has no input available
+/// at the moment, i.e. it can be resumed later.
+pub fn is_would_block(err: &anyhow::Error) -> bool {
+    has_io_error(err, io::ErrorKind::WouldBlock)
+}
+
+fn has_io_error(err: &anyhow::Error, kind: io::ErrorKind) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == kind)
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Bytes of a header or a footer that have been read so far.
+///
+/// Headers are parsed from a byte slice, starting over whenever more input arrives. The bytes
+/// the parser has seen are kept here, so a reader failing with `WouldBlock` in the middle of
+/// a header does not lose any of them.
+#[derive(Default)]
+pub struct PartialRead {
+    data: Vec<u8>,
+}
+
+impl PartialRead {
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Read a value with `parse`, which fails with `UnexpectedEof` if the slice given to it
+    /// is too short. Only the bytes the parser has advanced over are consumed, even if it fails.
+    pub fn read<R: BufRead, T>(
+        &mut self,
+        reader: &mut R,
+        mut parse: impl FnMut(&mut &[u8]) -> Result<T>,
+    ) -> Result<T> {
+        loop {
+            let chunk = reader.fill_buf()?;
+            let at_eof = chunk.is_empty();
+            let chunk_len = chunk.len();
+            let prev_len = self.data.len();
+
+            // NB: most of the time the whole value is in the first chunk, so try to avoid
+            // copying it.
+            let (result, parsed) = if prev_len == 0 {
+                let mut rest = chunk;
+                let result = parse(&mut rest);
+                (result, chunk_len - rest.len())
+            } else {
+                self.data.extend_from_slice(chunk);
+                let mut rest = &self.data[..];
+                let result = parse(&mut rest);
+                (result, self.data.len() - rest.len())
+            };
+
+            match result {
+                Err(err) if !at_eof && has_io_error(&err, io::ErrorKind::UnexpectedEof) => {
+                    if prev_len == 0 {
+                        self.data.extend_from_slice(chunk);
+                    }
+                    reader.consume(chunk_len);
+                }
+                result => {
+                    reader.consume(parsed.saturating_sub(prev_len));
+                    self.data.clear();
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::BufReader;
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    fn parse_pair(data: &mut &[u8]) -> Result<(u16, u16)> {
+        Ok((
+            data.read_u16::<LittleEndian>()?,
+            data.read_u16::<LittleEndian>()?,
+        ))
+    }
+
+    #[test]
+    fn small_chunks() -> Result<()> {
+        let data: &[u8] = &[1, 0, 2, 0, 3];
+        let mut reader = BufReader::with_capacity(1, data);
+        let mut partial = PartialRead::default();
+        assert_eq!(partial.read(&mut reader, parse_pair)?, (1, 2));
+        assert!(partial.is_empty());
+        assert_eq!(reader.fill_buf()?, &[3]);
+
+        let err = partial.read(&mut reader, parse_pair).unwrap_err();
+        assert!(has_io_error(&err, io::ErrorKind::UnexpectedEof));
+        Ok(())
+    }
+}
@@ -2,50 +2,113 @@ use std::collections::VecDeque;
 use std::io::{self, Write};
 
 use anyhow::{bail, Result};
-use byteorder::WriteBytesExt;
 use crc::{crc32, Hasher32};
 
+use crate::error::DecodeError;
+
 ////////////////////////////////////////////////////////////////////////////////
 
-const HISTORY_SIZE: usize = 32768;
+pub const HISTORY_SIZE: usize = 32768;
 
 pub struct TrackingWriter<T> {
     inner: T,
-    // TODO: your code here.
+    history: VecDeque<u8>,
+    byte_count: usize,
+    digest: crc32::Digest,
 }
 
 impl<T: Write> Write for TrackingWriter<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        // TODO: your code here.
-        unimplemented!()
+        let written = self.inner.write(buf)?;
+        let buf = &buf[..written];
+
+        self.byte_count += written;
+        self.digest.write(buf);
+
+        let tail = &buf[buf.len().saturating_sub(HISTORY_SIZE)..];
+        let overflow = (self.history.len() + tail.len()).saturating_sub(HISTORY_SIZE);
+        self.history.drain(..overflow);
+        self.history.extend(tail);
+
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+        self.inner.flush()
     }
 }
 
 impl<T: Write> TrackingWriter<T> {
     pub fn new(inner: T) -> Self {
-        // TODO: your code here.
-        unimplemented!()
+        Self {
+            inner,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            byte_count: 0,
+            digest: crc32::Digest::new(crc32::IEEE),
+        }
+    }
+
+    /// Create a writer that continues a stream of `byte_count` bytes with the given checksum,
+    /// the last of which are `history`.
+    pub fn resume(inner: T, history: &[u8], byte_count: usize, data_crc32: u32) -> Self {
+        let history = &history[history.len().saturating_sub(HISTORY_SIZE)..];
+        let mut writer = Self {
+            inner,
+            history: VecDeque::with_capacity(HISTORY_SIZE),
+            byte_count,
+            digest: crc32::Digest::new_with_initial(crc32::IEEE, data_crc32),
+        };
+        writer.history.extend(history);
+        writer
     }
 
     /// Write a sequence of `len` bytes written `dist` bytes ago.
     pub fn write_previous(&mut self, dist: usize, len: usize) -> Result<()> {
-        // TODO: your code here.
-        unimplemented!()
+        if dist == 0 || dist > self.history.len() {
+            bail!(DecodeError::InvalidBackReference {
+                distance: dist,
+                history_size: self.history.len(),
+            });
+        }
+
+        let start = self.history.len() - dist;
+        let mut buf = Vec::with_capacity(len);
+        for i in 0..len {
+            let byte = if i < dist {
+                self.history[start + i]
+            } else {
+                buf[i - dist]
+            };
+            buf.push(byte);
+        }
+
+        self.write_all(&buf)?;
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
     }
 
     pub fn byte_count(&self) -> usize {
-        // TODO: your code here.
-        unimplemented!()
+        self.byte_count
     }
 
     pub fn crc32(&self) -> u32 {
-        // TODO: your code here.
-        unimplemented!()
+        self.digest.sum32()
+    }
+
+    /// Return a copy of the last `HISTORY_SIZE` bytes written.
+    pub fn history(&self) -> Vec<u8> {
+        self.history.iter().copied().collect()
     }
 }
 
@@ -55,6 +118,8 @@ impl<T: Write> TrackingWriter<T> {
 mod tests {
     use super::*;
 
+    use byteorder::WriteBytesExt;
+
     #[test]
     fn write() -> Result<()> {
         let mut buf: &mut [u8] = &mut [0u8; 10];
@@ -0,0 +1,258 @@
+use std::io::{self, BufRead, Read, Write};
+
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{error::DecodeError, resumable::PartialRead};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CM_DEFLATE: u8 = 8;
+const MAX_CINFO: u8 = 7;
+
+const FDICT_OFFSET: u8 = 5;
+const FLEVEL_OFFSET: u8 = 6;
+
+/// Check whether the first two bytes of a stream form a valid zlib header.
+pub fn is_zlib_header(cmf: u8, flg: u8) -> bool {
+    cmf & 0x0f == CM_DEFLATE && cmf >> 4 <= MAX_CINFO && has_valid_fcheck(cmf, flg)
+}
+
+fn has_valid_fcheck(cmf: u8, flg: u8) -> bool {
+    (cmf as u16 * 256 + flg as u16).is_multiple_of(31)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub struct ZlibHeader {
+    /// Base-2 logarithm of the window size, minus eight.
+    pub compression_info: u8,
+    pub compression_level: u8,
+}
+
+#[derive(Debug)]
+pub struct ZlibFooter {
+    pub data_adler32: u32,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ZlibReader<T> {
+    reader: T,
+    header: PartialRead,
+}
+
+impl<T: BufRead> ZlibReader<T> {
+    pub fn new(reader: T) -> Self {
+        Self {
+            reader,
+            header: PartialRead::default(),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.reader
+    }
+
+    pub fn into_inner(self) -> T {
+        self.reader
+    }
+
+    /// Read the stream header, see `into_stream_reader`. Can be called again if the reader
+    /// fails with `WouldBlock`.
+    pub fn read_header(&mut self) -> Result<ZlibHeader> {
+        self.header.read(&mut self.reader, read_header)
+    }
+
+    /// Start reading the compressed data once the header has been read.
+    pub fn into_stream_reader(self) -> ZlibStreamReader<T> {
+        ZlibStreamReader {
+            inner: self.reader,
+            footer: PartialRead::default(),
+        }
+    }
+}
+
+fn read_header(reader: &mut &[u8]) -> Result<ZlibHeader> {
+    // See RFC 1950, section 2.2.
+    let cmf = reader.read_u8()?;
+    let flg = reader.read_u8()?;
+    if cmf & 0x0f != CM_DEFLATE {
+        bail!(DecodeError::UnsupportedCompressionMethod(cmf & 0x0f));
+    }
+    if cmf >> 4 > MAX_CINFO {
+        bail!(DecodeError::UnsupportedWindowSize(cmf >> 4));
+    }
+    if !has_valid_fcheck(cmf, flg) {
+        bail!(DecodeError::ZlibHeaderCheck);
+    }
+    if (flg >> FDICT_OFFSET) & 1 != 0 {
+        bail!(DecodeError::PresetDictionary);
+    }
+
+    Ok(ZlibHeader {
+        compression_info: cmf >> 4,
+        compression_level: flg >> FLEVEL_OFFSET,
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ZlibStreamReader<T> {
+    inner: T,
+    footer: PartialRead,
+}
+
+impl<T: BufRead> ZlibStreamReader<T> {
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Read the footer that follows the compressed data. Can be called again if the reader
+    /// fails with `WouldBlock`.
+    pub fn read_footer(&mut self) -> Result<ZlibFooter> {
+        self.footer.read(&mut self.inner, |reader| {
+            Ok(ZlibFooter {
+                data_adler32: reader.read_u32::<BigEndian>()?,
+            })
+        })
+    }
+}
+
+impl<T: BufRead> Read for ZlibStreamReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: BufRead> BufRead for ZlibStreamReader<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct ZlibWriter<T> {
+    writer: T,
+}
+
+impl<T: Write> ZlibWriter<T> {
+    pub fn new(writer: T) -> Self {
+        Self { writer }
+    }
+
+    pub fn write_header(mut self, header: &ZlibHeader) -> Result<ZlibStreamWriter<T>> {
+        let cmf = header.compression_info << 4 | CM_DEFLATE;
+        let mut flg = header.compression_level << FLEVEL_OFFSET;
+        flg += (31 - (cmf as u16 * 256 + flg as u16) % 31) as u8 % 31;
+        self.writer.write_all(&[cmf, flg])?;
+        Ok(ZlibStreamWriter { inner: self.writer })
+    }
+}
+
+pub struct ZlibStreamWriter<T> {
+    inner: T,
+}
+
+impl<T: Write> ZlibStreamWriter<T> {
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn write_footer(mut self, footer: &ZlibFooter) -> Result<T> {
+        self.inner.write_u32::<BigEndian>(footer.data_adler32)?;
+        Ok(self.inner)
+    }
+}
+
+impl<T: Write> Write for ZlibStreamWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const ADLER_MODULUS: u32 = 65521;
+/// The largest number of bytes that can be summed before `b` may overflow.
+const ADLER_MAX_RUN: usize = 5552;
+
+/// Running Adler-32 checksum, see RFC 1950, section 8.
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for run in data.chunks(ADLER_MAX_RUN) {
+            for &byte in run {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_MODULUS;
+            self.b %= ADLER_MODULUS;
+        }
+    }
+
+    pub fn sum(&self) -> u32 {
+        self.b << 16 | self.a
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adler32() {
+        let mut adler = Adler32::new();
+        assert_eq!(adler.sum(), 1);
+        adler.update(b"Wikipedia");
+        assert_eq!(adler.sum(), 0x11e60398);
+
+        let mut adler = Adler32::new();
+        adler.update(&[0xff; 100_000]);
+        assert_eq!(adler.sum(), 0x149a_302c);
+    }
+
+    #[test]
+    fn header() -> Result<()> {
+        let mut buf = Vec::new();
+        let header = ZlibHeader {
+            compression_info: 7,
+            compression_level: 2,
+        };
+        ZlibWriter::new(&mut buf).write_header(&header)?;
+        assert_eq!(buf, [0x78, 0x9c]);
+        assert!(is_zlib_header(buf[0], buf[1]));
+        assert!(!is_zlib_header(0x1f, 0x8b));
+
+        let header = ZlibReader::new(&buf[..]).read_header()?;
+        assert_eq!(header.compression_info, 7);
+        assert_eq!(header.compression_level, 2);
+
+        assert!(ZlibReader::new(&[0x78u8, 0x9d][..]).read_header().is_err());
+        Ok(())
+    }
+}
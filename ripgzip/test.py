@@ -1,10 +1,13 @@
 #!/usr/bin/env python3
 
 import gzip
+import zlib
 import pathlib
 import subprocess
 import sys
 import random
+import os
+import tempfile
 
 DIR = pathlib.Path(__file__).parent.absolute()
 DEBUG_BINARY_PATH = DIR / ".."/ "target" / "debug" / "ripgzip"
@@ -14,9 +17,15 @@ CORRUPTED_TESTS_PATH = DIR / "data" / "corrupted"
 DUMP_PATH = DIR / "dump.gz"
 
 
-def decompress_file_ripgzip(data, debug=True):
+def decompress_file_ripgzip(data, debug=True, args=()):
     path = DEBUG_BINARY_PATH if debug else RELEASE_BINARY_PATH
-    proc = subprocess.run([path, "-d"], input=data, capture_output=True, check=True)
+    proc = subprocess.run([path, "-d", *args], input=data, capture_output=True, check=True)
+    return proc.stdout
+
+
+def compress_file_ripgzip(data, debug=True, args=()):
+    path = DEBUG_BINARY_PATH if debug else RELEASE_BINARY_PATH
+    proc = subprocess.run([path, *args], input=data, capture_output=True, check=True)
     return proc.stdout
 
 
@@ -71,11 +80,134 @@ def test_big_random_cases():
             raise
 
 
+def test_compression_cases():
+    random.seed(4632463)
+
+    cases = [gzip.decompress(path.read_bytes()) for path in sorted(OK_TESTS_PATH.iterdir())]
+    cases += [bytes(random.randrange(4) for _ in range(100000)) for _ in range(5)]
+
+    for i, data in enumerate(cases):
+        print(f"testing compression, case #{i}")
+
+        compressed = compress_file_ripgzip(data, debug=False)
+        assert gzip.decompress(compressed) == data, f"incorrect output"
+        assert decompress_file_ripgzip(compressed, debug=False) == data, f"incorrect output"
+
+
+def test_format_cases():
+    random.seed(8326231)
+
+    data = gzip.decompress((OK_TESTS_PATH / "06-war-and-peace.txt.gz").read_bytes())
+    cases = [data, bytes(random.randrange(16) for _ in range(100000))]
+
+    for i, data in enumerate(cases):
+        print(f"testing zlib and raw deflate, case #{i}")
+
+        compressed = zlib.compress(data)
+        assert decompress_file_ripgzip(compressed, debug=False) == data
+        assert decompress_file_ripgzip(compressed, debug=False, args=["--format", "zlib"]) == data
+        compressed = compress_file_ripgzip(data, debug=False, args=["--format", "zlib"])
+        assert zlib.decompress(compressed) == data
+
+        compressor = zlib.compressobj(wbits=-15)
+        compressed = compressor.compress(data) + compressor.flush()
+        assert decompress_file_ripgzip(compressed, debug=False, args=["--format", "raw"]) == data
+        compressed = compress_file_ripgzip(data, debug=False, args=["--format", "raw"])
+        assert zlib.decompress(compressed, wbits=-15) == data
+
+
+def test_parallel_cases():
+    random.seed(2135215)
+
+    cases = [gzip.decompress((OK_TESTS_PATH / "06-war-and-peace.txt.gz").read_bytes())]
+    cases += [bytes(random.randrange(8) for _ in range(3000000))]
+
+    for i, data in enumerate(cases):
+        print(f"testing parallel mode, case #{i}")
+
+        compressed = compress_file_ripgzip(data, debug=False, args=["-p", "4"])
+        assert gzip.decompress(compressed) == data
+        assert decompress_file_ripgzip(compressed, debug=False, args=["-p", "4"]) == data
+
+        compressed = b"".join(gzip.compress(data[i:i + 100000]) for i in range(0, len(data), 100000))
+        assert decompress_file_ripgzip(compressed, debug=False, args=["-p", "4"]) == data
+
+
+def run_ripgzip(args, cwd, check=True):
+    return subprocess.run([RELEASE_BINARY_PATH, *args], cwd=cwd, capture_output=True, check=check)
+
+
+def test_file_cases():
+    data = gzip.decompress((OK_TESTS_PATH / "06-war-and-peace.txt.gz").read_bytes())
+    mtime = 1617626096
+
+    with tempfile.TemporaryDirectory() as tmp:
+        tmp = pathlib.Path(tmp)
+        sizes = []
+        for level in [1, 6, 9]:
+            print(f"testing file compression, level {level}")
+            path = tmp / "book.txt"
+            path.write_bytes(data)
+            os.utime(path, (mtime, mtime))
+
+            run_ripgzip([f"-{level}", "book.txt"], tmp)
+            assert not path.exists()
+            compressed = (tmp / "book.txt.gz").read_bytes()
+            sizes.append(len(compressed))
+            assert gzip.decompress(compressed) == data
+            assert compressed[3] & 0x08, "name is not stored"
+            assert int.from_bytes(compressed[4:8], "little") == mtime
+
+            listing = run_ripgzip(["-l", "book.txt.gz"], tmp).stdout.decode()
+            assert "book.txt" in listing and str(len(data)) in listing
+            run_ripgzip(["-t", "book.txt.gz"], tmp)
+
+            (tmp / "book.txt.gz").rename(tmp / "renamed.gz")
+            run_ripgzip(["-dN", "renamed.gz"], tmp)
+            assert path.read_bytes() == data
+            assert int(path.stat().st_mtime) == mtime
+            path.unlink()
+        assert sizes[0] > sizes[1] > sizes[2]
+
+        print("testing -c, -k and -f")
+        path = tmp / "book.txt"
+        path.write_bytes(data)
+        compressed = run_ripgzip(["-c", "book.txt"], tmp).stdout
+        assert gzip.decompress(compressed) == data and path.exists()
+        run_ripgzip(["-k", "book.txt"], tmp)
+        assert path.exists()
+        assert run_ripgzip(["book.txt"], tmp, check=False).returncode == 2
+        assert run_ripgzip(["-d", "book.txt.gz"], tmp, check=False).returncode == 2
+        run_ripgzip(["-df", "book.txt.gz"], tmp)
+        assert path.read_bytes() == data and not (tmp / "book.txt.gz").exists()
+
+        print("testing -N with the stored name of the input")
+        (tmp / "self.gz").write_bytes(data)
+        run_ripgzip(["-f", "self.gz"], tmp)
+        (tmp / "self.gz.gz").rename(tmp / "self.gz")
+        compressed = (tmp / "self.gz").read_bytes()
+        assert run_ripgzip(["-dNf", "self.gz"], tmp, check=False).returncode == 2
+        assert (tmp / "self.gz").read_bytes() == compressed
+        assert sorted(p.name for p in tmp.iterdir() if p.name.startswith(".")) == []
+
+        print("testing integrity check")
+        corrupted = bytearray(gzip.compress(data))
+        corrupted[-6] ^= 1
+        (tmp / "corrupted.gz").write_bytes(corrupted)
+        assert run_ripgzip(["-t", "corrupted.gz"], tmp, check=False).returncode == 1
+        assert run_ripgzip(["-d", "corrupted.gz"], tmp, check=False).returncode == 1
+        assert not (tmp / "corrupted").exists() and (tmp / "corrupted.gz").exists()
+
+
 def main():
     bundles = [
         test_static_cases,
         test_small_random_cases,
         test_big_random_cases,
+        test_compression_cases,
+        test_format_cases,
+        test_parallel_cases,
+        test_file_cases,
     ]
 
     if len(sys.argv) > 1:
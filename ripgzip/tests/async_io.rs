@@ -0,0 +1,148 @@
+#![cfg(feature = "tokio")]
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ripgzip::{AsyncGzipDecoder, AsyncGzipEncoder, Format};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, ReadBuf};
+
+/// Returns `chunk_size` bytes at a time, every other call being `Pending`.
+struct ChunkedReader {
+    data: Vec<u8>,
+    pos: usize,
+    chunk_size: usize,
+    is_ready: bool,
+}
+
+impl ChunkedReader {
+    fn new(data: &[u8], chunk_size: usize) -> Self {
+        Self {
+            data: data.to_vec(),
+            pos: 0,
+            chunk_size,
+            is_ready: false,
+        }
+    }
+}
+
+impl AsyncRead for ChunkedReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.is_ready = !self.is_ready;
+        if !self.is_ready {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let len = self
+            .chunk_size
+            .min(buf.remaining())
+            .min(self.data.len() - self.pos);
+        buf.put_slice(&self.data[self.pos..self.pos + len]);
+        self.pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn sample_data(len: usize) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| b"abcdefgh"[(i.wrapping_mul(2_654_435_761) >> 29) as usize])
+        .collect()
+}
+
+fn compress(data: &[u8], format: Format) -> Vec<u8> {
+    let mut output = Vec::new();
+    ripgzip::compress_format(data, &mut output, format).unwrap();
+    output
+}
+
+async fn decode(data: &[u8], format: Format, chunk_size: usize) -> io::Result<Vec<u8>> {
+    let mut decoder = AsyncGzipDecoder::with_format(ChunkedReader::new(data, chunk_size), format);
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).await?;
+    Ok(output)
+}
+
+#[tokio::test]
+async fn decode_in_chunks() {
+    let data = sample_data(20_000);
+    for format in [Format::Gzip, Format::Zlib, Format::Raw] {
+        let compressed = compress(&data, format);
+        for chunk_size in [1, 7, 1000, 1 << 20] {
+            let output = decode(&compressed, format, chunk_size).await.unwrap();
+            assert!(output == data, "{:?}, chunk size {}", format, chunk_size);
+        }
+    }
+
+    let compressed = include_bytes!("../data/ok/09-concat.gz");
+    let mut expected = Vec::new();
+    ripgzip::decompress(&compressed[..], &mut expected).unwrap();
+    let output = decode(compressed, Format::Gzip, 1000).await.unwrap();
+    assert!(output == expected, "decoded data differs");
+}
+
+#[tokio::test]
+async fn decode_errors() {
+    let err = decode(
+        include_bytes!("../data/corrupted/01-bad-crc32.gz"),
+        Format::Gzip,
+        5,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("crc32 check failed"));
+
+    let err = decode(
+        include_bytes!("../data/corrupted/02-unexpected-eof.gz"),
+        Format::Gzip,
+        5,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn encode() {
+    let data = sample_data(600_000);
+    for format in [Format::Gzip, Format::Zlib, Format::Raw] {
+        let mut encoder = AsyncGzipEncoder::with_format(Vec::new(), format).unwrap();
+        for piece in data.chunks(10_000) {
+            encoder.write_all(piece).await.unwrap();
+        }
+        encoder.shutdown().await.unwrap();
+        assert!(
+            encoder.into_inner() == compress(&data, format),
+            "{:?}: compressed data differs",
+            format
+        );
+    }
+}
+
+#[tokio::test]
+async fn pipe() {
+    let data = sample_data(300_000);
+    let (writer, reader) = tokio::io::duplex(64);
+
+    let write = async {
+        let mut encoder = AsyncGzipEncoder::new(writer).unwrap();
+        encoder.write_all(&data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+    };
+    let read = async {
+        let mut output = Vec::new();
+        AsyncGzipDecoder::new(reader)
+            .read_to_end(&mut output)
+            .await
+            .unwrap();
+        output
+    };
+    let ((), output) = tokio::join!(write, read);
+    assert!(output == data, "decoded data differs");
+}
@@ -0,0 +1,151 @@
+use std::io::{BufRead, ErrorKind, Read};
+
+use ripgzip::{CompressOptions, DecodeError, Format, GzipDecoder, MemberHeader};
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    ripgzip::decompress(data, &mut output).unwrap();
+    output
+}
+
+#[test]
+fn read_lines() {
+    let data = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let expected = decompress(data);
+
+    let decoder = GzipDecoder::new(&data[..]);
+    let lines: Vec<Vec<u8>> = decoder.split(b'\n').take(100).map(|l| l.unwrap()).collect();
+    let expected_lines: Vec<&[u8]> = expected.split(|&b| b == b'\n').take(100).collect();
+    assert_eq!(lines, expected_lines);
+}
+
+#[test]
+fn read_in_small_pieces() {
+    let data = include_bytes!("../data/ok/09-concat.gz");
+    let expected = decompress(data);
+
+    let mut decoder = GzipDecoder::new(&data[..]);
+    let mut output = Vec::new();
+    let mut buf = [0; 7];
+    loop {
+        let len = decoder.read(&mut buf).unwrap();
+        if len == 0 {
+            break;
+        }
+        output.extend_from_slice(&buf[..len]);
+    }
+    assert!(output == expected, "decoded data differs");
+}
+
+#[test]
+fn read_error() {
+    let data = include_bytes!("../data/corrupted/01-bad-crc32.gz");
+    let mut decoder = GzipDecoder::new(&data[..]);
+    let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("crc32 check failed"));
+    let err = decoder.read(&mut [0; 16]).unwrap_err();
+    assert!(matches!(
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<DecodeError>()),
+        Some(DecodeError::DecoderFailed)
+    ));
+}
+
+/// Makes `step` more bytes of `data` available every time it runs out of them, failing
+/// with `WouldBlock` in between.
+struct Trickle<'a> {
+    data: &'a [u8],
+    pos: usize,
+    available: usize,
+    step: usize,
+}
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for Trickle<'_> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.pos == self.available && self.available < self.data.len() {
+            self.available = (self.available + self.step).min(self.data.len());
+            return Err(ErrorKind::WouldBlock.into());
+        }
+        Ok(&self.data[self.pos..self.available])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+fn decode_trickle(data: &[u8], format: Format, step: usize) -> Vec<u8> {
+    let reader = Trickle {
+        data,
+        pos: 0,
+        available: 0,
+        step,
+    };
+    let mut decoder = GzipDecoder::with_format(reader, format);
+    let mut output = Vec::new();
+    let mut buf = [0; 1000];
+    loop {
+        match decoder.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => output.extend_from_slice(&buf[..len]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => panic!("decoding failed: {}", err),
+        }
+    }
+    output
+}
+
+#[test]
+fn resume_after_would_block() {
+    let text: Vec<u8> = (0..2000)
+        .flat_map(|i| format!("line {}\n", i * i).into_bytes())
+        .collect();
+    let header = MemberHeader::builder()
+        .name("lines.txt")
+        .comment("squares")
+        .extra_subfield(*b"ab", b"extra data".to_vec())
+        .header_crc(true)
+        .build()
+        .unwrap();
+    let options = CompressOptions {
+        header,
+        ..CompressOptions::default()
+    };
+
+    let mut data = Vec::new();
+    ripgzip::compress_with(&text[..], &mut data, Format::Gzip, &options).unwrap();
+    ripgzip::compress(&text[..], &mut data).unwrap();
+    for step in [1, 2, 5, 100] {
+        let output = decode_trickle(&data, Format::Gzip, step);
+        assert!(
+            output == text.repeat(2),
+            "step {}: decoded data differs",
+            step
+        );
+    }
+
+    for format in [Format::Zlib, Format::Raw] {
+        let mut data = Vec::new();
+        ripgzip::compress_format(&text[..], &mut data, format).unwrap();
+        for step in [1, 3] {
+            let output = decode_trickle(&data, format, step);
+            assert!(
+                output == text,
+                "{:?}, step {}: decoded data differs",
+                format,
+                step
+            );
+        }
+    }
+}
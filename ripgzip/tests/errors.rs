@@ -1,14 +1,33 @@
-fn check_decompression_error(mut data: &[u8], msg: &'static str) {
-    let res = ripgzip::decompress(&mut data, &mut std::io::sink());
-    if res.is_ok() {
-        panic!("expected Err, got Ok");
+use std::io;
+
+use ripgzip::DecodeError;
+
+fn decompression_error(mut data: &[u8]) -> DecodeError {
+    match ripgzip::decompress(&mut data, &mut io::sink()) {
+        Ok(()) => panic!("expected Err, got Ok"),
+        Err(err) => err,
+    }
+}
+
+fn check_decompression_error(
+    data: &[u8],
+    msg: &'static str,
+    is_expected: fn(&DecodeError) -> bool,
+) {
+    let err = decompression_error(data);
+    if !err.to_string().contains(msg) {
+        panic!("error does not contain message: {}", msg);
     }
-    for inner in res.unwrap_err().chain() {
-        if inner.to_string().contains(msg) {
-            return;
-        }
+    if !is_expected(&err) {
+        panic!("unexpected error variant: {:?}", err);
     }
-    panic!("error does not contain message: {}", msg);
+}
+
+fn check_unexpected_eof(data: &[u8]) {
+    let err = decompression_error(data);
+    let is_eof =
+        matches!(&err, DecodeError::Io(inner) if inner.kind() == io::ErrorKind::UnexpectedEof);
+    assert!(is_eof, "expected unexpected EOF, got: {}", err);
 }
 
 #[test]
@@ -16,31 +35,54 @@ fn errors() {
     check_decompression_error(
         include_bytes!("../data/corrupted/00-bad-length.gz"),
         "length check failed",
+        |err| matches!(err, DecodeError::LengthMismatch { .. }),
     );
     check_decompression_error(
         include_bytes!("../data/corrupted/01-bad-crc32.gz"),
         "crc32 check failed",
+        |err| matches!(err, DecodeError::Crc32Mismatch { .. }),
     );
-    check_decompression_error(include_bytes!("../data/corrupted/02-unexpected-eof.gz"), "");
+    check_unexpected_eof(include_bytes!("../data/corrupted/02-unexpected-eof.gz"));
     check_decompression_error(
         include_bytes!("../data/corrupted/03-wrong-id.gz"),
         "wrong id values",
+        |err| matches!(err, DecodeError::WrongId { .. }),
     );
-    check_decompression_error(include_bytes!("../data/corrupted/04-header-eof.gz"), "");
+    check_unexpected_eof(include_bytes!("../data/corrupted/04-header-eof.gz"));
     check_decompression_error(
         include_bytes!("../data/corrupted/05-bad-header-crc16.gz"),
         "header crc16 check failed",
+        |err| matches!(err, DecodeError::HeaderCrc16Mismatch { .. }),
     );
     check_decompression_error(
         include_bytes!("../data/corrupted/06-invalid-btype.gz"),
         "unsupported block type",
+        |err| matches!(err, DecodeError::UnsupportedBlockType),
     );
     check_decompression_error(
         include_bytes!("../data/corrupted/07-invalid-cm.gz"),
         "unsupported compression method",
+        |err| matches!(err, DecodeError::UnsupportedCompressionMethod(_)),
     );
     check_decompression_error(
         include_bytes!("../data/corrupted/08-bad-nlen.gz"),
         "nlen check failed",
+        |err| matches!(err, DecodeError::NlenMismatch { .. }),
     );
 }
+
+#[test]
+fn invalid_huffman_tree() {
+    // A dynamic block whose code length tree has a single code of length 1 for symbol 0
+    // and no other codes: incomplete, but valid. The litlen tree then consists of
+    // zero-length codes only, so the end of block code is missing.
+    let mut data = vec![0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0xff];
+    // BFINAL = 1, BTYPE = 2, HLIT = 0, HDIST = 0, HCLEN = 0: bits 1, 01, 00000, 00000, 0000.
+    // Code length code lengths for symbols 16, 17, 18, 0: 0, 0, 0, 1.
+    // Then 258 zero lengths, each encoded with the single one-bit code 0.
+    data.extend_from_slice(&[0b0000_0101, 0, 0, 0b0000_0100]);
+    data.extend_from_slice(&[0; 40]);
+    check_decompression_error(&data, "invalid litlen tree", |err| {
+        matches!(err, DecodeError::InvalidHuffmanTree { tree: "litlen", .. })
+    });
+}
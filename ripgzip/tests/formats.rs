@@ -0,0 +1,77 @@
+use ripgzip::{Format, GzipDecoder};
+
+fn cargo_toml() -> Vec<u8> {
+    let mut data = Vec::new();
+    ripgzip::decompress(
+        &include_bytes!("../data/ok/00-Cargo.toml.gz")[..],
+        &mut data,
+    )
+    .unwrap();
+    data
+}
+
+#[test]
+fn detect() {
+    assert_eq!(
+        Format::detect(include_bytes!("../data/ok/00-Cargo.toml.gz")),
+        Format::Gzip
+    );
+    assert_eq!(
+        Format::detect(include_bytes!("../data/formats/00-Cargo.toml.zlib")),
+        Format::Zlib
+    );
+    assert_eq!(
+        Format::detect(include_bytes!("../data/formats/00-Cargo.toml.deflate")),
+        Format::Raw
+    );
+    assert_eq!(Format::detect(&[]), Format::Raw);
+}
+
+#[test]
+fn decompress_formats() {
+    let expected = cargo_toml();
+    for (data, format) in [
+        (
+            &include_bytes!("../data/formats/00-Cargo.toml.zlib")[..],
+            Format::Zlib,
+        ),
+        (
+            &include_bytes!("../data/formats/00-Cargo.toml.deflate")[..],
+            Format::Raw,
+        ),
+    ] {
+        let mut output = Vec::new();
+        ripgzip::decompress_format(data, &mut output, format).unwrap();
+        assert_eq!(output, expected);
+
+        let mut output = Vec::new();
+        ripgzip::decompress_auto(data, &mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+}
+
+#[test]
+fn roundtrip_formats() {
+    let data = cargo_toml().repeat(100);
+    for format in [Format::Gzip, Format::Zlib, Format::Raw] {
+        let mut compressed = Vec::new();
+        ripgzip::compress_format(&data[..], &mut compressed, format).unwrap();
+        assert_eq!(Format::detect(&compressed), format);
+
+        let mut output = Vec::new();
+        std::io::copy(
+            &mut GzipDecoder::detect(&compressed[..]).unwrap(),
+            &mut output,
+        )
+        .unwrap();
+        assert!(output == data, "roundtrip changed the data");
+    }
+}
+
+#[test]
+fn bad_adler32() {
+    let mut data = include_bytes!("../data/formats/00-Cargo.toml.zlib").to_vec();
+    *data.last_mut().unwrap() ^= 1;
+    let err = ripgzip::decompress_format(&data[..], std::io::sink(), Format::Zlib).unwrap_err();
+    assert!(err.to_string().contains("adler32 check failed"));
+}
@@ -0,0 +1,143 @@
+use std::io::{BufRead, Read};
+
+use ripgzip::{compress_with, CompressOptions, ExtraSubfield, Format, GzipDecoder, MemberHeader};
+
+fn compress(data: &[u8], header: &MemberHeader) -> Vec<u8> {
+    let options = CompressOptions {
+        header: header.clone(),
+        ..CompressOptions::default()
+    };
+    let mut output = Vec::new();
+    compress_with(data, &mut output, Format::Gzip, &options).unwrap();
+    output
+}
+
+fn decode(data: &[u8]) -> (Vec<u8>, MemberHeader) {
+    let mut decoder = GzipDecoder::new(data);
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output).unwrap();
+    (output, decoder.header().unwrap().clone())
+}
+
+#[test]
+fn roundtrip() {
+    let header = MemberHeader::builder()
+        .name("data.txt")
+        .comment("written by ripgzip")
+        .modification_time(1617626096)
+        .os(3)
+        .is_text(true)
+        .extra_subfield(*b"AP", b"apollo".to_vec())
+        .extra_subfield(*b"RG", Vec::new())
+        .header_crc(true)
+        .build()
+        .unwrap();
+
+    let data = b"some text\n".repeat(1000);
+    let compressed = compress(&data, &header);
+    let (output, parsed) = decode(&compressed);
+    assert_eq!(output, data);
+    assert_eq!(
+        parsed,
+        MemberHeader {
+            extra_flags: 0,
+            ..header
+        }
+    );
+    assert!(parsed.has_crc);
+    assert_eq!(
+        parsed.extra_subfields().unwrap(),
+        vec![
+            ExtraSubfield {
+                id: *b"AP",
+                data: b"apollo".to_vec(),
+            },
+            ExtraSubfield {
+                id: *b"RG",
+                data: Vec::new(),
+            },
+        ]
+    );
+
+    // The header CRC covers every field.
+    let name_offset = compressed
+        .windows(8)
+        .position(|window| window == b"data.txt")
+        .unwrap();
+    let mut corrupted = compressed.clone();
+    corrupted[name_offset] = b'D';
+    let err = ripgzip::decompress(&corrupted[..], Vec::new()).unwrap_err();
+    assert!(format!("{:#}", err).contains("header crc16 check failed"));
+}
+
+#[test]
+fn level_sets_extra_flags() {
+    let header = MemberHeader::builder().name("best").build().unwrap();
+    let options = CompressOptions { level: 9, header };
+    let mut compressed = Vec::new();
+    compress_with(&b"abc"[..], &mut compressed, Format::Gzip, &options).unwrap();
+    let (_, parsed) = decode(&compressed);
+    assert_eq!(parsed.extra_flags, 2);
+    assert_eq!(parsed.name.as_deref(), Some(&b"best"[..]));
+}
+
+#[test]
+fn parsed_header() {
+    // NB: only the first member of this file has a header CRC.
+    let mut decoder = GzipDecoder::new(&include_bytes!("../data/ok/10-header-crc16.gz")[..]);
+    decoder.fill_buf().unwrap();
+    let header = decoder.header().unwrap();
+    assert!(header.has_crc);
+    assert_eq!(header.modification_time, 1617639136);
+    assert!(header.name.is_none());
+    // The extra field holds plain text rather than subfields.
+    assert!(header.extra.as_ref().unwrap().starts_with(b"The first one"));
+    assert!(header.extra_subfields().is_err());
+
+    assert!(MemberHeader::default()
+        .extra_subfields()
+        .unwrap()
+        .is_empty());
+    let header = MemberHeader {
+        extra: Some(vec![b'A', b'B', 2, 0, 1, 2, b'C']),
+        ..MemberHeader::default()
+    };
+    assert!(header.extra_subfields().is_err());
+}
+
+#[test]
+fn invalid_fields() {
+    assert!(MemberHeader::builder().name("a\0b").build().is_err());
+    assert!(MemberHeader::builder().comment("\0").build().is_err());
+    assert!(MemberHeader::builder()
+        .extra_subfield([b'A', 0], Vec::new())
+        .build()
+        .is_err());
+    assert!(MemberHeader::builder()
+        .extra_subfield(*b"AB", vec![0; 65536])
+        .build()
+        .is_err());
+    assert!(MemberHeader::builder()
+        .extra_subfield(*b"AB", vec![0; 40000])
+        .extra_subfield(*b"CD", vec![0; 40000])
+        .build()
+        .is_err());
+}
+
+#[test]
+fn non_utf8_name() {
+    // Written by hand: FHCRC | FNAME with a Latin-1 name "café.txt" and data "hello\n".
+    let compressed = [
+        0x1f, 0x8b, 0x08, 0x0a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x63, 0x61, 0x66, 0xe9, 0x2e,
+        0x74, 0x78, 0x74, 0x00, 0x90, 0xb2, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00, 0x20,
+        0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+    ];
+    let (output, parsed) = decode(&compressed);
+    assert_eq!(output, b"hello\n");
+    assert_eq!(parsed.name.as_deref(), Some(&b"caf\xe9.txt"[..]));
+    assert_eq!(parsed.name_lossy().as_deref(), Some("caf\u{fffd}.txt"));
+    assert_eq!(parsed.crc16(), 0xb290);
+
+    // The raw bytes are written back as they were.
+    assert_eq!(compress(&output, &parsed)[..21], compressed[..21]);
+}
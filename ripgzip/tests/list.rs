@@ -0,0 +1,79 @@
+use ripgzip::{compress_with, list_members, CompressOptions, Format, MemberHeader, MemberInfo};
+
+fn compress(data: &[u8], options: &CompressOptions) -> Vec<u8> {
+    let mut output = Vec::new();
+    compress_with(data, &mut output, Format::Gzip, options).unwrap();
+    output
+}
+
+#[test]
+fn name_and_mtime() {
+    let data = b"hello, world\n".repeat(100);
+    let header = MemberHeader::builder()
+        .name("hello.txt")
+        .modification_time(1617626096)
+        .build()
+        .unwrap();
+    let options = CompressOptions {
+        header: header.clone(),
+        ..CompressOptions::default()
+    };
+    let first = compress(&data, &options);
+    let second = compress(b"", &CompressOptions::default());
+    let members = list_members(&[first.clone(), second.clone()].concat()[..]).unwrap();
+
+    assert_eq!(
+        members,
+        vec![
+            MemberInfo {
+                header,
+                compressed_size: first.len() as u64,
+                uncompressed_size: data.len() as u64,
+                crc32: u32::from_le_bytes(first[first.len() - 8..][..4].try_into().unwrap()),
+            },
+            MemberInfo {
+                header: MemberHeader::default(),
+                compressed_size: second.len() as u64,
+                uncompressed_size: 0,
+                crc32: 0,
+            },
+        ]
+    );
+}
+
+#[test]
+fn concatenated_members() {
+    let members = list_members(&include_bytes!("../data/ok/09-concat.gz")[..]).unwrap();
+    assert!(members.len() > 1);
+    let total: u64 = members.iter().map(|m| m.compressed_size).sum();
+    assert_eq!(
+        total,
+        include_bytes!("../data/ok/09-concat.gz").len() as u64
+    );
+}
+
+#[test]
+fn levels() {
+    let data = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let mut text = Vec::new();
+    ripgzip::decompress(&data[..], &mut text).unwrap();
+
+    let sizes = [1, 6, 9].map(|level| {
+        let options = CompressOptions {
+            level,
+            ..CompressOptions::default()
+        };
+        let compressed = compress(&text, &options);
+        let mut output = Vec::new();
+        ripgzip::decompress(&compressed[..], &mut output).unwrap();
+        assert_eq!(output, text, "level {}", level);
+        compressed.len()
+    });
+    assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2], "{:?}", sizes);
+
+    let options = CompressOptions {
+        level: 10,
+        ..CompressOptions::default()
+    };
+    assert!(compress_with(&text[..], Vec::new(), Format::Gzip, &options).is_err());
+}
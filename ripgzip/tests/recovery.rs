@@ -0,0 +1,114 @@
+use ripgzip::{DecodeError, MemberError};
+
+fn sample_data(seed: usize) -> Vec<u8> {
+    (0..2000)
+        .flat_map(|i| format!("member {}, line {}\n", seed, i * seed).into_bytes())
+        .collect()
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    ripgzip::compress(data, &mut output).unwrap();
+    output
+}
+
+fn recover(data: &[u8]) -> (Vec<u8>, Vec<MemberError>) {
+    let mut output = Vec::new();
+    let errors = ripgzip::decompress_recover(data, &mut output).unwrap();
+    (output, errors)
+}
+
+#[test]
+fn no_errors() {
+    let (first, second) = (sample_data(1), sample_data(2));
+    let data = [compress(&first), compress(&second)].concat();
+    let (output, errors) = recover(&data);
+    assert_eq!(output, [first, second].concat());
+    assert!(errors.is_empty());
+}
+
+#[test]
+fn bad_footer() {
+    let (first, second, third) = (sample_data(1), sample_data(2), sample_data(3));
+    let mut broken = compress(&second);
+    let len = broken.len();
+    broken[len - 8] ^= 1;
+    let first_member = compress(&first);
+    let data = [first_member.clone(), broken, compress(&third)].concat();
+
+    let (output, errors) = recover(&data);
+    assert_eq!(output, [first, second, third].concat());
+    assert_eq!(errors.len(), 1);
+    let err = &errors[0];
+    assert_eq!(err.member_index, 1);
+    assert_eq!(err.member_offset, first_member.len() as u64);
+    assert_eq!(err.offset, err.member_offset + len as u64);
+    assert!(matches!(err.error, DecodeError::Crc32Mismatch { .. }));
+}
+
+#[test]
+fn bad_header() {
+    let (first, second) = (sample_data(1), sample_data(2));
+    let first_member = compress(&first);
+    // Set FHCRC and insert a wrong CRC16 right after the fixed part of the header.
+    let mut broken = compress(&sample_data(3));
+    broken[3] |= 0x02;
+    broken.splice(10..10, [0x12, 0x34]);
+    let data = [first_member.clone(), broken, compress(&second)].concat();
+
+    let (output, errors) = recover(&data);
+    assert_eq!(output, [first, second].concat());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].member_index, 1);
+    assert_eq!(errors[0].member_offset, first_member.len() as u64);
+    assert_eq!(errors[0].offset, errors[0].member_offset + 12);
+    assert!(matches!(
+        errors[0].error,
+        DecodeError::HeaderCrc16Mismatch { .. }
+    ));
+}
+
+#[test]
+fn truncated_member() {
+    let (first, second) = (sample_data(1), sample_data(2));
+    let first_member = compress(&first);
+    let data = [&first_member[..first_member.len() / 2], &compress(&second)].concat();
+
+    // The broken member swallows the header of the next one, which has to be found again.
+    let (output, errors) = recover(&data);
+    assert!(output.ends_with(&second));
+    assert!(!errors.is_empty());
+    assert_eq!(errors[0].member_index, 0);
+    assert_eq!(errors[0].member_offset, 0);
+    assert!(errors[0].offset > first_member.len() as u64 / 2);
+}
+
+#[test]
+fn leading_garbage() {
+    let data = sample_data(1);
+    let (output, errors) = recover(&[b"garbage".as_slice(), &compress(&data)].concat());
+    assert_eq!(output, data);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].member_offset, 0);
+    assert!(matches!(
+        errors[0].error,
+        DecodeError::WrongId {
+            id1: b'g',
+            id2: b'a'
+        }
+    ));
+}
+
+#[test]
+fn trailing_eof() {
+    let data = sample_data(1);
+    let member = compress(&data);
+    let (output, errors) = recover(&[&member[..], &member[..5]].concat());
+    assert_eq!(output, data);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].member_index, 1);
+    assert_eq!(errors[0].member_offset, member.len() as u64);
+    assert!(
+        matches!(&errors[0].error, DecodeError::Io(err) if err.kind() == std::io::ErrorKind::UnexpectedEof)
+    );
+}
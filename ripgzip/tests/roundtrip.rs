@@ -0,0 +1,29 @@
+fn roundtrip(data: &[u8]) {
+    let mut compressed = Vec::new();
+    ripgzip::compress(data, &mut compressed).unwrap();
+
+    let mut decompressed = Vec::new();
+    ripgzip::decompress(compressed.as_slice(), &mut decompressed).unwrap();
+    assert!(decompressed == data, "roundtrip changed the data");
+}
+
+#[test]
+fn roundtrip_small() {
+    roundtrip(b"");
+    roundtrip(b"a");
+    roundtrip(b"abcabcabcabcabcabcabc");
+    roundtrip(&[0; 100_000]);
+}
+
+#[test]
+fn roundtrip_files() {
+    for gz in [
+        include_bytes!("../data/ok/00-Cargo.toml.gz").as_slice(),
+        include_bytes!("../data/ok/02-doc.pdf.gz").as_slice(),
+        include_bytes!("../data/ok/01-page.gz").as_slice(),
+    ] {
+        let mut data = Vec::new();
+        ripgzip::decompress(gz, &mut data).unwrap();
+        roundtrip(&data);
+    }
+}
@@ -0,0 +1,123 @@
+use std::io::{Cursor, ErrorKind, Read, Seek, SeekFrom};
+
+use ripgzip::{GzipIndex, SeekableGzipReader};
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    ripgzip::decompress(data, &mut output).unwrap();
+    output
+}
+
+fn read_range<R: Read + Seek>(reader: &mut R, offset: u64, len: usize) -> Vec<u8> {
+    reader.seek(SeekFrom::Start(offset)).unwrap();
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf).unwrap();
+    buf
+}
+
+fn check_random_access(data: &[u8], span: u64) {
+    let expected = decompress(data);
+    let index = GzipIndex::build(Cursor::new(data), span).unwrap();
+    assert_eq!(index.uncompressed_size(), expected.len() as u64);
+    assert!(index.checkpoint_count() > 2);
+
+    let mut reader = SeekableGzipReader::new(Cursor::new(data), index).unwrap();
+    let len = expected.len() as u64;
+    let offsets = (0..50u64)
+        .map(|i| i.wrapping_mul(2_654_435_761) % len)
+        .chain([0, len - 1, len, span, span - 1]);
+    for offset in offsets {
+        let start = offset as usize;
+        let end = (start + 1000).min(expected.len());
+        assert_eq!(
+            read_range(&mut reader, offset, 1000),
+            &expected[start..end],
+            "offset {}",
+            offset
+        );
+    }
+}
+
+#[test]
+fn single_member() {
+    check_random_access(
+        include_bytes!("../data/ok/06-war-and-peace.txt.gz"),
+        1 << 17,
+    );
+}
+
+#[test]
+fn multiple_members() {
+    check_random_access(include_bytes!("../data/ok/09-concat.gz"), 1 << 16);
+}
+
+#[test]
+fn stored_blocks() {
+    check_random_access(include_bytes!("../data/ok/03-photo.jpg.gz"), 1 << 16);
+}
+
+#[test]
+fn seek_from_end_and_current() {
+    let data = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let expected = decompress(data);
+    let index = GzipIndex::build(Cursor::new(&data[..]), 1 << 18).unwrap();
+    let mut reader = SeekableGzipReader::new(Cursor::new(&data[..]), index).unwrap();
+
+    let mut buf = [0; 100];
+    assert_eq!(
+        reader.seek(SeekFrom::End(-100)).unwrap(),
+        expected.len() as u64 - 100
+    );
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &expected[expected.len() - 100..]);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+
+    let position = reader.seek(SeekFrom::Current(-1_000_000)).unwrap() as usize;
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &expected[position..position + 100]);
+
+    let err = reader.seek(SeekFrom::Current(-10_000_000)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let mut rest = Vec::new();
+    reader.seek(SeekFrom::Start(0)).unwrap();
+    reader.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, expected);
+}
+
+#[test]
+fn save_and_load() {
+    let data = include_bytes!("../data/ok/09-concat.gz");
+    let index = GzipIndex::build(Cursor::new(&data[..]), 1 << 16).unwrap();
+
+    let mut saved = Vec::new();
+    index.write_to(&mut saved).unwrap();
+    assert_eq!(&saved[..8], b"RGZINDEX");
+    let loaded = GzipIndex::read_from(&saved[..]).unwrap();
+    assert_eq!(loaded, index);
+
+    assert!(GzipIndex::read_from(&saved[..saved.len() - 1]).is_err());
+    assert!(GzipIndex::read_from(&b"RGZINDEY"[..]).is_err());
+
+    let other = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let err = SeekableGzipReader::new(Cursor::new(&other[..]), loaded)
+        .err()
+        .unwrap();
+    assert!(format!("{:#}", err).contains("index does not match the file"));
+}
+
+#[test]
+fn corrupted_data() {
+    let data = include_bytes!("../data/ok/06-war-and-peace.txt.gz");
+    let index = GzipIndex::build(Cursor::new(&data[..]), 1 << 18).unwrap();
+
+    // The footer is still verified when starting from a checkpoint.
+    let mut corrupted = data.to_vec();
+    let len = corrupted.len();
+    corrupted[len - 6] ^= 1;
+    let mut reader = SeekableGzipReader::new(Cursor::new(corrupted), index).unwrap();
+    reader.seek(SeekFrom::End(-10_000)).unwrap();
+    let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("crc32 check failed"));
+}